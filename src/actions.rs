@@ -0,0 +1,179 @@
+//! At-a-glance summary of what the agent did during a run.
+//!
+//! Built from the `tool_use` blocks of assistant events and the matching `tool_result` blocks
+//! of user events, so callers can see files touched and commands run without requesting the
+//! full event stream.
+
+use rmcp::schemars::{self, JsonSchema};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Entries kept per list; later actions of the same kind are counted but not listed
+const MAX_ENTRIES: usize = 100;
+/// Commands longer than this are cut, since heredocs can embed whole files
+const MAX_COMMAND_CHARS: usize = 500;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ActionSummary {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files_read: Vec<String>,
+    /// Files changed with `Edit`, `MultiEdit`, or `NotebookEdit`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files_edited: Vec<String>,
+    /// Files written whole with `Write`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files_created: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<CommandAction>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub web_fetches: Vec<String>,
+    /// Actions beyond the per-list cap
+    #[serde(skip_serializing_if = "is_zero")]
+    pub omitted: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct CommandAction {
+    pub command: String,
+    /// Exit code from the tool result; `None` until the result arrives or when it does not
+    /// say (e.g. the command was denied)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip)]
+    tool_use_id: String,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl ActionSummary {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Record a `tool_use` block from an assistant event
+    pub(crate) fn record_tool_use(&mut self, id: &str, name: &str, input: &Value) {
+        let field = |key: &str| input.get(key).and_then(|v| v.as_str());
+        match name {
+            "Read" => self.push_file(ListKind::Read, field("file_path")),
+            "Edit" | "MultiEdit" => self.push_file(ListKind::Edited, field("file_path")),
+            "NotebookEdit" => self.push_file(ListKind::Edited, field("notebook_path")),
+            "Write" => self.push_file(ListKind::Created, field("file_path")),
+            "Bash" => {
+                if let Some(command) = field("command") {
+                    if self.commands.len() < MAX_ENTRIES {
+                        self.commands.push(CommandAction {
+                            command: command.chars().take(MAX_COMMAND_CHARS).collect(),
+                            exit_code: None,
+                            tool_use_id: id.to_string(),
+                        });
+                    } else {
+                        self.omitted += 1;
+                    }
+                }
+            }
+            "WebFetch" => {
+                if let Some(url) = field("url") {
+                    if self.web_fetches.len() < MAX_ENTRIES {
+                        self.web_fetches.push(url.to_string());
+                    } else {
+                        self.omitted += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Record a `tool_result` block from a user event, filling in command exit codes
+    pub(crate) fn record_tool_result(
+        &mut self,
+        tool_use_id: &str,
+        is_error: bool,
+        content: Option<&str>,
+    ) {
+        if let Some(command) = self
+            .commands
+            .iter_mut()
+            .find(|c| c.tool_use_id == tool_use_id)
+        {
+            command.exit_code = exit_code(is_error, content);
+        }
+    }
+
+    fn push_file(&mut self, kind: ListKind, path: Option<&str>) {
+        let Some(path) = path else {
+            return;
+        };
+        let list = match kind {
+            ListKind::Read => &mut self.files_read,
+            ListKind::Edited => &mut self.files_edited,
+            ListKind::Created => &mut self.files_created,
+        };
+        if list.iter().any(|p| p == path) {
+            return;
+        }
+        if list.len() < MAX_ENTRIES {
+            list.push(path.to_string());
+        } else {
+            self.omitted += 1;
+        }
+    }
+}
+
+enum ListKind {
+    Read,
+    Edited,
+    Created,
+}
+
+/// Successful Bash results carry no exit code; failures start with `Exit code N`
+fn exit_code(is_error: bool, content: Option<&str>) -> Option<i32> {
+    if !is_error {
+        return Some(0);
+    }
+    let rest = content?.strip_prefix("Exit code ")?;
+    let digits: String = rest
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '-')
+        .collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tool_uses_are_grouped_by_kind() {
+        let mut actions = ActionSummary::default();
+        actions.record_tool_use("t1", "Read", &json!({"file_path": "src/lib.rs"}));
+        actions.record_tool_use("t2", "Read", &json!({"file_path": "src/lib.rs"}));
+        actions.record_tool_use("t3", "Edit", &json!({"file_path": "src/main.rs"}));
+        actions.record_tool_use("t4", "Write", &json!({"file_path": "NOTES.md"}));
+        actions.record_tool_use("t5", "WebFetch", &json!({"url": "https://example.com"}));
+        actions.record_tool_use("t6", "Grep", &json!({"pattern": "fn main"}));
+
+        assert_eq!(actions.files_read, vec!["src/lib.rs"]);
+        assert_eq!(actions.files_edited, vec!["src/main.rs"]);
+        assert_eq!(actions.files_created, vec!["NOTES.md"]);
+        assert_eq!(actions.web_fetches, vec!["https://example.com"]);
+        assert!(actions.commands.is_empty());
+    }
+
+    #[test]
+    fn test_command_exit_codes_come_from_tool_results() {
+        let mut actions = ActionSummary::default();
+        actions.record_tool_use("ok", "Bash", &json!({"command": "cargo build"}));
+        actions.record_tool_use("fail", "Bash", &json!({"command": "cargo test"}));
+        actions.record_tool_use("denied", "Bash", &json!({"command": "rm -rf target"}));
+        actions.record_tool_result("ok", false, Some("Finished"));
+        actions.record_tool_result("fail", true, Some("Exit code 101\nerror: test failed"));
+        actions.record_tool_result("denied", true, Some("Permission denied"));
+
+        let codes: Vec<Option<i32>> = actions.commands.iter().map(|c| c.exit_code).collect();
+        assert_eq!(codes, vec![Some(0), Some(101), None]);
+    }
+}
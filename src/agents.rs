@@ -0,0 +1,107 @@
+//! Subagent definitions passed to the CLI with `--agents`.
+//!
+//! Subagents the CLI can hand work to (a reviewer, a test writer) usually live in the
+//! repository's `.claude/agents`. Defined under `agents` in `claude-mcp.config.json`, or per
+//! call with `AGENTS`, they are passed as JSON on the command line instead, so an
+//! orchestrator can give each run its own specialists without touching the repository. A
+//! call's definitions are added to the configured ones and replace those of the same name.
+
+use rmcp::schemars::{self, JsonSchema};
+use rmcp::ErrorData as McpError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// One subagent, in the form the CLI's `--agents` takes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AgentDefinition {
+    /// When Claude should hand work to the agent, e.g. "Reviews diffs for security issues"
+    pub description: String,
+    /// System prompt of the agent
+    pub prompt: String,
+    /// Tools the agent may use, e.g. `["Read", "Grep"]`; all of the session's when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<String>>,
+    /// Model alias or full model name; the session's when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// Why a definition cannot be passed to the CLI, if it cannot
+pub(crate) fn problem(name: &str, agent: &AgentDefinition) -> Option<String> {
+    if name.trim().is_empty() {
+        Some("agent names must not be empty".to_string())
+    } else if agent.description.trim().is_empty() {
+        Some(format!("agent '{}' needs a description", name))
+    } else if agent.prompt.trim().is_empty() {
+        Some(format!("agent '{}' needs a prompt", name))
+    } else {
+        None
+    }
+}
+
+/// The value of `--agents` for the `configured` definitions and those of the call, or
+/// `None` when there are none
+pub(crate) fn arg(
+    configured: BTreeMap<String, AgentDefinition>,
+    call: Option<&BTreeMap<String, AgentDefinition>>,
+) -> Result<Option<String>, McpError> {
+    let mut agents = configured;
+    for (name, agent) in call.into_iter().flatten() {
+        if let Some(problem) = problem(name, agent) {
+            return Err(McpError::invalid_params(
+                format!("AGENTS: {}", problem),
+                None,
+            ));
+        }
+        agents.insert(name.clone(), agent.clone());
+    }
+    if agents.is_empty() {
+        return Ok(None);
+    }
+    serde_json::to_string(&agents)
+        .map(Some)
+        .map_err(|e| McpError::internal_error(format!("Failed to encode AGENTS: {}", e), None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(description: &str, prompt: &str) -> AgentDefinition {
+        AgentDefinition {
+            description: description.to_string(),
+            prompt: prompt.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_call_agents_are_added_to_configured_ones() {
+        let configured = BTreeMap::from([
+            ("reviewer".to_string(), agent("Reviews diffs", "Be strict")),
+            ("tester".to_string(), agent("Writes tests", "Use proptest")),
+        ]);
+        let call = BTreeMap::from([(
+            "reviewer".to_string(),
+            AgentDefinition {
+                tools: Some(vec!["Read".to_string()]),
+                ..agent("Reviews diffs", "Be lenient")
+            },
+        )]);
+
+        let passed = arg(configured.clone(), Some(&call)).unwrap().unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&passed).unwrap(),
+            serde_json::json!({
+                "reviewer": {"description": "Reviews diffs", "prompt": "Be lenient", "tools": ["Read"]},
+                "tester": {"description": "Writes tests", "prompt": "Use proptest"},
+            })
+        );
+        assert_eq!(arg(BTreeMap::new(), None).unwrap(), None);
+
+        let unprompted = BTreeMap::from([("fixer".to_string(), agent("Fixes things", " "))]);
+        let err = arg(configured, Some(&unprompted)).unwrap_err();
+        assert_eq!(err.message, "AGENTS: agent 'fixer' needs a prompt");
+    }
+}
@@ -0,0 +1,88 @@
+//! Read-only questions about the repository for `claude_ask`.
+//!
+//! Runs are always in plan mode with the editing tools disallowed and a low turn limit, so a
+//! question like "where is X implemented?" gets a quick answer and can never change files.
+//! The files Claude read to answer are reported as its citations.
+
+use crate::claude::PLAN_MODE_ARGS;
+use serde::Serialize;
+use std::path::Path;
+
+/// Turns a question gets when the caller does not say
+pub(crate) const DEFAULT_MAX_TURNS: u32 = 8;
+/// Most turns a caller can ask for
+pub(crate) const MAX_TURNS: u32 = 30;
+
+/// Tools the CLI refuses during a question, on top of plan mode
+const DISALLOWED_TOOLS: &str = "Edit,MultiEdit,Write,NotebookEdit";
+
+/// Outcome of `claude_ask`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct Answer {
+    pub success: bool,
+    pub answer: String,
+    /// Files read while answering, relative to the working directory when inside it
+    pub files: Vec<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Flags that keep the run read-only and within `max_turns`
+pub(crate) fn read_only_args(max_turns: u32) -> Vec<String> {
+    let mut args: Vec<String> = PLAN_MODE_ARGS.map(String::from).to_vec();
+    args.extend([
+        "--disallowedTools".to_string(),
+        DISALLOWED_TOOLS.to_string(),
+        "--max-turns".to_string(),
+        max_turns.to_string(),
+    ]);
+    args
+}
+
+/// The request to answer `question` from the code, citing where the answer comes from
+pub(crate) fn prompt(question: &str) -> String {
+    format!(
+        "Answer the question below about the code in the current working directory. Look \
+         things up in the code rather than guessing, and keep the answer short. Cite the \
+         files, with line numbers, that the answer is based on.\n\n{}",
+        question.trim()
+    )
+}
+
+/// `files_read` as citations, relative to `working_dir` when inside it
+pub(crate) fn cited(files_read: &[String], working_dir: &Path) -> Vec<String> {
+    files_read
+        .iter()
+        .map(|file| match Path::new(file).strip_prefix(working_dir) {
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => file.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_are_read_only_and_citations_relative() {
+        assert_eq!(
+            read_only_args(5),
+            [
+                "--permission-mode",
+                "plan",
+                "--disallowedTools",
+                "Edit,MultiEdit,Write,NotebookEdit",
+                "--max-turns",
+                "5"
+            ]
+        );
+        let files = ["/repo/src/lib.rs", "/elsewhere/notes.md"].map(String::from);
+        assert_eq!(
+            cited(&files, Path::new("/repo")),
+            ["src/lib.rs", "/elsewhere/notes.md"]
+        );
+    }
+}
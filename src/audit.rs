@@ -0,0 +1,255 @@
+//! Append-only audit trail of tool calls.
+//!
+//! When `audit_log` is set in `claude-mcp.config.json`, every tool call appends one JSON line
+//! recording who called, what was asked, where it ran, and how it ended. The file is rotated
+//! by size (`audit.jsonl` → `audit.jsonl.1` → …). Writing is best effort: a failing disk is
+//! logged and never fails the call.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Number of prompt characters kept in `truncated` mode unless configured otherwise
+const TRUNCATED_PROMPT_CHARS: usize = 200;
+
+fn default_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_max_files() -> usize {
+    5
+}
+
+/// Audit log settings, configured via `audit_log` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLog {
+    /// JSONL file records are appended to
+    pub path: PathBuf,
+    /// Size at which the file is rotated
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// Rotated files kept besides the active one; 0 truncates instead of rotating
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    /// How much of the prompt is logged; unset, `prompt_retention` decides
+    #[serde(default)]
+    pub prompt: Option<PromptRecord>,
+}
+
+/// How much of a prompt is persisted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptRecord {
+    /// Only the SHA-256 of the prompt
+    #[default]
+    Hash,
+    /// The hash plus the first characters of the prompt
+    Truncated,
+    /// The hash plus the whole prompt
+    Full,
+}
+
+fn default_max_chars() -> usize {
+    TRUNCATED_PROMPT_CHARS
+}
+
+/// What is kept of the prompts persisted by the server, configured via `prompt_retention`
+/// in `claude-mcp.config.json`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PromptRetention {
+    #[serde(default)]
+    pub mode: PromptRecord,
+    /// Characters kept in `truncated` mode
+    #[serde(default = "default_max_chars")]
+    pub max_chars: usize,
+}
+
+impl Default for PromptRetention {
+    fn default() -> Self {
+        Self {
+            mode: PromptRecord::Hash,
+            max_chars: TRUNCATED_PROMPT_CHARS,
+        }
+    }
+}
+
+impl PromptRetention {
+    /// Retention of audit records and the state store's call records: `prompt_retention`,
+    /// with the mode of `audit_log.prompt` when that is set
+    pub(crate) fn for_calls(audit_log: Option<&AuditLog>) -> Self {
+        let mut retention = crate::claude::prompt_retention().unwrap_or_default();
+        if let Some(mode) = audit_log.and_then(|log| log.prompt) {
+            retention.mode = mode;
+        }
+        retention
+    }
+
+    /// The part of `prompt` kept besides its hash
+    pub(crate) fn kept(&self, prompt: &str) -> Option<String> {
+        match self.mode {
+            PromptRecord::Hash => None,
+            PromptRecord::Truncated => Some(prompt.chars().take(self.max_chars).collect()),
+            PromptRecord::Full => Some(prompt.to_string()),
+        }
+    }
+}
+
+/// Hex SHA-256 of `prompt`
+pub(crate) fn prompt_sha256(prompt: &str) -> String {
+    format!("{:x}", Sha256::digest(prompt.as_bytes()))
+}
+
+/// One audit record; fields are filled in as the call progresses
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct AuditEntry {
+    pub timestamp: String,
+    /// MCP client name and version from the initialize handshake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    pub tool: String,
+    pub prompt_sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    /// Session the call asked to resume
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_session_id: Option<String>,
+    /// Session the run reported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub duration_ms: u64,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_cost_usd: Option<f64>,
+}
+
+impl AuditEntry {
+    pub(crate) fn new(tool: &str, prompt: &str, retention: PromptRetention) -> Self {
+        Self {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            tool: tool.to_string(),
+            prompt_sha256: prompt_sha256(prompt),
+            prompt: retention.kept(prompt),
+            ..Default::default()
+        }
+    }
+}
+
+/// Serializes appends and rotation across concurrent calls
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Append `entry` to the configured log
+pub(crate) fn record(config: &AuditLog, entry: &AuditEntry) {
+    if let Err(e) = append(config, entry) {
+        warn!("failed to write audit log {}: {}", config.path.display(), e);
+    }
+}
+
+fn append(config: &AuditLog, entry: &AuditEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let current = std::fs::metadata(&config.path).map_or(0, |m| m.len());
+    if current > 0 && current + line.len() as u64 > config.max_bytes {
+        rotate(&config.path, config.max_files)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.path)?;
+    file.write_all(&line)
+}
+
+/// Shift `path.N` to `path.N+1`, dropping the oldest, and move `path` to `path.1`
+fn rotate(path: &Path, max_files: usize) -> std::io::Result<()> {
+    if max_files == 0 {
+        return std::fs::remove_file(path);
+    }
+    let numbered = |n: usize| {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    let _ = std::fs::remove_file(numbered(max_files));
+    for n in (1..max_files).rev() {
+        let from = numbered(n);
+        if from.exists() {
+            std::fs::rename(&from, numbered(n + 1))?;
+        }
+    }
+    std::fs::rename(path, numbered(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_is_hashed_and_optionally_truncated() {
+        let prompt = "x".repeat(500);
+        let retention = |mode, max_chars| PromptRetention { mode, max_chars };
+        let hashed = AuditEntry::new("claude", &prompt, PromptRetention::default());
+        assert_eq!(hashed.prompt_sha256.len(), 64);
+        assert_eq!(hashed.prompt, None);
+
+        let truncated = AuditEntry::new(
+            "claude",
+            &prompt,
+            retention(PromptRecord::Truncated, TRUNCATED_PROMPT_CHARS),
+        );
+        assert_eq!(truncated.prompt_sha256, hashed.prompt_sha256);
+        assert_eq!(
+            truncated.prompt.map(|p| p.len()),
+            Some(TRUNCATED_PROMPT_CHARS)
+        );
+        let shorter = AuditEntry::new("claude", &prompt, retention(PromptRecord::Truncated, 10));
+        assert_eq!(shorter.prompt.map(|p| p.len()), Some(10));
+        let full = AuditEntry::new("claude", &prompt, retention(PromptRecord::Full, 10));
+        assert_eq!(full.prompt, Some(prompt));
+    }
+
+    #[test]
+    fn test_log_rotates_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AuditLog {
+            path: dir.path().join("audit.jsonl"),
+            max_bytes: 300,
+            max_files: 2,
+            prompt: Some(PromptRecord::Hash),
+        };
+        for _ in 0..6 {
+            let retention = PromptRetention::default();
+            record(&config, &AuditEntry::new("claude", "fix it", retention));
+        }
+
+        let numbered = |n: usize| dir.path().join(format!("audit.jsonl.{}", n));
+        assert!(config.path.exists());
+        assert!(numbered(1).exists());
+        assert!(numbered(2).exists());
+        assert!(!numbered(3).exists());
+        for path in [config.path.clone(), numbered(1)] {
+            let content = std::fs::read_to_string(path).unwrap();
+            for line in content.lines() {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                assert_eq!(value["tool"], "claude");
+            }
+        }
+    }
+}
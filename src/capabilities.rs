@@ -0,0 +1,202 @@
+//! Self-description of a running server for `claude_capabilities`.
+//!
+//! Orchestrators that drive several servers cannot read their config files, and guessing
+//! from errors is slow. The description names how the server is reached, the tools it
+//! offers, how runs execute and are isolated, where state and the job journal live, the
+//! limits a call runs into, the tenant's budget, and which optional subsystems are
+//! configured, all as currently in effect for the calling tenant.
+
+use crate::claude::{self, ExecutionMode, MAX_TIMEOUT_SECS};
+use crate::context;
+use crate::sandbox::Sandbox;
+use crate::store::{self, StoreBackend};
+use serde::Serialize;
+use std::sync::OnceLock;
+
+static TRANSPORT: OnceLock<&'static str> = OnceLock::new();
+
+/// Record how clients reach this server, `stdio` or `http`
+pub(crate) fn set_transport(transport: &'static str) {
+    let _ = TRANSPORT.set(transport);
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Capabilities {
+    pub version: &'static str,
+    /// `stdio` or `http`; unset when the server is not serving MCP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<&'static str>,
+    /// Tools that can be called, after `enabled_tools` and `disabled_tools`
+    pub tools: Vec<String>,
+    pub execution: Execution,
+    pub jobs: Jobs,
+    pub limits: Limits,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<Budget>,
+    /// Optional subsystems that are configured, e.g. `snapshots` or `response_cache`
+    pub features: Vec<&'static str>,
+}
+
+/// How runs are started and isolated
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Execution {
+    /// `spawn` or `interactive`
+    pub mode: &'static str,
+    /// `none`, `docker`, or `bubblewrap`
+    pub sandbox: &'static str,
+    /// Host the runs go to over SSH
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_host: Option<String>,
+}
+
+/// Where calls are recorded and whether they outlive the server
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Jobs {
+    /// `memory`, `file`, or `sqlite`
+    pub state_store: &'static str,
+    /// Whether the store opened, so `claude_jobs` and `claude_sessions` can answer
+    pub journal: bool,
+    /// Whether queued calls are run again and running ones listed after a restart
+    pub survives_restart: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Limits {
+    pub default_timeout_secs: u64,
+    pub max_timeout_secs: u64,
+    pub max_prompt_bytes: usize,
+    /// Runs at once, from `run_queue`; unlimited when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_runs: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_queued_runs: Option<usize>,
+    /// Log notifications a run sends per second; unlimited when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_notifications_per_sec: Option<u32>,
+}
+
+/// The calling tenant's spending
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Budget {
+    pub tenant: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget_usd: Option<f64>,
+    pub spent_usd: f64,
+}
+
+/// Describe the server for the current context, offering `tools`
+pub(crate) fn describe(tools: Vec<String>) -> Capabilities {
+    let run_queue = claude::default_run_queue();
+    let backend = claude::default_state_store().backend;
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        transport: TRANSPORT.get().copied(),
+        tools,
+        execution: Execution {
+            mode: match claude::default_execution_mode() {
+                ExecutionMode::Spawn => "spawn",
+                ExecutionMode::Interactive => "interactive",
+            },
+            sandbox: match claude::default_sandbox() {
+                Sandbox::None => "none",
+                Sandbox::Docker(_) => "docker",
+                Sandbox::Bubblewrap(_) => "bubblewrap",
+            },
+            remote_host: claude::default_remote().map(|remote| remote.host),
+        },
+        jobs: Jobs {
+            state_store: match backend {
+                StoreBackend::Memory => "memory",
+                StoreBackend::File => "file",
+                StoreBackend::Sqlite => "sqlite",
+            },
+            journal: store::current().is_some(),
+            survives_restart: backend != StoreBackend::Memory,
+        },
+        limits: Limits {
+            default_timeout_secs: claude::default_timeout_secs(),
+            max_timeout_secs: MAX_TIMEOUT_SECS,
+            max_prompt_bytes: claude::max_prompt_bytes(),
+            max_concurrent_runs: run_queue.as_ref().map(|queue| queue.max_concurrent),
+            max_queued_runs: run_queue.as_ref().map(|queue| queue.max_queued),
+            max_notifications_per_sec: claude::max_notifications_per_sec(),
+        },
+        budget: context::current().tenant().map(|tenant| Budget {
+            tenant: tenant.name().to_string(),
+            budget_usd: tenant.budget_usd(),
+            spent_usd: tenant.spent_usd(),
+        }),
+        features: features(),
+    }
+}
+
+fn features() -> Vec<&'static str> {
+    [
+        ("audit_log", claude::default_audit_log().is_some()),
+        ("debug_capture", claude::default_debug_capture()),
+        ("fallbacks", !claude::fallbacks().is_empty()),
+        ("git_context", claude::git_context().is_some()),
+        ("lint_fix", claude::lint_fix().is_some()),
+        (
+            "metrics",
+            claude::default_metrics_addr().is_some() || claude::default_metrics_file().is_some(),
+        ),
+        ("output_filters", !claude::output_filters().is_empty()),
+        ("record", claude::record_dir().is_some()),
+        (
+            "resource_limits",
+            !claude::default_resource_limits().is_empty(),
+        ),
+        ("response_cache", claude::default_response_cache().is_some()),
+        ("run_reports", claude::run_reports().is_some()),
+        ("sanitize_text", claude::default_sanitize_text()),
+        ("schedules", !claude::schedules().is_empty()),
+        ("screen_injections", claude::default_screen_injections()),
+        ("snapshots", claude::snapshots().is_some()),
+        ("write_tests", claude::write_tests().is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, configured)| configured.then_some(name))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claude::ServerConfig;
+    use crate::context::ServerContext;
+
+    #[test]
+    fn test_description_follows_the_config() {
+        let config = ServerConfig::from_json(
+            r#"{
+                "timeout_secs": 120,
+                "max_prompt_bytes": 4096,
+                "run_queue": {"max_concurrent": 2},
+                "sandbox": {"mode": "docker", "image": "claude:latest"},
+                "snapshots": {},
+                "screen_injections": true
+            }"#,
+        )
+        .unwrap();
+        let described = ServerContext::new(config)
+            .sync_scope(|| describe(vec!["claude".to_string(), "claude_jobs".to_string()]));
+
+        assert_eq!(described.tools, ["claude", "claude_jobs"]);
+        assert_eq!(described.execution.mode, "spawn");
+        assert_eq!(described.execution.sandbox, "docker");
+        assert_eq!(described.execution.remote_host, None);
+        assert_eq!(described.jobs.state_store, "memory");
+        assert!(described.jobs.journal);
+        assert!(!described.jobs.survives_restart);
+        assert_eq!(described.limits.default_timeout_secs, 120);
+        assert_eq!(described.limits.max_prompt_bytes, 4096);
+        assert_eq!(described.limits.max_concurrent_runs, Some(2));
+        assert_eq!(described.limits.max_queued_runs, Some(16));
+        assert_eq!(described.budget, None);
+        assert_eq!(
+            described.features,
+            ["sanitize_text", "screen_injections", "snapshots"]
+        );
+    }
+}
@@ -1,14 +1,71 @@
+pub use crate::actions::{ActionSummary, CommandAction};
+use crate::agents;
+pub use crate::agents::AgentDefinition;
+pub use crate::audit::{AuditLog, PromptRecord, PromptRetention};
+use crate::cli_failure::{self, CliFailure};
+use crate::cli_features;
+use crate::coalesce;
+use crate::config_file;
+pub use crate::config_file::ConfigIssue;
+use crate::context;
+use crate::deadlines::Deadline;
+use crate::debug_capture::{self, DebugCapture, OutputStream};
+use crate::events;
+pub use crate::events::{EventLevel, EventSender, RunEvent};
+pub use crate::git_context::GitContextConfig;
+use crate::injection::Screener;
+pub use crate::injection::{InjectionKind, InjectionWarning};
+use crate::interactive;
+pub use crate::limits::{ProcessPriority, ResourceLimits};
+use crate::lint_fix;
+pub use crate::lint_fix::LintFixConfig;
+use crate::memory;
+use crate::metrics;
+pub use crate::metrics::{MetricsFile, SnapshotFormat};
+use crate::orphans;
+use crate::output_filter;
+pub use crate::output_filter::OutputFilter;
+pub use crate::parser_limits::ParserLimits;
+use crate::parser_limits::{self, LimitExceeded};
+use crate::platform::{self, ProcessTreeGuard};
+pub use crate::pull_request::PullRequestConfig;
+use crate::recording;
+pub use crate::remote::SshRemote;
+use crate::response_cache;
+pub use crate::response_cache::ResponseCache;
+use crate::run_dir::RunDir;
+pub use crate::run_queue::RunQueueConfig;
+pub use crate::run_report::RunReportConfig;
+pub use crate::sandbox::{BubblewrapSandbox, DockerSandbox, Sandbox};
+pub use crate::scheduler::{CronExpr, Schedule};
+use crate::sessions;
+pub use crate::snapshots::{SnapshotConfig, Verification};
+use crate::stderr_lines;
+pub use crate::stderr_lines::{StderrLevel, StderrLine};
+pub use crate::store::{StoreBackend, StoreConfig};
+use crate::stream_scan::{EventScanner, ScannedEvent};
+pub use crate::templates::{PromptTemplate, TemplateArgument};
+pub use crate::tenants::Tenant;
+pub use crate::test_writer::WriteTestsConfig;
+use crate::truncation::AgentText;
+pub use crate::truncation::TruncationStrategy;
 use anyhow::{Context, Result};
+use base64::Engine as _;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use serde::Deserialize;
 use serde_json::Value;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::OnceLock;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
 use tokio::process::Command;
+use tracing::{debug, info, warn};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Options {
     pub prompt: String,
     pub working_dir: PathBuf,
@@ -18,20 +75,372 @@ pub struct Options {
     /// Timeout in seconds for the Claude execution. If None, defaults to 600 seconds (10 minutes).
     /// Set to a specific value to override. The library enforces a timeout to prevent unbounded execution.
     pub timeout_secs: Option<u64>,
+    /// Write every raw stream-json event to a temp JSONL file so the full record survives
+    /// the in-memory `all_messages` cap. The file path is returned in `all_messages_file`.
+    pub spill_all_messages: bool,
+    /// How the CLI process is driven; see [`ExecutionMode`].
+    pub execution_mode: ExecutionMode,
+    /// Number of idle CLI processes to keep pre-spawned per working dir and argument set
+    /// for new interactive sessions. 0 disables the warm pool.
+    pub warm_pool_size: usize,
+    /// OS resource limits (address space, CPU time, open files) for the CLI process
+    pub resource_limits: ResourceLimits,
+    /// Nice level and cgroup slice for the CLI process
+    pub priority: ProcessPriority,
+    /// Isolation wrapped around the CLI process, e.g. a container
+    pub sandbox: Sandbox,
+    /// Run the CLI on another host over SSH instead of locally
+    pub remote: Option<SshRemote>,
+    /// Receives progress events (spawn, warnings, stderr highlights, timeout) during the run
+    pub events: Option<EventSender>,
+    /// Record argv, redacted env, and raw stdout/stderr of the CLI process to a per-run
+    /// directory returned in `debug_dir`
+    pub debug_capture: bool,
+    /// Collect extended-thinking blocks into `ClaudeResult::thinking`
+    pub include_thinking: bool,
+    /// Screen agent text and tool results for prompt injection, reporting matches in
+    /// `ClaudeResult::injection_warnings`
+    pub screen_injections: bool,
+    /// CLI program to launch, e.g. one picked from `binaries`. Unset, the config's
+    /// `claude_bin` is used, then `CLAUDE_BIN`, then `claude` on `PATH`.
+    pub binary: Option<PathBuf>,
+    /// Environment variables set for the CLI process on top of the server's, e.g. from a
+    /// profile
+    pub env: BTreeMap<String, String>,
+    /// Strip ANSI escapes and control characters from `agent_messages` and `thinking`
+    pub sanitize_text: bool,
+    /// Which part of `agent_messages` is kept when it exceeds the size limit
+    pub truncation: TruncationStrategy,
+    /// Size limit for `agent_messages` below the built-in MAX_AGENT_MESSAGES_SIZE
+    pub max_output_bytes: Option<usize>,
+    /// Gzip the raw event stream into `ClaudeResult::all_messages_gzip`; the compressed size
+    /// rather than the raw size is held to the `all_messages` cap
+    pub compress_all_messages: bool,
+    /// Reuse the result of an identical earlier run; see [`ResponseCache`]
+    pub response_cache: Option<ResponseCache>,
+    /// Copy the CLI's raw stdout to this file, for replaying the run with `replay_from`.
+    /// Spawned runs only; interactive sessions are not recorded.
+    pub record_to: Option<PathBuf>,
+    /// Read stream-json events from this file, e.g. a recording, instead of starting the CLI
+    pub replay_from: Option<PathBuf>,
+    /// Nesting and key limits an event must stay within to be parsed
+    pub parser_limits: ParserLimits,
+    /// Malformed stdout lines logged and skipped before the run fails; 0 fails on the first.
+    /// A run that skipped lines still fails when its `result` event never arrives.
+    pub max_skipped_lines: usize,
+}
+
+impl Options {
+    /// Options for `prompt` in `working_dir` with the binary, `additional_args`, and timeout
+    /// of the current server context's config
+    pub fn from_config(prompt: impl Into<String>, working_dir: impl Into<PathBuf>) -> Self {
+        Options {
+            prompt: prompt.into(),
+            working_dir: working_dir.into(),
+            additional_args: default_additional_args(),
+            timeout_secs: Some(default_timeout_secs()),
+            binary: Some(claude_bin()),
+            ..Default::default()
+        }
+    }
 }
 
+/// Strategy used to drive the Claude CLI for a call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    /// Spawn a fresh CLI process per call and continue sessions with `--resume`
+    #[default]
+    Spawn,
+    /// Keep one CLI process alive per session and send each turn as a stream-json user
+    /// message on stdin (`--input-format stream-json`), avoiding cold starts between turns
+    Interactive,
+}
+
+/// What a call does when the CLI reports that the conversation no longer fits the context
+/// window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextOverflow {
+    /// Fail the call with the CLI's error
+    #[default]
+    Fail,
+    /// Run `/compact` in the session, then retry the prompt once
+    Compact,
+    /// Retry the prompt once in a new session, without the earlier history
+    FreshSession,
+}
+
+/// What a call does when the session it asks to resume does not exist (any more)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingSession {
+    /// Fail the call with a `session_not_found` error
+    #[default]
+    Fail,
+    /// Run the prompt in a new session and note it in `warnings`
+    FreshSession,
+}
+
+/// Lowercase phrases the CLI and the API use when a model is overloaded or unavailable;
+/// these runs may succeed with a fallback
+const RETRYABLE_MARKERS: [&str; 5] = [
+    "overloaded",
+    "model unavailable",
+    "model is unavailable",
+    "model not found",
+    "not_found_error",
+];
+
+/// Lowercase phrases the CLI and the API use when the context window is exceeded
+const CONTEXT_OVERFLOW_MARKERS: [&str; 5] = [
+    "prompt is too long",
+    "context window",
+    "context length",
+    "context_length_exceeded",
+    "exceed context limit",
+];
+
 const DEFAULT_TIMEOUT_SECS: u64 = 600;
-const MAX_TIMEOUT_SECS: u64 = 3600;
+/// Largest prompt accepted unless configured otherwise. The prompt is a single argument of
+/// the CLI, which Linux caps at 128 KiB; this leaves room for the headers added to it.
+const DEFAULT_MAX_PROMPT_BYTES: usize = 100 * 1024;
+pub(crate) const MAX_TIMEOUT_SECS: u64 = 3600;
+const MAX_WARM_POOL_SIZE: usize = 4;
+const DEFAULT_HEARTBEAT_SECS: u64 = 30;
+pub(crate) const MAX_AGENT_MESSAGES_SIZE: usize = 10 * 1024 * 1024; // 10MB limit for agent messages
+const MAX_STDERR_SIZE: usize = 1024 * 1024; // 1MB limit for stderr
+pub(crate) const MAX_LINE_LENGTH: usize = 1024 * 1024; // 1MB per line to prevent memory spikes
+const MAX_ALL_MESSAGES_SIZE: usize = 50 * 1024 * 1024; // 50MB limit for all messages combined
+const MAX_THINKING_SIZE: usize = 1024 * 1024; // 1MB limit for collected thinking
 
 /// Configuration loaded from `claude-mcp.config.json` (or `CLAUDE_MCP_CONFIG_PATH`).
-#[derive(Debug, Clone, Deserialize)]
-struct ServerConfig {
+/// Embedders build one with [`ServerConfig::from_json`] and hand it to
+/// [`ServerContext::new`](crate::context::ServerContext::new).
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ServerConfig {
     #[serde(default)]
     additional_args: Vec<String>,
     timeout_secs: Option<u64>,
+    claude_bin: Option<PathBuf>,
+    #[serde(default)]
+    spill_all_messages: bool,
+    #[serde(default)]
+    execution_mode: ExecutionMode,
+    #[serde(default)]
+    warm_pool_size: usize,
+    #[serde(default)]
+    resource_limits: ResourceLimits,
+    #[serde(default)]
+    priority: ProcessPriority,
+    #[serde(default)]
+    sandbox: Sandbox,
+    remote: Option<SshRemote>,
+    settings: Option<CliSettings>,
+    #[serde(default)]
+    agents: BTreeMap<String, AgentDefinition>,
+    #[serde(default)]
+    cli_features: Vec<String>,
+    output_language: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    tools: HashMap<String, ToolDefaults>,
+    log_level: Option<String>,
+    log_file: Option<PathBuf>,
+    #[serde(default)]
+    debug_capture: bool,
+    record_dir: Option<PathBuf>,
+    replay_file: Option<PathBuf>,
+    #[serde(default)]
+    parser_limits: ParserLimits,
+    #[serde(default)]
+    max_skipped_lines: usize,
+    max_prompt_bytes: Option<usize>,
+    snapshots: Option<SnapshotConfig>,
+    metrics_addr: Option<std::net::SocketAddr>,
+    metrics_file: Option<MetricsFile>,
+    run_queue: Option<RunQueueConfig>,
+    audit_log: Option<AuditLog>,
+    prompt_retention: Option<PromptRetention>,
+    #[serde(default)]
+    truncation: TruncationStrategy,
+    #[serde(default)]
+    prompt_templates: HashMap<String, PromptTemplate>,
+    #[serde(default)]
+    disabled_tools: Vec<String>,
+    enabled_tools: Option<Vec<String>>,
+    instructions: Option<String>,
+    #[serde(default)]
+    tool_descriptions: HashMap<String, String>,
+    response_cache: Option<ResponseCache>,
+    #[serde(default)]
+    schedules: Vec<Schedule>,
+    #[serde(default)]
+    context_overflow: ContextOverflow,
+    #[serde(default)]
+    missing_session: MissingSession,
+    memory_file: Option<PathBuf>,
+    #[serde(default)]
+    output_filters: Vec<OutputFilter>,
+    sanitize_text: Option<bool>,
+    #[serde(default)]
+    screen_injections: bool,
+    heartbeat_secs: Option<u64>,
+    max_notifications_per_sec: Option<u32>,
+    #[serde(default)]
+    binaries: HashMap<String, PathBuf>,
+    #[serde(default)]
+    fallbacks: Vec<Fallback>,
+    #[serde(default)]
+    state_store: StoreConfig,
+    #[serde(default)]
+    tenants: BTreeMap<String, Tenant>,
+    #[serde(default)]
+    pull_requests: PullRequestConfig,
+    write_tests: Option<WriteTestsConfig>,
+    lint_fix: Option<LintFixConfig>,
+    git_context: Option<GitContextConfig>,
+    run_reports: Option<RunReportConfig>,
+    /// Problems hit while loading this config, replayed once logging is up
+    #[serde(skip)]
+    load_errors: Vec<String>,
+}
+
+/// Alternative model and/or binary tried, in `fallbacks` order, when a run fails with a
+/// retryable error
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fallback {
+    pub model: Option<String>,
+    /// Name of an entry in `binaries`
+    pub binary: Option<String>,
+}
+
+impl Fallback {
+    /// Options for the retry: `opts` with the model and binary replaced
+    pub fn apply(&self, mut opts: Options) -> Options {
+        if let Some(model) = &self.model {
+            let mut args = std::mem::take(&mut opts.additional_args).into_iter();
+            while let Some(arg) = args.next() {
+                if arg == "--model" {
+                    args.next();
+                } else if !arg.starts_with("--model=") {
+                    opts.additional_args.push(arg);
+                }
+            }
+            opts.additional_args
+                .extend(["--model".to_string(), model.clone()]);
+        }
+        if let Some(name) = &self.binary {
+            opts.binary = binary(name).or(opts.binary);
+        }
+        opts
+    }
+}
+
+impl std::fmt::Display for Fallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.model, &self.binary) {
+            (Some(model), Some(binary)) => write!(f, "model {} on binary {}", model, binary),
+            (Some(model), None) => write!(f, "model {}", model),
+            (None, Some(binary)) => write!(f, "binary {}", binary),
+            (None, None) => write!(f, "the same settings"),
+        }
+    }
+}
+
+/// Named bundle of run settings selectable per call with the `PROFILE` tool parameter.
+/// Unset fields fall back to the top-level configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Extra CLI flags appended after the global `additional_args`
+    #[serde(default)]
+    pub additional_args: Vec<String>,
+    pub timeout_secs: Option<u64>,
+    pub sandbox: Option<Sandbox>,
+    pub remote: Option<SshRemote>,
+    /// Name of an entry in `binaries` to launch instead of the default CLI
+    pub binary: Option<String>,
+    /// Path of the CLI to launch, e.g. a wrapper script, when it is not in `binaries`
+    pub claude_bin: Option<PathBuf>,
+    /// Environment variables set for the CLI process
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Directory runs use instead of the server's working directory; a relative path is
+    /// resolved against it
+    pub working_dir: Option<PathBuf>,
+    /// CLI settings used instead of the top-level `settings`
+    pub settings: Option<CliSettings>,
+    /// API beta features enabled on top of the top-level `cli_features`
+    #[serde(default)]
+    pub cli_features: Vec<String>,
+    /// Language of the answers, instead of the top-level `output_language`
+    pub output_language: Option<String>,
+}
+
+/// CLI settings (hooks, permissions, environment) passed with `--settings`, so runs do not
+/// depend on the user's own `~/.claude/settings.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum CliSettings {
+    /// Settings file; a relative path is resolved against the run's working directory
+    File(PathBuf),
+    /// The settings themselves, passed as JSON, so they reach sandboxes and remote hosts
+    /// without a file to copy
+    Inline(serde_json::Map<String, serde_json::Value>),
+}
+
+impl CliSettings {
+    /// The value of `--settings`
+    pub fn to_arg(&self) -> String {
+        match self {
+            CliSettings::File(path) => path.display().to_string(),
+            CliSettings::Inline(settings) => serde_json::to_string(settings).unwrap_or_default(),
+        }
+    }
+}
+
+/// Defaults of one tool, configured under `tools.<name>` in `claude-mcp.config.json`.
+/// They apply on top of the top-level configuration; a profile, whether the call or the
+/// block names it, and the call's own arguments take precedence.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToolDefaults {
+    /// Extra CLI flags appended after the global `additional_args` and before the profile's
+    #[serde(default)]
+    pub additional_args: Vec<String>,
+    pub timeout_secs: Option<u64>,
+    /// Profile used when the call names none
+    pub profile: Option<String>,
+    /// Model used when the call names none
+    pub model: Option<String>,
+}
+
+/// Settings given on the command line. They take precedence over the config file and
+/// survive config reloads.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigOverrides {
+    /// Config file to load instead of `CLAUDE_MCP_CONFIG_PATH` or the working directory's
+    pub config_path: Option<PathBuf>,
+    pub timeout_secs: Option<u64>,
+    /// CLI flags appended after the configured `additional_args`
+    pub additional_args: Vec<String>,
+}
+
+static OVERRIDES: OnceLock<ConfigOverrides> = OnceLock::new();
+
+/// Install command-line overrides. Must run before anything reads the config; later calls
+/// are ignored.
+pub fn set_overrides(overrides: ConfigOverrides) {
+    let _ = OVERRIDES.set(overrides);
 }
 
 fn resolve_config_path() -> Option<PathBuf> {
+    if let Some(path) = OVERRIDES.get().and_then(|o| o.config_path.clone()) {
+        return Some(path);
+    }
     if let Ok(env_path) = std::env::var("CLAUDE_MCP_CONFIG_PATH") {
         let trimmed = env_path.trim();
         if !trimmed.is_empty() {
@@ -45,55 +454,496 @@ fn resolve_config_path() -> Option<PathBuf> {
         .map(|cwd| cwd.join("claude-mcp.config.json"))
 }
 
-fn load_server_config() -> ServerConfig {
-    let mut cfg = ServerConfig {
-        additional_args: Vec::new(),
-        timeout_secs: None,
-    };
+/// Config file location: `--config`, `CLAUDE_MCP_CONFIG_PATH`, or `claude-mcp.config.json`
+/// in the current directory. The file need not exist.
+pub fn config_path() -> Option<PathBuf> {
+    resolve_config_path()
+}
 
-    let Some(config_path) = resolve_config_path() else {
-        return cfg;
-    };
+/// The process-wide config: `--config`, `CLAUDE_MCP_CONFIG_PATH`, or the working directory's
+/// file, with the command-line overrides applied
+pub(crate) fn load_server_config() -> ServerConfig {
+    let mut cfg = resolve_config_path()
+        .map(|path| read_server_config(&path))
+        .unwrap_or_default();
+    if let Some(overrides) = OVERRIDES.get() {
+        apply_overrides(&mut cfg, overrides);
+    }
+    cfg
+}
+
+fn apply_overrides(cfg: &mut ServerConfig, overrides: &ConfigOverrides) {
+    if overrides.timeout_secs.is_some() {
+        cfg.timeout_secs = overrides.timeout_secs;
+    }
+    cfg.additional_args
+        .extend(overrides.additional_args.iter().cloned());
+}
+
+impl ServerConfig {
+    /// Parse config in the format of `claude-mcp.config.json`, comments included
+    pub fn from_json(raw: &str) -> serde_json::Result<Self> {
+        let mut cfg: ServerConfig = serde_json::from_str(&config_file::strip_comments(raw))?;
+        cfg.additional_args = cfg
+            .additional_args
+            .into_iter()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        Ok(cfg)
+    }
+}
+
+/// Config read from `config_path`; a missing file gives the defaults
+pub(crate) fn read_server_config(config_path: &Path) -> ServerConfig {
+    let mut cfg = ServerConfig::default();
 
     if !config_path.is_file() {
         return cfg;
     }
 
-    match std::fs::read_to_string(&config_path) {
-        Ok(raw) => match serde_json::from_str::<ServerConfig>(&raw) {
-            Ok(parsed) => {
-                let mut cleaned = parsed;
-                cleaned.additional_args = cleaned
-                    .additional_args
-                    .into_iter()
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect();
-                cfg = cleaned;
-            }
+    match std::fs::read_to_string(config_path) {
+        Ok(raw) => match ServerConfig::from_json(&raw) {
+            Ok(parsed) => cfg = parsed,
             Err(err) => {
-                eprintln!(
-                    "claude-mcp-rs: failed to parse config {}: {}",
-                    config_path.display(),
-                    err
-                );
+                let message = format!("failed to parse config {}: {}", config_path.display(), err);
+                warn!("{}", message);
+                cfg.load_errors.push(message);
             }
         },
         Err(err) => {
-            eprintln!(
-                "claude-mcp-rs: failed to read config {}: {}",
-                config_path.display(),
-                err
-            );
+            let message = format!("failed to read config {}: {}", config_path.display(), err);
+            warn!("{}", message);
+            cfg.load_errors.push(message);
         }
     }
 
     cfg
 }
 
-fn server_config() -> &'static ServerConfig {
-    static SERVER_CONFIG: OnceLock<ServerConfig> = OnceLock::new();
-    SERVER_CONFIG.get_or_init(load_server_config)
+/// Flags that run the CLI in plan mode, where it reads and plans but does not edit files
+pub(crate) const PLAN_MODE_ARGS: [&str; 2] = ["--permission-mode", "plan"];
+
+/// Flags the server sets itself; passing them through `additional_args` breaks parsing
+const MANAGED_FLAGS: [&str; 6] = [
+    "--print",
+    "-p",
+    "--output-format",
+    "--input-format",
+    "--resume",
+    "-r",
+];
+
+/// Problems in a config file's contents: parse failures, unknown options, flags the server
+/// manages, and values that are clamped or ignored at runtime
+pub(crate) fn config_issues(raw: &str) -> Vec<ConfigIssue> {
+    /// Known options are taken by `config`; whatever is left over is unknown
+    #[derive(Deserialize)]
+    struct Checked {
+        #[serde(flatten)]
+        config: ServerConfig,
+        #[serde(flatten)]
+        unknown: std::collections::BTreeMap<String, serde_json::Value>,
+    }
+
+    let checked = match serde_json::from_str::<Checked>(&config_file::strip_comments(raw)) {
+        Ok(checked) => checked,
+        Err(err) => return vec![ConfigIssue::error(format!("failed to parse: {}", err))],
+    };
+    let cfg = checked.config;
+    let mut issues: Vec<ConfigIssue> = checked
+        .unknown
+        .keys()
+        .map(|key| ConfigIssue::error(format!("unknown option '{}'", key)))
+        .collect();
+
+    let mut check_args = |args: &[String], origin: &str| {
+        for arg in args {
+            let flag = arg.split('=').next().unwrap_or_default();
+            if MANAGED_FLAGS.contains(&flag) {
+                issues.push(ConfigIssue::error(format!(
+                    "{}: '{}' is set by the server and must not be passed",
+                    origin, flag
+                )));
+            }
+        }
+    };
+    check_args(&cfg.additional_args, "additional_args");
+    for (name, profile) in &cfg.profiles {
+        check_args(
+            &profile.additional_args,
+            &format!("profiles.{}.additional_args", name),
+        );
+    }
+
+    let check_timeout = |timeout: Option<u64>, origin: &str| match timeout {
+        Some(0) => Some(ConfigIssue::warning(format!(
+            "{}: 0 falls back to {} seconds",
+            origin, DEFAULT_TIMEOUT_SECS
+        ))),
+        Some(t) if t > MAX_TIMEOUT_SECS => Some(ConfigIssue::warning(format!(
+            "{}: {} is clamped to {} seconds",
+            origin, t, MAX_TIMEOUT_SECS
+        ))),
+        _ => None,
+    };
+    issues.extend(check_timeout(cfg.timeout_secs, "timeout_secs"));
+    for (name, tool) in &cfg.tools {
+        issues.extend(check_timeout(
+            tool.timeout_secs,
+            &format!("tools.{}.timeout_secs", name),
+        ));
+        if let Some(profile) = tool
+            .profile
+            .as_ref()
+            .filter(|p| !cfg.profiles.contains_key(*p))
+        {
+            issues.push(ConfigIssue::error(format!(
+                "tools.{}.profile: unknown profile '{}'",
+                name, profile
+            )));
+        }
+    }
+    for (name, profile) in &cfg.profiles {
+        issues.extend(check_timeout(
+            profile.timeout_secs,
+            &format!("profiles.{}.timeout_secs", name),
+        ));
+        if let Some(binary) = profile
+            .binary
+            .as_ref()
+            .filter(|b| !cfg.binaries.contains_key(*b))
+        {
+            issues.push(ConfigIssue::error(format!(
+                "profiles.{}.binary: unknown binary '{}'",
+                name, binary
+            )));
+        }
+        if profile.binary.is_some() && profile.claude_bin.is_some() {
+            issues.push(ConfigIssue::error(format!(
+                "profiles.{}: set either binary or claude_bin, not both",
+                name
+            )));
+        }
+        if let Some(var) = profile
+            .env
+            .keys()
+            .find(|var| var.is_empty() || var.contains('=') || var.contains('\0'))
+        {
+            issues.push(ConfigIssue::error(format!(
+                "profiles.{}.env: '{}' is not a valid variable name",
+                name, var
+            )));
+        }
+        if let Some(dir) = profile.working_dir.as_ref().filter(|dir| !dir.is_dir()) {
+            issues.push(ConfigIssue::warning(format!(
+                "profiles.{}.working_dir: {} is not a directory",
+                name,
+                dir.display()
+            )));
+        }
+    }
+
+    let check_settings = |settings: Option<&CliSettings>, origin: &str| match settings {
+        // Relative paths depend on the run's working directory
+        Some(CliSettings::File(path)) if path.is_absolute() && !path.is_file() => Some(
+            ConfigIssue::warning(format!("{}: {} is not a file", origin, path.display())),
+        ),
+        _ => None,
+    };
+    issues.extend(check_settings(cfg.settings.as_ref(), "settings"));
+    for (name, profile) in &cfg.profiles {
+        issues.extend(check_settings(
+            profile.settings.as_ref(),
+            &format!("profiles.{}.settings", name),
+        ));
+    }
+
+    let mut check_features = |features: &[String], origin: &str| {
+        for feature in features {
+            if let Some(problem) = cli_features::problem(feature) {
+                issues.push(ConfigIssue::error(format!("{}: {}", origin, problem)));
+            }
+        }
+    };
+    check_features(&cfg.cli_features, "cli_features");
+    for (name, profile) in &cfg.profiles {
+        check_features(
+            &profile.cli_features,
+            &format!("profiles.{}.cli_features", name),
+        );
+    }
+    let check_language = |language: Option<&String>, origin: &str| {
+        language
+            .filter(|language| language.trim().is_empty())
+            .map(|_| ConfigIssue::warning(format!("{}: empty, so it is ignored", origin)))
+    };
+    issues.extend(check_language(
+        cfg.output_language.as_ref(),
+        "output_language",
+    ));
+    for (name, profile) in &cfg.profiles {
+        issues.extend(check_language(
+            profile.output_language.as_ref(),
+            &format!("profiles.{}.output_language", name),
+        ));
+    }
+    for (name, agent) in &cfg.agents {
+        if let Some(problem) = agents::problem(name, agent) {
+            issues.push(ConfigIssue::error(format!("agents: {}", problem)));
+        }
+    }
+
+    if cfg.enabled_tools.as_ref().is_some_and(Vec::is_empty) {
+        issues.push(ConfigIssue::warning(
+            "enabled_tools is empty, so no tool is available",
+        ));
+    }
+    if cfg
+        .run_queue
+        .as_ref()
+        .is_some_and(|queue| queue.max_concurrent == 0)
+    {
+        issues.push(ConfigIssue::error(
+            "run_queue.max_concurrent must be at least 1",
+        ));
+    }
+    if cfg.warm_pool_size > MAX_WARM_POOL_SIZE {
+        issues.push(ConfigIssue::warning(format!(
+            "warm_pool_size: {} is clamped to {}",
+            cfg.warm_pool_size, MAX_WARM_POOL_SIZE
+        )));
+    }
+    if cfg.parser_limits.max_depth == 0 || cfg.parser_limits.max_keys == 0 {
+        issues.push(ConfigIssue::error(
+            "parser_limits: max_depth and max_keys must be at least 1",
+        ));
+    }
+    if cfg.parser_limits.max_depth > parser_limits::SERDE_RECURSION_LIMIT {
+        issues.push(ConfigIssue::warning(format!(
+            "parser_limits.max_depth: events nested deeper than {} levels fail to parse anyway",
+            parser_limits::SERDE_RECURSION_LIMIT
+        )));
+    }
+    if cfg.record_dir.is_some() && cfg.replay_file.is_some() {
+        issues.push(ConfigIssue::warning(
+            "record_dir is ignored while replay_file is set",
+        ));
+    }
+    if cfg.warm_pool_size > 0 && cfg.execution_mode == ExecutionMode::Spawn {
+        issues.push(ConfigIssue::warning(
+            "warm_pool_size only applies with execution_mode \"interactive\"",
+        ));
+    }
+    if let Some(nice) = cfg.priority.nice.filter(|n| !(-20..=19).contains(n)) {
+        issues.push(ConfigIssue::error(format!(
+            "priority.nice: {} is outside -20..=19",
+            nice
+        )));
+    }
+    if let Some(level) = &cfg.log_level {
+        if let Err(err) = tracing_subscriber::EnvFilter::try_new(level) {
+            issues.push(ConfigIssue::error(format!("log_level: {}", err)));
+        }
+    }
+    for (index, fallback) in cfg.fallbacks.iter().enumerate() {
+        match &fallback.binary {
+            Some(binary) if !cfg.binaries.contains_key(binary) => {
+                issues.push(ConfigIssue::error(format!(
+                    "fallbacks[{}].binary: unknown binary '{}'",
+                    index, binary
+                )));
+            }
+            None if fallback.model.is_none() => {
+                issues.push(ConfigIssue::warning(format!(
+                    "fallbacks[{}]: sets neither model nor binary, so it repeats the run",
+                    index
+                )));
+            }
+            _ => {}
+        }
+    }
+    if cfg.state_store.backend != StoreBackend::Memory && cfg.state_store.path.is_none() {
+        issues.push(ConfigIssue::error(
+            "state_store.path is required by the file and sqlite backends".to_string(),
+        ));
+    }
+    let mut schedule_names = std::collections::HashSet::new();
+    for schedule in &cfg.schedules {
+        let origin = format!("schedules.{}", schedule.name);
+        if !schedule_names.insert(&schedule.name) {
+            issues.push(ConfigIssue::error(format!("{}: duplicate name", origin)));
+        }
+        if let Some(profile) = schedule
+            .profile
+            .as_ref()
+            .filter(|p| !cfg.profiles.contains_key(*p))
+        {
+            issues.push(ConfigIssue::error(format!(
+                "{}.profile: unknown profile '{}'",
+                origin, profile
+            )));
+        }
+        if !schedule.working_dir.is_dir() {
+            issues.push(ConfigIssue::warning(format!(
+                "{}.working_dir: {} is not a directory",
+                origin,
+                schedule.working_dir.display()
+            )));
+        }
+        if let Some(webhook) = schedule
+            .webhook
+            .as_ref()
+            .filter(|url| !url.starts_with("http://") && !url.starts_with("https://"))
+        {
+            issues.push(ConfigIssue::error(format!(
+                "{}.webhook: '{}' must be an http:// or https:// URL",
+                origin, webhook
+            )));
+        }
+    }
+    if let Some(Err(err)) = cfg
+        .memory_file
+        .as_ref()
+        .map(|file| memory::resolve(Path::new("."), file))
+    {
+        issues.push(ConfigIssue::error(err));
+    }
+    if cfg
+        .output_filters
+        .iter()
+        .any(|filter| matches!(filter, OutputFilter::Command(command) if command.is_empty()))
+    {
+        issues.push(ConfigIssue::error(
+            "output_filters: command must name a program".to_string(),
+        ));
+    }
+    if cfg
+        .snapshots
+        .as_ref()
+        .is_some_and(|s| !s.verify_command.is_empty() && s.verify_timeout_secs == 0)
+    {
+        issues.push(ConfigIssue::error(
+            "snapshots.verify_timeout_secs: must be at least 1",
+        ));
+    }
+    if cfg.pull_requests.create_command.is_empty() {
+        issues.push(ConfigIssue::error(
+            "pull_requests.create_command: must name a program",
+        ));
+    }
+    if cfg.pull_requests.verify_timeout_secs == 0 {
+        issues.push(ConfigIssue::error(
+            "pull_requests.verify_timeout_secs: must be at least 1",
+        ));
+    }
+    if let Some(write_tests) = &cfg.write_tests {
+        if write_tests.test_command.is_empty() {
+            issues.push(ConfigIssue::error(
+                "write_tests.test_command: must name a program",
+            ));
+        }
+        if write_tests.test_timeout_secs == 0 {
+            issues.push(ConfigIssue::error(
+                "write_tests.test_timeout_secs: must be at least 1",
+            ));
+        }
+    }
+    if let Some(lint_fix) = &cfg.lint_fix {
+        if lint_fix.lint_command.is_empty() {
+            issues.push(ConfigIssue::error(
+                "lint_fix.lint_command: must name a program",
+            ));
+        }
+        if lint_fix.lint_timeout_secs == 0 {
+            issues.push(ConfigIssue::error(
+                "lint_fix.lint_timeout_secs: must be at least 1",
+            ));
+        }
+        if lint_fix.max_rounds > lint_fix::MAX_ROUNDS {
+            issues.push(ConfigIssue::warning(format!(
+                "lint_fix.max_rounds: capped at {}",
+                lint_fix::MAX_ROUNDS
+            )));
+        }
+    }
+    let mut tokens = HashSet::new();
+    for (name, tenant) in &cfg.tenants {
+        if tenant.token.is_empty() {
+            issues.push(ConfigIssue::error(format!(
+                "tenants.{}.token: must not be empty",
+                name
+            )));
+        } else if !tokens.insert(tenant.token.as_str()) {
+            issues.push(ConfigIssue::error(format!(
+                "tenants.{}.token: already used by another tenant",
+                name
+            )));
+        }
+        if tenant
+            .budget_usd
+            .is_some_and(|budget| budget.is_nan() || budget < 0.0)
+        {
+            issues.push(ConfigIssue::error(format!(
+                "tenants.{}.budget_usd: must not be negative",
+                name
+            )));
+        }
+        if let Some(config) = tenant.config.as_ref().filter(|path| !path.is_file()) {
+            issues.push(ConfigIssue::error(format!(
+                "tenants.{}.config: {} does not exist",
+                name,
+                config.display()
+            )));
+        }
+        for dir in tenant.allowed_dirs.iter().filter(|dir| !dir.is_dir()) {
+            issues.push(ConfigIssue::warning(format!(
+                "tenants.{}.allowed_dirs: {} is not a directory",
+                name,
+                dir.display()
+            )));
+        }
+    }
+    if cfg.response_cache.as_ref().is_some_and(|c| c.ttl_secs == 0) {
+        issues.push(ConfigIssue::warning(
+            "response_cache.ttl_secs: 0 disables the cache",
+        ));
+    }
+    for (origin, path) in [
+        (
+            "audit_log.path",
+            cfg.audit_log.as_ref().map(|log| &log.path),
+        ),
+        ("log_file", cfg.log_file.as_ref()),
+    ] {
+        let Some(dir) = path
+            .and_then(|p| p.parent())
+            .filter(|d| !d.as_os_str().is_empty())
+        else {
+            continue;
+        };
+        if !dir.is_dir() {
+            issues.push(ConfigIssue::warning(format!(
+                "{}: directory {} does not exist",
+                origin,
+                dir.display()
+            )));
+        }
+    }
+    issues
+}
+
+/// Config of the current [`ServerContext`](crate::context::ServerContext)
+fn server_config() -> Arc<ServerConfig> {
+    context::current().config()
+}
+
+/// Re-read the current context's config file. Settings read per call (arguments, profiles,
+/// timeouts, disabled tools, ...) take effect for later calls; runs in flight keep the old
+/// ones, and settings applied at startup (logging, metrics address and file, run queue) stay
+/// as they were.
+pub fn reload_config() {
+    context::current().reload_config();
 }
 
 /// Default extra CLI flags applied to every Claude CLI invocation.
@@ -103,43 +953,477 @@ pub fn default_additional_args() -> Vec<String> {
     server_config().additional_args.clone()
 }
 
+/// CLI settings passed to every run with `--settings`, configurable via `settings` in
+/// `claude-mcp.config.json`. A profile's replace them.
+pub fn default_settings() -> Option<CliSettings> {
+    server_config().settings.clone()
+}
+
+/// Subagents passed to every run with `--agents`, configurable via `agents` in
+/// `claude-mcp.config.json`
+pub fn agents() -> BTreeMap<String, AgentDefinition> {
+    server_config().agents.clone()
+}
+
+/// API beta features every run enables with `--betas`, configurable via `cli_features` in
+/// `claude-mcp.config.json`
+pub fn cli_features() -> Vec<String> {
+    server_config().cli_features.clone()
+}
+
+/// Language every run answers in, configurable via `output_language` in
+/// `claude-mcp.config.json`. A profile's replaces it.
+pub fn output_language() -> Option<String> {
+    server_config().output_language.clone()
+}
+
+/// Whether runs should spill the full event stream to disk, configurable via
+/// `spill_all_messages` in `claude-mcp.config.json`.
+pub fn default_spill_all_messages() -> bool {
+    server_config().spill_all_messages
+}
+
+/// Execution strategy for runs, configurable via `execution_mode` in
+/// `claude-mcp.config.json` (`"spawn"` or `"interactive"`).
+pub fn default_execution_mode() -> ExecutionMode {
+    server_config().execution_mode
+}
+
+/// Number of pre-spawned idle processes for interactive mode, configurable via
+/// `warm_pool_size` in `claude-mcp.config.json`. Capped at MAX_WARM_POOL_SIZE.
+pub fn default_warm_pool_size() -> usize {
+    server_config().warm_pool_size.min(MAX_WARM_POOL_SIZE)
+}
+
+/// Resource limits for the CLI process, configurable via `resource_limits` in
+/// `claude-mcp.config.json`.
+pub fn default_resource_limits() -> ResourceLimits {
+    server_config().resource_limits.clone()
+}
+
+/// Default CPU priority for the CLI process, configurable via `priority` in
+/// `claude-mcp.config.json`.
+pub fn default_priority() -> ProcessPriority {
+    server_config().priority.clone()
+}
+
+/// Sandbox for the CLI process, configurable via `sandbox` in `claude-mcp.config.json`.
+pub fn default_sandbox() -> Sandbox {
+    server_config().sandbox.clone()
+}
+
+/// SSH host to run the CLI on, configurable via `remote` in `claude-mcp.config.json`.
+pub fn default_remote() -> Option<SshRemote> {
+    server_config().remote.clone()
+}
+
+/// Log filter directive (e.g. `info` or `claude_mcp_rs=debug`), configurable via
+/// `log_level` in `claude-mcp.config.json`. `RUST_LOG` takes precedence.
+pub fn default_log_level() -> Option<String> {
+    server_config().log_level.clone()
+}
+
+/// File receiving JSON-formatted logs, configurable via `log_file` in `claude-mcp.config.json`.
+pub fn default_log_file() -> Option<PathBuf> {
+    server_config().log_file.clone()
+}
+
+pub(crate) fn config_load_errors() -> Vec<String> {
+    server_config().load_errors.clone()
+}
+
+/// Whether every run records its raw CLI I/O, configurable via `debug_capture` in
+/// `claude-mcp.config.json`. Callers can also enable it per call.
+pub fn default_debug_capture() -> bool {
+    server_config().debug_capture
+}
+
+/// Concurrency limit for tool calls, configurable via `run_queue` in
+/// `claude-mcp.config.json`
+pub fn default_run_queue() -> Option<RunQueueConfig> {
+    server_config().run_queue.clone()
+}
+
+/// Periodic metrics snapshot file, configurable via `metrics_file` in
+/// `claude-mcp.config.json`
+pub fn default_metrics_file() -> Option<MetricsFile> {
+    server_config().metrics_file.clone()
+}
+
+/// Address for the Prometheus `/metrics` endpoint, configurable via `metrics_addr` in
+/// `claude-mcp.config.json`. Disabled when unset.
+pub fn default_metrics_addr() -> Option<std::net::SocketAddr> {
+    server_config().metrics_addr
+}
+
+/// Verification, push remote, and PR command of `claude_pr`, configurable via
+/// `pull_requests` in `claude-mcp.config.json`
+pub fn pull_requests() -> PullRequestConfig {
+    server_config().pull_requests.clone()
+}
+
+/// Test command of `claude_write_tests`, configurable via `write_tests` in
+/// `claude-mcp.config.json`. The tool is unavailable when unset.
+pub fn write_tests() -> Option<WriteTestsConfig> {
+    server_config().write_tests.clone()
+}
+
+/// Linter of `claude_fix_lints`, configurable via `lint_fix` in `claude-mcp.config.json`.
+/// The tool is unavailable when unset.
+pub fn lint_fix() -> Option<LintFixConfig> {
+    server_config().lint_fix.clone()
+}
+
+/// Tenants of the HTTP transport, by name, configurable via `tenants` in
+/// `claude-mcp.config.json`. Every client is served alike when empty.
+pub fn tenants() -> BTreeMap<String, Tenant> {
+    server_config().tenants.clone()
+}
+
+/// Where every tool call is recorded for `claude_sessions`, configurable via `state_store`
+/// in `claude-mcp.config.json`. Kept in memory when unset.
+pub fn default_state_store() -> StoreConfig {
+    server_config().state_store.clone()
+}
+
+/// Append-only audit log of tool calls, configurable via `audit_log` in
+/// `claude-mcp.config.json`. Disabled when unset.
+pub fn default_audit_log() -> Option<AuditLog> {
+    server_config().audit_log.clone()
+}
+
+/// What is kept of prompts in the audit log, the state store, run reports, and the job
+/// journal, configurable via `prompt_retention` in `claude-mcp.config.json`. Unset, each keeps
+/// its own default.
+pub fn prompt_retention() -> Option<PromptRetention> {
+    server_config().prompt_retention
+}
+
+/// Directory receiving a recording of every run's raw output, configurable via `record_dir`
+/// in `claude-mcp.config.json`. Disabled when unset.
+pub fn record_dir() -> Option<PathBuf> {
+    server_config().record_dir.clone()
+}
+
+/// Nesting and key limits for stream-json events, configurable via `parser_limits` in
+/// `claude-mcp.config.json`
+pub fn parser_limits() -> ParserLimits {
+    server_config().parser_limits
+}
+
+/// Malformed output lines skipped per run before it fails, configurable via
+/// `max_skipped_lines` in `claude-mcp.config.json`
+pub fn max_skipped_lines() -> usize {
+    server_config().max_skipped_lines
+}
+
+/// Largest `PROMPT` in bytes a tool call may pass, configurable via `max_prompt_bytes` in
+/// `claude-mcp.config.json`
+pub fn max_prompt_bytes() -> usize {
+    server_config()
+        .max_prompt_bytes
+        .unwrap_or(DEFAULT_MAX_PROMPT_BYTES)
+}
+
+/// Working-tree snapshots before runs and verification after them, configurable via
+/// `snapshots` in `claude-mcp.config.json`. Disabled when unset.
+pub fn snapshots() -> Option<SnapshotConfig> {
+    server_config().snapshots.clone()
+}
+
+/// Git context header put in front of prompts, configurable via `git_context` in
+/// `claude-mcp.config.json`. Disabled when unset.
+pub fn git_context() -> Option<GitContextConfig> {
+    server_config().git_context.clone()
+}
+
+/// Reports of runs written under their working directories, configurable via `run_reports`
+/// in `claude-mcp.config.json`. Disabled when unset.
+pub fn run_reports() -> Option<RunReportConfig> {
+    server_config().run_reports.clone()
+}
+
+/// Recording every run replays instead of starting the CLI, configurable via `replay_file`
+/// in `claude-mcp.config.json`
+pub fn replay_file() -> Option<PathBuf> {
+    server_config().replay_file.clone()
+}
+
+/// Result cache for repeated runs over an unchanged tree, configurable via `response_cache`
+/// in `claude-mcp.config.json`. Disabled when unset.
+pub fn default_response_cache() -> Option<ResponseCache> {
+    server_config().response_cache.clone()
+}
+
+/// Prompts run on cron schedules while the server is up, configurable via `schedules` in
+/// `claude-mcp.config.json`
+pub fn schedules() -> Vec<Schedule> {
+    server_config().schedules.clone()
+}
+
+/// Recovery from a full context window, configurable via `context_overflow` in
+/// `claude-mcp.config.json` (`"fail"`, `"compact"`, or `"fresh_session"`)
+pub fn default_context_overflow() -> ContextOverflow {
+    server_config().context_overflow
+}
+
+/// Handling of a `SESSION_ID` the CLI does not know, configurable via `missing_session` in
+/// `claude-mcp.config.json`
+pub fn default_missing_session() -> MissingSession {
+    server_config().missing_session
+}
+
+/// Models and binaries tried in order after a retryable failure, configurable via
+/// `fallbacks` in `claude-mcp.config.json`
+pub fn fallbacks() -> Vec<Fallback> {
+    server_config().fallbacks.clone()
+}
+
+/// Project memory file edited by the `claude_memory_*` tools, relative to the working
+/// directory, configurable via `memory_file` in `claude-mcp.config.json`
+pub fn memory_file() -> PathBuf {
+    server_config()
+        .memory_file
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("CLAUDE.md"))
+}
+
+/// Steps applied to agent text before it is returned, configurable via `output_filters` in
+/// `claude-mcp.config.json`
+pub fn output_filters() -> Vec<OutputFilter> {
+    server_config().output_filters.clone()
+}
+
+/// Whether ANSI escapes and control characters are stripped from extracted text,
+/// configurable via `sanitize_text` in `claude-mcp.config.json` (on by default)
+pub fn default_sanitize_text() -> bool {
+    server_config().sanitize_text.unwrap_or(true)
+}
+
+/// Whether run output is screened for prompt injection, configurable via
+/// `screen_injections` in `claude-mcp.config.json` (off by default)
+pub fn default_screen_injections() -> bool {
+    server_config().screen_injections
+}
+
+/// How long a tool call may go without notifications before a heartbeat is sent,
+/// configurable via `heartbeat_secs` in `claude-mcp.config.json` (0 disables heartbeats)
+pub fn heartbeat_interval() -> Option<std::time::Duration> {
+    let secs = server_config()
+        .heartbeat_secs
+        .unwrap_or(DEFAULT_HEARTBEAT_SECS);
+    (secs > 0).then(|| std::time::Duration::from_secs(secs))
+}
+
+/// Log notifications a run may send per second, configurable via
+/// `max_notifications_per_sec` in `claude-mcp.config.json`. Unset or 0 sends every one.
+pub fn max_notifications_per_sec() -> Option<u32> {
+    server_config()
+        .max_notifications_per_sec
+        .filter(|per_sec| *per_sec > 0)
+}
+
+/// Which part of oversized agent text is kept, configurable via `truncation` in
+/// `claude-mcp.config.json` (`"head"`, `"tail"`, or `"middle"`).
+pub fn default_truncation() -> TruncationStrategy {
+    server_config().truncation
+}
+
+/// Prompt templates from `prompt_templates` in `claude-mcp.config.json`, added to the
+/// built-in ones offered through the MCP `prompts` capability.
+pub fn prompt_templates() -> HashMap<String, PromptTemplate> {
+    server_config().prompt_templates.clone()
+}
+
+/// Tool names listed in `disabled_tools` in `claude-mcp.config.json`; these are neither
+/// listed to clients nor callable.
+pub fn disabled_tools() -> Vec<String> {
+    server_config().disabled_tools.clone()
+}
+
+/// Tool names listed in `enabled_tools` in `claude-mcp.config.json`. When set, only these
+/// are listed and callable, less any in `disabled_tools`; unset, every tool is.
+pub fn enabled_tools() -> Option<Vec<String>> {
+    server_config().enabled_tools.clone()
+}
+
+/// Whether the tool `name` may be listed and called under `enabled_tools` and
+/// `disabled_tools`
+pub fn is_tool_enabled(name: &str) -> bool {
+    let config = server_config();
+    config
+        .enabled_tools
+        .as_ref()
+        .is_none_or(|enabled| enabled.iter().any(|n| n == name))
+        && !config.disabled_tools.iter().any(|n| n == name)
+}
+
+/// Replacement for the server instructions sent to clients on initialization, configurable
+/// via `instructions` in `claude-mcp.config.json`
+pub fn instructions() -> Option<String> {
+    server_config().instructions.clone()
+}
+
+/// Replacement tool descriptions by tool name, configurable via `tool_descriptions` in
+/// `claude-mcp.config.json`
+pub fn tool_descriptions() -> HashMap<String, String> {
+    server_config().tool_descriptions.clone()
+}
+
+/// Look up a named profile from the `profiles` map in `claude-mcp.config.json`.
+pub fn profile(name: &str) -> Option<Profile> {
+    server_config().profiles.get(name).cloned()
+}
+
+/// Defaults configured for the tool `name` under `tools`; empty when it has none
+pub fn tool_defaults(name: &str) -> ToolDefaults {
+    server_config().tools.get(name).cloned().unwrap_or_default()
+}
+
+/// Program configured under `name` in `binaries`
+pub fn binary(name: &str) -> Option<PathBuf> {
+    server_config().binaries.get(name).cloned()
+}
+
+/// Names and programs of all configured binaries, sorted by name
+pub fn binaries() -> Vec<(String, PathBuf)> {
+    let mut binaries: Vec<_> = server_config()
+        .binaries
+        .iter()
+        .map(|(name, program)| (name.clone(), program.clone()))
+        .collect();
+    binaries.sort();
+    binaries
+}
+
+/// Names of all configured profiles, sorted
+pub fn profile_names() -> Vec<String> {
+    let mut names: Vec<String> = server_config().profiles.keys().cloned().collect();
+    names.sort();
+    names
+}
+
 /// Default timeout (in seconds) for Claude runs, configurable via
 /// `timeout_secs` in `claude-mcp.config.json`. Values <= 0 or missing
 /// fall back to 600; values above MAX_TIMEOUT_SECS are clamped.
 pub fn default_timeout_secs() -> u64 {
-    static CACHED_TIMEOUT: OnceLock<u64> = OnceLock::new();
-    *CACHED_TIMEOUT.get_or_init(|| {
-        let cfg = server_config();
-        match cfg.timeout_secs {
-            Some(t) if t > 0 && t <= MAX_TIMEOUT_SECS => t,
-            Some(t) if t > MAX_TIMEOUT_SECS => MAX_TIMEOUT_SECS,
-            _ => DEFAULT_TIMEOUT_SECS,
-        }
-    })
+    clamp_timeout_secs(server_config().timeout_secs)
+}
+
+/// Apply the timeout rules above to a configured value
+pub fn clamp_timeout_secs(timeout_secs: Option<u64>) -> u64 {
+    match timeout_secs {
+        Some(t) if t > 0 && t <= MAX_TIMEOUT_SECS => t,
+        Some(t) if t > MAX_TIMEOUT_SECS => MAX_TIMEOUT_SECS,
+        _ => DEFAULT_TIMEOUT_SECS,
+    }
 }
 
-#[derive(Debug)]
+/// Token usage reported in the CLI's final `result` event
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, serde::Serialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cache_creation_input_tokens: u64,
+    #[serde(default)]
+    pub cache_read_input_tokens: u64,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct ClaudeResult {
     pub success: bool,
     pub session_id: String,
     pub agent_messages: String,
     pub agent_messages_truncated: bool,
-    pub all_messages: Vec<HashMap<String, Value>>,
+    /// Agent messages dropped entirely or in part by truncation
+    pub agent_messages_omitted: usize,
+    /// Raw stream-json events, each a JSON object as the CLI printed it
+    pub all_messages: Vec<Value>,
     pub all_messages_truncated: bool,
+    /// Events left out of `all_messages` by the size cap
+    pub all_messages_omitted: usize,
+    /// Gzip-compressed event stream, when requested with `Options::compress_all_messages`
+    pub all_messages_gzip: Option<CompressedMessages>,
+    /// Path of the JSONL file holding the complete event stream, when spilling is enabled
+    pub all_messages_file: Option<PathBuf>,
+    /// Directory with the raw CLI I/O of this run, when debug capture is enabled
+    pub debug_dir: Option<PathBuf>,
+    /// File holding this run's raw stdout, when recording with `Options::record_to`
+    pub recording_file: Option<PathBuf>,
+    /// Directory of this run's report, when `run_reports` is configured
+    pub report_dir: Option<PathBuf>,
+    /// Token usage from the final `result` event
+    pub usage: Option<Usage>,
+    /// Cost in USD from the final `result` event
+    pub total_cost_usd: Option<f64>,
+    /// Exit code of the CLI process, when it exited normally
+    pub exit_code: Option<i32>,
+    /// Number of agent turns from the final `result` event
+    pub num_turns: Option<u64>,
+    /// Run duration reported by the CLI in the final `result` event
+    pub cli_duration_ms: Option<u64>,
+    /// Time the CLI spent waiting on the API, from the final `result` event
+    pub api_duration_ms: Option<u64>,
+    /// Files touched, commands run, and pages fetched by the agent
+    pub actions: ActionSummary,
+    /// Classified stderr lines of the CLI process
+    pub stderr: Vec<StderrLine>,
+    /// Extended-thinking text, when requested with `Options::include_thinking`
+    pub thinking: String,
+    pub thinking_truncated: bool,
+    pub timing: RunTiming,
+    /// Served from the response cache instead of running the CLI
+    pub cached: bool,
+    /// Copied from an identical run that was already in flight
+    pub coalesced: bool,
+    /// Snapshot of the working tree taken before this failed run, for `claude_rollback_run`
+    pub snapshot_id: Option<String>,
+    /// Outcome of the configured `verify_command` after the run
+    pub verification: Option<Verification>,
     pub error: Option<String>,
     pub warnings: Option<String>,
+    /// Suspected prompt injections in the output, when screening with
+    /// `Options::screen_injections`
+    pub injection_warnings: Vec<InjectionWarning>,
+}
+
+/// Raw JSONL event stream compressed with gzip and encoded as base64
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressedMessages {
+    pub gzip_base64: String,
+    /// Size of the JSONL before compression
+    pub uncompressed_bytes: u64,
+    /// The compressed stream reached the size cap and later events were left out
+    pub truncated: bool,
+}
+
+/// Latency breakdown of a run, measured by the server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunTiming {
+    /// Wall-clock time of the whole run, including waiting for the process to exit
+    pub duration_ms: u64,
+    /// Time until the CLI process was started; `None` when an interactive turn reused a
+    /// live process
+    pub spawn_ms: Option<u64>,
+    /// Time until the first stream-json event arrived
+    pub first_event_ms: Option<u64>,
+    /// Number of stream-json events received
+    pub num_events: u64,
 }
 
 /// Result of reading a line with length limit
 #[derive(Debug)]
-struct ReadLineResult {
-    bytes_read: usize,
-    truncated: bool,
+pub(crate) struct ReadLineResult {
+    pub(crate) bytes_read: usize,
+    pub(crate) truncated: bool,
 }
 
 /// Validation mode for enforce_required_fields
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum ValidationMode {
+pub(crate) enum ValidationMode {
     /// Perform full validation (check session_id and agent_messages)
     Full,
     /// Skip validation (for cases with well-defined errors like timeout or truncation)
@@ -156,10 +1440,15 @@ enum ValidationMode {
 /// 1. The Claude CLI always outputs newline-terminated JSON
 /// 2. Process-level timeout prevents indefinite blocking
 /// 3. We stop allocating memory once max_len is hit, preventing OOM
-async fn read_line_with_limit<R: AsyncBufReadExt + Unpin>(
+///
+/// When `overflow` is provided, it is reset and the whole line (buffered prefix and the
+/// remainder) is streamed into it once max_len is hit, so the caller can still extract fields
+/// from oversized lines. One scanner can serve every line of a stream.
+pub(crate) async fn read_line_with_limit<R: AsyncBufReadExt + Unpin>(
     reader: &mut R,
     buf: &mut Vec<u8>,
     max_len: usize,
+    mut overflow: Option<&mut EventScanner>,
 ) -> std::io::Result<ReadLineResult> {
     let mut total_read = 0;
     let mut truncated = false;
@@ -171,26 +1460,40 @@ async fn read_line_with_limit<R: AsyncBufReadExt + Unpin>(
             break; // EOF
         }
 
-        // Process available bytes
-        for (i, &byte) in available.iter().enumerate() {
-            if !truncated && buf.len() < max_len {
-                buf.push(byte);
-                total_read += 1;
-            } else if !truncated {
-                truncated = true;
-            }
+        // Only look at bytes up to and including the next newline
+        let (chunk, found_newline) = match available.iter().position(|&b| b == b'\n') {
+            Some(pos) => (&available[..=pos], true),
+            None => (available, false),
+        };
 
-            if byte == b'\n' {
-                reader.consume(i + 1);
-                return Ok(ReadLineResult {
-                    bytes_read: total_read,
-                    truncated,
-                });
+        if !truncated {
+            let room = max_len.saturating_sub(buf.len());
+            if chunk.len() <= room {
+                buf.extend_from_slice(chunk);
+                total_read += chunk.len();
+            } else {
+                buf.extend_from_slice(&chunk[..room]);
+                total_read += room;
+                truncated = true;
+                if let Some(scanner) = overflow.as_deref_mut() {
+                    scanner.reset();
+                    scanner.feed(buf);
+                    scanner.feed(&chunk[room..]);
+                }
             }
+        } else if let Some(scanner) = overflow.as_deref_mut() {
+            scanner.feed(chunk);
         }
 
-        let consumed = available.len();
+        let consumed = chunk.len();
         reader.consume(consumed);
+        if found_newline {
+            break;
+        }
+    }
+
+    if truncated {
+        trim_partial_utf8(buf);
     }
 
     Ok(ReadLineResult {
@@ -199,24 +1502,179 @@ async fn read_line_with_limit<R: AsyncBufReadExt + Unpin>(
     })
 }
 
+/// Drop a trailing, incomplete UTF-8 sequence left behind when a line is cut at the byte limit,
+/// so truncated output never ends in a mangled character
+fn trim_partial_utf8(buf: &mut Vec<u8>) {
+    // A UTF-8 sequence is at most 4 bytes, so the last lead byte is within the final 4
+    let tail_start = buf.len().saturating_sub(4);
+    let Some(lead_offset) = buf[tail_start..]
+        .iter()
+        .rposition(|&b| (b & 0b1100_0000) != 0b1000_0000)
+    else {
+        return;
+    };
+    let lead_idx = tail_start + lead_offset;
+    let expected_len = match buf[lead_idx] {
+        b if b < 0x80 => 1,
+        b if b >> 5 == 0b110 => 2,
+        b if b >> 4 == 0b1110 => 3,
+        b if b >> 3 == 0b1_1110 => 4,
+        _ => 1,
+    };
+    if buf.len() - lead_idx < expected_len {
+        buf.truncate(lead_idx);
+    }
+}
+
+/// Strip the line terminator (`\n` or `\r\n`) from a raw line
+pub(crate) fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
 /// Execute Claude CLI with the given options and return the result
 /// Requires timeout to be set to prevent unbounded execution
+#[tracing::instrument(
+    name = "claude_run",
+    skip_all,
+    fields(
+        working_dir = %opts.working_dir.display(),
+        session_id = opts.session_id.as_deref(),
+        mode = ?opts.execution_mode,
+    )
+)]
 pub async fn run(mut opts: Options) -> Result<ClaudeResult> {
     // Ensure timeout is always set
     if opts.timeout_secs.is_none() {
         opts.timeout_secs = Some(default_timeout_secs());
     }
 
+    let started = std::time::Instant::now();
+    let cache = match opts.response_cache.clone() {
+        Some(config) => response_cache::key(&opts).await.map(|key| (key, config)),
+        None => None,
+    };
+    if let Some((key, config)) = &cache {
+        if let Some(mut result) = response_cache::get(key, config.ttl_secs) {
+            debug!("serving cached result");
+            result.cached = true;
+            result.timing = RunTiming {
+                duration_ms: elapsed_ms(started),
+                ..Default::default()
+            };
+            return Ok(result);
+        }
+    }
+
+    let mut outcome = match coalesce::request_key(&opts, &[]) {
+        Some(key) => loop {
+            match coalesce::join(&key) {
+                coalesce::Slot::Leader(leader) => {
+                    let outcome = execute(opts, started).await;
+                    leader.finish(&outcome);
+                    break outcome;
+                }
+                coalesce::Slot::Follower(follower) => {
+                    if let Some(mut outcome) = follower.wait().await {
+                        debug!("shared the result of an identical run in flight");
+                        if let Ok(result) = outcome.as_mut() {
+                            result.coalesced = true;
+                            result.timing.duration_ms = elapsed_ms(started);
+                        }
+                        break outcome;
+                    }
+                    // The caller running it went away; start it again
+                }
+            }
+        },
+        None => execute(opts, started).await,
+    };
+
+    if let (Some((key, config)), Ok(result)) = (cache, outcome.as_mut()) {
+        if !result.coalesced {
+            response_cache::put(key, result, &config);
+        }
+    }
+    outcome
+}
+
+/// Run the CLI once under the timeout, recording metrics and the session
+async fn execute(mut opts: Options, started: std::time::Instant) -> Result<ClaudeResult> {
     let timeout_secs = opts.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
-    let duration = std::time::Duration::from_secs(timeout_secs);
+    let (deadline, mut deadline_moved) = Deadline::new(
+        std::time::Duration::from_secs(timeout_secs),
+        std::time::Duration::from_secs(MAX_TIMEOUT_SECS),
+    );
+    let context = context::current();
+    let events = opts.events.clone();
+    // Events pass through here so the session is known even when the timeout cuts the run off,
+    // and so its deadline can be extended by session id
+    let (relay, mut relayed) = tokio::sync::mpsc::unbounded_channel();
+    opts.events = Some(relay);
+    let mut seen_session = opts.session_id.clone();
+    let mut registered: Vec<String> = seen_session.iter().cloned().collect();
+    for session_id in &registered {
+        context.deadlines().register(session_id, &deadline);
+    }
+    let mut pass_on = |event: RunEvent| {
+        if let RunEvent::Output {
+            event_type,
+            tool,
+            bytes_read,
+        } = event
+        {
+            // Only kept for `claude_status`; one per line would flood the client's log
+            deadline.record_output(event_type, tool, bytes_read);
+            return;
+        }
+        if let RunEvent::SessionStarted { session_id } = &event {
+            seen_session = Some(session_id.clone());
+            if !registered.contains(session_id) {
+                context.deadlines().register(session_id, &deadline);
+                registered.push(session_id.clone());
+            }
+        }
+        events::emit(events.as_ref(), event);
+    };
+    let metrics = metrics::metrics();
+    metrics.run_started();
+    let execution = async move {
+        if let Some(path) = opts.replay_from.clone() {
+            return recording::replay(opts, &path).await;
+        }
+        match opts.execution_mode {
+            ExecutionMode::Spawn => run_internal(opts).await,
+            ExecutionMode::Interactive => interactive::run_turn(opts).await,
+        }
+    };
 
-    match tokio::time::timeout(duration, run_internal(opts)).await {
-        Ok(result) => result,
-        Err(_) => {
+    tokio::pin!(execution);
+    let outcome = loop {
+        let at = *deadline_moved.borrow_and_update();
+        tokio::select! {
+            outcome = &mut execution => break Some(outcome),
+            () = tokio::time::sleep_until(at) => break None,
+            // An extension; wait again for the new deadline
+            Ok(()) = deadline_moved.changed() => {}
+            Some(event) = relayed.recv() => pass_on(event),
+        }
+    };
+    while let Ok(event) = relayed.try_recv() {
+        pass_on(event);
+    }
+    for session_id in &registered {
+        context.deadlines().unregister(session_id, &deadline);
+    }
+    let outcome = match outcome {
+        Some(result) => result,
+        None => {
             // Timeout occurred - the child process will be killed automatically via kill_on_drop
+            let timeout_secs = deadline.timeout_secs();
+            warn!(timeout_secs, "claude run timed out");
+            events::emit(events.as_ref(), RunEvent::TimedOut { timeout_secs });
             let result = ClaudeResult {
                 success: false,
-                session_id: String::new(),
+                session_id: seen_session.unwrap_or_default(),
                 agent_messages: String::new(),
                 agent_messages_truncated: false,
                 all_messages: Vec::new(),
@@ -226,314 +1684,1007 @@ pub async fn run(mut opts: Options) -> Result<ClaudeResult> {
                     timeout_secs
                 )),
                 warnings: None,
+                ..Default::default()
             };
             // Skip validation since timeout error is already well-defined
             Ok(enforce_required_fields(result, ValidationMode::Skip))
         }
+    };
+
+    let mut outcome = outcome;
+    if let Ok(result) = outcome.as_mut() {
+        result.timing.duration_ms = elapsed_ms(started);
+        sessions::record(&result.session_id);
     }
+    metrics.run_finished(outcome.as_ref().ok(), started.elapsed());
+    outcome
 }
 
-/// Internal implementation of Claude CLI execution
-async fn run_internal(opts: Options) -> Result<ClaudeResult> {
-    // Allow overriding the claude binary for tests or custom setups
-    let claude_bin = std::env::var("CLAUDE_BIN").unwrap_or_else(|_| "claude".to_string());
+pub(crate) fn elapsed_ms(since: std::time::Instant) -> u64 {
+    since.elapsed().as_millis() as u64
+}
 
-    // Build the base command
-    let mut cmd = Command::new(claude_bin);
+/// Program and arguments for one CLI launch. Kept separate from `Command` so wrappers
+/// (e.g. a systemd scope) can be layered around the `claude` invocation before spawning.
+#[derive(Debug, Clone)]
+pub(crate) struct Invocation {
+    pub(crate) program: OsString,
+    pub(crate) args: Vec<OsString>,
+}
 
-    // Run in the configured working directory (Claude CLI uses the current
-    // process directory as its workspace context).
-    cmd.current_dir(&opts.working_dir);
+impl Invocation {
+    pub(crate) fn arg(&mut self, arg: impl Into<OsString>) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Run this invocation through `wrapper_program wrapper_args... -- program args...`
+    pub(crate) fn wrap<I, S>(self, wrapper_program: impl Into<OsString>, wrapper_args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        let mut args: Vec<OsString> = wrapper_args.into_iter().map(Into::into).collect();
+        args.push(self.program);
+        args.extend(self.args);
+        Self {
+            program: wrapper_program.into(),
+            args,
+        }
+    }
+}
+
+/// CLI program to launch: the config's `claude_bin`, else `CLAUDE_BIN` for tests or custom
+/// setups, else `claude`
+pub(crate) fn claude_bin() -> PathBuf {
+    server_config()
+        .claude_bin
+        .clone()
+        .or_else(|| std::env::var_os("CLAUDE_BIN").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("claude"))
+}
+
+/// Build the `claude` invocation shared by every execution strategy: binary, stream-json
+/// output, additional args, and `--resume`. Callers add the prompt or input format.
+pub(crate) fn base_invocation(opts: &Options) -> Invocation {
+    let mut invocation = Invocation {
+        program: opts
+            .binary
+            .clone()
+            .unwrap_or_else(claude_bin)
+            .into_os_string(),
+        args: Vec::new(),
+    };
 
     // Always request JSON-streaming output suitable for MCP
-    cmd.arg("--print");
-    cmd.args(["--output-format", "stream-json"]);
+    invocation.arg("--print");
+    invocation.arg("--output-format").arg("stream-json");
+
+    // Interactive sessions read their turns from stdin instead of a positional prompt
+    if opts.execution_mode == ExecutionMode::Interactive {
+        invocation.arg("--input-format").arg("stream-json");
+    }
 
     // Append any extra CLI flags requested by the caller, before the prompt delimiter.
     for arg in &opts.additional_args {
-        cmd.arg(arg);
+        invocation.arg(arg);
     }
 
     // Add session resume flag when resuming an existing conversation
     if let Some(ref session_id) = opts.session_id {
-        cmd.args(["--resume", session_id]);
+        invocation.arg("--resume").arg(session_id);
     }
 
-    // Add the prompt as a positional argument at the end - Command::arg()
-    // handles proper escaping across platforms.
-    cmd.arg(&opts.prompt);
+    invocation
+}
+
+/// Turn an invocation into a configured `Command`: wrappers, working directory, piped
+/// output, kill-on-drop, resource limits, and priority.
+pub(crate) fn build_command(opts: &Options, mut invocation: Invocation) -> Command {
+    let invocation = match &opts.remote {
+        Some(remote) => {
+            // Local binary paths (CLAUDE_BIN, `binaries`) mean nothing on the remote host
+            invocation.program = remote.claude_bin.clone().into();
+            let remote_dir = remote.remote_dir(&opts.working_dir);
+            remote.wrap(opts.sandbox.wrap(invocation, remote_dir), remote_dir)
+        }
+        None => opts.sandbox.wrap(invocation, &opts.working_dir),
+    };
+    let invocation = platform::resolve_program(opts.priority.wrap(invocation));
+
+    // Build the base command
+    let mut cmd = Command::new(&invocation.program);
+    cmd.args(&invocation.args);
+
+    // Run in the configured working directory (Claude CLI uses the current
+    // process directory as its workspace context).
+    cmd.current_dir(&opts.working_dir);
+    cmd.envs(&opts.env);
 
     // Configure process
-    cmd.stdin(Stdio::null());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
     cmd.kill_on_drop(true); // Ensure child is killed if this future is dropped (e.g., on timeout)
+    opts.resource_limits.apply_to(&mut cmd);
+    opts.priority.apply_to(&mut cmd);
+
+    cmd
+}
+
+/// Start debug capture for `cmd` if the run asked for it. A failure to start is returned as a
+/// warning for the result rather than failing the run.
+pub(crate) fn start_debug_capture(
+    opts: &Options,
+    cmd: &Command,
+) -> (Option<DebugCapture>, Option<String>) {
+    if !opts.debug_capture {
+        return (None, None);
+    }
+    match DebugCapture::start(cmd) {
+        Ok(capture) => (Some(capture), None),
+        Err(e) => (None, Some(format!("Failed to start debug capture: {}", e))),
+    }
+}
+
+/// Internal implementation of Claude CLI execution
+#[tracing::instrument(name = "claude_process", skip_all, fields(pid = tracing::field::Empty))]
+async fn run_internal(opts: Options) -> Result<ClaudeResult> {
+    let mut invocation = base_invocation(&opts);
+
+    // Add the prompt as a positional argument at the end - Command::arg()
+    // handles proper escaping across platforms. `--` keeps a prompt starting with `-` from
+    // being read as an option, and a variadic flag in `additional_args` from taking it.
+    invocation.arg("--").arg(&opts.prompt);
+
+    let mut cmd = build_command(&opts, invocation);
+    cmd.stdin(Stdio::null());
+    // Removed when this future completes or is dropped, whatever ended the run
+    let run_dir = RunDir::create()
+        .inspect_err(|e| warn!("failed to create the run directory: {}", e))
+        .ok();
+    // Sandboxes have their own temp directory, and a remote host cannot see this one
+    if let Some(run_dir) = run_dir
+        .as_ref()
+        .filter(|_| opts.remote.is_none() && matches!(opts.sandbox, Sandbox::None))
+    {
+        for (name, path) in run_dir.temp_env() {
+            if !opts.env.contains_key(name) {
+                cmd.env(name, path);
+            }
+        }
+    }
+    let (capture, capture_warning) = start_debug_capture(&opts, &cmd);
+
+    // Spawn the process
+    let started = std::time::Instant::now();
+    let mut child = cmd.spawn().context("Failed to spawn claude command")?;
+    let spawn_ms = elapsed_ms(started);
+    let mut tree_guard = ProcessTreeGuard::new(child.id());
+    let _pid_record = orphans::record(child.id());
+    tracing::Span::current().record("pid", child.id());
+    debug!("spawned claude process");
+    events::emit(opts.events.as_ref(), RunEvent::Spawned { pid: child.id() });
+
+    // Read stdout
+    let stdout = child.stdout.take().context("Failed to get stdout")?;
+    let stderr = child.stderr.take().context("Failed to get stderr")?;
+    let (stdout, stderr) = match &capture {
+        Some(capture) => capture.tee(stdout, stderr),
+        None => debug_capture::passthrough(stdout, stderr),
+    };
+    let (stdout, recording_warning) = match &opts.record_to {
+        Some(path) => recording::record(stdout, path),
+        None => (stdout, None),
+    };
+
+    let mut collector = EventCollector::new(&opts, started).await;
+
+    // Spawn a task to drain stderr and capture diagnostics with better error handling
+    let stderr_output = Arc::new(Mutex::new(String::new()));
+    let stderr_handle = spawn_stderr_drain(stderr, Arc::clone(&stderr_output), opts.events.clone());
+
+    read_stdout(stdout, &mut collector, || {
+        // Stop the child so it cannot block on a full pipe, then keep draining
+        warn!("unparseable output from claude; stopping process");
+        tree_guard.kill_tree();
+        let _ = child.start_kill();
+    })
+    .await;
+
+    let mut result = collector.finish().await;
+    result.timing.spawn_ms = Some(spawn_ms);
+    result.debug_dir = capture.map(|capture| capture.dir().to_path_buf());
+    if let Some(warning) = capture_warning {
+        result.warnings = push_warning(result.warnings.take(), &warning);
+    }
+    match recording_warning {
+        Some(warning) => result.warnings = push_warning(result.warnings.take(), &warning),
+        None => result.recording_file = opts.record_to.clone(),
+    }
+
+    // Wait for process to finish
+    let status = child
+        .wait()
+        .await
+        .context("Failed to wait for claude command")?;
+    tree_guard.disarm();
+    info!(%status, "claude process exited");
+
+    // Collect stderr output with better error handling
+    if let Err(e) = stderr_handle.await {
+        // Log the join error but continue processing
+        warn!("failed to join stderr task: {}", e);
+    }
+    let stderr_output = take_stderr(&stderr_output);
+
+    apply_exit_status(&mut result, &status, stderr_output);
+
+    Ok(enforce_required_fields(result, ValidationMode::Full))
+}
+
+/// Spawn a task that drains the child's stderr into `sink`, capped at MAX_STDERR_SIZE.
+/// The sink is shared so interactive sessions can collect stderr per turn. Lines that look
+/// like problems are also reported to `events` as they arrive.
+pub(crate) fn spawn_stderr_drain(
+    stderr: impl AsyncRead + Unpin + Send + 'static,
+    sink: Arc<Mutex<String>>,
+    events: Option<EventSender>,
+) -> tokio::task::JoinHandle<()> {
+    const TRUNCATION_MARKER: &str = "[... stderr truncated due to size limit ...]";
+
+    tokio::spawn(async move {
+        let mut stderr_reader = BufReader::new(stderr);
+        let mut line_buf = Vec::new();
+
+        loop {
+            line_buf.clear();
+            match read_line_with_limit(&mut stderr_reader, &mut line_buf, MAX_LINE_LENGTH, None)
+                .await
+            {
+                Ok(read_result) => {
+                    if read_result.bytes_read == 0 {
+                        break; // EOF
+                    }
+                    // Convert to string, handling invalid UTF-8
+                    let line = String::from_utf8_lossy(trim_line_ending(&line_buf));
+                    if events::is_stderr_highlight(&line) {
+                        events::emit(events.as_ref(), RunEvent::Stderr(line.to_string()));
+                    }
+                    let mut stderr_output = sink.lock().unwrap_or_else(|e| e.into_inner());
+
+                    // Check if adding this line would exceed the limit
+                    let new_size = stderr_output.len() + line.len() + 1; // +1 for newline
+                    if new_size > MAX_STDERR_SIZE {
+                        if !stderr_output.ends_with(TRUNCATION_MARKER) {
+                            if !stderr_output.is_empty() {
+                                stderr_output.push('\n');
+                            }
+                            stderr_output.push_str(TRUNCATION_MARKER);
+                        }
+                        // Continue draining to prevent blocking the child process
+                    } else {
+                        if !stderr_output.is_empty() {
+                            stderr_output.push('\n');
+                        }
+                        stderr_output.push_str(line.as_ref());
+                    }
+                }
+                Err(e) => {
+                    // Log the read error but continue - this preserves diagnostic info
+                    warn!("failed to read from stderr: {}", e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Take everything captured from stderr so far, leaving the buffer empty
+pub(crate) fn take_stderr(sink: &Mutex<String>) -> String {
+    std::mem::take(&mut *sink.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Feed stdout to `collector` line by line, with the line length limit, until EOF or a read
+/// error. `on_parse_failure` runs when the first unparseable line is seen.
+pub(crate) async fn read_stdout(
+    stdout: OutputStream,
+    collector: &mut EventCollector,
+    mut on_parse_failure: impl FnMut(),
+) {
+    let mut reader = BufReader::new(stdout);
+    let mut line_buf = Vec::new();
+    let mut scanner = EventScanner::new(MAX_AGENT_MESSAGES_SIZE);
+
+    loop {
+        line_buf.clear();
+        match read_line_with_limit(
+            &mut reader,
+            &mut line_buf,
+            MAX_LINE_LENGTH,
+            Some(&mut scanner),
+        )
+        .await
+        {
+            Ok(read_result) => {
+                if read_result.bytes_read == 0 {
+                    break; // EOF
+                }
+                if collector
+                    .process_line(&line_buf, &read_result, &mut scanner)
+                    .await
+                    == LineOutcome::ParseFailed
+                {
+                    on_parse_failure();
+                }
+            }
+            Err(e) => {
+                collector.record_io_error(&e);
+                break;
+            }
+        }
+    }
+}
+
+/// Fold the process exit status and captured stderr into the result
+pub(crate) fn apply_exit_status(
+    result: &mut ClaudeResult,
+    status: &std::process::ExitStatus,
+    stderr_output: String,
+) {
+    result.exit_code = status.code();
+    if !status.success() {
+        result.success = false;
+        if result.error.is_none() {
+            result.error = Some(cli_failure::describe(status, &stderr_output));
+        }
+    }
+    // Diagnostics go to the structured `stderr` list rather than into `error`/`warnings`
+    result.stderr.extend(stderr_lines::parse(&stderr_output));
+}
+
+/// What the reader loop should do after a line has been processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LineOutcome {
+    Continue,
+    /// The first unparseable line was seen; the child should be stopped
+    ParseFailed,
+    /// A `result` event closed the current turn
+    TurnComplete,
+}
+
+/// Accumulates a `ClaudeResult` from the raw stdout lines of one run (or one interactive turn)
+pub(crate) struct EventCollector {
+    result: ClaudeResult,
+    spill: Option<SpillFile>,
+    gzip: Option<GzipStream>,
+    all_messages_size: usize,
+    parse_error_seen: bool,
+    max_skipped_lines: usize,
+    skipped_lines: usize,
+    /// Whether a `result` event arrived; a run that skipped lines fails without one
+    result_seen: bool,
+    events: Option<EventSender>,
+    /// Bytes of stdout read so far, reported with each [`RunEvent::Output`]
+    bytes_read: u64,
+    /// Reference point for `first_event_ms`
+    started: std::time::Instant,
+    include_thinking: bool,
+    sanitize_text: bool,
+    parser_limits: ParserLimits,
+    agent_text: AgentText,
+    screener: Option<Screener>,
+}
+
+impl EventCollector {
+    pub(crate) async fn new(opts: &Options, started: std::time::Instant) -> Self {
+        let mut result = ClaudeResult {
+            success: true,
+            ..Default::default()
+        };
+
+        let spill = if opts.spill_all_messages {
+            match SpillFile::create().await {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    result.warnings = push_warning(
+                        result.warnings.take(),
+                        &format!("Failed to create all_messages spill file: {}", e),
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        Self {
+            result,
+            spill,
+            gzip: opts.compress_all_messages.then(GzipStream::new),
+            all_messages_size: 0,
+            parse_error_seen: false,
+            max_skipped_lines: opts.max_skipped_lines,
+            skipped_lines: 0,
+            result_seen: false,
+            events: opts.events.clone(),
+            bytes_read: 0,
+            started,
+            include_thinking: opts.include_thinking,
+            sanitize_text: opts.sanitize_text,
+            parser_limits: opts.parser_limits,
+            screener: opts.screen_injections.then(Screener::default),
+            agent_text: AgentText::new(
+                opts.truncation,
+                opts.max_output_bytes
+                    .map_or(MAX_AGENT_MESSAGES_SIZE, |max| {
+                        max.min(MAX_AGENT_MESSAGES_SIZE)
+                    }),
+            ),
+        }
+    }
+
+    /// Process one line as returned by `read_line_with_limit`, with the scanner that was
+    /// passed to it for oversized lines
+    pub(crate) async fn process_line(
+        &mut self,
+        line_buf: &[u8],
+        read_result: &ReadLineResult,
+        scanner: &mut EventScanner,
+    ) -> LineOutcome {
+        self.bytes_read += read_result.bytes_read as u64;
+        if read_result.truncated {
+            return self.process_oversized(scanner);
+        }
+
+        let line = trim_line_ending(line_buf);
+
+        if line.is_empty() {
+            return LineOutcome::Continue;
+        }
+
+        // The spill file keeps every raw line, including ones past the in-memory cap
+        if let Some(file) = self.spill.as_mut() {
+            if let Err(e) = file.write_line(line).await {
+                self.result.warnings = push_warning(
+                    self.result.warnings.take(),
+                    &format!("Stopped writing all_messages spill file: {}", e),
+                );
+                self.spill = None;
+            }
+        }
+        if let Some(gzip) = self.gzip.as_mut() {
+            gzip.write_line(line);
+        }
+
+        // After a parse error, keep draining stdout to avoid blocking the child process
+        if self.parse_error_seen {
+            return LineOutcome::Continue;
+        }
+
+        if let Err(exceeded) = self.parser_limits.check(line) {
+            record_limit_error(&mut self.result, exceeded, line);
+            return self.parse_failed();
+        }
+
+        // Parse JSON straight from the raw bytes; everything below works off this value
+        let line_data: Value = match serde_json::from_slice(line) {
+            Ok(data) => data,
+            Err(e) if self.skipped_lines < self.max_skipped_lines => {
+                self.skip_line(&e, line);
+                return LineOutcome::Continue;
+            }
+            Err(e) => {
+                record_parse_error(&mut self.result, &e, &String::from_utf8_lossy(line));
+                return self.parse_failed();
+            }
+        };
+
+        let event = StreamEvent::from_value(&line_data);
+        self.count_event();
+        self.handle_event(&event);
+        if self.include_thinking && event.event_type == Some("assistant") {
+            for block in &event.content {
+                if let ContentBlock::Thinking(text) = block {
+                    push_thinking(&mut self.result, text);
+                }
+            }
+        }
+        let outcome = if event.event_type == Some("result") {
+            LineOutcome::TurnComplete
+        } else {
+            LineOutcome::Continue
+        };
+
+        // Collect all messages with bounds checking. The raw line length is a close
+        // enough estimate of the serialized size and avoids re-encoding the event.
+        if line_data.is_object() {
+            // Once an event does not fit, later ones are dropped too so the kept events
+            // stay a contiguous prefix of the stream
+            let message_size = line.len();
+            if !self.result.all_messages_truncated
+                && self.all_messages_size + message_size <= MAX_ALL_MESSAGES_SIZE
+            {
+                self.all_messages_size += message_size;
+                self.result.all_messages.push(line_data);
+            } else {
+                self.result.all_messages_truncated = true;
+                self.result.all_messages_omitted += 1;
+            }
+        }
+
+        outcome
+    }
+
+    /// Oversized lines are never buffered whole; use the fields the scanner extracted while
+    /// the line streamed past instead.
+    fn process_oversized(&mut self, scanner: &mut EventScanner) -> LineOutcome {
+        if self.parse_error_seen {
+            return LineOutcome::Continue;
+        }
+
+        match scanner.finish() {
+            Ok(scanned) => {
+                let event = StreamEvent::from_scanned(&scanned);
+                self.count_event();
+                self.handle_event(&event);
+                // The event itself is too large to keep in all_messages
+                self.result.all_messages_truncated = true;
+                self.result.all_messages_omitted += 1;
+                let warning = format!(
+                    "Summarized an oversized {}-byte stream-json event; only type, session_id and text were kept.",
+                    scanned.bytes
+                );
+                events::emit(self.events.as_ref(), RunEvent::Warning(warning.clone()));
+                self.result.warnings = push_warning(self.result.warnings.take(), &warning);
+                if event.event_type == Some("result") {
+                    LineOutcome::TurnComplete
+                } else {
+                    LineOutcome::Continue
+                }
+            }
+            Err(_) => {
+                self.result.success = false;
+                self.result.error = Some(format!(
+                    "Output line exceeded {} byte limit and was truncated, cannot parse JSON.",
+                    MAX_LINE_LENGTH
+                ));
+                self.parse_failed()
+            }
+        }
+    }
 
-    // Spawn the process
-    let mut child = cmd.spawn().context("Failed to spawn claude command")?;
+    /// Note a malformed line that is tolerated under `max_skipped_lines`
+    fn skip_line(&mut self, error: &serde_json::Error, line: &[u8]) {
+        self.skipped_lines += 1;
+        let excerpt = String::from_utf8_lossy(&line[..line.len().min(LIMIT_ERROR_EXCERPT)]);
+        warn!(%error, line = %excerpt, "skipping malformed output line");
+        let warning = format!("Skipped malformed output line ({}): {}", error, excerpt);
+        events::emit(self.events.as_ref(), RunEvent::Warning(warning.clone()));
+        self.result.warnings = push_warning(self.result.warnings.take(), &warning);
+    }
 
-    // Read stdout
-    let stdout = child.stdout.take().context("Failed to get stdout")?;
-    let stderr = child.stderr.take().context("Failed to get stderr")?;
+    /// [`handle_event`], announcing the session once its ID is known
+    fn handle_event(&mut self, event: &StreamEvent<'_>) {
+        self.result_seen |= event.event_type == Some("result");
+        let known = !self.result.session_id.is_empty();
+        handle_event(&mut self.result, &mut self.agent_text, event);
+        if let Some(screener) = self.screener.as_mut() {
+            for block in &event.content {
+                match block {
+                    ContentBlock::ToolUse { id, name, .. } => screener.tool_use(id, name),
+                    ContentBlock::ToolResult {
+                        tool_use_id,
+                        content: Some(content),
+                        ..
+                    } => screener.tool_result(tool_use_id, content),
+                    _ => {}
+                }
+            }
+        }
+        if !known && !self.result.session_id.is_empty() {
+            let session_id = self.result.session_id.clone();
+            events::emit(
+                self.events.as_ref(),
+                RunEvent::SessionStarted { session_id },
+            );
+        }
+        let tool = event.content.iter().rev().find_map(|block| match block {
+            ContentBlock::ToolUse { name, .. } => Some(name.to_string()),
+            _ => None,
+        });
+        events::emit(
+            self.events.as_ref(),
+            RunEvent::Output {
+                event_type: event.event_type.unwrap_or("unknown").to_string(),
+                tool,
+                bytes_read: self.bytes_read,
+            },
+        );
+    }
 
-    let mut result = ClaudeResult {
-        success: true,
-        session_id: String::new(),
-        agent_messages: String::new(),
-        agent_messages_truncated: false,
-        all_messages: Vec::new(),
-        all_messages_truncated: false,
-        error: None,
-        warnings: None,
-    };
+    fn count_event(&mut self) {
+        let timing = &mut self.result.timing;
+        if timing.first_event_ms.is_none() {
+            timing.first_event_ms = Some(elapsed_ms(self.started));
+        }
+        timing.num_events += 1;
+    }
 
-    // Spawn a task to drain stderr and capture diagnostics with better error handling
-    const MAX_STDERR_SIZE: usize = 1024 * 1024; // 1MB limit for stderr
-    const MAX_LINE_LENGTH: usize = 1024 * 1024; // 1MB per line to prevent memory spikes
-    const MAX_AGENT_MESSAGES_SIZE: usize = 10 * 1024 * 1024; // 10MB limit for agent messages
-    const MAX_ALL_MESSAGES_SIZE: usize = 50 * 1024 * 1024; // 50MB limit for all messages combined
-    let stderr_handle = tokio::spawn(async move {
-        let mut stderr_output = String::new();
-        let mut stderr_reader = BufReader::new(stderr);
-        let mut truncated = false;
-        let mut line_buf = Vec::new();
+    fn parse_failed(&mut self) -> LineOutcome {
+        if self.parse_error_seen {
+            LineOutcome::Continue
+        } else {
+            self.parse_error_seen = true;
+            let message = self.result.error.clone().unwrap_or_default();
+            events::emit(self.events.as_ref(), RunEvent::ParseError(message));
+            LineOutcome::ParseFailed
+        }
+    }
 
-        loop {
-            line_buf.clear();
-            match read_line_with_limit(&mut stderr_reader, &mut line_buf, MAX_LINE_LENGTH).await {
-                Ok(read_result) => {
-                    if read_result.bytes_read == 0 {
-                        break; // EOF
-                    }
-                    // Convert to string, handling invalid UTF-8
-                    let line = String::from_utf8_lossy(&line_buf);
-                    let line = line.trim_end_matches('\n').trim_end_matches('\r');
+    pub(crate) fn record_io_error(&mut self, error: &std::io::Error) {
+        // Create a simple IO error for the parse error
+        let io_error = std::io::Error::from(error.kind());
+        record_parse_error(&mut self.result, &serde_json::Error::io(io_error), "");
+    }
 
-                    // Check if adding this line would exceed the limit
-                    let new_size = stderr_output.len() + line.len() + 1; // +1 for newline
-                    if new_size > MAX_STDERR_SIZE {
-                        if !truncated {
-                            if !stderr_output.is_empty() {
-                                stderr_output.push('\n');
-                            }
-                            stderr_output.push_str("[... stderr truncated due to size limit ...]");
-                            truncated = true;
-                        }
-                        // Continue draining to prevent blocking the child process
-                    } else if !truncated {
-                        if !stderr_output.is_empty() {
-                            stderr_output.push('\n');
-                        }
-                        stderr_output.push_str(line.as_ref());
-                    }
-                }
+    /// Flush the spill file and hand back the accumulated result
+    pub(crate) async fn finish(mut self) -> ClaudeResult {
+        if self.skipped_lines > 0 && !self.result_seen && self.result.success {
+            self.result.success = false;
+            self.result.error = Some(format!(
+                "JSON parse error: no result event after skipping {} malformed output line(s)",
+                self.skipped_lines
+            ));
+        }
+        let (mut agent_messages, omitted) = self.agent_text.finish();
+        if self.sanitize_text {
+            agent_messages = output_filter::sanitize(agent_messages);
+            let thinking = std::mem::take(&mut self.result.thinking);
+            self.result.thinking = output_filter::sanitize(thinking);
+        }
+        self.result.agent_messages = agent_messages;
+        self.result.agent_messages_truncated = omitted > 0;
+        if let Some(screener) = self.screener.take() {
+            let warnings = screener.finish(&self.result.agent_messages);
+            if !warnings.is_empty() {
+                self.result.warnings = push_warning(
+                    self.result.warnings.take(),
+                    &format!(
+                        "possible prompt injection in the output ({} passage(s), see injection_warnings)",
+                        warnings.len()
+                    ),
+                );
+            }
+            self.result.injection_warnings = warnings;
+        }
+        self.result.agent_messages_omitted = omitted;
+        self.result.all_messages_gzip = self.gzip.take().map(GzipStream::finish);
+        if let Some(file) = self.spill.take() {
+            match file.finish().await {
+                Ok(path) => self.result.all_messages_file = Some(path),
                 Err(e) => {
-                    // Log the read error but continue - this preserves diagnostic info
-                    eprintln!("Warning: Failed to read from stderr: {}", e);
-                    break;
+                    self.result.warnings = push_warning(
+                        self.result.warnings.take(),
+                        &format!("Failed to flush all_messages spill file: {}", e),
+                    );
                 }
             }
         }
+        self.result
+    }
+}
 
-        stderr_output
-    });
+/// Temp JSONL file receiving the raw event stream of a single run
+struct SpillFile {
+    path: PathBuf,
+    writer: tokio::io::BufWriter<tokio::fs::File>,
+}
 
-    // Read stdout line by line with length limit
-    let mut reader = BufReader::new(stdout);
-    let mut parse_error_seen = false;
-    let mut line_buf = Vec::new();
-    let mut all_messages_size: usize = 0;
+impl SpillFile {
+    async fn create() -> std::io::Result<Self> {
+        let dir = std::env::temp_dir().join("claude-mcp-rs");
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(format!("all-messages-{}.jsonl", uuid::Uuid::new_v4()));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(Self {
+            path,
+            writer: tokio::io::BufWriter::new(file),
+        })
+    }
 
-    loop {
-        line_buf.clear();
-        match read_line_with_limit(&mut reader, &mut line_buf, MAX_LINE_LENGTH).await {
-            Ok(read_result) => {
-                if read_result.bytes_read == 0 {
-                    break; // EOF
-                }
+    async fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(line).await?;
+        self.writer.write_all(b"\n").await
+    }
 
-                // Check for line truncation - short-circuit to error instead of attempting parse
-                if read_result.truncated {
-                    let error_msg = format!(
-                        "Output line exceeded {} byte limit and was truncated, cannot parse JSON.",
-                        MAX_LINE_LENGTH
-                    );
-                    result.success = false;
-                    result.error = Some(error_msg);
-                    if !parse_error_seen {
-                        parse_error_seen = true;
-                        // Stop the child so it cannot block on a full pipe, then keep draining
-                        let _ = child.start_kill();
-                    }
-                    continue;
-                }
+    async fn finish(mut self) -> std::io::Result<PathBuf> {
+        self.writer.flush().await?;
+        Ok(self.path)
+    }
+}
 
-                // Convert to string
-                let line = String::from_utf8_lossy(&line_buf);
-                let line = line.trim_end_matches('\n').trim_end_matches('\r');
+/// In-memory gzip of the raw event stream of a single run
+struct GzipStream {
+    encoder: GzEncoder<Vec<u8>>,
+    uncompressed_bytes: u64,
+    truncated: bool,
+}
 
-                if line.is_empty() {
-                    continue;
-                }
+impl GzipStream {
+    fn new() -> Self {
+        Self {
+            encoder: GzEncoder::new(Vec::new(), Compression::default()),
+            uncompressed_bytes: 0,
+            truncated: false,
+        }
+    }
 
-                // After a parse error, keep draining stdout to avoid blocking the child process
-                if parse_error_seen {
-                    continue;
-                }
+    fn write_line(&mut self, line: &[u8]) {
+        if self.truncated {
+            return;
+        }
+        if self.encoder.get_ref().len() >= MAX_ALL_MESSAGES_SIZE {
+            self.truncated = true;
+            return;
+        }
+        // Writing into a Vec cannot fail
+        let _ = self.encoder.write_all(line);
+        let _ = self.encoder.write_all(b"\n");
+        self.uncompressed_bytes += line.len() as u64 + 1;
+    }
 
-                // Parse JSON line
-                let line_data: Value = match serde_json::from_str(line) {
-                    Ok(data) => data,
-                    Err(e) => {
-                        record_parse_error(&mut result, &e, line);
-                        if !parse_error_seen {
-                            parse_error_seen = true;
-                            // Stop the child so it cannot block on a full pipe, then keep draining
-                            let _ = child.start_kill();
-                        }
-                        continue;
-                    }
-                };
+    fn finish(self) -> CompressedMessages {
+        let data = self.encoder.finish().unwrap_or_default();
+        CompressedMessages {
+            gzip_base64: base64::engine::general_purpose::STANDARD.encode(data),
+            uncompressed_bytes: self.uncompressed_bytes,
+            truncated: self.truncated,
+        }
+    }
+}
 
-                // Collect all messages with bounds checking
-                if let Ok(map) = serde_json::from_value::<HashMap<String, Value>>(line_data.clone())
-                {
-                    // Estimate size of this message (JSON serialized size)
-                    let message_size = serde_json::to_string(&map).map(|s| s.len()).unwrap_or(0);
-
-                    // Check if adding this message would exceed byte limit
-                    if all_messages_size + message_size <= MAX_ALL_MESSAGES_SIZE {
-                        all_messages_size += message_size;
-                        result.all_messages.push(map);
-                    } else if !result.all_messages_truncated {
-                        result.all_messages_truncated = true;
-                    }
-                }
+/// Typed view over the fields of a stream-json event that the runner inspects.
+/// Borrows from the parsed `Value` so each line is only parsed once.
+#[derive(Debug, Default)]
+struct StreamEvent<'a> {
+    event_type: Option<&'a str>,
+    session_id: Option<&'a str>,
+    content: Vec<ContentBlock<'a>>,
+    result: Option<&'a str>,
+    is_error: bool,
+    usage: Option<Usage>,
+    total_cost_usd: Option<f64>,
+    num_turns: Option<u64>,
+    duration_ms: Option<u64>,
+    duration_api_ms: Option<u64>,
+}
 
-                // Extract session_id from any event that includes it
-                if let Some(session_id) = line_data.get("session_id").and_then(|v| v.as_str()) {
-                    if !session_id.is_empty() {
-                        result.session_id = session_id.to_string();
-                    }
-                }
+/// A single block from `message.content` of an assistant event
+#[derive(Debug)]
+enum ContentBlock<'a> {
+    Text(&'a str),
+    Thinking(&'a str),
+    ToolUse {
+        id: &'a str,
+        name: &'a str,
+        input: &'a Value,
+    },
+    ToolResult {
+        tool_use_id: &'a str,
+        is_error: bool,
+        /// First text of the result, whether given as a string or as text blocks
+        content: Option<&'a str>,
+    },
+    Other,
+}
 
-                // Extract assistant text from Claude stream-json output.
-                // We primarily look at `type == "assistant"` events and pull
-                // text blocks from `message.content[*].text`. As a fallback,
-                // we also consider `type == "result"` lines with a string
-                // `result` field.
-                if let Some(line_type) = line_data.get("type").and_then(|v| v.as_str()) {
-                    match line_type {
-                        "assistant" => {
-                            if let Some(message) =
-                                line_data.get("message").and_then(|v| v.as_object())
-                            {
-                                if let Some(content) =
-                                    message.get("content").and_then(|v| v.as_array())
-                                {
-                                    for block in content {
-                                        if block.get("type").and_then(|v| v.as_str())
-                                            == Some("text")
-                                        {
-                                            if let Some(text) =
-                                                block.get("text").and_then(|v| v.as_str())
-                                            {
-                                                let new_size =
-                                                    result.agent_messages.len() + text.len();
-                                                if new_size > MAX_AGENT_MESSAGES_SIZE {
-                                                    if !result.agent_messages_truncated {
-                                                        result.agent_messages.push_str(
-                                                            "\n[... Agent messages truncated due to size limit ...]",
-                                                        );
-                                                        result.agent_messages_truncated = true;
-                                                    }
-                                                } else if !result.agent_messages_truncated {
-                                                    if !result.agent_messages.is_empty()
-                                                        && !text.is_empty()
-                                                    {
-                                                        result.agent_messages.push('\n');
-                                                    }
-                                                    result.agent_messages.push_str(text);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        "result" => {
-                            // Note: We don't extract text from "result" events because
-                            // the same content is already captured from "assistant" events.
-                            // We only use "result" events for error handling.
-
-                            // If this result represents an error (`is_error: true`),
-                            // surface it as a failure.
-                            if line_data
+impl<'a> StreamEvent<'a> {
+    fn from_scanned(scanned: &'a ScannedEvent) -> Self {
+        Self {
+            event_type: scanned.event_type.as_deref(),
+            session_id: scanned.session_id.as_deref(),
+            content: scanned
+                .texts
+                .iter()
+                .map(|text| ContentBlock::Text(text))
+                .collect(),
+            result: scanned.result.as_deref(),
+            is_error: scanned.is_error,
+            // Usage blocks are small; oversized events are assistant text, not results
+            usage: None,
+            total_cost_usd: None,
+            num_turns: None,
+            duration_ms: None,
+            duration_api_ms: None,
+        }
+    }
+
+    fn from_value(value: &'a Value) -> Self {
+        let content = value
+            .get("message")
+            .and_then(|m| m.get("content"))
+            .and_then(|c| c.as_array())
+            .map(|blocks| {
+                blocks
+                    .iter()
+                    .map(|block| match block.get("type").and_then(|v| v.as_str()) {
+                        Some("text") => block
+                            .get("text")
+                            .and_then(|v| v.as_str())
+                            .map_or(ContentBlock::Other, ContentBlock::Text),
+                        Some("thinking") => block
+                            .get("thinking")
+                            .and_then(|v| v.as_str())
+                            .map_or(ContentBlock::Other, ContentBlock::Thinking),
+                        Some("tool_use") => ContentBlock::ToolUse {
+                            id: block.get("id").and_then(|v| v.as_str()).unwrap_or_default(),
+                            name: block
+                                .get("name")
+                                .and_then(|v| v.as_str())
+                                .unwrap_or_default(),
+                            input: block.get("input").unwrap_or(&Value::Null),
+                        },
+                        Some("tool_result") => ContentBlock::ToolResult {
+                            tool_use_id: block
+                                .get("tool_use_id")
+                                .and_then(|v| v.as_str())
+                                .unwrap_or_default(),
+                            is_error: block
                                 .get("is_error")
                                 .and_then(|v| v.as_bool())
-                                .unwrap_or(false)
-                            {
-                                result.success = false;
-                                if let Some(result_text) =
-                                    line_data.get("result").and_then(|v| v.as_str())
-                                {
-                                    result.error = Some(format!("Claude error: {}", result_text));
-                                }
-                            }
-                        }
-                        _ => {}
+                                .unwrap_or(false),
+                            content: block.get("content").and_then(|c| match c {
+                                Value::String(text) => Some(text.as_str()),
+                                Value::Array(parts) => parts
+                                    .iter()
+                                    .find_map(|p| p.get("text").and_then(|v| v.as_str())),
+                                _ => None,
+                            }),
+                        },
+                        _ => ContentBlock::Other,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            event_type: value.get("type").and_then(|v| v.as_str()),
+            session_id: value.get("session_id").and_then(|v| v.as_str()),
+            content,
+            result: value.get("result").and_then(|v| v.as_str()),
+            is_error: value
+                .get("is_error")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            usage: value.get("usage").and_then(|u| Usage::deserialize(u).ok()),
+            total_cost_usd: value.get("total_cost_usd").and_then(|v| v.as_f64()),
+            num_turns: value.get("num_turns").and_then(|v| v.as_u64()),
+            duration_ms: value.get("duration_ms").and_then(|v| v.as_u64()),
+            duration_api_ms: value.get("duration_api_ms").and_then(|v| v.as_u64()),
+        }
+    }
+}
+
+/// Fold a single stream-json event into the accumulated result; assistant text goes to
+/// `agent_text` and is copied into the result when the run finishes
+fn handle_event(result: &mut ClaudeResult, agent_text: &mut AgentText, event: &StreamEvent<'_>) {
+    // Extract session_id from any event that includes it
+    if let Some(session_id) = event.session_id {
+        if !session_id.is_empty() {
+            result.session_id = session_id.to_string();
+        }
+    }
+
+    if event.event_type == Some("result") {
+        if event.usage.is_some() {
+            result.usage = event.usage.clone();
+        }
+        if event.total_cost_usd.is_some() {
+            result.total_cost_usd = event.total_cost_usd;
+        }
+        if event.num_turns.is_some() {
+            result.num_turns = event.num_turns;
+        }
+        if event.duration_ms.is_some() {
+            result.cli_duration_ms = event.duration_ms;
+        }
+        if event.duration_api_ms.is_some() {
+            result.api_duration_ms = event.duration_api_ms;
+        }
+    }
+
+    // Extract assistant text from Claude stream-json output. We only look at
+    // `type == "assistant"` events and pull text blocks from `message.content[*].text`.
+    match event.event_type {
+        Some("assistant") => {
+            for block in &event.content {
+                match block {
+                    ContentBlock::Text(text) => agent_text.push(text),
+                    ContentBlock::ToolUse { id, name, input } => {
+                        result.actions.record_tool_use(id, name, input)
                     }
+                    _ => {}
                 }
             }
-            Err(e) => {
-                // Create a simple IO error for the parse error
-                let io_error = std::io::Error::from(e.kind());
-                record_parse_error(&mut result, &serde_json::Error::io(io_error), "");
-                break;
+        }
+        // Tool results come back to the model as user messages
+        Some("user") => {
+            for block in &event.content {
+                if let ContentBlock::ToolResult {
+                    tool_use_id,
+                    is_error,
+                    content,
+                } = block
+                {
+                    result
+                        .actions
+                        .record_tool_result(tool_use_id, *is_error, *content);
+                }
+            }
+        }
+        // Note: We don't extract text from "result" events because the same content is
+        // already captured from "assistant" events. We only use "result" events for error
+        // handling: if this result represents an error (`is_error: true`), surface it as a
+        // failure.
+        Some("result") if event.is_error => {
+            result.success = false;
+            if let Some(result_text) = event.result {
+                result.error = Some(format!("Claude error: {}", result_text));
             }
         }
+        _ => {}
     }
+}
 
-    // Wait for process to finish
-    let status = child
-        .wait()
-        .await
-        .context("Failed to wait for claude command")?;
-
-    // Collect stderr output with better error handling
-    let stderr_output = match stderr_handle.await {
-        Ok(output) => output,
-        Err(e) => {
-            // Log the join error but continue processing
-            eprintln!("Warning: Failed to join stderr task: {}", e);
-            String::new()
-        }
+fn push_thinking(result: &mut ClaudeResult, text: &str) {
+    if result.thinking_truncated || text.is_empty() {
+        return;
+    }
+    let separator = if result.thinking.is_empty() {
+        ""
+    } else {
+        "\n\n"
     };
+    let room = MAX_THINKING_SIZE.saturating_sub(result.thinking.len());
+    if separator.len() + text.len() <= room {
+        result.thinking.push_str(separator);
+        result.thinking.push_str(text);
+        return;
+    }
 
-    if !status.success() {
-        result.success = false;
-        let error_msg = if let Some(ref err) = result.error {
-            err.clone()
-        } else {
-            format!("claude command failed with exit code: {:?}", status.code())
-        };
-
-        // Append stderr diagnostics if available
-        if !stderr_output.is_empty() {
-            result.error = Some(format!("{}\nStderr: {}", error_msg, stderr_output));
-        } else {
-            result.error = Some(error_msg);
+    result.thinking_truncated = true;
+    if room > separator.len() {
+        let mut cut = room - separator.len();
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
         }
-    } else if !stderr_output.is_empty() {
-        // On success, put stderr in warnings field instead of error
-        result.warnings = Some(stderr_output);
+        result.thinking.push_str(separator);
+        result.thinking.push_str(&text[..cut]);
     }
-
-    Ok(enforce_required_fields(result, ValidationMode::Full))
 }
 
 fn record_parse_error(result: &mut ClaudeResult, error: &serde_json::Error, line: &str) {
-    let parse_msg = format!("JSON parse error: {}. Line: {}", error, line);
+    fail_parse(
+        result,
+        format!("JSON parse error: {}. Line: {}", error, line),
+    );
+}
+
+/// Bytes of an over-limit or skipped line quoted in messages; the line itself may be huge
+const LIMIT_ERROR_EXCERPT: usize = 200;
+
+fn record_limit_error(result: &mut ClaudeResult, exceeded: LimitExceeded, line: &[u8]) {
+    let excerpt = String::from_utf8_lossy(&line[..line.len().min(LIMIT_ERROR_EXCERPT)]);
+    fail_parse(
+        result,
+        format!(
+            "JSON parse error: event exceeds parser limit ({}). Line starts: {}",
+            exceeded, excerpt
+        ),
+    );
+}
+
+fn fail_parse(result: &mut ClaudeResult, parse_msg: String) {
     result.success = false;
     result.error = match result.error.take() {
         Some(existing) if !existing.is_empty() => Some(format!("{existing}\n{parse_msg}")),
@@ -541,7 +2692,39 @@ fn record_parse_error(result: &mut ClaudeResult, error: &serde_json::Error, line
     };
 }
 
-fn push_warning(existing: Option<String>, warning: &str) -> Option<String> {
+/// Whether a failed run was stopped by the conversation outgrowing the context window
+pub fn is_context_overflow(result: &ClaudeResult) -> bool {
+    let overflowed = |text: &str| {
+        let text = text.to_ascii_lowercase();
+        CONTEXT_OVERFLOW_MARKERS
+            .iter()
+            .any(|marker| text.contains(marker))
+    };
+    !result.success
+        && (result.error.as_deref().is_some_and(overflowed)
+            || result.stderr.iter().any(|line| overflowed(&line.message)))
+}
+
+/// Whether a failed run could not resume its session because the CLI does not know it
+pub fn is_missing_session(result: &ClaudeResult) -> bool {
+    let named = |text: &str| CliFailure::SessionNotFound.is_named_in(text);
+    !result.success
+        && (result.error.as_deref().is_some_and(named)
+            || result.stderr.iter().any(|line| named(&line.message)))
+}
+
+/// Whether a failed run hit an overloaded or unavailable model, which a fallback may avoid
+pub fn is_retryable_failure(result: &ClaudeResult) -> bool {
+    let retryable = |text: &str| {
+        let text = text.to_ascii_lowercase();
+        RETRYABLE_MARKERS.iter().any(|marker| text.contains(marker))
+    };
+    !result.success
+        && (result.error.as_deref().is_some_and(retryable)
+            || result.stderr.iter().any(|line| retryable(&line.message)))
+}
+
+pub(crate) fn push_warning(existing: Option<String>, warning: &str) -> Option<String> {
     match existing {
         Some(mut current) => {
             if !current.is_empty() {
@@ -554,7 +2737,10 @@ fn push_warning(existing: Option<String>, warning: &str) -> Option<String> {
     }
 }
 
-fn enforce_required_fields(mut result: ClaudeResult, mode: ValidationMode) -> ClaudeResult {
+pub(crate) fn enforce_required_fields(
+    mut result: ClaudeResult,
+    mode: ValidationMode,
+) -> ClaudeResult {
     // Skip validation for cases where we already have a well-defined error (e.g., timeout, truncation)
     if mode == ValidationMode::Skip {
         return result;
@@ -588,6 +2774,7 @@ mod tests {
             session_id: None,
             additional_args: Vec::new(),
             timeout_secs: None,
+            ..Default::default()
         };
 
         assert_eq!(opts.prompt, "test prompt");
@@ -602,12 +2789,33 @@ mod tests {
             session_id: Some("test-session-123".to_string()),
             additional_args: vec!["--json".to_string()],
             timeout_secs: Some(600),
+            ..Default::default()
         };
 
         assert_eq!(opts.session_id, Some("test-session-123".to_string()));
         assert_eq!(opts.timeout_secs, Some(600));
     }
 
+    #[test]
+    fn test_overrides_win_over_file_settings() {
+        let mut cfg: ServerConfig =
+            serde_json::from_str(r#"{"timeout_secs": 60, "additional_args": ["--verbose"]}"#)
+                .unwrap();
+        apply_overrides(&mut cfg, &ConfigOverrides::default());
+        assert_eq!(cfg.timeout_secs, Some(60));
+
+        apply_overrides(
+            &mut cfg,
+            &ConfigOverrides {
+                timeout_secs: Some(900),
+                additional_args: vec!["--model".to_string(), "opus".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(cfg.timeout_secs, Some(900));
+        assert_eq!(cfg.additional_args, vec!["--verbose", "--model", "opus"]);
+    }
+
     #[test]
     fn test_record_parse_error_sets_failure_and_appends_message() {
         let mut result = ClaudeResult {
@@ -619,6 +2827,7 @@ mod tests {
             all_messages_truncated: false,
             error: Some("existing".to_string()),
             warnings: None,
+            ..Default::default()
         };
 
         let err = serde_json::from_str::<Value>("not-json").unwrap_err();
@@ -636,10 +2845,11 @@ mod tests {
             session_id: "session".to_string(),
             agent_messages: String::new(),
             agent_messages_truncated: false,
-            all_messages: vec![HashMap::new()],
+            all_messages: vec![serde_json::json!({})],
             all_messages_truncated: false,
             error: None,
             warnings: None,
+            ..Default::default()
         };
 
         let updated = enforce_required_fields(result, ValidationMode::Full);
@@ -663,6 +2873,7 @@ mod tests {
             all_messages_truncated: false,
             error: None,
             warnings: None,
+            ..Default::default()
         };
 
         let updated = enforce_required_fields(result, ValidationMode::Full);
@@ -675,6 +2886,61 @@ mod tests {
             .contains("Failed to get SESSION_ID"));
     }
 
+    #[test]
+    fn test_context_overflow_is_detected_in_errors_only() {
+        let failed = |error: &str| ClaudeResult {
+            error: Some(error.to_string()),
+            ..Default::default()
+        };
+        assert!(is_context_overflow(&failed(
+            "Claude error: Prompt is too long"
+        )));
+        assert!(is_context_overflow(&failed(
+            "input length and `max_tokens` exceed context limit: 198000 + 8192 > 200000"
+        )));
+        assert!(!is_context_overflow(&failed("Claude error: rate limited")));
+        assert!(!is_context_overflow(&ClaudeResult {
+            success: true,
+            ..failed("Prompt is too long")
+        }));
+    }
+
+    #[test]
+    fn test_fallbacks_replace_model_and_match_retryable_errors() {
+        let failed = |error: &str| ClaudeResult {
+            error: Some(error.to_string()),
+            ..Default::default()
+        };
+        assert!(is_retryable_failure(&failed(
+            "Claude error: API Error: 529 {\"type\":\"overloaded_error\"}"
+        )));
+        assert!(is_retryable_failure(&failed(
+            "Claude error: model not found: claude-next"
+        )));
+        assert!(!is_retryable_failure(&failed(
+            "Claude error: Prompt is too long"
+        )));
+
+        let opts = Options {
+            additional_args: vec![
+                "--model".to_string(),
+                "opus".to_string(),
+                "--verbose".to_string(),
+                "--model=opus".to_string(),
+            ],
+            ..Default::default()
+        };
+        let fallback = Fallback {
+            model: Some("sonnet".to_string()),
+            binary: None,
+        };
+        assert_eq!(
+            fallback.apply(opts).additional_args,
+            ["--verbose", "--model", "sonnet"]
+        );
+        assert_eq!(fallback.to_string(), "model sonnet");
+    }
+
     #[test]
     fn test_push_warning_appends_with_newline() {
         let combined = push_warning(Some("first".to_string()), "second").unwrap();
@@ -695,6 +2961,7 @@ mod tests {
             all_messages_truncated: false,
             error: Some("Claude execution timed out after 10 seconds".to_string()),
             warnings: None,
+            ..Default::default()
         };
 
         let updated = enforce_required_fields(result, ValidationMode::Skip);
@@ -726,6 +2993,7 @@ mod tests {
                     .to_string(),
             ),
             warnings: None,
+            ..Default::default()
         };
 
         let updated = enforce_required_fields(result, ValidationMode::Full);
@@ -742,4 +3010,347 @@ mod tests {
         assert!(updated.warnings.is_some());
         assert!(updated.warnings.unwrap().contains("No agent_messages"));
     }
+
+    #[test]
+    fn test_handle_event_collects_text_and_session_from_single_parse() {
+        let mut result = ClaudeResult {
+            success: true,
+            session_id: String::new(),
+            agent_messages: String::new(),
+            agent_messages_truncated: false,
+            all_messages: Vec::new(),
+            all_messages_truncated: false,
+            error: None,
+            warnings: None,
+            ..Default::default()
+        };
+
+        let assistant: Value = serde_json::from_str(
+            r#"{"type":"assistant","session_id":"s-1","message":{"content":[{"type":"text","text":"hi"},{"type":"tool_use","name":"Read"},{"type":"text","text":"there"}]}}"#,
+        )
+        .unwrap();
+        let mut agent_text = AgentText::new(TruncationStrategy::Head, MAX_AGENT_MESSAGES_SIZE);
+        handle_event(
+            &mut result,
+            &mut agent_text,
+            &StreamEvent::from_value(&assistant),
+        );
+
+        let failed: Value = serde_json::from_str(
+            r#"{"type":"result","result":"boom","is_error":true,"session_id":"s-1"}"#,
+        )
+        .unwrap();
+        handle_event(
+            &mut result,
+            &mut agent_text,
+            &StreamEvent::from_value(&failed),
+        );
+
+        assert_eq!(result.session_id, "s-1");
+        assert_eq!(agent_text.finish(), ("hi\nthere".to_string(), 0));
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("Claude error: boom"));
+    }
+
+    #[test]
+    fn test_result_event_metadata_is_parsed() {
+        let mut result = ClaudeResult::default();
+        let event: Value = serde_json::from_str(
+            r#"{"type":"result","result":"ok","session_id":"s-1","total_cost_usd":0.042,"duration_ms":5120,"duration_api_ms":4800,"num_turns":2,"usage":{"input_tokens":120,"output_tokens":45,"cache_read_input_tokens":900}}"#,
+        )
+        .unwrap();
+        let mut agent_text = AgentText::new(TruncationStrategy::Head, MAX_AGENT_MESSAGES_SIZE);
+        handle_event(
+            &mut result,
+            &mut agent_text,
+            &StreamEvent::from_value(&event),
+        );
+
+        assert_eq!(result.total_cost_usd, Some(0.042));
+        assert_eq!(result.cli_duration_ms, Some(5120));
+        assert_eq!(result.api_duration_ms, Some(4800));
+        assert_eq!(result.num_turns, Some(2));
+        assert_eq!(
+            result.usage,
+            Some(Usage {
+                input_tokens: 120,
+                output_tokens: 45,
+                cache_creation_input_tokens: 0,
+                cache_read_input_tokens: 900,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_thinking_is_collected_only_on_request() {
+        let line: &[u8] = br#"{"type":"assistant","message":{"content":[{"type":"thinking","thinking":"plan first"},{"type":"text","text":"done"}]}}"#;
+        let read = ReadLineResult {
+            bytes_read: line.len() + 1,
+            truncated: false,
+        };
+
+        for include_thinking in [false, true] {
+            let opts = Options {
+                include_thinking,
+                ..Default::default()
+            };
+            let mut collector = EventCollector::new(&opts, std::time::Instant::now()).await;
+            let mut scanner = EventScanner::new(MAX_AGENT_MESSAGES_SIZE);
+            collector.process_line(line, &read, &mut scanner).await;
+            let result = collector.finish().await;
+
+            assert_eq!(result.agent_messages, "done");
+            let expected = if include_thinking { "plan first" } else { "" };
+            assert_eq!(result.thinking, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_messages_respect_max_output_bytes() {
+        let opts = Options {
+            truncation: TruncationStrategy::Tail,
+            max_output_bytes: Some(16),
+            ..Default::default()
+        };
+        let mut collector = EventCollector::new(&opts, std::time::Instant::now()).await;
+        for text in ["first message", "final answer"] {
+            let line = serde_json::json!({
+                "type": "assistant",
+                "message": {"content": [{"type": "text", "text": text}]}
+            })
+            .to_string();
+            let read = ReadLineResult {
+                bytes_read: line.len() + 1,
+                truncated: false,
+            };
+            let mut scanner = EventScanner::new(MAX_AGENT_MESSAGES_SIZE);
+            collector
+                .process_line(line.as_bytes(), &read, &mut scanner)
+                .await;
+        }
+
+        let result = collector.finish().await;
+        assert!(result.agent_messages_truncated);
+        assert_eq!(result.agent_messages_omitted, 1);
+        assert!(result.agent_messages.ends_with("final answer"));
+    }
+
+    #[tokio::test]
+    async fn test_extracted_text_is_sanitized_when_enabled() {
+        let line: &[u8] =
+            br#"{"type":"assistant","message":{"content":[{"type":"text","text":"\u001b[31mred\u001b[0m\r\nok"}]}}"#;
+        let read = ReadLineResult {
+            bytes_read: line.len() + 1,
+            truncated: false,
+        };
+
+        for sanitize_text in [false, true] {
+            let opts = Options {
+                sanitize_text,
+                ..Default::default()
+            };
+            let mut collector = EventCollector::new(&opts, std::time::Instant::now()).await;
+            let mut scanner = EventScanner::new(MAX_AGENT_MESSAGES_SIZE);
+            collector.process_line(line, &read, &mut scanner).await;
+            let result = collector.finish().await;
+
+            let expected = if sanitize_text {
+                "red\nok"
+            } else {
+                "\u{1b}[31mred\u{1b}[0m\r\nok"
+            };
+            assert_eq!(result.agent_messages, expected);
+        }
+    }
+
+    #[test]
+    fn test_thinking_is_capped_on_char_boundary() {
+        let mut result = ClaudeResult::default();
+        push_thinking(&mut result, &"a".repeat(MAX_THINKING_SIZE - 5));
+        // Three bytes of room after the separator fit one two-byte character
+        push_thinking(&mut result, "ééé");
+        assert!(result.thinking_truncated);
+        assert_eq!(result.thinking.len(), MAX_THINKING_SIZE - 1);
+        assert!(result.thinking.ends_with("\n\né"));
+    }
+
+    #[tokio::test]
+    async fn test_collector_counts_events_and_turns() {
+        let mut collector =
+            EventCollector::new(&Options::default(), std::time::Instant::now()).await;
+        let lines: [&[u8]; 3] = [
+            br#"{"type":"system","session_id":"s-1"}"#,
+            b"",
+            br#"{"type":"result","result":"ok","num_turns":3,"session_id":"s-1"}"#,
+        ];
+        for line in lines {
+            let read = ReadLineResult {
+                bytes_read: line.len() + 1,
+                truncated: false,
+            };
+            let mut scanner = EventScanner::new(MAX_AGENT_MESSAGES_SIZE);
+            collector.process_line(line, &read, &mut scanner).await;
+        }
+
+        let result = collector.finish().await;
+        assert_eq!(result.timing.num_events, 2);
+        assert!(result.timing.first_event_ms.is_some());
+        assert_eq!(result.num_turns, Some(3));
+    }
+
+    #[test]
+    fn test_gzip_stream_round_trips_raw_lines() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let mut gzip = GzipStream::new();
+        gzip.write_line(br#"{"type":"system"}"#);
+        gzip.write_line(br#"{"type":"assistant"}"#);
+        let compressed = gzip.finish();
+        assert_eq!(compressed.uncompressed_bytes, 39);
+        assert!(!compressed.truncated);
+
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(compressed.gzip_base64)
+            .unwrap();
+        let mut jsonl = String::new();
+        GzDecoder::new(&data[..])
+            .read_to_string(&mut jsonl)
+            .unwrap();
+        assert_eq!(jsonl, "{\"type\":\"system\"}\n{\"type\":\"assistant\"}\n");
+    }
+
+    #[tokio::test]
+    async fn test_spill_file_keeps_raw_lines() {
+        let mut spill = SpillFile::create().await.unwrap();
+        spill.write_line(br#"{"type":"system"}"#).await.unwrap();
+        spill.write_line(br#"{"type":"assistant"}"#).await.unwrap();
+        let path = spill.finish().await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            contents,
+            "{\"type\":\"system\"}\n{\"type\":\"assistant\"}\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_line_with_limit_truncates_on_char_boundary() {
+        // "é" is two bytes; a 4-byte limit would otherwise split the second one
+        let input: &[u8] = "aéé\nnext\n".as_bytes();
+        let mut reader = BufReader::new(input);
+        let mut buf = Vec::new();
+
+        let read = read_line_with_limit(&mut reader, &mut buf, 4, None)
+            .await
+            .unwrap();
+        assert!(read.truncated);
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), "aé");
+
+        buf.clear();
+        read_line_with_limit(&mut reader, &mut buf, 4, None)
+            .await
+            .unwrap();
+        assert_eq!(trim_line_ending(&buf), b"next");
+    }
+
+    #[test]
+    fn test_trim_line_ending_handles_crlf() {
+        assert_eq!(trim_line_ending(b"{}\r\n"), b"{}");
+        assert_eq!(trim_line_ending(b"{}\n"), b"{}");
+        assert_eq!(trim_line_ending(b"{}"), b"{}");
+    }
+
+    #[tokio::test]
+    async fn test_read_line_with_limit_streams_oversized_line_into_scanner() {
+        let long_text = "x".repeat(64);
+        let input = format!(
+            "{{\"type\":\"assistant\",\"session_id\":\"s-big\",\"message\":{{\"content\":[{{\"type\":\"text\",\"text\":\"{}\"}}]}}}}\n{{}}\n",
+            long_text
+        );
+        let mut reader = BufReader::with_capacity(8, input.as_bytes());
+        let mut buf = Vec::new();
+        let mut scanner = EventScanner::new(MAX_AGENT_MESSAGES_SIZE);
+
+        let read = read_line_with_limit(&mut reader, &mut buf, 16, Some(&mut scanner))
+            .await
+            .unwrap();
+        assert!(read.truncated);
+        let scanned = scanner.finish().unwrap();
+        assert_eq!(scanned.session_id.as_deref(), Some("s-big"));
+        assert_eq!(scanned.texts, vec![long_text]);
+
+        buf.clear();
+        read_line_with_limit(&mut reader, &mut buf, 16, None)
+            .await
+            .unwrap();
+        assert_eq!(trim_line_ending(&buf), b"{}");
+    }
+
+    #[tokio::test]
+    async fn test_extended_deadline_lets_a_slow_run_finish() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = "2b7c9e14-6f3a-4d85-b0c2-9e1f4a7d3c68";
+        let script = crate::fake_cli::FakeCli::new()
+            .event(serde_json::json!({"type": "system", "subtype": "init", "session_id": session}))
+            .sleep(std::time::Duration::from_secs(2))
+            .result(session, "done")
+            .write(dir.path(), "slow")
+            .unwrap();
+
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let opts = Options {
+            working_dir: dir.path().to_path_buf(),
+            binary: Some(script),
+            timeout_secs: Some(1),
+            events: Some(events),
+            ..Default::default()
+        };
+        let extend = async {
+            while let Some(event) = received.recv().await {
+                if let RunEvent::SessionStarted { session_id } = event {
+                    let extended = context::current()
+                        .deadlines()
+                        .extend(&session_id, std::time::Duration::from_secs(5))
+                        .unwrap();
+                    assert_eq!(extended.timeout_secs, 6);
+                }
+            }
+        };
+        let (result, ()) = tokio::join!(run(opts), extend);
+        let result = result.unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.session_id, session);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_gets_a_temp_dir_removed_afterwards() {
+        let dir = tempfile::tempdir().unwrap();
+        let tmpdir_log = dir.path().join("tmpdir.log");
+        let script = crate::fake_cli::FakeCli::new()
+            .write_file("$TMPDIR/scratch", "")
+            .log_env("TMPDIR", &tmpdir_log)
+            .result("s", "")
+            .write(dir.path(), "tmpdir")
+            .unwrap();
+
+        let result = run(Options {
+            working_dir: dir.path().to_path_buf(),
+            binary: Some(script),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        let logged = std::fs::read_to_string(&tmpdir_log).unwrap();
+        let run_dir = PathBuf::from(logged.trim_end());
+        assert!(
+            run_dir.starts_with(std::env::temp_dir().join("claude-mcp-rs").join("runs")),
+            "{}",
+            run_dir.display()
+        );
+        assert!(!run_dir.exists());
+    }
 }
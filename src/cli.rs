@@ -0,0 +1,627 @@
+//! Command-line interface of the `claude-mcp-rs` binary.
+//!
+//! Without a subcommand the binary serves MCP over stdio, which is what MCP clients launch.
+//! The other subcommands exercise the same config and CLI wrapper from a terminal. Global
+//! flags override the config file so launcher definitions can adjust settings inline.
+
+use crate::context::ServerContext;
+#[cfg(unix)]
+use crate::daemon;
+use crate::server::{self, ClaudeArgs, ClaudeServer};
+use crate::tenants::Tenant;
+use crate::{
+    capabilities, claude, config_file, doctor, idle, journal, logging, metrics, orphans, run_dir,
+    scheduler, tenants,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rmcp::transport::streamable_http_server::{
+    session::local::LocalSessionManager, StreamableHttpServerConfig, StreamableHttpService,
+};
+use rmcp::{transport::stdio, ServiceExt};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Address the HTTP transport listens on when `--bind` is not given
+const DEFAULT_BIND: &str = "127.0.0.1:8080";
+
+#[derive(Debug, Parser)]
+#[command(version, about = "MCP server wrapping the Claude CLI")]
+pub struct Cli {
+    #[command(flatten)]
+    pub overrides: Overrides,
+    /// Transport settings used when no subcommand is given
+    #[command(flatten)]
+    pub serve: ServeArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Settings that take precedence over the config file
+#[derive(Debug, Args)]
+pub struct Overrides {
+    /// Config file to load instead of `CLAUDE_MCP_CONFIG_PATH` or `./claude-mcp.config.json`
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Maximum runtime per call in seconds
+    #[arg(long, global = true)]
+    pub timeout_secs: Option<u64>,
+    /// Extra Claude CLI flag appended after the configured `additional_args`; repeatable
+    #[arg(
+        long = "additional-arg",
+        global = true,
+        value_name = "ARG",
+        allow_hyphen_values = true
+    )]
+    pub additional_args: Vec<String>,
+}
+
+impl From<Overrides> for claude::ConfigOverrides {
+    fn from(overrides: Overrides) -> Self {
+        Self {
+            config_path: overrides.config,
+            timeout_secs: overrides.timeout_secs,
+            additional_args: overrides.additional_args,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ServeArgs {
+    /// How MCP clients connect
+    #[arg(long, value_enum, default_value_t = Transport::Stdio)]
+    pub transport: Transport,
+    /// Listen address for `--transport http` [default: 127.0.0.1:8080]
+    #[arg(long)]
+    pub bind: Option<SocketAddr>,
+    /// Detach into the background after binding (HTTP only, Unix only)
+    #[arg(long)]
+    pub daemon: bool,
+    /// PID file written with `--daemon` [default: <temp>/claude-mcp-rs/server.pid]
+    #[arg(long, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+    /// Exit once no tool call has arrived for this many seconds and no run is active
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub idle_exit_secs: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct PidFileArgs {
+    /// PID file of the daemon [default: <temp>/claude-mcp-rs/server.pid]
+    #[arg(long, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    /// MCP over stdin/stdout, for clients that launch the server
+    Stdio,
+    /// Streamable HTTP at `http://<bind>/mcp`, for clients that connect to a running server
+    Http,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve MCP (the default)
+    Serve(ServeArgs),
+    /// Run one prompt through the Claude CLI and print the result as JSON
+    Run(RunArgs),
+    /// Check the config, CLI binary, and writable directories; exits nonzero on problems
+    Doctor,
+    /// Create or check `claude-mcp.config.json`
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Stop a server started with `--daemon`
+    Stop(PidFileArgs),
+    /// Report whether a server started with `--daemon` is running; exits 3 if not
+    Status(PidFileArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Write a commented starter config
+    Init {
+        /// Where to write it; defaults to `--config`, `CLAUDE_MCP_CONFIG_PATH`, or
+        /// `./claude-mcp.config.json`
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// Replace an existing file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check a config for unknown options, managed flags, and out-of-range values
+    Validate {
+        /// Config to check; defaults to the one the server would load
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Working directory for the run
+    #[arg(long, default_value = ".")]
+    pub dir: PathBuf,
+    /// Resume this session instead of starting a new one
+    #[arg(long)]
+    pub session_id: Option<String>,
+    /// Profile from the config's `profiles`
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Model alias or full model name
+    #[arg(long)]
+    pub model: Option<String>,
+    /// JSON Schema the answer must match; the parsed answer is printed in `answer`
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub response_schema: Option<serde_json::Value>,
+    /// Print the command line, environment, and timeout the run would use without starting it
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Instruction sent to Claude
+    pub prompt: String,
+}
+
+/// Serve MCP until the client disconnects (stdio) or the process is stopped (HTTP). The
+/// listener is bound, and a daemon detached, before logging and the runtime start threads.
+pub fn serve(args: ServeArgs) -> Result<()> {
+    let listener = match args.transport {
+        Transport::Stdio => {
+            if args.bind.is_some() || args.daemon {
+                bail!("--bind and --daemon require --transport http");
+            }
+            None
+        }
+        Transport::Http => {
+            let addr = match args.bind {
+                Some(addr) => addr,
+                None => DEFAULT_BIND.parse()?,
+            };
+            let listener = std::net::TcpListener::bind(addr)
+                .with_context(|| format!("failed to bind {}", addr))?;
+            Some(listener)
+        }
+    };
+
+    #[cfg(unix)]
+    let _pid_file = match &listener {
+        Some(listener) if args.daemon => {
+            let pid_file = args.pid_file.unwrap_or_else(daemon::default_pid_file);
+            let url = format!("http://{}/mcp", listener.local_addr()?);
+            Some(daemon::detach(&pid_file, &url)?)
+        }
+        _ => None,
+    };
+    #[cfg(not(unix))]
+    if args.daemon {
+        bail!("--daemon is only supported on Unix");
+    }
+
+    let idle_exit = args.idle_exit_secs.map(Duration::from_secs);
+    logging::init();
+    let runtime = tokio::runtime::Runtime::new()?;
+    let served = runtime.block_on(async {
+        tokio::task::spawn_blocking(orphans::clean_up);
+        tokio::task::spawn_blocking(run_dir::sweep);
+        tokio::spawn(journal::recover());
+        if let Some(addr) = claude::default_metrics_addr() {
+            tokio::spawn(metrics::serve(addr));
+        }
+        if let Some(file) = claude::default_metrics_file() {
+            tokio::spawn(metrics::write_snapshots(file));
+        }
+        tokio::spawn(scheduler::run());
+        match listener {
+            None => {
+                capabilities::set_transport("stdio");
+                serve_stdio(idle_exit).await
+            }
+            Some(listener) => {
+                capabilities::set_transport("http");
+                serve_http(listener, idle_exit).await
+            }
+        }
+    });
+    // After an idle exit, the blocking stdin read would hold a regular shutdown until the
+    // client writes again
+    runtime.shutdown_background();
+    served
+}
+
+/// Resolve after `idle_exit` without tool calls, or never when it is not set
+async fn idle_timeout(idle_exit: Option<Duration>) {
+    match idle_exit {
+        Some(limit) => idle::wait(limit).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn serve_stdio(idle_exit: Option<Duration>) -> Result<()> {
+    // Create an instance of our Claude server
+    let server = ClaudeServer::new();
+    let service = server.clone().serve(stdio()).await.inspect_err(|e| {
+        tracing::error!("serving error: {:?}", e);
+    })?;
+
+    #[cfg(unix)]
+    {
+        let peer = service.peer().clone();
+        tokio::spawn(on_hangup(move || {
+            let (server, peer) = (server.clone(), peer.clone());
+            async move { server.reload_config(&peer).await }
+        }));
+    }
+
+    let cancel = service.cancellation_token();
+    tokio::spawn(async move {
+        idle_timeout(idle_exit).await;
+        cancel.cancel();
+    });
+
+    service.waiting().await?;
+    Ok(())
+}
+
+/// Serve streamable HTTP at `/mcp`, one server session per client session, until Ctrl-C,
+/// SIGTERM, or `idle_exit` without tool calls
+async fn serve_http(listener: std::net::TcpListener, idle_exit: Option<Duration>) -> Result<()> {
+    let config = StreamableHttpServerConfig::default();
+    let sessions = config.cancellation_token.clone();
+    let configured = claude::tenants();
+    let router = if configured.is_empty() {
+        let service = StreamableHttpService::new(
+            || Ok(ClaudeServer::new()),
+            LocalSessionManager::default().into(),
+            config,
+        );
+        #[cfg(unix)]
+        tokio::spawn(on_hangup(|| async {
+            claude::reload_config();
+            if !claude::tenants().is_empty() {
+                tracing::warn!("tenants were added to the config; restart to serve them");
+            }
+        }));
+        axum::Router::new().nest_service("/mcp", service)
+    } else {
+        let services: Arc<TenantServices> = Arc::new(RwLock::new(tenant_services(
+            &configured,
+            &HashMap::new(),
+            &config,
+        )));
+        // Tokens, budgets, and allowed directories take effect on reload, so a revoked
+        // token stops working without a restart
+        #[cfg(unix)]
+        tokio::spawn(on_hangup({
+            let services = Arc::clone(&services);
+            move || {
+                claude::reload_config();
+                let mut services = services.write().unwrap_or_else(|e| e.into_inner());
+                let reloaded = tenant_services(&claude::tenants(), &services, &config);
+                tracing::info!(tenants = reloaded.len(), "tenants reloaded");
+                *services = reloaded;
+                std::future::ready(())
+            }
+        }));
+        axum::Router::new()
+            .route("/mcp", axum::routing::any(serve_tenant))
+            .with_state(services)
+    };
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    tracing::info!("serving MCP at http://{}/mcp", listener.local_addr()?);
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            tokio::select! {
+                _ = shutdown_signal() => {}
+                _ = idle_timeout(idle_exit) => {}
+            }
+            tracing::info!("shutting down");
+            // Open event streams would otherwise hold the graceful shutdown forever
+            sessions.cancel();
+        })
+        .await?;
+    Ok(())
+}
+
+/// A tenant being served: its context and the MCP service of its requests. Each tenant has
+/// its own service, so MCP sessions cannot be reached with another's token.
+struct ServedTenant {
+    name: String,
+    /// The config entry it was built from
+    tenant: Tenant,
+    context: ServerContext,
+    service: StreamableHttpService<ClaudeServer, LocalSessionManager>,
+}
+
+/// Served tenants by [`tenants::token_key`], rebuilt from the config on SIGHUP
+type TenantServices = RwLock<HashMap<String, Arc<ServedTenant>>>;
+
+/// Serve the `configured` tenants. One whose entry is unchanged in `previous` keeps its
+/// context and MCP sessions and has its config reloaded; the others are built anew, and
+/// tenants no longer configured are dropped.
+fn tenant_services(
+    configured: &BTreeMap<String, Tenant>,
+    previous: &HashMap<String, Arc<ServedTenant>>,
+    config: &StreamableHttpServerConfig,
+) -> HashMap<String, Arc<ServedTenant>> {
+    configured
+        .iter()
+        .map(|(name, tenant)| {
+            let before = previous.values().find(|served| served.name == *name);
+            let served = match before {
+                Some(served) if served.tenant == *tenant => {
+                    served.context.reload_config();
+                    Arc::clone(served)
+                }
+                _ => {
+                    let context =
+                        tenants::context(name, tenant, before.map(|served| &served.context));
+                    let service = StreamableHttpService::new(
+                        {
+                            let context = context.clone();
+                            move || Ok(ClaudeServer::with_context(context.clone()))
+                        },
+                        LocalSessionManager::default().into(),
+                        config.clone(),
+                    );
+                    Arc::new(ServedTenant {
+                        name: name.clone(),
+                        tenant: tenant.clone(),
+                        context,
+                        service,
+                    })
+                }
+            };
+            (tenants::token_key(&tenant.token), served)
+        })
+        .collect()
+}
+
+/// Hand a request to the service of the tenant its bearer token names
+async fn serve_tenant(
+    axum::extract::State(services): axum::extract::State<Arc<TenantServices>>,
+    request: axum::extract::Request,
+) -> axum::response::Response {
+    use axum::http::{header, StatusCode};
+    use axum::response::IntoResponse;
+
+    let served = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| {
+            let services = services.read().unwrap_or_else(|e| e.into_inner());
+            services.get(&tenants::token_key(token.trim())).cloned()
+        });
+    match served {
+        Some(served) => served.service.handle(request).await.into_response(),
+        None => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "missing or unknown bearer token\n",
+        )
+            .into_response(),
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Reload the config file whenever the process receives SIGHUP
+#[cfg(unix)]
+async fn on_hangup<F: Future<Output = ()>>(mut reload: impl FnMut() -> F) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::warn!("failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        tracing::info!("SIGHUP received, reloading config");
+        reload().await;
+    }
+}
+
+/// Run one prompt and print the tool response to stdout; fails when the run does
+pub async fn run(args: RunArgs) -> Result<ExitCode> {
+    let tool_args = ClaudeArgs {
+        prompt: args.prompt,
+        session_id: args.session_id,
+        profile: args.profile,
+        model: args.model,
+        response_schema: args.response_schema,
+        dry_run: Some(args.dry_run),
+        ..Default::default()
+    };
+    let (success, json) = server::run_once(tool_args, &args.dir)
+        .await
+        .map_err(|e| anyhow!("{}", e.message))?;
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn parse_json(raw: &str) -> Result<serde_json::Value, String> {
+    serde_json::from_str(raw).map_err(|e| e.to_string())
+}
+
+/// Stop the daemon recorded in the PID file; succeeds if none was running
+pub fn stop(args: PidFileArgs) -> Result<ExitCode> {
+    #[cfg(unix)]
+    {
+        let pid_file = args.pid_file.unwrap_or_else(daemon::default_pid_file);
+        match daemon::stop(&pid_file)? {
+            Some(pid) => println!("stopped (pid {})", pid),
+            None => println!("not running"),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = args;
+        bail!("stop is only supported on Unix")
+    }
+}
+
+/// Report whether the daemon recorded in the PID file is running; exit code 3 (the LSB
+/// init-script convention) when it is not
+pub fn status(args: PidFileArgs) -> Result<ExitCode> {
+    #[cfg(unix)]
+    {
+        let pid_file = args.pid_file.unwrap_or_else(daemon::default_pid_file);
+        match daemon::running(&pid_file) {
+            Some(recorded) => {
+                println!(
+                    "running (pid {}){}",
+                    recorded.pid,
+                    recorded
+                        .url
+                        .map(|url| format!(" at {}", url))
+                        .unwrap_or_default()
+                );
+                Ok(ExitCode::SUCCESS)
+            }
+            None => {
+                println!("not running");
+                Ok(ExitCode::from(3))
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = args;
+        bail!("status is only supported on Unix")
+    }
+}
+
+/// Print the preflight checks; fails when any of them does
+pub async fn doctor() -> ExitCode {
+    let checks = doctor::run_checks().await;
+    for check in &checks {
+        let status = if check.ok { "ok" } else { "FAIL" };
+        println!("[{:>4}] {}: {}", status, check.name, check.detail);
+    }
+    if checks.iter().all(|check| check.ok) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Run a `config` subcommand; `validate` fails on errors but not on warnings
+pub fn config(command: ConfigCommand) -> Result<ExitCode> {
+    let resolve = |path: Option<PathBuf>| {
+        path.or_else(claude::config_path)
+            .ok_or_else(|| anyhow!("cannot determine the config path; pass --path"))
+    };
+    match command {
+        ConfigCommand::Init { path, force } => {
+            let path = resolve(path)?;
+            config_file::init(&path, force).map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => {
+                    anyhow!(
+                        "{} already exists; pass --force to replace it",
+                        path.display()
+                    )
+                }
+                _ => anyhow!("failed to write {}: {}", path.display(), e),
+            })?;
+            println!("wrote {}", path.display());
+            Ok(ExitCode::SUCCESS)
+        }
+        ConfigCommand::Validate { path } => {
+            let path = resolve(path)?;
+            let issues = config_file::validate(&path).map_err(|e| anyhow!(e))?;
+            for issue in &issues {
+                let level = if issue.error { "error" } else { "warning" };
+                println!("{}: {}", level, issue.message);
+            }
+            if issues.iter().any(|issue| issue.error) {
+                return Ok(ExitCode::FAILURE);
+            }
+            println!("{} is valid", path.display());
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_subcommand_means_serve_and_run_takes_a_prompt() {
+        let cli = Cli::try_parse_from(["claude-mcp-rs"]).unwrap();
+        assert!(cli.command.is_none());
+
+        let cli = Cli::try_parse_from(["claude-mcp-rs", "run", "--dir", "/tmp", "fix the build"])
+            .unwrap();
+        let Some(Command::Run(args)) = cli.command else {
+            panic!("expected run subcommand");
+        };
+        assert_eq!(args.dir, PathBuf::from("/tmp"));
+        assert_eq!(args.prompt, "fix the build");
+
+        assert!(Cli::try_parse_from(["claude-mcp-rs", "run"]).is_err());
+    }
+
+    #[test]
+    fn test_override_flags_are_global_and_repeatable() {
+        let cli = Cli::try_parse_from([
+            "claude-mcp-rs",
+            "--transport",
+            "http",
+            "--bind",
+            "0.0.0.0:9000",
+            "--additional-arg",
+            "--model",
+            "--additional-arg",
+            "opus",
+        ])
+        .unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.serve.transport, Transport::Http);
+        assert_eq!(cli.serve.bind, Some("0.0.0.0:9000".parse().unwrap()));
+        assert_eq!(cli.overrides.additional_args, vec!["--model", "opus"]);
+
+        let cli = Cli::try_parse_from([
+            "claude-mcp-rs",
+            "doctor",
+            "--config",
+            "/etc/claude-mcp.json",
+            "--timeout-secs",
+            "120",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.overrides.config,
+            Some(PathBuf::from("/etc/claude-mcp.json"))
+        );
+        assert_eq!(cli.overrides.timeout_secs, Some(120));
+    }
+}
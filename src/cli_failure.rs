@@ -0,0 +1,186 @@
+//! Actionable messages for known Claude CLI failures.
+//!
+//! A nonzero exit says little on its own ("exit code: Some(1)"). The CLI's stderr usually
+//! names the cause, so known signatures (expired credentials, an unknown session, a rejected
+//! flag, an outdated CLI) and the exit statuses of wrappers and signals are mapped to a
+//! message that tells the caller what to do about it.
+
+use std::process::ExitStatus;
+
+/// A recognized cause of a failed CLI run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CliFailure {
+    AuthExpired,
+    SessionNotFound,
+    InvalidFlag,
+    UpdateRequired,
+    /// Exit status 127 from a wrapper (sandbox, remote shell) that could not find the CLI
+    CommandNotFound,
+    /// Exit status 126: the CLI was found but could not be executed
+    NotExecutable,
+    /// Stopped by SIGKILL, usually the OOM killer or a resource limit
+    Killed,
+}
+
+/// Lowercase stderr fragments that identify a failure, checked in order
+const SIGNATURES: [(CliFailure, &[&str]); 4] = [
+    (
+        CliFailure::SessionNotFound,
+        &["no conversation found", "session not found"],
+    ),
+    (
+        CliFailure::AuthExpired,
+        &[
+            "invalid api key",
+            "please run /login",
+            "oauth token has expired",
+            "authentication_error",
+            "not logged in",
+        ],
+    ),
+    (
+        CliFailure::UpdateRequired,
+        &[
+            "update required",
+            "please update",
+            "no longer supported",
+            "minimum required version",
+        ],
+    ),
+    (
+        CliFailure::InvalidFlag,
+        &["unknown option", "unexpected argument", "invalid value for"],
+    ),
+];
+
+impl CliFailure {
+    /// The failure behind a run that exited with `status` after writing `stderr`
+    pub(crate) fn classify(status: &ExitStatus, stderr: &str) -> Option<Self> {
+        if status.success() {
+            return None;
+        }
+        let stderr = stderr.to_ascii_lowercase();
+        let signature = SIGNATURES
+            .iter()
+            .find(|(_, markers)| markers.iter().any(|marker| stderr.contains(marker)))
+            .map(|(failure, _)| *failure);
+        signature.or(match status.code() {
+            Some(127) => Some(CliFailure::CommandNotFound),
+            Some(126) => Some(CliFailure::NotExecutable),
+            Some(_) => None,
+            None => killed_by_sigkill(status).then_some(CliFailure::Killed),
+        })
+    }
+
+    /// Whether `text` carries one of this failure's stderr signatures
+    pub(crate) fn is_named_in(self, text: &str) -> bool {
+        let text = text.to_ascii_lowercase();
+        SIGNATURES
+            .iter()
+            .filter(|(failure, _)| *failure == self)
+            .any(|(_, markers)| markers.iter().any(|marker| text.contains(marker)))
+    }
+
+    pub(crate) fn message(self) -> &'static str {
+        match self {
+            CliFailure::AuthExpired => {
+                "the Claude CLI is not logged in or its credentials expired; run `claude` and \
+                 `/login` as the user the server runs as, or set ANTHROPIC_API_KEY"
+            }
+            CliFailure::SessionNotFound => {
+                "the session to resume was not found; it may have expired or belong to another \
+                 working directory. Omit SESSION_ID to start a new session"
+            }
+            CliFailure::InvalidFlag => {
+                "the Claude CLI rejected a command-line flag; check `additional_args` and \
+                 profile `args` against `claude --help` for the installed version"
+            }
+            CliFailure::UpdateRequired => {
+                "the installed Claude CLI is too old; update it with `claude update`"
+            }
+            CliFailure::CommandNotFound => {
+                "the Claude CLI was not found by the wrapper that launches it; check the \
+                 binary path and PATH for the sandbox or remote host"
+            }
+            CliFailure::NotExecutable => {
+                "the Claude CLI could not be executed; check the binary's permissions"
+            }
+            CliFailure::Killed => {
+                "the Claude CLI was killed (SIGKILL), often by the OOM killer or \
+                 `resource_limits`"
+            }
+        }
+    }
+}
+
+/// `ClaudeResult.error` for a run that exited with `status`
+pub(crate) fn describe(status: &ExitStatus, stderr: &str) -> String {
+    match CliFailure::classify(status, stderr) {
+        Some(failure) => match status.code() {
+            Some(code) => format!("{} (exit code {})", failure.message(), code),
+            None => failure.message().to_string(),
+        },
+        None => format!("claude command failed with exit code: {:?}", status.code()),
+    }
+}
+
+#[cfg(unix)]
+fn killed_by_sigkill(status: &ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal() == Some(libc::SIGKILL)
+}
+
+#[cfg(not(unix))]
+fn killed_by_sigkill(_status: &ExitStatus) -> bool {
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn test_stderr_signatures_and_statuses_are_classified() {
+        let classify = CliFailure::classify;
+        assert_eq!(
+            classify(&exited(1), "No conversation found with session ID: 1234"),
+            Some(CliFailure::SessionNotFound)
+        );
+        assert_eq!(
+            classify(&exited(1), "Invalid API key · Please run /login"),
+            Some(CliFailure::AuthExpired)
+        );
+        assert_eq!(
+            classify(&exited(1), "error: unknown option '--bogus'"),
+            Some(CliFailure::InvalidFlag)
+        );
+        assert_eq!(
+            classify(&exited(127), ""),
+            Some(CliFailure::CommandNotFound)
+        );
+        assert_eq!(
+            classify(&ExitStatus::from_raw(libc::SIGKILL), ""),
+            Some(CliFailure::Killed)
+        );
+        assert_eq!(classify(&exited(1), "something else"), None);
+        assert_eq!(classify(&exited(0), "please run /login"), None);
+    }
+
+    #[test]
+    fn test_unknown_failures_keep_the_exit_code_message() {
+        assert_eq!(
+            describe(&exited(2), ""),
+            "claude command failed with exit code: Some(2)"
+        );
+        assert!(
+            describe(&exited(1), "Update required: 1.0.3 is no longer supported")
+                .ends_with("`claude update` (exit code 1)")
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Beta features of the Anthropic API that runs opt into through the CLI's `--betas`.
+//!
+//! Features are named under `cli_features` at the top level of `claude-mcp.config.json`, in
+//! a profile, or per call with `CLI_FEATURES`; a run gets all three. Only the beta headers
+//! listed in [`KNOWN`] are accepted, so a typo fails the call instead of being sent along
+//! and ignored by the API. The CLI passes betas only when it authenticates with an API key.
+
+use rmcp::ErrorData as McpError;
+use serde_json::json;
+
+/// Beta headers the CLI can send
+pub(crate) const KNOWN: [&str; 10] = [
+    "code-execution-2025-05-22",
+    "context-1m-2025-08-07",
+    "context-management-2025-06-27",
+    "extended-cache-ttl-2025-04-11",
+    "files-api-2025-04-14",
+    "fine-grained-tool-streaming-2025-05-14",
+    "interleaved-thinking-2025-05-14",
+    "mcp-client-2025-04-04",
+    "output-128k-2025-02-19",
+    "token-efficient-tools-2025-02-19",
+];
+
+/// Why `feature` cannot be passed, if it cannot
+pub(crate) fn problem(feature: &str) -> Option<String> {
+    (!KNOWN.contains(&feature)).then(|| {
+        format!(
+            "unknown feature '{}'; known features: [{}]",
+            feature,
+            KNOWN.join(", ")
+        )
+    })
+}
+
+/// CLI flags enabling `features`, each once
+pub(crate) fn args(features: &[String]) -> Result<Vec<String>, McpError> {
+    let mut args: Vec<String> = Vec::new();
+    for feature in features {
+        if let Some(problem) = problem(feature) {
+            return Err(McpError::invalid_params(
+                format!("CLI_FEATURES: {}", problem),
+                Some(json!({ "reason": "unknown_cli_feature", "feature": feature })),
+            ));
+        }
+        // One value per flag: a bare `--betas` would take the prompt as another beta
+        let arg = format!("--betas={}", feature);
+        if !args.contains(&arg) {
+            args.push(arg);
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_features_become_flags_and_unknown_ones_are_rejected() {
+        let features = [
+            "context-1m-2025-08-07".to_string(),
+            "interleaved-thinking-2025-05-14".to_string(),
+            "context-1m-2025-08-07".to_string(),
+        ];
+        assert_eq!(
+            args(&features).unwrap(),
+            [
+                "--betas=context-1m-2025-08-07",
+                "--betas=interleaved-thinking-2025-05-14"
+            ]
+        );
+
+        let err = args(&["context-2m".to_string()]).unwrap_err();
+        assert!(
+            err.message
+                .starts_with("CLI_FEATURES: unknown feature 'context-2m'; known features: ["),
+            "{}",
+            err.message
+        );
+        assert_eq!(err.data.unwrap()["reason"], "unknown_cli_feature");
+    }
+}
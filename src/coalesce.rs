@@ -0,0 +1,256 @@
+//! Coalescing of identical runs that are in flight at the same time.
+//!
+//! When a caller asks for a new-session run with the same prompt, working directory, and
+//! options as a run that is still going, it waits for that run and gets a copy of its
+//! result instead of starting a second CLI process. Both callers then share the session. If
+//! the first caller goes away before the run finishes, a waiting caller starts it again.
+
+use crate::claude::{ClaudeResult, Options};
+use crate::context::{self, ServerContext};
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// Final outcome of a run, shared with every caller waiting on it
+type Outcome = Option<Result<ClaudeResult, String>>;
+
+/// Runs in flight in one server context, by [`request_key`]
+#[derive(Default)]
+pub(crate) struct InFlightRuns(Mutex<BTreeMap<String, watch::Receiver<Outcome>>>);
+
+/// Identity of a new-session run: every option that changes its output or how it executes,
+/// and `extra`. `None` for runs that resume a session, which must never be merged.
+pub(crate) fn request_key(opts: &Options, extra: &[u8]) -> Option<String> {
+    // Destructured in full so a new option has to be placed here or deliberately left out
+    let Options {
+        prompt,
+        working_dir,
+        session_id,
+        additional_args,
+        timeout_secs,
+        spill_all_messages,
+        execution_mode,
+        // The pool only decides whether a process is started ahead of time
+        warm_pool_size: _,
+        resource_limits,
+        priority,
+        sandbox,
+        remote,
+        // Progress events go to whichever caller runs the CLI
+        events: _,
+        debug_capture,
+        include_thinking,
+        screen_injections,
+        binary,
+        env,
+        sanitize_text,
+        truncation,
+        max_output_bytes,
+        compress_all_messages,
+        // Only decides whether a finished result may be reused
+        response_cache: _,
+        record_to,
+        replay_from,
+        parser_limits,
+        max_skipped_lines,
+    } = opts;
+    if session_id.is_some() {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    let mut part = |bytes: &[u8]| {
+        // Length prefixes keep ("ab", "c") and ("a", "bc") apart
+        hasher.update((bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+    part(prompt.as_bytes());
+    part(format!("{:?}", binary).as_bytes());
+    for arg in additional_args {
+        part(arg.as_bytes());
+    }
+    for (name, value) in env {
+        part(name.as_bytes());
+        part(value.as_bytes());
+    }
+    part(working_dir.to_string_lossy().as_bytes());
+    part(format!("{:?} {:?}", truncation, max_output_bytes).as_bytes());
+    part(
+        format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?}",
+            timeout_secs, execution_mode, resource_limits, priority, sandbox, remote
+        )
+        .as_bytes(),
+    );
+    part(
+        format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?}",
+            include_thinking,
+            screen_injections,
+            sanitize_text,
+            debug_capture,
+            spill_all_messages,
+            compress_all_messages
+        )
+        .as_bytes(),
+    );
+    part(
+        format!(
+            "{:?} {:?} {:?} {:?}",
+            record_to, replay_from, parser_limits, max_skipped_lines
+        )
+        .as_bytes(),
+    );
+    part(extra);
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+pub(crate) enum Slot {
+    /// No identical run is in flight; the caller runs it and reports through [`Leader`]
+    Leader(Leader),
+    /// An identical run is in flight; the caller waits for it
+    Follower(Follower),
+}
+
+/// Claim the run identified by `key`, or join the caller already running it
+pub(crate) fn join(key: &str) -> Slot {
+    let context = context::current();
+    let mut runs = context
+        .in_flight()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(outcome) = runs.get(key) {
+        return Slot::Follower(Follower(outcome.clone()));
+    }
+    let (sender, outcome) = watch::channel(None);
+    runs.insert(key.to_string(), outcome);
+    drop(runs);
+    Slot::Leader(Leader {
+        key: key.to_string(),
+        sender,
+        context,
+    })
+}
+
+/// Held by the caller executing a run; releases the key when dropped
+pub(crate) struct Leader {
+    key: String,
+    sender: watch::Sender<Outcome>,
+    /// Where the run is in flight
+    context: ServerContext,
+}
+
+impl Leader {
+    /// Hand the outcome to every waiting caller
+    pub(crate) fn finish(self, outcome: &Result<ClaudeResult>) {
+        let shared = match outcome {
+            Ok(result) => Ok(result.clone()),
+            Err(err) => Err(format!("{:#}", err)),
+        };
+        let _ = self.sender.send(Some(shared));
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        let mut runs = self
+            .context
+            .in_flight()
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        runs.remove(&self.key);
+    }
+}
+
+pub(crate) struct Follower(watch::Receiver<Outcome>);
+
+impl Follower {
+    /// Outcome of the run being waited on, or `None` if its caller went away first
+    pub(crate) async fn wait(mut self) -> Option<Result<ClaudeResult>> {
+        let outcome = self.0.wait_for(Option::is_some).await.ok()?;
+        outcome
+            .clone()
+            .map(|shared| shared.map_err(anyhow::Error::msg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(prompt: &str) -> Options {
+        Options {
+            prompt: prompt.to_string(),
+            working_dir: std::path::PathBuf::from("/repo"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_only_new_session_runs_have_a_key() {
+        let key = request_key(&options("explain"), &[]).unwrap();
+        assert_eq!(request_key(&options("explain"), &[]), Some(key.clone()));
+        assert_ne!(request_key(&options("explain this"), &[]), Some(key));
+
+        let resumed = Options {
+            session_id: Some("11111111-1111-1111-1111-111111111111".to_string()),
+            ..options("explain")
+        };
+        assert_eq!(request_key(&resumed, &[]), None);
+    }
+
+    #[test]
+    fn test_options_that_change_the_output_change_the_key() {
+        let plain = request_key(&options("explain"), &[]);
+        let thinking = Options {
+            include_thinking: true,
+            ..options("explain")
+        };
+        assert_ne!(request_key(&thinking, &[]), plain);
+        let compressed = Options {
+            compress_all_messages: true,
+            ..options("explain")
+        };
+        assert_ne!(request_key(&compressed, &[]), plain);
+        let pooled = Options {
+            warm_pool_size: 2,
+            ..options("explain")
+        };
+        assert_eq!(request_key(&pooled, &[]), plain);
+    }
+
+    #[tokio::test]
+    async fn test_followers_share_the_leader_outcome() {
+        let Slot::Leader(leader) = join("shared") else {
+            panic!("first caller leads");
+        };
+        let Slot::Follower(follower) = join("shared") else {
+            panic!("second caller follows");
+        };
+        let waiting = tokio::spawn(follower.wait());
+        leader.finish(&Ok(ClaudeResult {
+            success: true,
+            agent_messages: "done".to_string(),
+            ..Default::default()
+        }));
+        let shared = waiting.await.unwrap().unwrap().unwrap();
+        assert_eq!(shared.agent_messages, "done");
+        assert!(matches!(join("shared"), Slot::Leader(_)), "key is released");
+    }
+
+    #[tokio::test]
+    async fn test_followers_retry_when_the_leader_goes_away() {
+        let Slot::Leader(leader) = join("abandoned") else {
+            panic!("first caller leads");
+        };
+        let Slot::Follower(follower) = join("abandoned") else {
+            panic!("second caller follows");
+        };
+        drop(leader);
+        assert!(follower.wait().await.is_none());
+        assert!(matches!(join("abandoned"), Slot::Leader(_)));
+    }
+}
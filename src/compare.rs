@@ -0,0 +1,298 @@
+//! Side-by-side comparison of two sessions for `claude_compare_runs`.
+//!
+//! Both sessions are read from their transcripts, so any two runs can be compared, e.g. the
+//! same task given to two models or with two prompts. Each side is summarized as its final
+//! answer, models, turns, and actions, and the file edits it made (`Edit`, `MultiEdit`, and
+//! `Write` inputs rendered as unified diffs) are matched up per file.
+
+use crate::actions::ActionSummary;
+use serde::Serialize;
+use serde_json::Value;
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Bytes of edits kept per file and side
+const MAX_EDIT_BYTES: usize = 16 * 1024;
+
+/// What one session did
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct RunSummary {
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    /// Assistant messages in the transcript
+    pub turns: usize,
+    /// Text of the last assistant message that had any
+    pub answer: String,
+    #[serde(skip_serializing_if = "ActionSummary::is_empty")]
+    pub actions: ActionSummary,
+    /// Unified diffs of the edits made to each file, in order
+    #[serde(skip)]
+    edits: BTreeMap<String, String>,
+}
+
+/// Which sessions changed a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ChangedIn {
+    A,
+    B,
+    Both,
+}
+
+/// A file changed by either session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct FileComparison {
+    pub path: String,
+    pub changed_in: ChangedIn,
+    /// Whether both sessions made the same edits
+    pub same_edits: bool,
+    /// Edits of `a`, unless both made the same ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edits_a: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edits_b: Option<String>,
+}
+
+/// Outcome of `claude_compare_runs`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Comparison {
+    pub a: RunSummary,
+    pub b: RunSummary,
+    pub same_answer: bool,
+    /// Line diff from `a`'s answer to `b`'s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_diff: Option<String>,
+    pub files: Vec<FileComparison>,
+}
+
+/// Summarize the transcript of `session_id` at `path`
+pub(crate) fn summarize(session_id: &str, path: &Path) -> Result<RunSummary, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    let mut summary = RunSummary {
+        session_id: session_id.to_string(),
+        ..Default::default()
+    };
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        if let Ok(event) = serde_json::from_str::<Value>(&line) {
+            summary.record(&event);
+        }
+    }
+    Ok(summary)
+}
+
+impl RunSummary {
+    fn record(&mut self, event: &Value) {
+        let message = &event["message"];
+        let Some(content) = message["content"].as_array() else {
+            return;
+        };
+        match event["type"].as_str() {
+            Some("assistant") => {
+                self.turns += 1;
+                if let Some(model) = message["model"].as_str() {
+                    if !self.models.iter().any(|m| m == model) {
+                        self.models.push(model.to_string());
+                    }
+                }
+                let text: Vec<&str> = content
+                    .iter()
+                    .filter(|block| block["type"] == "text")
+                    .filter_map(|block| block["text"].as_str())
+                    .collect();
+                if !text.is_empty() {
+                    self.answer = text.join("\n");
+                }
+                for block in content.iter().filter(|block| block["type"] == "tool_use") {
+                    let name = block["name"].as_str().unwrap_or_default();
+                    let input = &block["input"];
+                    self.actions.record_tool_use(
+                        block["id"].as_str().unwrap_or_default(),
+                        name,
+                        input,
+                    );
+                    self.record_edit(name, input);
+                }
+            }
+            Some("user") => {
+                for block in content
+                    .iter()
+                    .filter(|block| block["type"] == "tool_result")
+                {
+                    self.actions.record_tool_result(
+                        block["tool_use_id"].as_str().unwrap_or_default(),
+                        block["is_error"].as_bool().unwrap_or(false),
+                        block["content"].as_str(),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn record_edit(&mut self, tool: &str, input: &Value) {
+        let Some(path) = input["file_path"].as_str() else {
+            return;
+        };
+        let str_of = |value: &Value, key: &str| value[key].as_str().unwrap_or_default().to_string();
+        let changes: Vec<(String, String)> = match tool {
+            "Edit" => vec![(str_of(input, "old_string"), str_of(input, "new_string"))],
+            "MultiEdit" => input["edits"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|edit| (str_of(edit, "old_string"), str_of(edit, "new_string")))
+                .collect(),
+            "Write" => vec![(String::new(), str_of(input, "content"))],
+            _ => return,
+        };
+        let edits = self.edits.entry(path.to_string()).or_default();
+        for (old, new) in changes {
+            let diff = TextDiff::from_lines(old.as_str(), new.as_str())
+                .unified_diff()
+                .header(path, path)
+                .to_string();
+            edits.push_str(&diff);
+        }
+    }
+}
+
+/// Compare two summarized sessions
+pub(crate) fn compare(a: RunSummary, b: RunSummary) -> Comparison {
+    let mut paths: Vec<&String> = a.edits.keys().chain(b.edits.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    let files = paths
+        .into_iter()
+        .map(|path| {
+            let (edits_a, edits_b) = (a.edits.get(path), b.edits.get(path));
+            let same_edits = edits_a == edits_b;
+            let shown =
+                |edits: Option<&String>| edits.filter(|_| !same_edits).map(|edits| capped(edits));
+            FileComparison {
+                path: path.clone(),
+                changed_in: match (edits_a, edits_b) {
+                    (Some(_), None) => ChangedIn::A,
+                    (None, Some(_)) => ChangedIn::B,
+                    _ => ChangedIn::Both,
+                },
+                same_edits,
+                edits_a: shown(edits_a),
+                edits_b: shown(edits_b),
+            }
+        })
+        .collect();
+    let same_answer = a.answer.trim() == b.answer.trim();
+    let answer_diff = (!same_answer).then(|| {
+        // Ended alike, so the last lines do not differ by their newline alone
+        let (old, new) = (
+            format!("{}\n", a.answer.trim_end()),
+            format!("{}\n", b.answer.trim_end()),
+        );
+        TextDiff::from_lines(old.as_str(), new.as_str())
+            .unified_diff()
+            .header("a", "b")
+            .to_string()
+    });
+    Comparison {
+        a,
+        b,
+        same_answer,
+        answer_diff,
+        files,
+    }
+}
+
+fn capped(edits: &str) -> String {
+    if edits.len() <= MAX_EDIT_BYTES {
+        return edits.to_string();
+    }
+    let mut end = MAX_EDIT_BYTES;
+    while !edits.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n... ({} more bytes)", &edits[..end], edits.len() - end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transcript(dir: &Path, name: &str, events: &[Value]) -> RunSummary {
+        let path = dir.join(format!("{}.jsonl", name));
+        let lines: Vec<String> = events.iter().map(Value::to_string).collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        summarize(name, &path).unwrap()
+    }
+
+    fn edit(file: &str, old: &str, new: &str) -> Value {
+        json!({"type": "tool_use", "id": "t", "name": "Edit",
+               "input": {"file_path": file, "old_string": old, "new_string": new}})
+    }
+
+    #[test]
+    fn test_compare_matches_edits_per_file_and_diffs_answers() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = transcript(
+            dir.path(),
+            "a",
+            &[
+                json!({"type": "user", "message": {"content": [{"type": "text", "text": "Fix it"}]}}),
+                json!({"type": "assistant", "message": {"model": "opus", "content": [
+                    edit("src/lib.rs", "x\n", "y\n"),
+                    edit("src/main.rs", "a\n", "b\n"),
+                ]}}),
+                json!({"type": "assistant", "message": {"model": "opus", "content": [
+                    {"type": "text", "text": "Fixed both"}
+                ]}}),
+            ],
+        );
+        let b = transcript(
+            dir.path(),
+            "b",
+            &[
+                json!({"type": "assistant", "message": {"model": "sonnet", "content": [
+                    edit("src/lib.rs", "x\n", "y\n"),
+                    {"type": "tool_use", "id": "w", "name": "Write",
+                     "input": {"file_path": "NOTES.md", "content": "done\n"}},
+                    {"type": "text", "text": "Fixed one"},
+                ]}}),
+            ],
+        );
+        assert_eq!(
+            (a.turns, a.models.as_slice()),
+            (2, ["opus".to_string()].as_slice())
+        );
+        assert_eq!(a.answer, "Fixed both");
+
+        let comparison = compare(a, b);
+        assert!(!comparison.same_answer);
+        assert!(comparison
+            .answer_diff
+            .unwrap()
+            .contains("-Fixed both\n+Fixed one"));
+        let files: Vec<(&str, ChangedIn, bool)> = comparison
+            .files
+            .iter()
+            .map(|f| (f.path.as_str(), f.changed_in, f.same_edits))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("NOTES.md", ChangedIn::B, false),
+                ("src/lib.rs", ChangedIn::Both, true),
+                ("src/main.rs", ChangedIn::A, false),
+            ]
+        );
+        assert_eq!(comparison.files[1].edits_a, None);
+        assert_eq!(
+            comparison.files[0].edits_b.as_deref(),
+            Some("--- NOTES.md\n+++ NOTES.md\n@@ -0,0 +1 @@\n+done\n")
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Helpers for `claude-mcp.config.json` behind the `config` subcommands.
+//!
+//! The file is JSON that may carry `//` line comments, so the starter config written by
+//! `config init` can explain each option in place. `config validate` reports problems the
+//! server would otherwise ignore or silently correct at runtime.
+
+use crate::claude;
+use std::io::Write;
+use std::path::Path;
+
+/// Written by `config init`; must stay free of validation issues
+pub(crate) const STARTER: &str = r#"// claude-mcp-rs configuration. Lines starting with // are comments.
+// Every option is optional; the README lists all of them.
+{
+  // Extra flags passed to every Claude CLI invocation, e.g. ["--model", "sonnet"].
+  // --print, --output-format, --input-format, and --resume are managed by the server.
+  "additional_args": [],
+
+  // Maximum runtime per call in seconds (at most 3600)
+  "timeout_secs": 600,
+
+  // "spawn" starts a CLI process per call; "interactive" keeps one alive per session
+  "execution_mode": "spawn",
+
+  // Part of an oversized response that is kept: "head", "tail", or "middle"
+  "truncation": "head",
+
+  // Named run settings callers select with the PROFILE tool parameter
+  "profiles": {
+    "review": {
+      "additional_args": ["--permission-mode", "plan"],
+      "timeout_secs": 300
+    }
+  }
+}
+"#;
+
+/// Something `config validate` found; errors make it exit nonzero
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub error: bool,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    pub(crate) fn error(message: impl Into<String>) -> Self {
+        Self {
+            error: true,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn warning(message: impl Into<String>) -> Self {
+        Self {
+            error: false,
+            message: message.into(),
+        }
+    }
+}
+
+/// Drop `//` comments outside strings. Line breaks are kept so parse errors point at the
+/// right line.
+pub(crate) fn strip_comments(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+            out.push(c);
+        } else if c == '/' && chars.peek() == Some(&'/') {
+            while chars.next_if(|&next| next != '\n').is_some() {}
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Write the starter config to `path`, refusing to replace an existing file unless `force`
+pub(crate) fn init(path: &Path, force: bool) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    options.open(path)?.write_all(STARTER.as_bytes())
+}
+
+/// Read and check the config at `path`
+pub(crate) fn validate(path: &Path) -> Result<Vec<ConfigIssue>, String> {
+    let raw = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(claude::config_issues(&raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comments_are_stripped_outside_strings() {
+        let raw = "{\n  // note\n  \"url\": \"https://example.com\" // trailing\n}";
+        let value: serde_json::Value = serde_json::from_str(&strip_comments(raw)).unwrap();
+        assert_eq!(value["url"], "https://example.com");
+        assert_eq!(strip_comments(raw).lines().count(), raw.lines().count());
+    }
+
+    #[test]
+    fn test_starter_config_is_valid_and_not_overwritten() {
+        assert_eq!(claude::config_issues(STARTER), Vec::new());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("claude-mcp.config.json");
+        init(&path, false).unwrap();
+        assert!(init(&path, false).is_err());
+        init(&path, true).unwrap();
+        assert_eq!(validate(&path), Ok(Vec::new()));
+    }
+}
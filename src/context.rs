@@ -0,0 +1,224 @@
+//! Per-server state: config, session registry, live interactive processes, cached and
+//! in-flight runs, run deadlines, metrics, run queue, and state store.
+//!
+//! A [`ClaudeServer`](crate::server::ClaudeServer) holds a [`ServerContext`] and runs every
+//! request inside it, so code below the handlers reaches the state through [`current`]
+//! instead of process-wide statics. Servers built with [`ClaudeServer::new`] share the
+//! global context, which reads the config named by `--config`, `CLAUDE_MCP_CONFIG_PATH`, or
+//! the working directory; [`ServerContext::from_config_file`] and [`ServerContext::new`]
+//! give a server its own. Each tenant of the HTTP transport has one too, with its policy.
+//!
+//! [`ClaudeServer::new`]: crate::server::ClaudeServer::new
+
+use crate::claude::{self, ServerConfig};
+use crate::coalesce::InFlightRuns;
+use crate::deadlines::DeadlineRegistry;
+use crate::interactive::LiveSessions;
+use crate::metrics::Metrics;
+use crate::response_cache::CachedResults;
+use crate::run_queue::RunQueue;
+use crate::sessions::SessionRegistry;
+use crate::store::{StateStore, StoreConfig};
+use crate::tenants::{Tenant, TenantPolicy};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+tokio::task_local! {
+    static CURRENT: ServerContext;
+}
+
+/// State shared by the tools of one server. Cloning is cheap and clones share everything.
+#[derive(Clone)]
+pub struct ServerContext(Arc<Inner>);
+
+/// Where a context's config comes from, and so what a reload reads
+enum ConfigSource {
+    /// The file named on the command line, by `CLAUDE_MCP_CONFIG_PATH`, or in the working
+    /// directory, with command-line overrides applied
+    Discovered,
+    File(PathBuf),
+    /// A value given by the embedder; reloads keep it
+    Fixed,
+}
+
+struct Inner {
+    source: ConfigSource,
+    config: RwLock<Arc<ServerConfig>>,
+    sessions: SessionRegistry,
+    /// Interactive CLI processes kept alive between turns
+    live_sessions: LiveSessions,
+    /// Results `response_cache` may serve again
+    response_cache: CachedResults,
+    /// Runs identical new-session calls wait for instead of starting their own
+    in_flight: InFlightRuns,
+    deadlines: DeadlineRegistry,
+    metrics: Arc<Metrics>,
+    /// Built from the config at first use and kept across reloads
+    run_queue: OnceLock<Option<RunQueue>>,
+    /// Opened on first use and reopened when a reload changes `state_store`
+    store: Mutex<Option<(StoreConfig, Arc<dyn StateStore>)>>,
+    tenant: Option<TenantPolicy>,
+}
+
+impl ServerContext {
+    fn with_config(source: ConfigSource, config: ServerConfig) -> Self {
+        Self::build(source, config, None)
+    }
+
+    fn build(source: ConfigSource, config: ServerConfig, tenant: Option<TenantPolicy>) -> Self {
+        Self(Arc::new(Inner {
+            source,
+            config: RwLock::new(Arc::new(config)),
+            sessions: SessionRegistry::default(),
+            live_sessions: LiveSessions::default(),
+            response_cache: CachedResults::default(),
+            in_flight: InFlightRuns::default(),
+            deadlines: DeadlineRegistry::default(),
+            metrics: Arc::default(),
+            run_queue: OnceLock::new(),
+            store: Mutex::new(None),
+            tenant,
+        }))
+    }
+
+    /// The context of servers built with [`ClaudeServer::new`](crate::server::ClaudeServer::new)
+    /// and of code running outside any server. Created on first use, so command-line
+    /// overrides must be installed before then.
+    pub fn global() -> &'static ServerContext {
+        static GLOBAL: OnceLock<ServerContext> = OnceLock::new();
+        GLOBAL.get_or_init(|| {
+            Self::with_config(ConfigSource::Discovered, claude::load_server_config())
+        })
+    }
+
+    /// A context with its own state and exactly `config`: no file or environment is read,
+    /// and command-line overrides do not apply
+    pub fn new(config: ServerConfig) -> Self {
+        Self::with_config(ConfigSource::Fixed, config)
+    }
+
+    /// A context with its own state, configured from `path`. Command-line overrides apply
+    /// only to the global context.
+    pub fn from_config_file(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let config = claude::read_server_config(&path);
+        Self::with_config(ConfigSource::File(path), config)
+    }
+
+    /// A context for `tenant`, configured from its `config` file or else like the global one
+    pub(crate) fn for_tenant(policy: TenantPolicy, tenant: &Tenant) -> Self {
+        let (source, config) = match &tenant.config {
+            Some(path) => (
+                ConfigSource::File(path.clone()),
+                claude::read_server_config(path),
+            ),
+            None => (ConfigSource::Discovered, claude::load_server_config()),
+        };
+        Self::build(source, config, Some(policy))
+    }
+
+    pub(crate) fn config(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.0.config.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Re-read this context's config file
+    pub(crate) fn reload_config(&self) {
+        let config = match &self.0.source {
+            ConfigSource::Discovered => claude::load_server_config(),
+            ConfigSource::File(path) => claude::read_server_config(path),
+            ConfigSource::Fixed => return,
+        };
+        *self.0.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+
+    pub(crate) fn sessions(&self) -> &SessionRegistry {
+        &self.0.sessions
+    }
+
+    pub(crate) fn live_sessions(&self) -> &LiveSessions {
+        &self.0.live_sessions
+    }
+
+    pub(crate) fn response_cache(&self) -> &CachedResults {
+        &self.0.response_cache
+    }
+
+    pub(crate) fn in_flight(&self) -> &InFlightRuns {
+        &self.0.in_flight
+    }
+
+    pub(crate) fn deadlines(&self) -> &DeadlineRegistry {
+        &self.0.deadlines
+    }
+
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.0.metrics)
+    }
+
+    pub(crate) fn store(&self) -> &Mutex<Option<(StoreConfig, Arc<dyn StateStore>)>> {
+        &self.0.store
+    }
+
+    /// The tenant this context serves, when it serves one
+    pub(crate) fn tenant(&self) -> Option<&TenantPolicy> {
+        self.0.tenant.as_ref()
+    }
+
+    pub(crate) fn run_queue(&self) -> Option<&RunQueue> {
+        self.0
+            .run_queue
+            .get_or_init(|| {
+                self.sync_scope(claude::default_run_queue)
+                    .map(|config| RunQueue::new(&config))
+            })
+            .as_ref()
+    }
+
+    /// Run `future` with this as the current context, e.g. a [`claude::run`] outside a server
+    pub async fn scope<F: Future>(&self, future: F) -> F::Output {
+        CURRENT.scope(self.clone(), future).await
+    }
+
+    /// Run `f` with this as the current context
+    pub(crate) fn sync_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        CURRENT.sync_scope(self.clone(), f)
+    }
+}
+
+/// The context of the request being handled, or the global one outside a request. Tasks
+/// spawned during a request start outside it.
+pub(crate) fn current() -> ServerContext {
+    CURRENT
+        .try_with(ServerContext::clone)
+        .unwrap_or_else(|_| ServerContext::global().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contexts_keep_their_own_config_and_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str, timeout: u64| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, format!(r#"{{"timeout_secs": {}}}"#, timeout)).unwrap();
+            path
+        };
+        let short = ServerContext::from_config_file(path("short.json", 60));
+        let long = ServerContext::from_config_file(path("long.json", 1200));
+
+        let session = "6a1f0d3e-52c4-4b8e-9f27-0c3d8e1b5a74";
+        short.sync_scope(|| crate::sessions::record(session));
+
+        assert_eq!(short.sync_scope(claude::default_timeout_secs), 60);
+        assert_eq!(long.sync_scope(claude::default_timeout_secs), 1200);
+        assert_eq!(short.sync_scope(crate::sessions::recent), [session]);
+        assert!(long.sync_scope(crate::sessions::recent).is_empty());
+
+        std::fs::write(dir.path().join("long.json"), r#"{"timeout_secs": 30}"#).unwrap();
+        long.reload_config();
+        assert_eq!(long.sync_scope(claude::default_timeout_secs), 30);
+    }
+}
@@ -0,0 +1,155 @@
+//! Background operation of the HTTP server for simple init scripts.
+//!
+//! `--daemon` forks, detaches from the terminal, and records the child's PID (and the URL it
+//! serves) in a PID file. `stop` and `status` find the server again through that file. Unix
+//! only.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long `stop` waits for the server to exit after SIGTERM
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// PID file used when `--pid-file` is not given
+pub(crate) fn default_pid_file() -> PathBuf {
+    std::env::temp_dir()
+        .join("claude-mcp-rs")
+        .join("server.pid")
+}
+
+/// Removes the PID file when the daemon exits normally
+#[derive(Debug)]
+pub(crate) struct PidFile(PathBuf);
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// PID and URL recorded for a running server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Recorded {
+    pub pid: i32,
+    pub url: Option<String>,
+}
+
+/// Read the PID file; `None` if it is missing or its process is gone
+pub(crate) fn running(pid_file: &Path) -> Option<Recorded> {
+    let content = std::fs::read_to_string(pid_file).ok()?;
+    let mut lines = content.lines();
+    let pid: i32 = lines.next()?.trim().parse().ok()?;
+    alive(pid).then(|| Recorded {
+        pid,
+        url: lines.next().map(str::to_string),
+    })
+}
+
+fn alive(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists and may be signalled
+    pid > 0 && unsafe { libc::kill(pid, 0) } == 0
+}
+
+/// Fork into the background. The parent writes `pid_file`, prints the child's PID, and
+/// exits; the child returns in a new session with stdio on /dev/null. Must be called before
+/// any threads are started.
+pub(crate) fn detach(pid_file: &Path, url: &str) -> Result<PidFile> {
+    if let Some(recorded) = running(pid_file) {
+        bail!(
+            "already running (pid {}); see {}",
+            recorded.pid,
+            pid_file.display()
+        );
+    }
+    if let Some(dir) = pid_file.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+
+    // SAFETY: the process is still single-threaded, so the child may continue normally
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error()).context("fork failed"),
+        0 => {}
+        child => {
+            std::fs::write(pid_file, format!("{}\n{}\n", child, url))
+                .with_context(|| format!("failed to write {}", pid_file.display()))?;
+            println!("started (pid {}) at {}", child, url);
+            std::process::exit(0);
+        }
+    }
+
+    // SAFETY: plain syscalls on file descriptors this process owns
+    unsafe {
+        if libc::setsid() == -1 {
+            return Err(std::io::Error::last_os_error()).context("setsid failed");
+        }
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        if null >= 0 {
+            for fd in 0..=2 {
+                libc::dup2(null, fd);
+            }
+            if null > 2 {
+                libc::close(null);
+            }
+        }
+    }
+    Ok(PidFile(pid_file.to_path_buf()))
+}
+
+/// Send SIGTERM to the recorded server and wait for it to exit. Returns the stopped PID, or
+/// `None` if nothing was running.
+pub(crate) fn stop(pid_file: &Path) -> Result<Option<i32>> {
+    let Some(recorded) = running(pid_file) else {
+        let _ = std::fs::remove_file(pid_file);
+        return Ok(None);
+    };
+    // SAFETY: sending a signal has no memory-safety preconditions
+    if unsafe { libc::kill(recorded.pid, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to signal pid {}", recorded.pid));
+    }
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while alive(recorded.pid) {
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "pid {} did not exit within {:?}",
+                recorded.pid,
+                STOP_TIMEOUT
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let _ = std::fs::remove_file(pid_file);
+    Ok(Some(recorded.pid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_is_only_trusted_for_live_processes() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("server.pid");
+        assert_eq!(running(&pid_file), None);
+
+        std::fs::write(
+            &pid_file,
+            format!("{}\nhttp://127.0.0.1:8080/mcp\n", std::process::id()),
+        )
+        .unwrap();
+        assert_eq!(
+            running(&pid_file),
+            Some(Recorded {
+                pid: std::process::id() as i32,
+                url: Some("http://127.0.0.1:8080/mcp".to_string()),
+            })
+        );
+
+        std::fs::write(&pid_file, format!("{}\n", i32::MAX)).unwrap();
+        assert_eq!(running(&pid_file), None);
+        assert_eq!(stop(&pid_file).unwrap(), None);
+        assert!(!pid_file.exists(), "stale PID file is removed");
+    }
+}
@@ -0,0 +1,244 @@
+//! Deadlines of runs in progress, extendable with the `claude_extend_timeout` tool.
+//!
+//! A run starts with its timeout as the deadline. Once the output names the session, the
+//! deadline is registered under the session id, so a client that sees healthy progress can
+//! grant the run more time instead of having it killed at the original deadline. No
+//! extension takes a run past [`MAX_TIMEOUT_SECS`](crate::claude) from its start.
+//!
+//! The registry doubles as the list of runs in flight for `claude_status`: each deadline also
+//! tracks the run's latest output, so a long but productive run can be told from a hung one.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// When a run is stopped; shared by the run and the registry
+pub(crate) struct Deadline {
+    started: Instant,
+    /// Latest the deadline may be moved to, relative to `started`
+    max: Duration,
+    at: watch::Sender<Instant>,
+    progress: Mutex<Progress>,
+}
+
+/// What the run's output has shown so far
+struct Progress {
+    last_event: Option<String>,
+    /// When the last event was read, or the start before any
+    last_output: Instant,
+    /// Tool of the latest `tool_use` block
+    current_tool: Option<String>,
+    bytes_read: u64,
+}
+
+/// A run in flight, as listed by `claude_status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct RunStatus {
+    pub session_id: String,
+    pub elapsed_secs: u64,
+    pub timeout_secs: u64,
+    pub remaining_secs: u64,
+    /// Type of the latest stream-json event, e.g. `assistant` or `user`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_event: Option<String>,
+    pub secs_since_output: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_tool: Option<String>,
+    pub bytes_read: u64,
+}
+
+/// Outcome of an extension, returned to the client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Extended {
+    /// Total time the run now has, from its start
+    pub timeout_secs: u64,
+    pub remaining_secs: u64,
+    /// Whether the extension was cut short by the maximum timeout
+    pub capped: bool,
+}
+
+impl Deadline {
+    /// A deadline `timeout` from now, extendable up to `max` from now. The receiver sees
+    /// every extension.
+    pub(crate) fn new(timeout: Duration, max: Duration) -> (Arc<Self>, watch::Receiver<Instant>) {
+        let started = Instant::now();
+        let (at, changed) = watch::channel(started + timeout);
+        let deadline = Self {
+            started,
+            max: max.max(timeout),
+            at,
+            progress: Mutex::new(Progress {
+                last_event: None,
+                last_output: started,
+                current_tool: None,
+                bytes_read: 0,
+            }),
+        };
+        (Arc::new(deadline), changed)
+    }
+
+    /// Time from the start to the current deadline, in whole seconds
+    pub(crate) fn timeout_secs(&self) -> u64 {
+        (*self.at.borrow() - self.started).as_secs()
+    }
+
+    /// Note an event read from the run's output
+    pub(crate) fn record_output(&self, event_type: String, tool: Option<String>, bytes_read: u64) {
+        let mut progress = self.progress();
+        progress.last_event = Some(event_type);
+        progress.last_output = Instant::now();
+        if tool.is_some() {
+            progress.current_tool = tool;
+        }
+        progress.bytes_read = bytes_read;
+    }
+
+    fn progress(&self) -> MutexGuard<'_, Progress> {
+        self.progress.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn status(&self, session_id: &str) -> RunStatus {
+        let now = Instant::now();
+        let progress = self.progress();
+        RunStatus {
+            session_id: session_id.to_string(),
+            elapsed_secs: (now - self.started).as_secs(),
+            timeout_secs: self.timeout_secs(),
+            remaining_secs: self.at.borrow().saturating_duration_since(now).as_secs(),
+            last_event: progress.last_event.clone(),
+            secs_since_output: (now - progress.last_output).as_secs(),
+            current_tool: progress.current_tool.clone(),
+            bytes_read: progress.bytes_read,
+        }
+    }
+
+    fn extend(&self, by: Duration) -> Extended {
+        let latest = self.started + self.max;
+        let wanted = *self.at.borrow() + by;
+        let at = wanted.min(latest);
+        self.at.send_replace(at);
+        Extended {
+            timeout_secs: (at - self.started).as_secs(),
+            remaining_secs: at.saturating_duration_since(Instant::now()).as_secs(),
+            capped: wanted > latest,
+        }
+    }
+}
+
+/// Deadlines of the runs in progress, by session id
+#[derive(Default)]
+pub(crate) struct DeadlineRegistry(Mutex<HashMap<String, Arc<Deadline>>>);
+
+impl DeadlineRegistry {
+    pub(crate) fn register(&self, session_id: &str, deadline: &Arc<Deadline>) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(session_id.to_string(), Arc::clone(deadline));
+    }
+
+    /// Forget `session_id` unless another run has registered it since
+    pub(crate) fn unregister(&self, session_id: &str, deadline: &Arc<Deadline>) {
+        let mut deadlines = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if deadlines
+            .get(session_id)
+            .is_some_and(|registered| Arc::ptr_eq(registered, deadline))
+        {
+            deadlines.remove(session_id);
+        }
+    }
+
+    /// Move the deadline of the run in `session_id` by `by`; `None` when no run of that
+    /// session is in progress
+    pub(crate) fn extend(&self, session_id: &str, by: Duration) -> Option<Extended> {
+        let deadline = self
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(session_id)
+            .cloned()?;
+        Some(deadline.extend(by))
+    }
+
+    /// The runs in progress, longest running first. A run registered under two sessions (a
+    /// resumed one and the one it forked into) is listed once.
+    pub(crate) fn statuses(&self) -> Vec<RunStatus> {
+        let deadlines = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let mut runs: Vec<(&String, &Arc<Deadline>)> = deadlines.iter().collect();
+        runs.sort_by(|a, b| a.0.cmp(b.0));
+        let mut statuses: Vec<RunStatus> = Vec::new();
+        for (index, (session_id, deadline)) in runs.iter().enumerate() {
+            if !runs[..index]
+                .iter()
+                .any(|(_, seen)| Arc::ptr_eq(seen, deadline))
+            {
+                statuses.push(deadline.status(session_id));
+            }
+        }
+        statuses.sort_by(|a, b| {
+            b.elapsed_secs
+                .cmp(&a.elapsed_secs)
+                .then_with(|| a.session_id.cmp(&b.session_id))
+        });
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extensions_move_the_deadline_up_to_the_maximum() {
+        let registry = DeadlineRegistry::default();
+        let (deadline, mut changed) =
+            Deadline::new(Duration::from_secs(60), Duration::from_secs(300));
+        registry.register("session", &deadline);
+        changed.borrow_and_update();
+
+        let extended = registry
+            .extend("session", Duration::from_secs(120))
+            .unwrap();
+        assert_eq!((extended.timeout_secs, extended.capped), (180, false));
+        assert!(changed.has_changed().unwrap());
+
+        let capped = registry
+            .extend("session", Duration::from_secs(600))
+            .unwrap();
+        assert_eq!((capped.timeout_secs, capped.capped), (300, true));
+        assert_eq!(deadline.timeout_secs(), 300);
+
+        registry.unregister("session", &deadline);
+        assert_eq!(registry.extend("session", Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn test_statuses_report_the_latest_output() {
+        let registry = DeadlineRegistry::default();
+        let (deadline, _changed) = Deadline::new(Duration::from_secs(60), Duration::from_secs(300));
+        registry.register("session", &deadline);
+        let status = &registry.statuses()[0];
+        assert_eq!((status.last_event.as_deref(), status.bytes_read), (None, 0));
+
+        deadline.record_output("assistant".to_string(), Some("Bash".to_string()), 512);
+        deadline.record_output("user".to_string(), None, 2048);
+        let status = &registry.statuses()[0];
+        assert_eq!(status.session_id, "session");
+        assert_eq!(status.last_event.as_deref(), Some("user"));
+        assert_eq!(status.current_tool.as_deref(), Some("Bash"));
+        assert_eq!(
+            (
+                status.bytes_read,
+                status.timeout_secs,
+                status.secs_since_output
+            ),
+            (2048, 60, 0)
+        );
+
+        registry.unregister("session", &deadline);
+        assert!(registry.statuses().is_empty());
+    }
+}
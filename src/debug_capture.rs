@@ -0,0 +1,412 @@
+//! Per-run capture of the raw CLI I/O, for turning "the CLI did something weird" into a
+//! reproducible report.
+//!
+//! When enabled, each CLI process gets a directory `<temp>/claude-mcp-rs/debug-<uuid>/` with
+//! `argv.json` (program, arguments, working directory), `env.json` (the child's environment
+//! with secrets redacted), `stdout`/`stderr` holding the exact bytes the process wrote, and
+//! `timeline.jsonl` merging both streams line by line in arrival order, each line stamped
+//! with milliseconds since the process started. Capture is best effort: a failing disk never
+//! fails the run.
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::process::{ChildStderr, ChildStdout, Command};
+use tracing::warn;
+
+/// Child output stream, possibly teed into a capture file
+pub(crate) type OutputStream = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Environment variable name fragments whose values are never written to disk
+const SECRET_MARKERS: &[&str] = &[
+    "KEY",
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "AUTH",
+    "COOKIE",
+];
+
+/// Bytes of a line kept in the timeline, which then notes the full size; `stdout` and
+/// `stderr` keep the whole line
+const MAX_TIMELINE_LINE: usize = 64 * 1024;
+
+pub(crate) struct DebugCapture {
+    dir: PathBuf,
+    started: Instant,
+}
+
+impl DebugCapture {
+    /// Create the capture directory and record how `cmd` is about to be run
+    pub(crate) fn start(cmd: &Command) -> std::io::Result<Self> {
+        let dir = std::env::temp_dir()
+            .join("claude-mcp-rs")
+            .join(format!("debug-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+
+        let cmd = cmd.as_std();
+        let argv: Vec<String> = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(lossy)
+            .collect();
+        let invocation = serde_json::json!({
+            "argv": argv,
+            "cwd": cmd.get_current_dir().map(|dir| dir.to_string_lossy()),
+        });
+        std::fs::write(
+            dir.join("argv.json"),
+            serde_json::to_vec_pretty(&invocation)?,
+        )?;
+
+        let mut env: BTreeMap<String, String> = std::env::vars_os()
+            .map(|(name, value)| (lossy(&name), lossy(&value)))
+            .collect();
+        for (name, value) in cmd.get_envs() {
+            match value {
+                Some(value) => env.insert(lossy(name), lossy(value)),
+                None => env.remove(&lossy(name)),
+            };
+        }
+        let env: BTreeMap<String, String> = env
+            .into_iter()
+            .map(|(name, value)| {
+                let value = if is_secret(&name) {
+                    "[redacted]".to_string()
+                } else {
+                    value
+                };
+                (name, value)
+            })
+            .collect();
+        std::fs::write(dir.join("env.json"), serde_json::to_vec_pretty(&env)?)?;
+
+        Ok(Self {
+            dir,
+            started: Instant::now(),
+        })
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Tee stdout and stderr into the capture directory and the timeline. A stream whose
+    /// file cannot be created is passed through uncaptured.
+    pub(crate) fn tee(
+        &self,
+        stdout: ChildStdout,
+        stderr: ChildStderr,
+    ) -> (OutputStream, OutputStream) {
+        let timeline = match File::create(self.dir.join("timeline.jsonl")) {
+            Ok(file) => Some(Arc::new(Mutex::new(Timeline {
+                started: self.started,
+                sink: Some(BufWriter::new(file)),
+            }))),
+            Err(e) => {
+                warn!("failed to create debug capture timeline: {}", e);
+                None
+            }
+        };
+        (
+            self.tee_one(stdout, "stdout", timeline.clone()),
+            self.tee_one(stderr, "stderr", timeline),
+        )
+    }
+
+    fn tee_one<R>(
+        &self,
+        reader: R,
+        name: &'static str,
+        timeline: Option<Arc<Mutex<Timeline>>>,
+    ) -> OutputStream
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        match File::create(self.dir.join(name)) {
+            Ok(file) => Box::new(TeeReader {
+                inner: reader,
+                sink: Some(BufWriter::new(file)),
+                lines: timeline.map(|timeline| TimelineLines {
+                    stream: name,
+                    partial: Vec::new(),
+                    line_bytes: 0,
+                    timeline,
+                }),
+            }),
+            Err(e) => {
+                warn!("failed to create debug capture file {}: {}", name, e);
+                Box::new(reader)
+            }
+        }
+    }
+}
+
+/// Streams without capture, boxed to match teed ones
+pub(crate) fn passthrough(
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+) -> (OutputStream, OutputStream) {
+    (Box::new(stdout), Box::new(stderr))
+}
+
+/// Copy every byte read from `reader` into `file`. Copying stops quietly at the first
+/// write error; reading goes on.
+pub(crate) fn tee_into<R>(reader: R, file: File) -> OutputStream
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    Box::new(TeeReader {
+        inner: reader,
+        sink: Some(BufWriter::new(file)),
+        lines: None,
+    })
+}
+
+fn lossy(value: impl AsRef<OsStr>) -> String {
+    value.as_ref().to_string_lossy().into_owned()
+}
+
+pub(crate) fn is_secret(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    SECRET_MARKERS.iter().any(|marker| upper.contains(marker))
+}
+
+/// `timeline.jsonl` shared by the stdout and stderr of one process
+struct Timeline {
+    started: Instant,
+    /// Dropped after the first write error so capture stops quietly
+    sink: Option<BufWriter<File>>,
+}
+
+impl Timeline {
+    /// Record `line`, the kept start of a line of `bytes` bytes
+    fn write(&mut self, stream: &str, line: &[u8], bytes: usize) {
+        let Some(sink) = self.sink.as_mut() else {
+            return;
+        };
+        let mut entry = serde_json::json!({
+            "ms": self.started.elapsed().as_millis() as u64,
+            "stream": stream,
+            "line": String::from_utf8_lossy(line),
+        });
+        if bytes > line.len() {
+            entry["bytes"] = bytes.into();
+        }
+        let written = serde_json::to_writer(&mut *sink, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|()| sink.write_all(b"\n"));
+        if written.is_err() {
+            self.sink = None;
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(sink) = self.sink.as_mut() {
+            if sink.flush().is_err() {
+                self.sink = None;
+            }
+        }
+    }
+}
+
+/// Splits one stream into lines for the timeline
+struct TimelineLines {
+    stream: &'static str,
+    /// Kept start of a line whose end has not been read yet
+    partial: Vec<u8>,
+    /// Full length of that line so far
+    line_bytes: usize,
+    timeline: Arc<Mutex<Timeline>>,
+}
+
+impl TimelineLines {
+    fn feed(&mut self, mut bytes: &[u8]) {
+        while let Some(end) = bytes.iter().position(|&b| b == b'\n') {
+            self.push(&bytes[..end]);
+            self.emit();
+            bytes = &bytes[end + 1..];
+        }
+        self.push(bytes);
+    }
+
+    /// Past the kept length only the size matters, so huge lines are not buffered
+    fn push(&mut self, chunk: &[u8]) {
+        self.line_bytes += chunk.len();
+        let room = MAX_TIMELINE_LINE.saturating_sub(self.partial.len());
+        self.partial
+            .extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+
+    fn emit(&mut self) {
+        let mut line: &[u8] = &self.partial;
+        let mut bytes = self.line_bytes;
+        if bytes == line.len() && line.ends_with(b"\r") {
+            line = &line[..line.len() - 1];
+            bytes -= 1;
+        }
+        self.timeline
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .write(self.stream, line, bytes);
+        self.partial.clear();
+        self.line_bytes = 0;
+    }
+
+    /// EOF: a last line without a newline still counts
+    fn finish(&mut self) {
+        if self.line_bytes > 0 {
+            self.emit();
+        }
+        self.timeline
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .flush();
+    }
+}
+
+/// Copies every byte read from `inner` into `sink`, and line by line into the timeline
+struct TeeReader<R> {
+    inner: R,
+    /// Dropped after the first write error so capture stops quietly
+    sink: Option<BufWriter<File>>,
+    lines: Option<TimelineLines>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TeeReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        let read = &buf.filled()[before..];
+        if let Some(sink) = this.sink.as_mut() {
+            // An empty read is EOF; flush so the file is complete while the run is reported
+            let written = if read.is_empty() {
+                sink.flush()
+            } else {
+                sink.write_all(read)
+            };
+            if written.is_err() {
+                this.sink = None;
+            }
+        }
+        if let Some(lines) = this.lines.as_mut() {
+            if read.is_empty() {
+                lines.finish();
+            } else {
+                lines.feed(read);
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_env_names_are_redacted() {
+        assert!(is_secret("ANTHROPIC_API_KEY"));
+        assert!(is_secret("github_token"));
+        assert!(is_secret("AWS_SECRET_ACCESS_KEY"));
+        assert!(!is_secret("PATH"));
+        assert!(!is_secret("HOME"));
+    }
+
+    #[test]
+    fn test_timeline_splits_lines_across_reads_and_caps_long_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("timeline.jsonl");
+        let timeline = Arc::new(Mutex::new(Timeline {
+            started: Instant::now(),
+            sink: Some(BufWriter::new(File::create(&path).unwrap())),
+        }));
+        let mut lines = TimelineLines {
+            stream: "stdout",
+            partial: Vec::new(),
+            line_bytes: 0,
+            timeline,
+        };
+        lines.feed(b"{\"type\":");
+        lines.feed(b"\"system\"}\r\n");
+        lines.feed(&vec![b'x'; MAX_TIMELINE_LINE + 10]);
+        lines.finish();
+
+        let entries: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries[0]["line"], r#"{"type":"system"}"#);
+        assert_eq!(entries[0].get("bytes"), None);
+        assert_eq!(
+            entries[1]["line"].as_str().unwrap().len(),
+            MAX_TIMELINE_LINE
+        );
+        assert_eq!(entries[1]["bytes"], MAX_TIMELINE_LINE + 10);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_capture_records_argv_and_exact_output() {
+        use tokio::io::AsyncReadExt;
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "printf 'out\\r\\n'; printf 'err' >&2"])
+            .env("CAPTURE_TEST_TOKEN", "hunter2")
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+
+        let capture = DebugCapture::start(&cmd).unwrap();
+        let mut child = cmd.spawn().unwrap();
+        let (mut stdout, mut stderr) =
+            capture.tee(child.stdout.take().unwrap(), child.stderr.take().unwrap());
+        let mut sink = Vec::new();
+        stdout.read_to_end(&mut sink).await.unwrap();
+        stderr.read_to_end(&mut sink).await.unwrap();
+        child.wait().await.unwrap();
+
+        let argv = std::fs::read_to_string(capture.dir().join("argv.json")).unwrap();
+        assert!(argv.contains("\"sh\""));
+        let env = std::fs::read_to_string(capture.dir().join("env.json")).unwrap();
+        assert!(env.contains("CAPTURE_TEST_TOKEN"));
+        assert!(!env.contains("hunter2"));
+        assert_eq!(
+            std::fs::read(capture.dir().join("stdout")).unwrap(),
+            b"out\r\n"
+        );
+        assert_eq!(std::fs::read(capture.dir().join("stderr")).unwrap(), b"err");
+        let timeline: Vec<serde_json::Value> =
+            std::fs::read_to_string(capture.dir().join("timeline.jsonl"))
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+        let streams: Vec<(&str, &str)> = timeline
+            .iter()
+            .map(|entry| {
+                (
+                    entry["stream"].as_str().unwrap(),
+                    entry["line"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(streams, [("stdout", "out"), ("stderr", "err")]);
+
+        std::fs::remove_dir_all(capture.dir()).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+//! Preflight checks behind the `doctor` subcommand.
+//!
+//! Each check looks at one thing a run depends on (config, CLI binary, writable directories)
+//! and says what it found, so install scripts and CI can fail early with a readable reason
+//! instead of on the first tool call.
+
+use crate::claude;
+use crate::platform;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long `claude --version` may take before the check fails
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, outcome: Result<String, String>) -> Self {
+        let (ok, detail) = match outcome {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        Self { name, ok, detail }
+    }
+}
+
+/// Run every check in order
+pub async fn run_checks() -> Vec<Check> {
+    let mut checks = vec![Check::new("config", check_config())];
+    match claude::default_remote() {
+        Some(remote) => checks.push(Check::new(
+            "claude binary",
+            Ok(format!("not checked locally; runs on {}", remote.host)),
+        )),
+        None => {
+            let binary = check_binary(claude::claude_bin().as_os_str());
+            let found = binary.is_ok();
+            checks.push(Check::new("claude binary", binary));
+            if found {
+                checks.push(Check::new("claude version", check_version().await));
+            }
+            for (name, program) in claude::binaries() {
+                let binary = check_binary(program.as_os_str())
+                    .map(|path| format!("{}: {}", name, path))
+                    .map_err(|e| format!("{}: {}", name, e));
+                checks.push(Check::new("named binary", binary));
+            }
+        }
+    }
+    checks.push(Check::new(
+        "state dir",
+        check_state_dir(&std::env::temp_dir().join("claude-mcp-rs")),
+    ));
+    if let Some(audit_log) = claude::default_audit_log() {
+        checks.push(Check::new("audit log", check_parent_dir(&audit_log.path)));
+    }
+    if let Some(log_file) = claude::default_log_file() {
+        checks.push(Check::new("log file", check_parent_dir(&log_file)));
+    }
+    checks.push(Check::new("working dir", check_working_dir()));
+    checks
+}
+
+fn check_config() -> Result<String, String> {
+    let errors = claude::config_load_errors();
+    if !errors.is_empty() {
+        return Err(errors.join("; "));
+    }
+    match claude::config_path() {
+        Some(path) if path.is_file() => Ok(format!("loaded {}", path.display())),
+        Some(path) => Ok(format!("no file at {}; using defaults", path.display())),
+        None => Ok("no config file; using defaults".to_string()),
+    }
+}
+
+fn check_binary(program: &OsStr) -> Result<String, String> {
+    let path = platform::locate_program(program).ok_or_else(|| {
+        format!(
+            "'{}' not found on PATH; install the Claude CLI or set CLAUDE_BIN",
+            program.to_string_lossy()
+        )
+    })?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .permissions()
+            .mode();
+        if mode & 0o111 == 0 {
+            return Err(format!("{} is not executable", path.display()));
+        }
+    }
+    Ok(path.display().to_string())
+}
+
+async fn check_version() -> Result<String, String> {
+    let mut command = tokio::process::Command::new(claude::claude_bin());
+    command.arg("--version").kill_on_drop(true);
+    let output = tokio::time::timeout(VERSION_TIMEOUT, command.output())
+        .await
+        .map_err(|_| format!("`claude --version` timed out after {:?}", VERSION_TIMEOUT))?
+        .map_err(|e| format!("failed to run `claude --version`: {}", e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.lines().next().unwrap_or_default().trim().to_string();
+    if !output.status.success() {
+        return Err(format!("`claude --version` exited with {}", output.status));
+    }
+    if version.is_empty() {
+        return Err("`claude --version` printed nothing".to_string());
+    }
+    Ok(version)
+}
+
+/// Create `dir` if needed and prove a file can be written in it
+fn check_state_dir(dir: &Path) -> Result<String, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    probe_write(dir)?;
+    Ok(dir.display().to_string())
+}
+
+/// The directory `file` will be created in must exist and be writable
+fn check_parent_dir(file: &Path) -> Result<String, String> {
+    let dir = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Err(format!("directory {} does not exist", dir.display()));
+    }
+    probe_write(dir)?;
+    Ok(file.display().to_string())
+}
+
+fn probe_write(dir: &Path) -> Result<(), String> {
+    let probe = dir.join(format!(".doctor-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&probe, b"").map_err(|e| format!("{} is not writable: {}", dir.display(), e))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+fn check_working_dir() -> Result<String, String> {
+    let dir = std::env::current_dir().map_err(|e| e.to_string())?;
+    dir.canonicalize()
+        .map(|dir| platform::normalize_path(dir).display().to_string())
+        .map_err(|e| format!("{}: {}", dir.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_checks_require_an_existing_writable_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state");
+        assert!(check_state_dir(&state).is_ok());
+        assert!(state.is_dir());
+        assert_eq!(std::fs::read_dir(&state).unwrap().count(), 0);
+
+        assert!(check_parent_dir(&dir.path().join("audit.jsonl")).is_ok());
+        let missing = dir.path().join("missing").join("audit.jsonl");
+        assert!(check_parent_dir(&missing)
+            .unwrap_err()
+            .contains("does not exist"));
+    }
+
+    #[test]
+    fn test_missing_binary_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("claude");
+        assert!(check_binary(program.as_os_str()).is_err());
+
+        std::fs::write(&program, "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        assert!(check_binary(program.as_os_str())
+            .unwrap_err()
+            .contains("not executable"));
+    }
+}
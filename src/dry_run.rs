@@ -0,0 +1,115 @@
+//! What a run would execute, reported without starting it.
+//!
+//! `DRY_RUN` resolves a call the way a real run does (config, profile, flags, working
+//! directory, and the sandbox, remote, and priority wrappers) and returns the command line,
+//! the environment the server changes for the CLI, and the timeout, for debugging profiles
+//! and flags. Values of secret-looking variables are redacted as in debug captures.
+
+use crate::claude::{self, ExecutionMode, Options};
+use crate::debug_capture;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The process a run would start
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct DryRun {
+    /// Program and arguments, after every wrapper
+    pub argv: Vec<String>,
+    pub working_dir: String,
+    /// Variables set for the CLI on top of the server's environment; removed ones are `null`
+    pub env: BTreeMap<String, Option<String>>,
+    pub timeout_secs: u64,
+    /// Interactive runs send the prompt on stdin instead of as the last argument
+    pub prompt_on_stdin: bool,
+    /// Recording that would be replayed instead of starting the process
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay_from: Option<String>,
+}
+
+/// Resolve `opts` into the process [`claude::run`] would start for them
+pub(crate) fn describe(opts: &Options) -> DryRun {
+    let prompt_on_stdin = opts.execution_mode == ExecutionMode::Interactive;
+    let mut invocation = claude::base_invocation(opts);
+    if !prompt_on_stdin {
+        invocation.arg("--").arg(&opts.prompt);
+    }
+    let cmd = claude::build_command(opts, invocation);
+    let cmd = cmd.as_std();
+    let argv = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let env = cmd
+        .get_envs()
+        .map(|(name, value)| {
+            let name = name.to_string_lossy().into_owned();
+            let value = value.map(|value| {
+                if debug_capture::is_secret(&name) {
+                    "[redacted]".to_string()
+                } else {
+                    value.to_string_lossy().into_owned()
+                }
+            });
+            (name, value)
+        })
+        .collect();
+    DryRun {
+        argv,
+        working_dir: opts.working_dir.to_string_lossy().into_owned(),
+        env,
+        timeout_secs: claude::clamp_timeout_secs(opts.timeout_secs),
+        prompt_on_stdin,
+        replay_from: opts
+            .replay_from
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_dry_run_reports_the_wrapped_command_line() {
+        let opts = Options {
+            prompt: "fix the build".to_string(),
+            working_dir: PathBuf::from("/repo"),
+            session_id: Some("6a1f0d3e-52c4-4b8e-9f27-0c3d8e1b5a74".to_string()),
+            additional_args: vec!["--model".to_string(), "haiku".to_string()],
+            timeout_secs: Some(120),
+            binary: Some(PathBuf::from("/opt/claude")),
+            ..Default::default()
+        };
+        let dry_run = describe(&opts);
+        assert_eq!(
+            dry_run.argv,
+            [
+                "/opt/claude",
+                "--print",
+                "--output-format",
+                "stream-json",
+                "--model",
+                "haiku",
+                "--resume",
+                "6a1f0d3e-52c4-4b8e-9f27-0c3d8e1b5a74",
+                "--",
+                "fix the build",
+            ]
+        );
+        assert_eq!(dry_run.working_dir, "/repo");
+        assert_eq!(dry_run.timeout_secs, 120);
+        assert!(!dry_run.prompt_on_stdin);
+
+        let sandboxed = describe(&Options {
+            sandbox: serde_json::from_str(r#"{"mode": "docker", "image": "claude:latest"}"#)
+                .unwrap(),
+            execution_mode: ExecutionMode::Interactive,
+            ..opts
+        });
+        assert_eq!(sandboxed.argv[0], "docker");
+        assert!(sandboxed.prompt_on_stdin);
+        assert_ne!(sandboxed.argv.last().unwrap(), "fix the build");
+    }
+}
@@ -0,0 +1,109 @@
+//! Events reported while a run is in flight.
+//!
+//! Callers that want live visibility into a run set [`Options::events`](crate::claude::Options);
+//! the MCP server forwards these as `notifications/message` log entries. Sending never blocks,
+//! and a dropped receiver is ignored.
+
+use tokio::sync::mpsc::UnboundedSender;
+
+pub type EventSender = UnboundedSender<RunEvent>;
+
+/// Severity of a [`RunEvent`], ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunEvent {
+    /// The CLI process was started
+    Spawned { pid: Option<u32> },
+    /// The output named the run's session
+    SessionStarted { session_id: String },
+    /// A non-fatal problem with the output stream, e.g. a summarized oversized event
+    Warning(String),
+    /// Output could not be parsed; the run is being stopped
+    ParseError(String),
+    /// A stderr line that looks like a warning or error
+    Stderr(String),
+    /// The run exceeded its timeout and was killed
+    TimedOut { timeout_secs: u64 },
+    /// A stream-json event was read, with the tool of its latest `tool_use` block and the
+    /// stdout bytes read so far
+    Output {
+        event_type: String,
+        tool: Option<String>,
+        bytes_read: u64,
+    },
+}
+
+impl RunEvent {
+    pub fn level(&self) -> EventLevel {
+        match self {
+            RunEvent::Spawned { .. } => EventLevel::Info,
+            RunEvent::SessionStarted { .. } | RunEvent::Output { .. } => EventLevel::Debug,
+            RunEvent::Warning(_) | RunEvent::Stderr(_) => EventLevel::Warning,
+            RunEvent::ParseError(_) | RunEvent::TimedOut { .. } => EventLevel::Error,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            RunEvent::Spawned { pid: Some(pid) } => format!("spawned claude process (pid {})", pid),
+            RunEvent::Spawned { pid: None } => "spawned claude process".to_string(),
+            RunEvent::SessionStarted { session_id } => format!("session {}", session_id),
+            RunEvent::Warning(message) => message.clone(),
+            RunEvent::ParseError(message) => format!("failed to parse claude output: {}", message),
+            RunEvent::Stderr(line) => format!("claude stderr: {}", line),
+            RunEvent::TimedOut { timeout_secs } => {
+                format!("claude run timed out after {} seconds", timeout_secs)
+            }
+            RunEvent::Output {
+                event_type,
+                bytes_read,
+                ..
+            } => format!("{} event ({} bytes read)", event_type, bytes_read),
+        }
+    }
+}
+
+/// Send `event` if the run has a listener
+pub(crate) fn emit(sender: Option<&EventSender>, event: RunEvent) {
+    if let Some(sender) = sender {
+        let _ = sender.send(event);
+    }
+}
+
+/// Whether a stderr line is worth surfacing live rather than only in the final result
+pub(crate) fn is_stderr_highlight(line: &str) -> bool {
+    let lower = line.to_ascii_lowercase();
+    ["error", "warn", "fatal", "panic", "denied"]
+        .iter()
+        .any(|needle| lower.contains(needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stderr_highlights_match_problems_only() {
+        assert!(is_stderr_highlight("Error: API key missing"));
+        assert!(is_stderr_highlight("WARNING: rate limited, retrying"));
+        assert!(is_stderr_highlight("permission denied: ./build.sh"));
+        assert!(!is_stderr_highlight("Loaded 3 MCP servers"));
+    }
+
+    #[test]
+    fn test_event_levels_are_ordered_by_severity() {
+        assert!(RunEvent::TimedOut { timeout_secs: 1 }.level() > EventLevel::Warning);
+        assert!(RunEvent::Spawned { pid: None }.level() < EventLevel::Warning);
+        assert_eq!(
+            RunEvent::Stderr("warn".to_string()).message(),
+            "claude stderr: warn"
+        );
+    }
+}
@@ -0,0 +1,441 @@
+//! Scriptable stand-in for the Claude CLI, for integration tests.
+//!
+//! Built with the `test-support` feature. [`FakeCli`] writes an executable (a `sh` script on
+//! Unix, a `.cmd` batch file on Windows) that plays back stdout lines, stderr lines, and
+//! pauses in order, then exits with a chosen status. Steps can also log the arguments and
+//! environment it was started with, write files, branch on the arguments (to answer a
+//! `--resume` or a `--model` differently), and answer every line read from stdin, as an
+//! interactive session does. Point [`Options::binary`], the `claude_bin` config option, or
+//! `CLAUDE_BIN` at the path it returns.
+//!
+//! Every line is stored in a file next to the script and copied to the output with
+//! `cat`/`type`, so lines need no shell escaping and may be far longer than a command line,
+//! e.g. events beyond the 1MB line limit.
+//!
+//! [`Options::binary`]: crate::claude::Options::binary
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+enum Step {
+    Stdout(String),
+    Stderr(String),
+    Sleep(Duration),
+    LogArgs(PathBuf),
+    LogEnv(String, PathBuf),
+    WriteFile { path: String, contents: String },
+    WhenArgs(String, FakeCli),
+    ReplyPerLine(Vec<Step>),
+}
+
+/// Builder for a fake CLI executable
+#[derive(Debug, Clone, Default)]
+pub struct FakeCli {
+    steps: Vec<Step>,
+    exit_code: i32,
+}
+
+impl FakeCli {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Print `line` to stdout as is
+    pub fn line(mut self, line: impl Into<String>) -> Self {
+        self.steps.push(Step::Stdout(line.into()));
+        self
+    }
+
+    /// Print `event` to stdout as one line of stream-json
+    pub fn event(self, event: Value) -> Self {
+        self.line(event.to_string())
+    }
+
+    /// An `assistant` event carrying `text`
+    pub fn assistant_text(self, session_id: &str, text: &str) -> Self {
+        self.event(json!({
+            "type": "assistant",
+            "message": {"content": [{"type": "text", "text": text}]},
+            "session_id": session_id,
+        }))
+    }
+
+    /// A successful `result` event carrying `text`
+    pub fn result(self, session_id: &str, text: &str) -> Self {
+        self.event(json!({
+            "type": "result",
+            "result": text,
+            "is_error": false,
+            "session_id": session_id,
+        }))
+    }
+
+    /// Print `line` to stderr
+    pub fn stderr(mut self, line: impl Into<String>) -> Self {
+        self.steps.push(Step::Stderr(line.into()));
+        self
+    }
+
+    /// Pause before the next step
+    pub fn sleep(mut self, duration: Duration) -> Self {
+        self.steps.push(Step::Sleep(duration));
+        self
+    }
+
+    /// Append the arguments, joined by spaces, to `path` as one line
+    pub fn log_args(mut self, path: impl Into<PathBuf>) -> Self {
+        self.steps.push(Step::LogArgs(path.into()));
+        self
+    }
+
+    /// Append the value of environment variable `name` to `path` as one line
+    pub fn log_env(mut self, name: &str, path: impl Into<PathBuf>) -> Self {
+        self.steps.push(Step::LogEnv(name.to_string(), path.into()));
+        self
+    }
+
+    /// Write `contents` to `path`, relative to the working directory the CLI runs in. A
+    /// `path` starting with `$NAME/` is placed in the directory environment variable `NAME`
+    /// names, e.g. `$TMPDIR/scratch`.
+    pub fn write_file(mut self, path: &str, contents: &str) -> Self {
+        self.steps.push(Step::WriteFile {
+            path: path.to_string(),
+            contents: contents.to_string(),
+        });
+        self
+    }
+
+    /// When the arguments, joined by spaces, contain `pattern`, play `then` and exit with
+    /// its status instead of running the steps after this one
+    pub fn when_args(mut self, pattern: &str, then: FakeCli) -> Self {
+        self.steps.push(Step::WhenArgs(pattern.to_string(), then));
+        self
+    }
+
+    /// Read stdin line by line until it closes, playing the steps of `reply` for every
+    /// line; its exit status is not used
+    pub fn reply_per_line(mut self, reply: FakeCli) -> Self {
+        self.steps.push(Step::ReplyPerLine(reply.steps));
+        self
+    }
+
+    /// Exit status once every step has run; 0 by default
+    pub fn exit_code(mut self, code: i32) -> Self {
+        self.exit_code = code;
+        self
+    }
+
+    /// Write the executable as `name` in `dir`, with its lines in `<name>.lines/`, and
+    /// return its path
+    pub fn write(&self, dir: &Path, name: &str) -> io::Result<PathBuf> {
+        let lines_dir = dir.join(format!("{}.lines", name));
+        fs::create_dir_all(&lines_dir)?;
+        let mut script = Script {
+            lines_dir,
+            next_id: 0,
+            blocks: Vec::new(),
+        };
+        let mut commands = script.commands(&self.steps)?;
+        commands.push(exit(self.exit_code));
+        commands.append(&mut script.blocks);
+        write_script(dir, name, &commands)
+    }
+}
+
+/// Commands of a script being written
+struct Script {
+    lines_dir: PathBuf,
+    /// Numbers the files in `lines_dir` and the labels of `blocks`
+    next_id: usize,
+    /// Commands placed after the main ones, reached by jumping to their label
+    blocks: Vec<String>,
+}
+
+impl Script {
+    fn id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn store(&mut self, contents: &str) -> io::Result<PathBuf> {
+        let file = format!("{}.txt", self.id());
+        let path = self.lines_dir.join(file);
+        fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    fn commands(&mut self, steps: &[Step]) -> io::Result<Vec<String>> {
+        let mut commands = Vec::with_capacity(steps.len());
+        for step in steps {
+            match step {
+                Step::Stdout(line) | Step::Stderr(line) => {
+                    let path = self.store(&format!("{}\n", line))?;
+                    commands.push(print_file(&path, matches!(step, Step::Stderr(_))));
+                }
+                Step::Sleep(duration) => commands.push(sleep(*duration)),
+                Step::LogArgs(log) => commands.push(log_args(log)),
+                Step::LogEnv(name, log) => commands.push(log_env(name, log)),
+                Step::WriteFile { path, contents } => {
+                    let stored = self.store(contents)?;
+                    commands.push(copy_file(&stored, path));
+                }
+                Step::WhenArgs(pattern, then) => {
+                    let mut body = self.commands(&then.steps)?;
+                    body.push(exit(then.exit_code));
+                    commands.extend(self.when_args(pattern, body));
+                }
+                Step::ReplyPerLine(reply) => {
+                    let body = self.commands(reply)?;
+                    commands.extend(self.reply_per_line(body));
+                }
+            }
+        }
+        Ok(commands)
+    }
+}
+
+/// `path` split into the environment variable its `$NAME/` prefix names, if any, and the rest
+fn env_relative(path: &str) -> (Option<&str>, &str) {
+    match path.strip_prefix('$').and_then(|rest| rest.split_once('/')) {
+        Some((name, rest)) => (Some(name), rest),
+        None => (None, path),
+    }
+}
+
+#[cfg(unix)]
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+#[cfg(unix)]
+fn quote_path(path: &Path) -> String {
+    quote(&path.display().to_string())
+}
+
+#[cfg(unix)]
+fn print_file(path: &Path, to_stderr: bool) -> String {
+    let redirect = if to_stderr { " >&2" } else { "" };
+    format!("cat {}{}", quote_path(path), redirect)
+}
+
+#[cfg(unix)]
+fn sleep(duration: Duration) -> String {
+    format!("sleep {:.3}", duration.as_secs_f64())
+}
+
+#[cfg(unix)]
+fn log_args(log: &Path) -> String {
+    format!("printf '%s\\n' \"$*\" >> {}", quote_path(log))
+}
+
+#[cfg(unix)]
+fn log_env(name: &str, log: &Path) -> String {
+    format!("printf '%s\\n' \"${}\" >> {}", name, quote_path(log))
+}
+
+#[cfg(unix)]
+fn copy_file(stored: &Path, path: &str) -> String {
+    let target = match env_relative(path) {
+        (Some(name), rest) => format!("\"${}\"/{}", name, quote(rest)),
+        (None, path) => quote(path),
+    };
+    format!("cat {} > {}", quote_path(stored), target)
+}
+
+#[cfg(unix)]
+fn exit(code: i32) -> String {
+    format!("exit {}", code)
+}
+
+#[cfg(unix)]
+impl Script {
+    fn when_args(&mut self, pattern: &str, body: Vec<String>) -> Vec<String> {
+        let mut commands = vec![format!("case \"$*\" in *{}*)", quote(pattern))];
+        commands.extend(body);
+        commands.push(";; esac".to_string());
+        commands
+    }
+
+    fn reply_per_line(&mut self, body: Vec<String>) -> Vec<String> {
+        let mut commands = vec!["while IFS= read -r line; do".to_string()];
+        commands.extend(body);
+        // `:` keeps the loop valid when `reply` has no steps
+        commands.push(": ; done".to_string());
+        commands
+    }
+}
+
+#[cfg(unix)]
+fn write_script(dir: &Path, name: &str, commands: &[String]) -> io::Result<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join(format!("{}.sh", name));
+    fs::write(&path, format!("#!/bin/sh\n{}\n", commands.join("\n")))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    Ok(path)
+}
+
+#[cfg(windows)]
+fn print_file(path: &Path, to_stderr: bool) -> String {
+    let redirect = if to_stderr { " 1>&2" } else { "" };
+    format!("type \"{}\"{}", path.display(), redirect)
+}
+
+#[cfg(windows)]
+fn sleep(duration: Duration) -> String {
+    format!(
+        "powershell -NoProfile -Command \"Start-Sleep -Milliseconds {}\"",
+        duration.as_millis()
+    )
+}
+
+#[cfg(windows)]
+fn log_args(log: &Path) -> String {
+    format!("echo(%*>> \"{}\"", log.display())
+}
+
+#[cfg(windows)]
+fn log_env(name: &str, log: &Path) -> String {
+    format!("echo(%{}%>> \"{}\"", name, log.display())
+}
+
+#[cfg(windows)]
+fn copy_file(stored: &Path, path: &str) -> String {
+    let target = match env_relative(path) {
+        (Some(name), rest) => format!("%{}%\\{}", name, rest.replace('/', "\\")),
+        (None, path) => path.replace('/', "\\"),
+    };
+    format!("type \"{}\" > \"{}\"", stored.display(), target)
+}
+
+#[cfg(windows)]
+fn exit(code: i32) -> String {
+    format!("exit /b {}", code)
+}
+
+#[cfg(windows)]
+impl Script {
+    fn label(&mut self) -> String {
+        format!(":fake_{}", self.id())
+    }
+
+    // Batch files have no block-structured branches, so the body is placed after the main
+    // commands and jumped to
+    fn when_args(&mut self, pattern: &str, body: Vec<String>) -> Vec<String> {
+        let label = self.label();
+        self.blocks.push(label.clone());
+        self.blocks.extend(body);
+        vec![format!(
+            "echo(%*| findstr /L /C:\"{}\" >nul && goto {}",
+            pattern, label
+        )]
+    }
+
+    fn reply_per_line(&mut self, body: Vec<String>) -> Vec<String> {
+        let label = self.label();
+        let mut commands = vec![
+            label.clone(),
+            "set \"line=\"".to_string(),
+            "set /p line=".to_string(),
+            format!("if not defined line goto {}_end", label),
+        ];
+        commands.extend(body);
+        commands.push(format!("goto {}", label));
+        commands.push(format!("{}_end", label));
+        commands
+    }
+}
+
+#[cfg(windows)]
+fn write_script(dir: &Path, name: &str, commands: &[String]) -> io::Result<PathBuf> {
+    let path = dir.join(format!("{}.cmd", name));
+    fs::write(&path, format!("@echo off\r\n{}\r\n", commands.join("\r\n")))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps_play_back_in_order_with_exit_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let giant = "x".repeat(2 * 1024 * 1024);
+        let path = FakeCli::new()
+            .assistant_text("fake-session", "it's <ok> & 100%")
+            .stderr("warning: slow")
+            .sleep(Duration::from_millis(10))
+            .line(giant.as_str())
+            .exit_code(3)
+            .write(dir.path(), "fake")
+            .unwrap();
+
+        let output = std::process::Command::new(&path).output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        let first: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["message"]["content"][0]["text"], "it's <ok> & 100%");
+        assert_eq!(lines[1].len(), giant.len());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr).trim(),
+            "warning: slow"
+        );
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[test]
+    fn test_args_branch_logs_writes_and_replies() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("args.log");
+        let path = FakeCli::new()
+            .log_args(&log)
+            .log_env("FAKE_CLI_DIR", &log)
+            .when_args("--resume", FakeCli::new().line("resumed").exit_code(4))
+            .write_file("$FAKE_CLI_DIR/made.txt", "made\n")
+            .reply_per_line(FakeCli::new().line("reply"))
+            .write(dir.path(), "fake")
+            .unwrap();
+
+        let run = |args: &[&str], input: &str| {
+            let mut child = Command::new(&path)
+                .args(args)
+                .env("FAKE_CLI_DIR", dir.path())
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(input.as_bytes())
+                .unwrap();
+            child.wait_with_output().unwrap()
+        };
+
+        let resumed = run(&["--resume", "abc"], "");
+        assert_eq!(String::from_utf8_lossy(&resumed.stdout).trim(), "resumed");
+        assert_eq!(resumed.status.code(), Some(4));
+        assert!(!dir.path().join("made.txt").exists());
+
+        let replied = run(&["-p", "hi"], "one\ntwo\n");
+        assert_eq!(String::from_utf8_lossy(&replied.stdout), "reply\nreply\n");
+        assert_eq!(replied.status.code(), Some(0));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("made.txt")).unwrap(),
+            "made\n"
+        );
+        let logged = fs::read_to_string(&log).unwrap();
+        let lines: Vec<&str> = logged.lines().collect();
+        assert_eq!(lines[0], "--resume abc");
+        assert_eq!(lines[2], "-p hi");
+        assert_eq!(lines[3], dir.path().display().to_string());
+    }
+}
@@ -0,0 +1,123 @@
+//! Flaky-command diagnosis for `claude_diagnose_flaky`.
+//!
+//! The command runs again and again, up to a bounded number of attempts, until it fails.
+//! The failing output then goes to Claude with a request to find the cause of the
+//! intermittent failure and fix it. The report carries Claude's analysis and, in a git
+//! repository, the patch it produced.
+
+use crate::snapshots::{self, Patch, Verification};
+use serde::Serialize;
+use std::path::Path;
+
+/// Attempts when the caller does not say
+pub(crate) const DEFAULT_ATTEMPTS: usize = 20;
+/// Most attempts a caller can ask for
+pub(crate) const MAX_ATTEMPTS: usize = 200;
+/// Time each attempt gets when the caller does not say
+pub(crate) const DEFAULT_ATTEMPT_TIMEOUT_SECS: u64 = 600;
+
+/// How many attempts it took to see the command fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Reproduction {
+    pub attempts: usize,
+    /// The failing attempt; `None` when every attempt passed
+    pub failure: Option<Verification>,
+}
+
+/// Outcome of `claude_diagnose_flaky`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct FlakyDiagnosis {
+    /// Whether a failure was reproduced and Claude's run finished
+    pub success: bool,
+    pub reproduced: bool,
+    /// Attempts run, the failing one included
+    pub attempts: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<Verification>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub session_id: String,
+    /// Claude's diagnosis of the failure
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub analysis: String,
+    /// Changes Claude made; unset outside git
+    #[serde(flatten)]
+    pub patch: Option<Patch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Run `command` in `working_dir` until it fails, at most `attempts` times. Fails when the
+/// command cannot be started at all.
+pub(crate) async fn reproduce(
+    command: &[String],
+    timeout_secs: u64,
+    attempts: usize,
+    working_dir: &Path,
+) -> Result<Reproduction, String> {
+    for attempt in 1..=attempts {
+        let run = snapshots::run_command(command, timeout_secs, working_dir).await?;
+        if !run.passed {
+            tracing::debug!(attempt, "flaky command failed");
+            return Ok(Reproduction {
+                attempts: attempt,
+                failure: Some(run),
+            });
+        }
+    }
+    Ok(Reproduction {
+        attempts,
+        failure: None,
+    })
+}
+
+/// The request to diagnose `failure` of `command`, seen on attempt `attempts`
+pub(crate) fn prompt(command: &[String], attempts: usize, failure: &Verification) -> String {
+    let exit = failure
+        .exit_code
+        .map(|code| format!("exit code {}", code))
+        .unwrap_or_else(|| "no exit code (timed out or killed)".to_string());
+    let history = match attempts {
+        1 => "it failed on the first run".to_string(),
+        _ => format!("it passed {} times in a row, then failed", attempts - 1),
+    };
+    format!(
+        "`{}` fails intermittently: {} with {} and the output below. Find the root cause \
+         of the intermittent failure, such as a race, ordering or timing assumption, shared \
+         state, or external dependency, and fix it in the code rather than by retrying or \
+         raising timeouts. Explain the cause and the fix.\n\n{}",
+        command.join(" "),
+        history,
+        exit,
+        failure.output
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reproduce_stops_at_the_first_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        // Passes twice, then fails
+        let command = [
+            "sh",
+            "-c",
+            "echo x >> count; test $(wc -l < count) -lt 3 || { echo 'boom' >&2; exit 1; }",
+        ]
+        .map(String::from);
+        let reproduction = reproduce(&command, 10, 5, dir.path()).await.unwrap();
+        assert_eq!(reproduction.attempts, 3);
+        let failure = reproduction.failure.unwrap();
+        assert_eq!(
+            (failure.exit_code, failure.output.as_str()),
+            (Some(1), "boom")
+        );
+        assert!(prompt(&command, 3, &failure).contains("passed 2 times in a row"));
+
+        let passing = reproduce(&["true".to_string()], 10, 4, dir.path()).await;
+        assert_eq!(passing.unwrap().failure, None);
+        let missing = reproduce(&["/nonexistent/tool".to_string()], 10, 4, dir.path()).await;
+        assert!(missing.unwrap_err().starts_with("failed to start"));
+    }
+}
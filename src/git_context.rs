@@ -0,0 +1,161 @@
+//! Git context header put in front of prompts.
+//!
+//! With `git_context` set in `claude-mcp.config.json`, each prompt starts with a short
+//! summary of the working directory's repository: the current branch, the subjects of the
+//! last `commits` commits, and the files with uncommitted changes. Claude then knows where
+//! it is without spending turns on `git status` and `git log`. Outside a git repository the
+//! prompt is left as it is.
+
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Settings of the header, configured via `git_context` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GitContextConfig {
+    /// Recent commit subjects listed
+    #[serde(default = "default_commits")]
+    pub commits: usize,
+    /// Uncommitted files listed; the rest are counted
+    #[serde(default = "default_max_dirty_files")]
+    pub max_dirty_files: usize,
+}
+
+fn default_commits() -> usize {
+    5
+}
+
+fn default_max_dirty_files() -> usize {
+    20
+}
+
+/// `prompt` with the header for `working_dir` in front, when `git_context` is configured
+pub(crate) async fn prepend(prompt: &str, working_dir: &Path) -> String {
+    let Some(config) = crate::claude::git_context() else {
+        return prompt.to_string();
+    };
+    match header(&config, working_dir).await {
+        Some(header) => format!("{}\n\n{}", header, prompt),
+        None => prompt.to_string(),
+    }
+}
+
+/// The header for the repository at `working_dir`; `None` outside a work tree
+pub(crate) async fn header(config: &GitContextConfig, working_dir: &Path) -> Option<String> {
+    let inside = git(working_dir, &["rev-parse", "--is-inside-work-tree"]).await?;
+    if inside != "true" {
+        return None;
+    }
+    let branch = match git(working_dir, &["branch", "--show-current"]).await {
+        Some(branch) if !branch.is_empty() => branch,
+        // Detached, or no commits yet
+        _ => match git(working_dir, &["rev-parse", "--short", "HEAD"]).await {
+            Some(commit) => format!("detached at {}", commit),
+            None => "no commits yet".to_string(),
+        },
+    };
+    let mut header = format!("<git-context>\nBranch: {}\n", branch);
+
+    if config.commits > 0 {
+        let count = format!("-{}", config.commits);
+        let log = git(working_dir, &["log", &count, "--format=%h %s"])
+            .await
+            .unwrap_or_default();
+        if !log.is_empty() {
+            header.push_str("Recent commits:\n");
+            for line in log.lines() {
+                header.push_str(&format!("- {}\n", line));
+            }
+        }
+    }
+
+    let status = git(
+        working_dir,
+        &["status", "--porcelain", "--untracked-files=all"],
+    )
+    .await
+    .unwrap_or_default();
+    let dirty: Vec<&str> = status.lines().collect();
+    if dirty.is_empty() {
+        header.push_str("Working tree clean\n");
+    } else {
+        header.push_str("Uncommitted changes:\n");
+        for line in dirty.iter().take(config.max_dirty_files) {
+            header.push_str(&format!("{}\n", line));
+        }
+        if dirty.len() > config.max_dirty_files {
+            header.push_str(&format!(
+                "... and {} more\n",
+                dirty.len() - config.max_dirty_files
+            ));
+        }
+    }
+    header.push_str("</git-context>");
+    Some(header)
+}
+
+/// Trimmed stdout of a git command that succeeded
+async fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    output.status.success().then(|| {
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_header_lists_branch_commits_and_dirty_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = GitContextConfig {
+            commits: 1,
+            max_dirty_files: 1,
+        };
+        assert_eq!(header(&config, dir.path()).await, None);
+
+        let run = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        run(&["init", "-q", "-b", "main"]);
+        for subject in ["First", "Second"] {
+            run(&["commit", "-q", "--allow-empty", "-m", subject]);
+        }
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "b").unwrap();
+
+        let header = header(&config, dir.path()).await.unwrap();
+        let lines: Vec<&str> = header.lines().collect();
+        assert_eq!(lines[..2], ["<git-context>", "Branch: main"]);
+        assert!(lines[3].ends_with(" Second"), "{}", header);
+        assert_eq!(
+            lines[4..],
+            [
+                "Uncommitted changes:",
+                "?? a.txt",
+                "... and 1 more",
+                "</git-context>"
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Idle shutdown for servers started with `--idle-exit-secs`.
+//!
+//! IDEs launch one server per workspace and do not always stop it when the workspace
+//! closes. Every tool call marks the server as active; once no call has arrived for the
+//! configured time and nothing is running, [`wait`] returns so the transport can shut down.
+
+use crate::metrics;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How often a busy server checks again whether it has gone idle
+const BUSY_RECHECK: Duration = Duration::from_secs(1);
+
+struct Activity {
+    calls_in_progress: AtomicUsize,
+    last_call: Mutex<Instant>,
+}
+
+fn activity() -> &'static Activity {
+    static ACTIVITY: OnceLock<Activity> = OnceLock::new();
+    ACTIVITY.get_or_init(|| Activity {
+        calls_in_progress: AtomicUsize::new(0),
+        last_call: Mutex::new(Instant::now()),
+    })
+}
+
+/// Marks a tool call as in progress until dropped
+pub(crate) struct CallGuard(());
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        let activity = activity();
+        *activity.last_call.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        activity.calls_in_progress.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Record the start of a tool call
+pub(crate) fn call_started() -> CallGuard {
+    let activity = activity();
+    activity.calls_in_progress.fetch_add(1, Ordering::Relaxed);
+    *activity.last_call.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    CallGuard(())
+}
+
+/// Time left before the server counts as idle, or `None` once it is
+fn remaining(limit: Duration) -> Option<Duration> {
+    let activity = activity();
+    let busy = activity.calls_in_progress.load(Ordering::Relaxed) > 0
+        || metrics::metrics().runs_in_flight() > 0;
+    let since = activity
+        .last_call
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .elapsed();
+    time_left(limit, busy, since)
+}
+
+fn time_left(limit: Duration, busy: bool, since_last_call: Duration) -> Option<Duration> {
+    if busy {
+        return Some(BUSY_RECHECK);
+    }
+    limit
+        .checked_sub(since_last_call)
+        .filter(|left| !left.is_zero())
+}
+
+/// Resolve once no tool call has arrived for `limit` and no run is active
+pub(crate) async fn wait(limit: Duration) {
+    while let Some(left) = remaining(limit) {
+        tokio::time::sleep(left).await;
+    }
+    tracing::info!("no tool calls for {}s, exiting", limit.as_secs());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_only_after_the_limit_with_nothing_running() {
+        let limit = Duration::from_secs(60);
+        assert_eq!(
+            time_left(limit, false, Duration::from_secs(45)),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(time_left(limit, false, limit), None);
+        assert_eq!(
+            time_left(limit, true, Duration::from_secs(600)),
+            Some(BUSY_RECHECK)
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! Interactive execution strategy: one long-lived Claude CLI process per session.
+//!
+//! Instead of spawning `claude --resume <id>` for every turn, the process is started with
+//! `--input-format stream-json` and kept alive. Each turn is written to stdin as a stream-json
+//! user message, and stdout is read until the turn's `result` event. Live processes are kept
+//! in a small registry keyed by SESSION_ID and evicted least-recently-used. A turn whose
+//! working directory, binary, arguments, or environment differ from those the process was
+//! started with stops it and resumes the session in a new process.
+//!
+//! When `warm_pool_size` is set, idle processes are also pre-spawned per working directory
+//! and argument set, and handed the first turn of new sessions to hide CLI startup latency.
+
+use crate::claude::{
+    apply_exit_status, base_invocation, build_command, elapsed_ms, enforce_required_fields,
+    push_warning, read_line_with_limit, spawn_stderr_drain, start_debug_capture, take_stderr,
+    ClaudeResult, EventCollector, LineOutcome, Options, ValidationMode, MAX_AGENT_MESSAGES_SIZE,
+    MAX_LINE_LENGTH,
+};
+use crate::context;
+use crate::debug_capture::{self, OutputStream};
+use crate::events::{self, RunEvent};
+use crate::orphans::{self, PidRecord};
+use crate::platform::ProcessTreeGuard;
+use crate::stderr_lines;
+use crate::stream_scan::EventScanner;
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin};
+use tracing::{debug, info, warn};
+
+/// Upper bound on idle CLI processes kept around between turns
+const MAX_LIVE_SESSIONS: usize = 8;
+
+struct LiveSession {
+    // Held so the process is killed (kill_on_drop) when the session is evicted
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<OutputStream>,
+    stderr: Arc<Mutex<String>>,
+    /// Debug capture directory covering this process's whole lifetime
+    debug_dir: Option<PathBuf>,
+    /// Reported on the first turn served by this process
+    capture_warning: Option<String>,
+    // Dropped alongside `child` so evicting a session also stops the CLI behind a shim
+    tree_guard: ProcessTreeGuard,
+    /// Lets a later server kill the process should this one die without evicting it
+    _pid_record: Option<PidRecord>,
+    /// Options the process was started with; a turn with others needs a new process
+    key: PoolKey,
+    last_used: Instant,
+}
+
+/// Live processes of one server context, by SESSION_ID
+#[derive(Default)]
+pub(crate) struct LiveSessions(Mutex<HashMap<String, LiveSession>>);
+
+/// Remove a live session from the registry so the current turn owns it exclusively. A
+/// process started with other options than `key` cannot serve the turn; it is stopped so
+/// the session can be resumed in a new one.
+async fn take_session(session_id: &str, key: &PoolKey) -> Option<LiveSession> {
+    let session = context::current()
+        .live_sessions()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(session_id)?;
+    if session.key == *key {
+        return Some(session);
+    }
+    debug!(
+        session_id,
+        "options changed since the session's process started; resuming in a new one"
+    );
+    stop_session(session).await;
+    None
+}
+
+/// Kill a live process and wait for it, so nothing else writes to its session
+async fn stop_session(mut session: LiveSession) {
+    session.tree_guard.kill_tree();
+    let _ = session.child.start_kill();
+    let _ = session.child.wait().await;
+}
+
+/// Put a session back after a completed turn, evicting the least recently used one if full
+fn store_session(session_id: String, session: LiveSession) {
+    let context = context::current();
+    let mut sessions = context
+        .live_sessions()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if sessions.len() >= MAX_LIVE_SESSIONS && !sessions.contains_key(&session_id) {
+        if let Some(oldest) = sessions
+            .iter()
+            .min_by_key(|(_, s)| s.last_used)
+            .map(|(id, _)| id.clone())
+        {
+            sessions.remove(&oldest);
+        }
+    }
+    sessions.insert(session_id, session);
+}
+
+/// Identifies which new-session calls an idle pre-spawned process can serve
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PoolKey {
+    working_dir: PathBuf,
+    binary: Option<PathBuf>,
+    additional_args: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+impl PoolKey {
+    fn for_options(opts: &Options) -> Self {
+        Self {
+            working_dir: opts.working_dir.clone(),
+            binary: opts.binary.clone(),
+            additional_args: opts.additional_args.clone(),
+            env: opts.env.clone(),
+        }
+    }
+}
+
+fn warm_pool() -> &'static Mutex<HashMap<PoolKey, Vec<LiveSession>>> {
+    static WARM_POOL: OnceLock<Mutex<HashMap<PoolKey, Vec<LiveSession>>>> = OnceLock::new();
+    WARM_POOL.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Take an idle process that is still running, discarding any that exited while waiting
+fn take_warm(key: &PoolKey) -> Option<LiveSession> {
+    let mut pool = warm_pool().lock().unwrap_or_else(|e| e.into_inner());
+    let idle = pool.get_mut(key)?;
+    while let Some(mut session) = idle.pop() {
+        if matches!(session.child.try_wait(), Ok(None)) {
+            return Some(session);
+        }
+    }
+    None
+}
+
+/// Top the pool for this key back up to `warm_pool_size` in the background
+fn replenish_warm_pool(opts: &Options) {
+    let mut template = opts.clone();
+    template.session_id = None;
+    let key = PoolKey::for_options(&template);
+
+    tokio::spawn(async move {
+        loop {
+            let idle = warm_pool()
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&key)
+                .map_or(0, Vec::len);
+            if idle >= template.warm_pool_size {
+                break;
+            }
+            match spawn_session(&template) {
+                Ok(session) => warm_pool()
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .entry(key.clone())
+                    .or_default()
+                    .push(session),
+                Err(e) => {
+                    warn!("failed to pre-spawn claude process: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn spawn_session(opts: &Options) -> Result<LiveSession> {
+    let mut cmd = build_command(opts, base_invocation(opts));
+    cmd.stdin(Stdio::piped());
+    let (capture, capture_warning) = start_debug_capture(opts, &cmd);
+
+    let mut child = cmd.spawn().context("Failed to spawn claude command")?;
+    debug!(pid = child.id(), "spawned interactive claude process");
+    let stdin = child.stdin.take().context("Failed to get stdin")?;
+    let stdout = child.stdout.take().context("Failed to get stdout")?;
+    let stderr = child.stderr.take().context("Failed to get stderr")?;
+    let (stdout, stderr) = match &capture {
+        Some(capture) => capture.tee(stdout, stderr),
+        None => debug_capture::passthrough(stdout, stderr),
+    };
+
+    let stderr_buf = Arc::new(Mutex::new(String::new()));
+    // The drain task ends on its own when the process exits
+    // Stderr highlights are reported per turn from the buffer instead, since a live process
+    // outlives the event listener of the turn that spawned it
+    drop(spawn_stderr_drain(stderr, Arc::clone(&stderr_buf), None));
+
+    Ok(LiveSession {
+        tree_guard: ProcessTreeGuard::new(child.id()),
+        _pid_record: orphans::record(child.id()),
+        child,
+        stdin,
+        stdout: BufReader::new(stdout),
+        stderr: stderr_buf,
+        debug_dir: capture.map(|capture| capture.dir().to_path_buf()),
+        capture_warning,
+        key: PoolKey::for_options(opts),
+        last_used: Instant::now(),
+    })
+}
+
+/// Encode a prompt as a stream-json user message line
+fn user_message_line(prompt: &str) -> String {
+    let message = serde_json::json!({
+        "type": "user",
+        "message": {
+            "role": "user",
+            "content": [{ "type": "text", "text": prompt }],
+        },
+    });
+    format!("{}\n", message)
+}
+
+/// Run one turn on a live session, spawning (and resuming) a process if none is alive
+#[tracing::instrument(name = "claude_process", skip_all, fields(pid = tracing::field::Empty))]
+pub(crate) async fn run_turn(opts: Options) -> Result<ClaudeResult> {
+    let started = Instant::now();
+    let key = PoolKey::for_options(&opts);
+    let existing = match opts.session_id.as_deref() {
+        Some(id) => take_session(id, &key).await,
+        None => None,
+    };
+    let warm = match (&existing, &opts.session_id) {
+        (None, None) if opts.warm_pool_size > 0 => take_warm(&key),
+        _ => None,
+    };
+    let mut spawn_ms = None;
+    let mut session = match existing.or(warm) {
+        Some(session) => session,
+        None => {
+            let session = spawn_session(&opts)?;
+            spawn_ms = Some(elapsed_ms(started));
+            let pid = session.child.id();
+            events::emit(opts.events.as_ref(), RunEvent::Spawned { pid });
+            session
+        }
+    };
+    tracing::Span::current().record("pid", session.child.id());
+    if opts.session_id.is_none() && opts.warm_pool_size > 0 {
+        replenish_warm_pool(&opts);
+    }
+
+    session
+        .stdin
+        .write_all(user_message_line(&opts.prompt).as_bytes())
+        .await
+        .context("Failed to write prompt to claude stdin")?;
+    session
+        .stdin
+        .flush()
+        .await
+        .context("Failed to flush claude stdin")?;
+
+    let mut collector = EventCollector::new(&opts, started).await;
+    let mut line_buf = Vec::new();
+    let mut turn_complete = false;
+    let mut scanner = EventScanner::new(MAX_AGENT_MESSAGES_SIZE);
+
+    loop {
+        line_buf.clear();
+        match read_line_with_limit(
+            &mut session.stdout,
+            &mut line_buf,
+            MAX_LINE_LENGTH,
+            Some(&mut scanner),
+        )
+        .await
+        {
+            Ok(read_result) => {
+                if read_result.bytes_read == 0 {
+                    break; // EOF: the process exited mid-session
+                }
+                match collector
+                    .process_line(&line_buf, &read_result, &mut scanner)
+                    .await
+                {
+                    LineOutcome::Continue => {}
+                    LineOutcome::TurnComplete => {
+                        turn_complete = true;
+                        break;
+                    }
+                    // The stream is no longer trustworthy; retire the process below
+                    LineOutcome::ParseFailed => break,
+                }
+            }
+            Err(e) => {
+                collector.record_io_error(&e);
+                break;
+            }
+        }
+    }
+
+    let mut result = collector.finish().await;
+    result.timing.spawn_ms = spawn_ms;
+    result.debug_dir = session.debug_dir.clone();
+    if let Some(warning) = session.capture_warning.take() {
+        result.warnings = push_warning(result.warnings.take(), &warning);
+    }
+
+    if turn_complete {
+        let stderr_output = take_stderr(&session.stderr);
+        for line in stderr_output
+            .lines()
+            .filter(|l| events::is_stderr_highlight(l))
+        {
+            events::emit(opts.events.as_ref(), RunEvent::Stderr(line.to_string()));
+        }
+        result.stderr = stderr_lines::parse(&stderr_output);
+        if !result.session_id.is_empty() {
+            session.last_used = Instant::now();
+            store_session(result.session_id.clone(), session);
+        }
+    } else {
+        session.tree_guard.kill_tree();
+        let _ = session.child.start_kill();
+        let status = session
+            .child
+            .wait()
+            .await
+            .context("Failed to wait for claude command")?;
+        info!(%status, "interactive claude process exited");
+        let stderr_output = take_stderr(&session.stderr);
+        apply_exit_status(&mut result, &status, stderr_output);
+    }
+
+    Ok(enforce_required_fields(result, ValidationMode::Full))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_message_line_is_single_stream_json_line() {
+        let line = user_message_line("line one\nline \"two\"");
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.matches('\n').count(), 1);
+
+        let parsed: serde_json::Value = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(parsed["type"], "user");
+        assert_eq!(
+            parsed["message"]["content"][0]["text"],
+            "line one\nline \"two\""
+        );
+    }
+}
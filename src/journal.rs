@@ -0,0 +1,293 @@
+//! Crash-safe journal of the runs that tool calls start.
+//!
+//! While a call waits in the run queue or runs, a [`Job`] describing it is kept in the
+//! `state_store`, together with the session id once the output names it, and removed when
+//! the call returns. After a crash the journal still holds the calls that were cut off, and
+//! [`recover`] settles them on the next start: a job that never left the queue is run again
+//! in the background, and one that was running is marked `interrupted` with its last known
+//! session id, so a client can find it with `claude_jobs` and resume it. Jobs of servers
+//! that are still running are left alone. With the default `memory` backend the journal
+//! goes away with the process.
+
+use crate::audit::PromptRecord;
+use crate::claude;
+use crate::orphans;
+use crate::server::{self, ClaudeArgs};
+use crate::store::{self, StateStore};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// How long interrupted jobs are listed before they are dropped
+const INTERRUPTED_RETENTION_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum JobState {
+    /// Waiting for a slot in the run queue
+    Queued,
+    Running,
+    /// Was running when its server stopped
+    Interrupted,
+}
+
+/// A journaled call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Job {
+    pub id: String,
+    pub tool: String,
+    pub state: JobState,
+    pub working_dir: PathBuf,
+    /// Arguments as the `claude` tool takes them; dropped once the job is interrupted
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub args: Value,
+    /// Flags the tool adds to the arguments, e.g. plan mode for `claude_ask`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// The server running the call, told apart from a later process with its PID by its
+    /// start time
+    pub server_pid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_started: Option<String>,
+    pub queued_at: String,
+    pub updated_at: String,
+}
+
+/// A job as `claude_jobs` lists it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ListedJob {
+    pub id: String,
+    pub tool: String,
+    pub state: JobState,
+    pub working_dir: String,
+    /// Pass as `SESSION_ID` to resume an interrupted job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub queued_at: String,
+    pub updated_at: String,
+}
+
+impl From<Job> for ListedJob {
+    fn from(job: Job) -> Self {
+        Self {
+            id: job.id,
+            tool: job.tool,
+            state: job.state,
+            working_dir: job.working_dir.display().to_string(),
+            session_id: job.session_id,
+            queued_at: job.queued_at,
+            updated_at: job.updated_at,
+        }
+    }
+}
+
+impl Job {
+    fn new(tool: &str, args: &ClaudeArgs, working_dir: &Path, additional_args: &[String]) -> Self {
+        let now = store::timestamp(Utc::now());
+        let server_pid = std::process::id();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            tool: tool.to_string(),
+            state: JobState::Queued,
+            working_dir: working_dir.to_path_buf(),
+            args: serde_json::to_value(args).unwrap_or_default(),
+            additional_args: additional_args.to_vec(),
+            session_id: args.session_id.clone().filter(|id| !id.is_empty()),
+            server_pid,
+            server_started: orphans::start_time(server_pid),
+            queued_at: now.clone(),
+            updated_at: now,
+        }
+    }
+
+    /// Whether the server that journaled the job is still running
+    fn owned(&self) -> bool {
+        self.server_started
+            .as_deref()
+            .is_some_and(|started| orphans::is_running(self.server_pid, started))
+    }
+}
+
+/// A job kept in the journal while its call runs; dropping it removes the job
+pub(crate) struct Journaled {
+    store: Arc<dyn StateStore>,
+    job: Mutex<Job>,
+}
+
+impl Journaled {
+    /// Journal a call to `tool` that is about to wait for the run queue
+    pub(crate) fn queued(
+        store: Arc<dyn StateStore>,
+        tool: &str,
+        args: &ClaudeArgs,
+        working_dir: &Path,
+        additional_args: &[String],
+    ) -> Arc<Self> {
+        Self::journal(store, Job::new(tool, args, working_dir, additional_args))
+    }
+
+    /// Journal a job taken over from a server that is gone, under this server
+    pub(crate) fn adopted(store: Arc<dyn StateStore>, mut job: Job) -> Arc<Self> {
+        job.server_pid = std::process::id();
+        job.server_started = orphans::start_time(job.server_pid);
+        job.state = JobState::Queued;
+        Self::journal(store, job)
+    }
+
+    fn journal(store: Arc<dyn StateStore>, job: Job) -> Arc<Self> {
+        let journaled = Self {
+            store,
+            job: Mutex::new(job),
+        };
+        journaled.update(|_| {});
+        Arc::new(journaled)
+    }
+
+    /// The call got its slot and the run starts. Its arguments are only needed to run it
+    /// again, so they are dropped here when `prompt_retention` keeps less than whole prompts.
+    pub(crate) fn running(&self) {
+        let keep_args =
+            claude::prompt_retention().is_none_or(|retention| retention.mode == PromptRecord::Full);
+        self.update(|job| {
+            job.state = JobState::Running;
+            if !keep_args {
+                job.args = Value::Null;
+                job.additional_args.clear();
+            }
+        });
+    }
+
+    /// The run's output named its session
+    pub(crate) fn session_started(&self, session_id: &str) {
+        self.update(|job| job.session_id = Some(session_id.to_string()));
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Job)) {
+        let mut job = self.job.lock().unwrap_or_else(|e| e.into_inner());
+        change(&mut job);
+        job.updated_at = store::timestamp(Utc::now());
+        if let Err(e) = self.store.put_job(&job) {
+            warn!(job = %job.id, "failed to journal job: {:#}", e);
+        }
+    }
+}
+
+impl Drop for Journaled {
+    fn drop(&mut self) {
+        let job = self.job.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = self.store.remove_job(&job.id) {
+            warn!(job = %job.id, "failed to remove job from the journal: {:#}", e);
+        }
+    }
+}
+
+/// Settle the jobs left by servers that are gone, and run the ones that never started
+pub(crate) async fn recover() {
+    let Some(store) = store::current() else {
+        return;
+    };
+    match settle(store.as_ref(), Utc::now()) {
+        Ok(queued) => {
+            for job in queued {
+                info!(job = %job.id, tool = %job.tool, "running a job queued before a restart");
+                tokio::spawn(server::rerun(job));
+            }
+        }
+        Err(e) => warn!("failed to read the job journal: {:#}", e),
+    }
+}
+
+/// Mark running jobs of servers that are gone interrupted, drop old interrupted ones, and
+/// return their queued jobs to be run again
+fn settle(store: &dyn StateStore, now: DateTime<Utc>) -> anyhow::Result<Vec<Job>> {
+    let expired = store::timestamp(now - chrono::Duration::days(INTERRUPTED_RETENTION_DAYS));
+    let mut queued = Vec::new();
+    for mut job in store.jobs()? {
+        if job.owned() {
+            continue;
+        }
+        match job.state {
+            // Stays journaled until the rerun adopts it
+            JobState::Queued => queued.push(job),
+            JobState::Running => {
+                warn!(job = %job.id, session_id = ?job.session_id, "job interrupted by a restart");
+                job.state = JobState::Interrupted;
+                job.args = Value::Null;
+                job.additional_args.clear();
+                job.updated_at = store::timestamp(now);
+                store.put_job(&job)?;
+            }
+            JobState::Interrupted if job.updated_at < expired => store.remove_job(&job.id)?,
+            JobState::Interrupted => {}
+        }
+    }
+    Ok(queued)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_settle_requeues_queued_and_interrupts_running_jobs() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::default());
+        let args = ClaudeArgs {
+            prompt: "Fix the build".to_string(),
+            ..Default::default()
+        };
+        let gone = |state: JobState, updated_at: &str| {
+            let mut job = Job::new("claude", &args, Path::new("/repo"), &[]);
+            job.state = state;
+            // No start time: the server that wrote it cannot be found again
+            job.server_started = None;
+            job.updated_at = updated_at.to_string();
+            store.put_job(&job).unwrap();
+            job
+        };
+        let queued = gone(JobState::Queued, "2026-10-15T10:00:00.000Z");
+        let mut running = gone(JobState::Running, "2026-10-15T10:00:00.000Z");
+        running.session_id = Some("5f0c6d1e-0000-4000-8000-000000000000".to_string());
+        store.put_job(&running).unwrap();
+        gone(JobState::Interrupted, "2026-10-01T10:00:00.000Z");
+        // A job of this server, which is still running
+        let own = Journaled::queued(store.clone(), "claude_ask", &args, Path::new("/repo"), &[]);
+        own.running();
+
+        let now = DateTime::parse_from_rfc3339("2026-10-16T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let rerun = settle(store.as_ref(), now).unwrap();
+        assert_eq!(rerun.len(), 1);
+        assert_eq!(rerun[0].id, queued.id);
+        assert_eq!(rerun[0].args["PROMPT"], "Fix the build");
+
+        let jobs = store.jobs().unwrap();
+        let state_of = |tool: &str, state: JobState| {
+            jobs.iter()
+                .filter(|job| job.tool == tool && job.state == state)
+                .count()
+        };
+        assert_eq!(jobs.len(), 3);
+        assert_eq!(state_of("claude", JobState::Queued), 1);
+        assert_eq!(state_of("claude_ask", JobState::Running), 1);
+        let interrupted = jobs
+            .iter()
+            .find(|job| job.state == JobState::Interrupted)
+            .unwrap();
+        assert_eq!(interrupted.session_id, running.session_id);
+        assert_eq!(interrupted.args, Value::Null);
+
+        let adopted = Journaled::adopted(store.clone(), rerun[0].clone());
+        let jobs = store.jobs().unwrap();
+        assert!(jobs.iter().any(|job| job.id == queued.id && job.owned()));
+        drop(adopted);
+        drop(own);
+        assert_eq!(store.jobs().unwrap().len(), 1);
+    }
+}
@@ -1,2 +1,66 @@
+mod actions;
+mod agents;
+mod ask;
+mod audit;
+mod capabilities;
 pub mod claude;
+pub mod cli;
+mod cli_failure;
+mod cli_features;
+mod coalesce;
+mod compare;
+mod config_file;
+pub mod context;
+#[cfg(unix)]
+mod daemon;
+mod deadlines;
+mod debug_capture;
+mod doctor;
+mod dry_run;
+mod events;
+#[cfg(any(test, feature = "test-support"))]
+pub mod fake_cli;
+mod flaky;
+mod git_context;
+mod idle;
+mod injection;
+mod interactive;
+mod journal;
+mod limits;
+mod lint_fix;
+pub mod logging;
+mod memory;
+pub mod metrics;
+mod migration;
+mod notify_throttle;
+mod orphans;
+mod output_filter;
+mod output_language;
+mod parser_limits;
+mod platform;
+mod prompt_preview;
+mod pull_request;
+mod recording;
+mod release_notes;
+mod remote;
+mod response_cache;
+mod run_dir;
+mod run_queue;
+mod run_report;
+mod sandbox;
+mod scheduler;
 pub mod server;
+mod sessions;
+mod snapshots;
+mod stderr_lines;
+mod store;
+mod stream_scan;
+mod structured;
+mod swarm;
+mod templates;
+mod tenants;
+mod test_writer;
+mod token_estimate;
+mod transcript;
+mod triage;
+mod truncation;
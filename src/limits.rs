@@ -0,0 +1,203 @@
+//! OS-level resource limits and scheduling priority applied to the spawned Claude CLI.
+//!
+//! Limits are installed with `setrlimit` in a pre-exec hook, so they apply to the CLI and
+//! everything it spawns (shell commands run by the agent) but never to the MCP server itself.
+//! Priority is set the same way with `setpriority`, optionally combined with running the CLI
+//! in a systemd slice so cgroup CPU weights apply.
+
+use crate::claude::Invocation;
+use serde::Deserialize;
+use tokio::process::Command;
+
+/// Nice levels accepted from callers; raising priority (negative nice) needs privileges
+pub const CALLER_NICE_RANGE: std::ops::RangeInclusive<i32> = 0..=19;
+
+/// Resource limits for the child process, configured via `resource_limits` in
+/// `claude-mcp.config.json`. Unset fields leave the inherited limit untouched.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// Maximum virtual address space in megabytes (`RLIMIT_AS`)
+    pub max_address_space_mb: Option<u64>,
+    /// Maximum CPU time in seconds (`RLIMIT_CPU`)
+    pub max_cpu_secs: Option<u64>,
+    /// Maximum number of open file descriptors (`RLIMIT_NOFILE`)
+    pub max_open_files: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.max_address_space_mb.is_none()
+            && self.max_cpu_secs.is_none()
+            && self.max_open_files.is_none()
+    }
+
+    /// Install the limits on `cmd` so they take effect in the child before exec.
+    /// Resource limits are Unix-only; on other platforms this is a no-op.
+    pub fn apply_to(&self, cmd: &mut Command) {
+        if self.is_empty() {
+            return;
+        }
+
+        #[cfg(unix)]
+        {
+            let limits = self.clone();
+            // SAFETY: the hook only calls setrlimit, which is async-signal-safe, and does
+            // not allocate or touch locks held by the parent.
+            unsafe {
+                cmd.pre_exec(move || limits.install());
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = cmd;
+        }
+    }
+
+    #[cfg(unix)]
+    fn install(&self) -> std::io::Result<()> {
+        if let Some(mb) = self.max_address_space_mb {
+            set_rlimit(libc::RLIMIT_AS, mb.saturating_mul(1024 * 1024))?;
+        }
+        if let Some(secs) = self.max_cpu_secs {
+            set_rlimit(libc::RLIMIT_CPU, secs)?;
+        }
+        if let Some(files) = self.max_open_files {
+            set_rlimit(libc::RLIMIT_NOFILE, files)?;
+        }
+        Ok(())
+    }
+}
+
+/// CPU priority for the child process, configured via `priority` in `claude-mcp.config.json`
+/// and overridable per call with the `PRIORITY` tool parameter.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessPriority {
+    /// Nice level for the CLI process (-20..=19, higher yields more CPU to other work)
+    pub nice: Option<i32>,
+    /// systemd slice to run the CLI in via `systemd-run --user --scope`, e.g. `background.slice`
+    pub cgroup_slice: Option<String>,
+}
+
+impl ProcessPriority {
+    /// Set the nice level in the child before exec. Unix-only; a no-op elsewhere.
+    pub fn apply_to(&self, cmd: &mut Command) {
+        let Some(nice) = self.nice else {
+            return;
+        };
+
+        #[cfg(unix)]
+        {
+            // SAFETY: the hook only calls setpriority, which is async-signal-safe
+            unsafe {
+                cmd.pre_exec(move || {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (cmd, nice);
+        }
+    }
+
+    /// Wrap the invocation in a transient systemd scope when a cgroup slice is configured
+    pub(crate) fn wrap(&self, invocation: Invocation) -> Invocation {
+        match self.cgroup_slice.as_deref() {
+            Some(slice) if !slice.is_empty() => invocation.wrap(
+                "systemd-run",
+                [
+                    "--user".to_string(),
+                    "--scope".to_string(),
+                    "--quiet".to_string(),
+                    format!("--slice={}", slice),
+                    "--".to_string(),
+                ],
+            ),
+            _ => invocation,
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type RlimitResource = libc::c_int;
+
+/// Lower both the soft and hard limit of `resource` to `value`
+#[cfg(unix)]
+fn set_rlimit(resource: RlimitResource, value: u64) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    // SAFETY: `limit` is a valid, initialized rlimit struct for the duration of the call
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_open_file_limit_applies_to_child() {
+        let limits = ResourceLimits {
+            max_open_files: Some(64),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "ulimit -n"]);
+        limits.apply_to(&mut cmd);
+
+        let output = cmd.output().await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "64");
+    }
+
+    #[tokio::test]
+    async fn test_nice_level_applies_to_child() {
+        let priority = ProcessPriority {
+            nice: Some(19),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "nice"]);
+        priority.apply_to(&mut cmd);
+
+        let output = cmd.output().await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "19");
+    }
+
+    #[test]
+    fn test_cgroup_slice_wraps_invocation_in_systemd_scope() {
+        let priority = ProcessPriority {
+            cgroup_slice: Some("background.slice".to_string()),
+            ..Default::default()
+        };
+        let invocation = Invocation {
+            program: "claude".into(),
+            args: vec!["--print".into()],
+        };
+
+        let wrapped = priority.wrap(invocation);
+        assert_eq!(wrapped.program, "systemd-run");
+        assert_eq!(
+            wrapped.args,
+            [
+                "--user",
+                "--scope",
+                "--quiet",
+                "--slice=background.slice",
+                "--",
+                "claude",
+                "--print"
+            ]
+        );
+    }
+}
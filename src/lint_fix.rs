@@ -0,0 +1,88 @@
+//! The `claude_fix_lints` loop: lint, have Claude fix the diagnostics, lint again.
+//!
+//! The configured `lint_fix.lint_command` (e.g. `cargo clippy -- -D warnings`) runs in the
+//! working directory. While it fails, its output goes to Claude with instructions to fix
+//! the diagnostics, in one session resumed each round, and the linter runs again, for up to
+//! `max_rounds` rounds. The diff of the whole loop is taken from working-tree snapshots
+//! before and after, so it covers new files and leaves earlier uncommitted work out.
+
+use crate::snapshots::{self, Patch, Verification};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Most rounds a caller can ask for
+pub(crate) const MAX_ROUNDS: usize = 10;
+
+/// Settings of `claude_fix_lints`, configured via `lint_fix` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LintFixConfig {
+    /// Program and arguments of the linter; a nonzero exit means diagnostics remain
+    pub lint_command: Vec<String>,
+    #[serde(default = "default_lint_timeout_secs")]
+    pub lint_timeout_secs: u64,
+    /// Rounds when the caller does not say
+    #[serde(default = "default_max_rounds")]
+    pub max_rounds: usize,
+}
+
+fn default_lint_timeout_secs() -> u64 {
+    600
+}
+
+fn default_max_rounds() -> usize {
+    3
+}
+
+/// Outcome of `claude_fix_lints`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct LintFix {
+    /// Whether the linter passes at the end
+    pub success: bool,
+    /// Rounds in which Claude was asked to fix diagnostics
+    pub rounds: usize,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub session_id: String,
+    /// The last lint run; its output holds the diagnostics that remain
+    pub lint: Verification,
+    /// Changes made by the loop; unset outside git
+    #[serde(flatten)]
+    pub patch: Option<Patch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub(crate) async fn lint(config: &LintFixConfig, working_dir: &Path) -> Verification {
+    snapshots::verify(&config.lint_command, config.lint_timeout_secs, working_dir).await
+}
+
+/// The request to fix the diagnostics of `lint`
+pub(crate) fn prompt(config: &LintFixConfig, lint: &Verification) -> String {
+    format!(
+        "`{}` reports the diagnostics below. Fix their causes in the code. Do not silence \
+         them with allow attributes, ignore comments, or config changes unless a diagnostic \
+         is a false positive, and keep behavior unchanged.\n\n{}",
+        config.lint_command.join(" "),
+        lint.output
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_prompt_carries_the_diagnostics() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = LintFixConfig {
+            lint_command: ["sh", "-c", "echo 'warning: unused variable' >&2; exit 1"]
+                .map(String::from)
+                .to_vec(),
+            lint_timeout_secs: 10,
+            max_rounds: 3,
+        };
+        let lint = lint(&config, dir.path()).await;
+        assert!(!lint.passed);
+        assert!(prompt(&config, &lint).ends_with("\n\nwarning: unused variable"));
+    }
+}
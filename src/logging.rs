@@ -0,0 +1,64 @@
+//! Structured logging setup for the server binary.
+//!
+//! Logs go to stderr (stdout carries the MCP stdio transport) and, when `log_file` is set in
+//! `claude-mcp.config.json`, also to that file as JSON lines. Levels come from `RUST_LOG`,
+//! falling back to `log_level` in the config and then to `info`.
+
+use crate::claude;
+use std::fs::OpenOptions;
+use std::sync::Mutex;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Install the global subscriber. Call once, before serving.
+pub fn init() {
+    let filter = || {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+            let level = claude::default_log_level();
+            EnvFilter::try_new(level.as_deref().unwrap_or(DEFAULT_LOG_LEVEL))
+                .unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL))
+        })
+    };
+
+    let stderr_layer = fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .with_filter(filter());
+
+    let mut file_error = None;
+    let file_layer = claude::default_log_file().and_then(|path| {
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(
+                fmt::layer()
+                    .json()
+                    .with_writer(Mutex::new(file))
+                    .with_filter(filter()),
+            ),
+            Err(err) => {
+                file_error = Some(format!(
+                    "failed to open log file {}: {}",
+                    path.display(),
+                    err
+                ));
+                None
+            }
+        }
+    });
+
+    // A subscriber may already be installed when embedded; keep it
+    let _ = tracing_subscriber::registry()
+        .with(stderr_layer)
+        .with(file_layer)
+        .try_init();
+
+    // The config is read before logging exists, so replay its problems now
+    for error in claude::config_load_errors() {
+        tracing::warn!("{}", error);
+    }
+    if let Some(error) = file_error {
+        tracing::warn!("{}", error);
+    }
+}
@@ -1,14 +1,28 @@
 use anyhow::Result;
-use claude_mcp_rs::server::ClaudeServer;
-use rmcp::{transport::stdio, ServiceExt};
+use clap::Parser;
+use claude_mcp_rs::cli::{self, Cli, Command};
+use claude_mcp_rs::{claude, logging};
+use std::future::Future;
+use std::process::ExitCode;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Create an instance of our Claude server
-    let service = ClaudeServer::new().serve(stdio()).await.inspect_err(|e| {
-        eprintln!("serving error: {:?}", e);
-    })?;
+fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
+    // Overrides must be in place before logging reads the config
+    claude::set_overrides(cli.overrides.into());
 
-    service.waiting().await?;
-    Ok(())
+    match cli.command.unwrap_or(Command::Serve(cli.serve)) {
+        // Serving sets up logging and the runtime itself, after a daemon has detached
+        Command::Serve(args) => cli::serve(args).map(|()| ExitCode::SUCCESS),
+        Command::Run(args) => with_runtime(cli::run(args)),
+        Command::Doctor => with_runtime(async { Ok(cli::doctor().await) }),
+        Command::Config(command) => with_runtime(async { cli::config(command) }),
+        Command::Stop(args) => cli::stop(args),
+        Command::Status(args) => cli::status(args),
+    }
+}
+
+/// Start logging and drive `task` to completion on a multi-threaded runtime
+fn with_runtime(task: impl Future<Output = Result<ExitCode>>) -> Result<ExitCode> {
+    logging::init();
+    tokio::runtime::Runtime::new()?.block_on(task)
 }
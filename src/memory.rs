@@ -0,0 +1,164 @@
+//! Project memory file maintenance behind the `claude_memory_*` tools.
+//!
+//! The CLI loads `CLAUDE.md` from the working directory as persistent project memory.
+//! Orchestrators read, append to, and rewrite it through these helpers instead of
+//! hand-editing files: the path is confined to the working directory, the file is capped in
+//! size so it cannot crowd out the context, and every change comes back as a unified diff.
+
+use serde::Serialize;
+use similar::TextDiff;
+use std::path::{Component, Path, PathBuf};
+
+/// Largest memory file the tools will write; the CLI loads all of it into every session
+pub(crate) const MAX_MEMORY_BYTES: usize = 64 * 1024;
+
+/// Result of a memory tool call
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct MemoryFile {
+    pub path: String,
+    pub bytes: usize,
+    /// Current contents, returned by reads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Unified diff of a change; empty when the file did not change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+/// Resolve the configured memory file inside `working_dir`. Absolute paths and `..` are
+/// rejected so the tools cannot touch files outside the project.
+pub(crate) fn resolve(working_dir: &Path, memory_file: &Path) -> Result<PathBuf, String> {
+    let inside = memory_file
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !inside || memory_file.as_os_str().is_empty() {
+        return Err(format!(
+            "memory_file must be a relative path inside the working directory: {}",
+            memory_file.display()
+        ));
+    }
+    Ok(working_dir.join(memory_file))
+}
+
+/// Contents of the memory file; a missing file reads as empty
+pub(crate) fn read(path: &Path) -> Result<MemoryFile, String> {
+    let content = current(path)?;
+    Ok(MemoryFile {
+        path: path.display().to_string(),
+        bytes: content.len(),
+        content: Some(content),
+        diff: None,
+    })
+}
+
+/// Add `text` at the end of the memory file, on a new line
+pub(crate) fn append(path: &Path, text: &str) -> Result<MemoryFile, String> {
+    let mut content = current(path)?;
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(text);
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    write(path, &content)
+}
+
+/// Replace the memory file with `content`
+pub(crate) fn write(path: &Path, content: &str) -> Result<MemoryFile, String> {
+    if content.len() > MAX_MEMORY_BYTES {
+        return Err(format!(
+            "memory file would be {} bytes; the limit is {}",
+            content.len(),
+            MAX_MEMORY_BYTES
+        ));
+    }
+    let before = current(path)?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let diff = TextDiff::from_lines(before.as_str(), content)
+        .unified_diff()
+        .header(&format!("a/{}", name), &format!("b/{}", name))
+        .to_string();
+    if before != content {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+        }
+        // Write beside the file and rename so a concurrent reader never sees half of it
+        let staging = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&staging, content)
+            .and_then(|()| std::fs::rename(&staging, path))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&staging);
+                format!("failed to write {}: {}", path.display(), e)
+            })?;
+    }
+    Ok(MemoryFile {
+        path: path.display().to_string(),
+        bytes: content.len(),
+        content: None,
+        diff: Some(diff),
+    })
+}
+
+fn current(path: &Path) -> Result<String, String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(format!("failed to read {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_file_stays_inside_the_working_dir() {
+        let dir = Path::new("/repo");
+        assert_eq!(
+            resolve(dir, Path::new("CLAUDE.md")),
+            Ok(PathBuf::from("/repo/CLAUDE.md"))
+        );
+        assert!(resolve(dir, Path::new("docs/CLAUDE.md")).is_ok());
+        assert!(resolve(dir, Path::new("../CLAUDE.md")).is_err());
+        assert!(resolve(dir, Path::new("/etc/CLAUDE.md")).is_err());
+        assert!(resolve(dir, Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_changes_are_reported_as_diffs_and_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CLAUDE.md");
+        assert_eq!(read(&path).unwrap().content.as_deref(), Some(""));
+
+        append(&path, "# Project").unwrap();
+        let appended = append(&path, "- run `cargo test` before committing").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# Project\n- run `cargo test` before committing\n"
+        );
+        let diff = appended.diff.unwrap();
+        assert!(
+            diff.starts_with("--- a/CLAUDE.md\n+++ b/CLAUDE.md\n"),
+            "{}",
+            diff
+        );
+        assert!(
+            diff.contains("+- run `cargo test` before committing"),
+            "{}",
+            diff
+        );
+
+        let unchanged = write(&path, "# Project\n- run `cargo test` before committing\n");
+        assert_eq!(unchanged.unwrap().diff.as_deref(), Some(""));
+
+        let oversized = "x".repeat(MAX_MEMORY_BYTES + 1);
+        assert!(write(&path, &oversized).unwrap_err().contains("limit"));
+        assert_eq!(read(&path).unwrap().bytes, 47);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
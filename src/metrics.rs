@@ -0,0 +1,415 @@
+//! Run metrics in Prometheus text exposition format.
+//!
+//! Every `claude::run` call is recorded in the metrics of its server context. The endpoint
+//! and the snapshot file report the global context. When `metrics_addr` is set in
+//! `claude-mcp.config.json`, the server answers `GET /metrics` on that address so operators
+//! can scrape and alert on failure spikes. Deployments that cannot expose a port set
+//! `metrics_file` instead, and a snapshot is written there periodically for node_exporter's
+//! textfile collector or for scripts.
+
+use crate::claude::ClaudeResult;
+use crate::context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Upper bounds (seconds) of the run duration histogram buckets
+const DURATION_BUCKETS: [f64; 10] = [
+    1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+fn default_interval_secs() -> u64 {
+    15
+}
+
+/// Periodic metrics snapshots, configured via `metrics_file` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsFile {
+    /// File replaced with each snapshot
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: SnapshotFormat,
+    /// Seconds between snapshots
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotFormat {
+    /// Prometheus text format, as served at `/metrics`; name the file `*.prom` for the
+    /// textfile collector
+    #[default]
+    Textfile,
+    /// One JSON object
+    Json,
+}
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    runs_started: AtomicU64,
+    runs_succeeded: AtomicU64,
+    runs_in_flight: AtomicI64,
+    output_bytes: AtomicU64,
+    input_tokens: AtomicU64,
+    output_tokens: AtomicU64,
+    state: Mutex<MetricsState>,
+}
+
+/// Values that are not plain counters
+#[derive(Default)]
+struct MetricsState {
+    runs_failed: BTreeMap<&'static str, u64>,
+    duration_buckets: [u64; DURATION_BUCKETS.len()],
+    duration_sum: f64,
+    duration_count: u64,
+    cost_usd: f64,
+}
+
+/// Metrics of the current server context
+pub(crate) fn metrics() -> Arc<Metrics> {
+    context::current().metrics()
+}
+
+impl Metrics {
+    pub(crate) fn run_started(&self) {
+        self.runs_started.fetch_add(1, Ordering::Relaxed);
+        self.runs_in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a finished run; `result` is `None` when the run could not be executed at all
+    pub(crate) fn run_finished(&self, result: Option<&ClaudeResult>, elapsed: Duration) {
+        self.runs_in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let secs = elapsed.as_secs_f64();
+        for (bucket, bound) in state.duration_buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        state.duration_sum += secs;
+        state.duration_count += 1;
+
+        let Some(result) = result else {
+            *state.runs_failed.entry("spawn").or_default() += 1;
+            return;
+        };
+        if result.success {
+            self.runs_succeeded.fetch_add(1, Ordering::Relaxed);
+        } else {
+            *state.runs_failed.entry(error_kind(result)).or_default() += 1;
+        }
+        if let Some(cost) = result.total_cost_usd {
+            state.cost_usd += cost;
+        }
+        drop(state);
+
+        self.output_bytes
+            .fetch_add(result.agent_messages.len() as u64, Ordering::Relaxed);
+        if let Some(usage) = &result.usage {
+            self.input_tokens
+                .fetch_add(usage.input_tokens, Ordering::Relaxed);
+            self.output_tokens
+                .fetch_add(usage.output_tokens, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn runs_in_flight(&self) -> i64 {
+        self.runs_in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Mean duration of the runs recorded so far
+    pub(crate) fn mean_run_duration(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        (state.duration_count > 0)
+            .then(|| Duration::from_secs_f64(state.duration_sum / state.duration_count as f64))
+    }
+
+    /// Render all metrics in Prometheus text exposition format
+    pub(crate) fn render(&self) -> String {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "claude_mcp_runs_started_total",
+            "Runs started",
+            self.runs_started.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "claude_mcp_runs_succeeded_total",
+            "Runs that completed successfully",
+            self.runs_succeeded.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP claude_mcp_runs_failed_total Failed runs by error kind"
+        );
+        let _ = writeln!(out, "# TYPE claude_mcp_runs_failed_total counter");
+        for (kind, count) in &state.runs_failed {
+            let _ = writeln!(
+                out,
+                "claude_mcp_runs_failed_total{{kind=\"{}\"}} {}",
+                kind, count
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP claude_mcp_runs_in_flight Runs currently executing"
+        );
+        let _ = writeln!(out, "# TYPE claude_mcp_runs_in_flight gauge");
+        let _ = writeln!(
+            out,
+            "claude_mcp_runs_in_flight {}",
+            self.runs_in_flight.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP claude_mcp_run_duration_seconds Run wall-clock duration"
+        );
+        let _ = writeln!(out, "# TYPE claude_mcp_run_duration_seconds histogram");
+        for (bound, count) in DURATION_BUCKETS.iter().zip(state.duration_buckets) {
+            let _ = writeln!(
+                out,
+                "claude_mcp_run_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "claude_mcp_run_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            state.duration_count
+        );
+        let _ = writeln!(
+            out,
+            "claude_mcp_run_duration_seconds_sum {}",
+            state.duration_sum
+        );
+        let _ = writeln!(
+            out,
+            "claude_mcp_run_duration_seconds_count {}",
+            state.duration_count
+        );
+
+        counter(
+            &mut out,
+            "claude_mcp_output_bytes_total",
+            "Bytes of agent text returned",
+            self.output_bytes.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "claude_mcp_input_tokens_total",
+            "Input tokens reported by the CLI",
+            self.input_tokens.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "claude_mcp_output_tokens_total",
+            "Output tokens reported by the CLI",
+            self.output_tokens.load(Ordering::Relaxed),
+        );
+        let _ = writeln!(
+            out,
+            "# HELP claude_mcp_cost_usd_total Cost reported by the CLI"
+        );
+        let _ = writeln!(out, "# TYPE claude_mcp_cost_usd_total counter");
+        let _ = writeln!(out, "claude_mcp_cost_usd_total {}", state.cost_usd);
+
+        out
+    }
+
+    /// All metrics as one JSON object
+    pub(crate) fn snapshot(&self) -> Value {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        json!({
+            "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "runs_started": self.runs_started.load(Ordering::Relaxed),
+            "runs_succeeded": self.runs_succeeded.load(Ordering::Relaxed),
+            "runs_failed": state.runs_failed,
+            "runs_in_flight": self.runs_in_flight.load(Ordering::Relaxed),
+            "run_duration_seconds": {
+                "sum": state.duration_sum,
+                "count": state.duration_count,
+            },
+            "output_bytes": self.output_bytes.load(Ordering::Relaxed),
+            "input_tokens": self.input_tokens.load(Ordering::Relaxed),
+            "output_tokens": self.output_tokens.load(Ordering::Relaxed),
+            "cost_usd": state.cost_usd,
+        })
+    }
+}
+
+/// Write a snapshot to `config.path` every `interval_secs` until the process exits
+pub async fn write_snapshots(config: MetricsFile) {
+    info!(
+        "writing metrics snapshots to {} every {}s",
+        config.path.display(),
+        config.interval_secs
+    );
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        let contents = match config.format {
+            SnapshotFormat::Textfile => metrics().render(),
+            SnapshotFormat::Json => format!("{}\n", metrics().snapshot()),
+        };
+        if let Err(e) = replace_file(&config.path, &contents) {
+            warn!(
+                "failed to write metrics snapshot {}: {}",
+                config.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Write beside `path` and rename over it, so readers never see a partial snapshot
+fn replace_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut staging = path.as_os_str().to_os_string();
+    staging.push(format!(".{}.tmp", std::process::id()));
+    let staging = PathBuf::from(staging);
+    std::fs::write(&staging, contents)
+        .and_then(|()| std::fs::rename(&staging, path))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&staging);
+        })
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Coarse failure class used as the `kind` label
+fn error_kind(result: &ClaudeResult) -> &'static str {
+    let error = result.error.as_deref().unwrap_or_default();
+    if error.contains("timed out") {
+        "timeout"
+    } else if error.contains("parser limit") {
+        "parse_limit"
+    } else if error.contains("JSON parse error") || error.contains("byte limit") {
+        "parse"
+    } else if error.starts_with("Claude error:") {
+        "cli_error"
+    } else if error.contains("exit code") {
+        "exit_status"
+    } else {
+        "other"
+    }
+}
+
+/// Serve `GET /metrics` on `addr` until the process exits
+pub async fn serve(addr: SocketAddr) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("failed to bind metrics endpoint on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("serving metrics on http://{}/metrics", addr);
+
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(async move {
+            // Only the request line matters; requests are tiny
+            let mut request = [0u8; 1024];
+            let Ok(read) = stream.read(&mut request).await else {
+                return;
+            };
+            let request = String::from_utf8_lossy(&request[..read]);
+            let (status, body) = if request.starts_with("GET /metrics ") {
+                ("200 OK", metrics().render())
+            } else {
+                ("404 Not Found", "not found\n".to_string())
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claude::Usage;
+
+    #[test]
+    fn test_render_counts_runs_by_outcome() {
+        let metrics = Metrics::default();
+        metrics.run_started();
+        metrics.run_started();
+        metrics.run_finished(
+            Some(&ClaudeResult {
+                success: true,
+                agent_messages: "done".to_string(),
+                usage: Some(Usage {
+                    input_tokens: 10,
+                    output_tokens: 5,
+                    ..Default::default()
+                }),
+                total_cost_usd: Some(0.25),
+                ..Default::default()
+            }),
+            Duration::from_secs(3),
+        );
+        metrics.run_finished(
+            Some(&ClaudeResult {
+                error: Some("Claude execution timed out after 5 seconds".to_string()),
+                ..Default::default()
+            }),
+            Duration::from_secs(5),
+        );
+
+        let text = metrics.render();
+        assert!(text.contains("claude_mcp_runs_started_total 2"));
+        assert!(text.contains("claude_mcp_runs_succeeded_total 1"));
+        assert!(text.contains("claude_mcp_runs_failed_total{kind=\"timeout\"} 1"));
+        assert!(text.contains("claude_mcp_runs_in_flight 0"));
+        assert!(text.contains("claude_mcp_run_duration_seconds_bucket{le=\"1\"} 0"));
+        assert!(text.contains("claude_mcp_run_duration_seconds_bucket{le=\"5\"} 2"));
+        assert!(text.contains("claude_mcp_output_bytes_total 4"));
+        assert!(text.contains("claude_mcp_input_tokens_total 10"));
+        assert!(text.contains("claude_mcp_cost_usd_total 0.25"));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot["runs_started"], 2);
+        assert_eq!(snapshot["runs_failed"]["timeout"], 1);
+        assert_eq!(snapshot["run_duration_seconds"]["count"], 2);
+        assert_eq!(snapshot["cost_usd"], 0.25);
+    }
+
+    #[test]
+    fn test_snapshot_file_is_replaced_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("claude_mcp.prom");
+        replace_file(&path, "first\n").unwrap();
+        replace_file(&path, "second\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second\n");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,323 @@
+//! Mechanical migrations for `claude_migrate`, split into chunks of files.
+//!
+//! The files to migrate are listed by the caller or matched by a glob in the working
+//! directory and any `EXTRA_DIRS`. They are split into chunks of `CHUNK_SIZE`, each chunk is
+//! a run with the same instructions, and chunks run in parallel up to the run queue's
+//! `max_concurrent`. Each file's contents are read before and after its chunk, so the
+//! outcome per file comes with its own diff.
+//!
+//! The job is saved as JSON in the temp state directory after every chunk. Calling the tool
+//! again with its `JOB_ID` runs only the files that are still pending or failed.
+
+use crate::claude::ClaudeResult;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Files per chunk when the caller does not say
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 10;
+/// Most files per chunk a caller can ask for
+pub(crate) const MAX_CHUNK_SIZE: usize = 100;
+/// Most files in one job
+pub(crate) const MAX_FILES: usize = 2000;
+/// Chunks running at once without a `run_queue`
+pub(crate) const DEFAULT_PARALLEL_CHUNKS: usize = 4;
+
+/// Where a file stands in its job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FileStatus {
+    Pending,
+    Changed,
+    Unchanged,
+    Failed,
+}
+
+/// A file of a job and what its chunk did to it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MigratedFile {
+    /// Relative to the working directory, or absolute for files in `EXTRA_DIRS`
+    pub path: String,
+    pub status: FileStatus,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub session_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A migration, as saved between calls
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Job {
+    pub id: String,
+    pub instructions: String,
+    pub working_dir: PathBuf,
+    pub extra_dirs: Vec<PathBuf>,
+    pub chunk_size: usize,
+    pub files: Vec<MigratedFile>,
+}
+
+/// Outcome of `claude_migrate`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct Migration {
+    pub job_id: String,
+    /// Whether every file was migrated without a failure
+    pub success: bool,
+    pub changed: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+    pub pending: usize,
+    pub files: Vec<MigratedFile>,
+}
+
+impl Job {
+    pub(crate) fn new(
+        instructions: String,
+        working_dir: PathBuf,
+        extra_dirs: Vec<PathBuf>,
+        chunk_size: usize,
+        paths: Vec<String>,
+    ) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            instructions,
+            working_dir,
+            extra_dirs,
+            chunk_size,
+            files: paths
+                .into_iter()
+                .map(|path| MigratedFile {
+                    path,
+                    status: FileStatus::Pending,
+                    session_id: String::new(),
+                    diff: None,
+                    error: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// The job saved under `id`
+    pub(crate) fn load(id: &str) -> Result<Self, String> {
+        let path = job_path(id)?;
+        let json = std::fs::read_to_string(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => format!("unknown JOB_ID '{}'", id),
+            _ => format!("failed to read {}: {}", path.display(), e),
+        })?;
+        serde_json::from_str(&json).map_err(|e| format!("{} is corrupt: {}", path.display(), e))
+    }
+
+    pub(crate) fn save(&self) -> Result<(), String> {
+        let path = job_path(&self.id)?;
+        let write = || -> std::io::Result<()> {
+            std::fs::create_dir_all(state_dir())?;
+            // Write beside the file and rename so a crash never leaves half a job behind
+            let staging = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+            std::fs::write(&staging, serde_json::to_vec_pretty(self)?)?;
+            std::fs::rename(&staging, &path).inspect_err(|_| {
+                let _ = std::fs::remove_file(&staging);
+            })
+        };
+        write().map_err(|e| format!("failed to save {}: {}", path.display(), e))
+    }
+
+    /// Indices of the files still to migrate, in chunks
+    pub(crate) fn chunks(&self) -> Vec<Vec<usize>> {
+        let todo: Vec<usize> = (0..self.files.len())
+            .filter(|&i| {
+                matches!(
+                    self.files[i].status,
+                    FileStatus::Pending | FileStatus::Failed
+                )
+            })
+            .collect();
+        todo.chunks(self.chunk_size.max(1))
+            .map(<[usize]>::to_vec)
+            .collect()
+    }
+
+    /// Paths of the files at `chunk`
+    pub(crate) fn paths(&self, chunk: &[usize]) -> Vec<String> {
+        chunk.iter().map(|&i| self.files[i].path.clone()).collect()
+    }
+
+    /// Contents of the files at `chunk`; a missing or unreadable file counts as empty
+    pub(crate) fn contents(&self, chunk: &[usize]) -> Vec<String> {
+        chunk
+            .iter()
+            .map(|&i| {
+                std::fs::read_to_string(self.working_dir.join(&self.files[i].path))
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Record the outcome of the run for `chunk`, given the files' contents `before` it
+    pub(crate) fn settle(
+        &mut self,
+        chunk: &[usize],
+        before: &[String],
+        outcome: Result<&ClaudeResult, String>,
+    ) {
+        let after = self.contents(chunk);
+        for ((&i, before), after) in chunk.iter().zip(before).zip(after) {
+            let file = &mut self.files[i];
+            file.diff = None;
+            file.error = None;
+            match outcome {
+                Ok(result) if result.success => {
+                    file.session_id = result.session_id.clone();
+                    file.status = if *before == after {
+                        FileStatus::Unchanged
+                    } else {
+                        file.diff = Some(
+                            TextDiff::from_lines(before.as_str(), after.as_str())
+                                .unified_diff()
+                                .header(&format!("a/{}", file.path), &format!("b/{}", file.path))
+                                .to_string(),
+                        );
+                        FileStatus::Changed
+                    };
+                }
+                Ok(result) => {
+                    file.session_id = result.session_id.clone();
+                    file.status = FileStatus::Failed;
+                    file.error = Some(
+                        result
+                            .error
+                            .clone()
+                            .unwrap_or_else(|| "the run failed".to_string()),
+                    );
+                }
+                Err(ref e) => {
+                    file.status = FileStatus::Failed;
+                    file.error = Some(e.clone());
+                }
+            }
+        }
+    }
+
+    pub(crate) fn report(&self) -> Migration {
+        let count = |status| self.files.iter().filter(|f| f.status == status).count();
+        let report = Migration {
+            job_id: self.id.clone(),
+            changed: count(FileStatus::Changed),
+            unchanged: count(FileStatus::Unchanged),
+            failed: count(FileStatus::Failed),
+            pending: count(FileStatus::Pending),
+            files: self.files.clone(),
+            ..Default::default()
+        };
+        Migration {
+            success: report.failed == 0 && report.pending == 0,
+            ..report
+        }
+    }
+}
+
+fn state_dir() -> PathBuf {
+    std::env::temp_dir().join("claude-mcp-rs")
+}
+
+/// Where job `id` is saved; only ids this module handed out are accepted
+fn job_path(id: &str) -> Result<PathBuf, String> {
+    let id = uuid::Uuid::parse_str(id).map_err(|_| format!("unknown JOB_ID '{}'", id))?;
+    Ok(state_dir().join(format!("migration-{}.json", id)))
+}
+
+/// Files matching `pattern` (a git glob such as `src/**/*.ts`) in `dir`, tracked or not but
+/// never ignored, as paths relative to `dir`
+pub(crate) async fn matching(dir: &Path, pattern: &str) -> Result<Vec<String>, String> {
+    let mut command = Command::new("git");
+    command
+        .args([
+            "ls-files",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+            "--",
+        ])
+        .arg(format!(":(glob){}", pattern))
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    let output = crate::snapshots::output_of(command, "git ls-files")
+        .await
+        .map_err(|e| {
+            format!(
+                "PATTERN needs a git repository in {}: {:#}",
+                dir.display(),
+                e
+            )
+        })?;
+    let mut files: Vec<String> = output.lines().map(str::to_string).collect();
+    files.dedup();
+    Ok(files)
+}
+
+/// The request to apply `instructions` to `paths`
+pub(crate) fn prompt(instructions: &str, paths: &[String]) -> String {
+    let mut prompt = format!(
+        "Apply the following migration to each of the files listed below, and only to \
+         them. Edit every file that needs it; leave a file untouched if it needs no change.\
+         \n\n{}\n\nFiles:\n",
+        instructions.trim()
+    );
+    for path in paths {
+        prompt.push_str(&format!("- {}\n", path));
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_settles_chunks_and_resumes_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(dir.path().join(name), "old\n").unwrap();
+        }
+        let paths = ["a.txt", "b.txt", "c.txt"].map(String::from).to_vec();
+        let mut job = Job::new("Rename old".into(), dir.path().into(), vec![], 2, paths);
+        let chunks = job.chunks();
+        assert_eq!(chunks, vec![vec![0, 1], vec![2]]);
+
+        let before = job.contents(&chunks[0]);
+        std::fs::write(dir.path().join("a.txt"), "new\n").unwrap();
+        let result = ClaudeResult {
+            success: true,
+            session_id: "s1".to_string(),
+            ..Default::default()
+        };
+        job.settle(&chunks[0], &before, Ok(&result));
+        let before = job.contents(&chunks[1]);
+        job.settle(&chunks[1], &before, Err("server busy".to_string()));
+
+        let report = job.report();
+        assert_eq!(
+            (
+                report.changed,
+                report.unchanged,
+                report.failed,
+                report.success
+            ),
+            (1, 1, 1, false)
+        );
+        assert_eq!(
+            report.files[0].diff.as_deref(),
+            Some("--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-old\n+new\n")
+        );
+        assert_eq!(report.files[2].error.as_deref(), Some("server busy"));
+
+        job.save().unwrap();
+        let resumed = Job::load(&job.id).unwrap();
+        assert_eq!(resumed.chunks(), vec![vec![2]]);
+        assert!(Job::load("../etc/passwd").is_err());
+        let _ = std::fs::remove_file(job_path(&job.id).unwrap());
+    }
+}
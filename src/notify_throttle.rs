@@ -0,0 +1,121 @@
+//! Rate limit for the log notifications a run sends.
+//!
+//! A chatty run produces an event for every line of CLI output, which can flood a client
+//! reading notifications over a slow connection. With `max_notifications_per_sec` in
+//! `claude-mcp.config.json`, each run sends at most that many per second, with bursts of up
+//! to a second's worth. Messages over the rate are held, not dropped, and go out together as
+//! one notification once the rate allows another.
+
+use std::time::{Duration, Instant};
+
+/// Lines of a batch spelled out before the rest are only counted
+const MAX_BATCH_LINES: usize = 20;
+
+/// Token bucket of one run's notifications, holding what it cannot send yet
+#[derive(Debug)]
+pub(crate) struct Throttle<L> {
+    per_sec: u32,
+    tokens: f64,
+    refilled: Instant,
+    held: Vec<(L, String)>,
+}
+
+impl<L> Throttle<L> {
+    pub(crate) fn new(per_sec: u32, now: Instant) -> Self {
+        Self {
+            per_sec,
+            tokens: f64::from(per_sec),
+            refilled: now,
+            held: Vec::new(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * f64::from(self.per_sec)).min(f64::from(self.per_sec));
+        self.refilled = now;
+    }
+
+    /// `message` back when it may be sent now; otherwise it is held for the next batch.
+    /// Once anything is held, later messages queue behind it to keep their order.
+    pub(crate) fn offer(&mut self, level: L, message: String, now: Instant) -> Option<(L, String)> {
+        self.refill(now);
+        if self.held.is_empty() && self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Some((level, message));
+        }
+        self.held.push((level, message));
+        None
+    }
+
+    /// When the held messages may go out, if any are held
+    pub(crate) fn flush_at(&self) -> Option<Instant> {
+        if self.held.is_empty() {
+            return None;
+        }
+        let missing = (1.0 - self.tokens).max(0.0);
+        Some(self.refilled + Duration::from_secs_f64(missing / f64::from(self.per_sec)))
+    }
+
+    /// Take the held messages to send as one notification
+    pub(crate) fn flush(&mut self, now: Instant) -> Vec<(L, String)> {
+        self.refill(now);
+        self.tokens = (self.tokens - 1.0).max(0.0);
+        std::mem::take(&mut self.held)
+    }
+}
+
+/// One notification text for `messages`: a single message as is, several as a count and
+/// their lines
+pub(crate) fn batch_message(messages: &[String]) -> String {
+    if let [message] = messages {
+        return message.clone();
+    }
+    let mut text = format!("{} events:", messages.len());
+    for message in messages.iter().take(MAX_BATCH_LINES) {
+        text.push('\n');
+        text.push_str(message);
+    }
+    if messages.len() > MAX_BATCH_LINES {
+        text.push_str(&format!("\n... {} more", messages.len() - MAX_BATCH_LINES));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_over_the_rate_are_held_and_batched() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(2, start);
+        assert!(throttle.offer(0, "a".to_string(), start).is_some());
+        assert!(throttle.offer(0, "b".to_string(), start).is_some());
+        assert_eq!(throttle.flush_at(), None);
+        assert!(throttle.offer(1, "c".to_string(), start).is_none());
+        assert!(throttle.offer(0, "d".to_string(), start).is_none());
+        // A token comes back after half a second at 2 per second
+        assert_eq!(
+            throttle.flush_at(),
+            Some(start + Duration::from_millis(500))
+        );
+
+        let later = start + Duration::from_millis(500);
+        // Still queued behind the held messages, though a token is available
+        assert!(throttle.offer(0, "e".to_string(), later).is_none());
+        let batch = throttle.flush(later);
+        let messages: Vec<String> = batch.into_iter().map(|(_, message)| message).collect();
+        assert_eq!(batch_message(&messages), "3 events:\nc\nd\ne");
+        assert_eq!(throttle.flush_at(), None);
+
+        let quiet = later + Duration::from_secs(5);
+        assert_eq!(
+            throttle.offer(0, "f".to_string(), quiet),
+            Some((0, "f".to_string()))
+        );
+        let many: Vec<String> = (0..25).map(|i| i.to_string()).collect();
+        assert!(batch_message(&many).ends_with("\n19\n... 5 more"));
+    }
+}
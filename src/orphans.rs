@@ -0,0 +1,210 @@
+//! Cleanup of CLI processes left behind by a server that did not shut down cleanly.
+//!
+//! Every spawned CLI process gets a PID record in the state directory for as long as the run
+//! owns it. A server that crashes or is killed never removes its records, and its CLI
+//! processes keep running, possibly still editing code. At startup the records are scanned:
+//! when the server that wrote one is gone and the recorded process is still the one that was
+//! spawned (same start time, so a recycled PID is never hit), it is killed, together with
+//! its process group when it leads one. Records of servers still running are left alone.
+//! Unix only; elsewhere nothing is recorded.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// What is known about a spawned CLI process and the server that spawned it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Record {
+    pid: u32,
+    /// Start time of the process, to tell it from a later one with the same PID
+    started: String,
+    /// Process group led by the CLI, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pgid: Option<u32>,
+    server_pid: u32,
+    server_started: String,
+}
+
+/// Removes the PID record when the run is done with its process
+#[derive(Debug)]
+pub(crate) struct PidRecord(PathBuf);
+
+impl Drop for PidRecord {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn records_dir() -> PathBuf {
+    std::env::temp_dir().join("claude-mcp-rs").join("pids")
+}
+
+/// Record the CLI process `pid` as spawned by this server. Failures only cost the cleanup
+/// after a crash, so they are logged and otherwise ignored.
+pub(crate) fn record(pid: Option<u32>) -> Option<PidRecord> {
+    record_in(&records_dir(), pid?)
+}
+
+fn record_in(dir: &Path, pid: u32) -> Option<PidRecord> {
+    let server_pid = std::process::id();
+    let record = Record {
+        pid,
+        started: start_time(pid)?,
+        pgid: process_group(pid),
+        server_pid,
+        server_started: start_time(server_pid)?,
+    };
+    let path = dir.join(format!("{}.json", pid));
+    let written = std::fs::create_dir_all(dir)
+        .and_then(|()| std::fs::write(&path, serde_json::to_vec(&record).unwrap_or_default()));
+    match written {
+        Ok(()) => Some(PidRecord(path)),
+        Err(e) => {
+            tracing::warn!(pid, "failed to write PID record {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Kill the CLI processes of servers that are gone and remove their records
+pub(crate) fn clean_up() {
+    let killed = clean_up_in(&records_dir());
+    if killed > 0 {
+        tracing::warn!(
+            killed,
+            "killed claude processes orphaned by a previous server"
+        );
+    }
+}
+
+/// [`clean_up`] for the records in `dir`, returning how many processes were killed
+fn clean_up_in(dir: &Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut killed = 0;
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let record: Option<Record> = std::fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok());
+        let Some(record) = record else {
+            let _ = std::fs::remove_file(&path);
+            continue;
+        };
+        if is_running(record.server_pid, &record.server_started) {
+            // Its server is still running and owns the process
+            continue;
+        }
+        if is_running(record.pid, &record.started) {
+            tracing::info!(pid = record.pid, "killing orphaned claude process");
+            kill(&record);
+            killed += 1;
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+    killed
+}
+
+/// Whether the process `pid` that started at `started` (as from [`start_time`]) still runs
+pub(crate) fn is_running(pid: u32, started: &str) -> bool {
+    start_time(pid).is_some_and(|time| time == started)
+}
+
+/// When `pid` started, in a form only compared for equality; `None` if it is not running
+#[cfg(target_os = "linux")]
+pub(crate) fn start_time(pid: u32) -> Option<String> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces and parentheses; the fields after it do not.
+    // `starttime` is field 22, the 20th after the name.
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(19).map(str::to_string)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub(crate) fn start_time(pid: u32) -> Option<String> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "lstart=", "-p", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    let started = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !started.is_empty()).then_some(started)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn start_time(_pid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+fn process_group(pid: u32) -> Option<u32> {
+    // SAFETY: getpgid only reads the process table
+    let pgid = unsafe { libc::getpgid(pid as libc::pid_t) };
+    // A group the CLI shares with its server would take down unrelated processes
+    (pgid == pid as libc::pid_t).then_some(pid)
+}
+
+#[cfg(not(unix))]
+fn process_group(_pid: u32) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn kill(record: &Record) {
+    let target = match record.pgid {
+        Some(pgid) => -(pgid as libc::pid_t),
+        None => record.pid as libc::pid_t,
+    };
+    // SAFETY: the start time check above makes sure the PID still names the recorded process
+    unsafe { libc::kill(target, libc::SIGKILL) };
+}
+
+#[cfg(not(unix))]
+fn kill(_record: &Record) {}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_up_kills_only_processes_of_servers_that_are_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut orphan = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let mut owned = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+
+        // A record of this (running) server keeps its process alive
+        let record = record_in(dir.path(), owned.id()).unwrap();
+        // One written by a server that is no longer running
+        let path = dir.path().join(format!("{}.json", orphan.id()));
+        let gone = Record {
+            pid: orphan.id(),
+            started: start_time(orphan.id()).unwrap(),
+            pgid: None,
+            server_pid: u32::MAX / 2,
+            server_started: "0".to_string(),
+        };
+        std::fs::write(&path, serde_json::to_vec(&gone).unwrap()).unwrap();
+        // A recycled PID is not the process that was recorded
+        let recycled = Record {
+            started: "0".to_string(),
+            ..gone.clone()
+        };
+        let recycled_path = dir.path().join("recycled.json");
+        std::fs::write(&recycled_path, serde_json::to_vec(&recycled).unwrap()).unwrap();
+
+        assert_eq!(clean_up_in(dir.path()), 1);
+        assert!(!orphan.wait().unwrap().success());
+        assert!(!path.exists() && !recycled_path.exists());
+        assert_eq!(owned.try_wait().unwrap(), None);
+
+        drop(record);
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+        owned.kill().unwrap();
+        owned.wait().unwrap();
+    }
+}
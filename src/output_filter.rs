@@ -0,0 +1,259 @@
+//! Post-processing of agent text before it is returned.
+//!
+//! `output_filters` in `claude-mcp.config.json` lists steps applied in order to
+//! `agent_messages`: built-ins that strip ANSI escapes, collapse whitespace, or summarize the
+//! text with a separate run, and external commands that read the text on stdin and write the
+//! replacement to stdout. Teams use them to normalize output for downstream ingestion. When
+//! a step fails, the unfiltered text is returned with a warning.
+
+use crate::claude::{self, Options};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// How long an external filter command may take
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Prompt for the `summarize` step; the agent text follows it
+const SUMMARIZE_PROMPT: &str = "Summarize the following response concisely. Keep every \
+    conclusion, file path, command, and open question; drop narration of intermediate \
+    steps. Reply with the summary only.";
+
+/// One step of `output_filters`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum OutputFilter {
+    /// Remove ANSI escape sequences such as colors
+    StripAnsi,
+    /// Trim trailing spaces, squeeze runs of blank lines to one, and trim the ends
+    CollapseWhitespace,
+    /// Replace the text with a summary written by a separate run in the same working
+    /// directory with the same CLI arguments
+    Summarize,
+    /// Program and arguments that read the text on stdin and print the replacement
+    Command(Vec<String>),
+}
+
+/// Apply `filters` to `text` in order. `opts` are the options of the run that produced it.
+pub(crate) async fn apply(
+    filters: &[OutputFilter],
+    mut text: String,
+    opts: &Options,
+) -> Result<String> {
+    for filter in filters {
+        text = match filter {
+            OutputFilter::StripAnsi => strip_ansi(&text),
+            OutputFilter::CollapseWhitespace => collapse_whitespace(&text),
+            OutputFilter::Summarize => summarize(text, opts).await?,
+            OutputFilter::Command(command) => run_command(command, &text, opts).await?,
+        };
+    }
+    Ok(text)
+}
+
+/// Strip ANSI escape sequences, turn `\r\n` and lone `\r` into `\n`, and drop other control
+/// characters except tabs. Applied to extracted text unless `sanitize_text` is off, because
+/// these characters corrupt TOON and JSON rendering in some clients.
+pub(crate) fn sanitize(text: String) -> String {
+    if !text
+        .chars()
+        .any(|c| c.is_control() && c != '\n' && c != '\t')
+    {
+        return text;
+    }
+    let stripped = strip_ansi(&text);
+    let mut out = String::with_capacity(stripped.len());
+    let mut chars = stripped.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                out.push('\n');
+            }
+            '\n' | '\t' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Remove CSI (`ESC [ ... final`) and OSC (`ESC ] ... BEL` or `ESC ] ... ESC \`) sequences
+/// and any other two-character escape
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                // Parameters and intermediates run until a final byte in @..~
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' || (c == '\u{1b}' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut blank_run = false;
+    for line in text.trim().lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_run = true;
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(if blank_run { "\n\n" } else { "\n" });
+        }
+        blank_run = false;
+        out.push_str(line);
+    }
+    out
+}
+
+async fn summarize(text: String, opts: &Options) -> Result<String> {
+    if text.trim().is_empty() {
+        return Ok(text);
+    }
+    let result = claude::run(Options {
+        prompt: format!("{}\n\n{}", SUMMARIZE_PROMPT, text),
+        session_id: None,
+        events: None,
+        response_cache: None,
+        max_output_bytes: None,
+        ..opts.clone()
+    })
+    .await?;
+    if !result.success {
+        bail!(
+            "summarize run failed: {}",
+            result.error.as_deref().unwrap_or("no error reported")
+        );
+    }
+    Ok(result.agent_messages)
+}
+
+async fn run_command(command: &[String], text: &str, opts: &Options) -> Result<String> {
+    let (program, args) = command.split_first().context("filter command is empty")?;
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .current_dir(&opts.working_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to start filter command '{}'", program))?;
+    let mut stdin = child.stdin.take().context("filter command has no stdin")?;
+    let input = text.to_string();
+    // Feed stdin while reading stdout so a filter that streams cannot deadlock on a full pipe
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(input.as_bytes()).await;
+    });
+    let output = tokio::time::timeout(COMMAND_TIMEOUT, child.wait_with_output())
+        .await
+        .with_context(|| {
+            format!(
+                "filter command '{}' timed out after {}s",
+                program,
+                COMMAND_TIMEOUT.as_secs()
+            )
+        })??;
+    let _ = writer.await;
+    if !output.status.success() {
+        bail!(
+            "filter command '{}' exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout)
+        .with_context(|| format!("filter command '{}' printed invalid UTF-8", program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_names_steps_in_snake_case() {
+        let filters: Vec<OutputFilter> = serde_json::from_str(
+            r#"["strip_ansi", "collapse_whitespace", "summarize", {"command": ["jq", "-R"]}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            filters,
+            [
+                OutputFilter::StripAnsi,
+                OutputFilter::CollapseWhitespace,
+                OutputFilter::Summarize,
+                OutputFilter::Command(vec!["jq".to_string(), "-R".to_string()]),
+            ]
+        );
+        assert!(serde_json::from_str::<OutputFilter>(r#""uppercase""#).is_err());
+    }
+
+    #[test]
+    fn test_builtin_filters_normalize_text() {
+        assert_eq!(
+            strip_ansi("\u{1b}[1;31merror\u{1b}[0m: \u{1b}]8;;http://x\u{7}link\u{1b}]8;;\u{1b}\\"),
+            "error: link"
+        );
+        assert_eq!(
+            collapse_whitespace("\n\n  first  \n\n\n\nsecond\t\nthird\n\n"),
+            "first\n\nsecond\nthird"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_normalizes_line_endings_and_controls() {
+        assert_eq!(
+            sanitize("\u{1b}[33mwarn\u{1b}[0m\r\nnext\rlast\u{7}\tend\u{0}".to_string()),
+            "warn\nnext\nlast\tend"
+        );
+        assert_eq!(sanitize("plain\ttext\n".to_string()), "plain\ttext\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_steps_run_in_order_through_commands() {
+        let opts = Options {
+            working_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let filters = [
+            OutputFilter::StripAnsi,
+            OutputFilter::Command(vec!["tr".to_string(), "a-z".to_string(), "A-Z".to_string()]),
+        ];
+        let filtered = apply(&filters, "\u{1b}[32mdone\u{1b}[0m".to_string(), &opts)
+            .await
+            .unwrap();
+        assert_eq!(filtered, "DONE");
+
+        let failing = [OutputFilter::Command(vec!["false".to_string()])];
+        let err = apply(&failing, "text".to_string(), &opts)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("exited with"), "{}", err);
+    }
+}
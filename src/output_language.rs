@@ -0,0 +1,68 @@
+//! Language of the agent's answers.
+//!
+//! With `output_language` in `claude-mcp.config.json`, in a profile, or per call with
+//! `OUTPUT_LANGUAGE`, a directive to answer in that language is appended to the system
+//! prompt, so a team gets summaries and explanations in one language whatever the prompts
+//! are written in. Code, identifiers, and quoted output are left as they are.
+
+/// Directive appended to the system prompt for `language`
+pub(crate) fn directive(language: &str) -> String {
+    format!(
+        "Write all answers, summaries, and explanations in {}. Keep code, identifiers, \
+         commands, file paths, and quoted output unchanged.",
+        language.trim()
+    )
+}
+
+/// Add `text` to the appended system prompt in `args`. The CLI keeps only the last
+/// `--append-system-prompt`, so an existing one is extended rather than followed by another.
+pub(crate) fn append_system_prompt(args: &mut Vec<String>, text: &str) {
+    const FLAG: &str = "--append-system-prompt";
+    let last = args
+        .iter()
+        .rposition(|arg| arg == FLAG || arg.starts_with("--append-system-prompt="));
+    match last {
+        Some(at) if args[at] == FLAG && at + 1 < args.len() => {
+            args[at + 1] = format!("{}\n\n{}", args[at + 1], text);
+        }
+        Some(at) if args[at] != FLAG => {
+            args[at] = format!("{}\n\n{}", args[at], text);
+        }
+        _ => args.extend([FLAG.to_string(), text.to_string()]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directive_extends_the_appended_system_prompt() {
+        let mut args = vec!["--model".to_string(), "opus".to_string()];
+        append_system_prompt(&mut args, &directive("German"));
+        assert_eq!(args[2], "--append-system-prompt");
+        assert!(args[3].starts_with("Write all answers, summaries, and explanations in German."));
+
+        let mut args = vec![
+            "--append-system-prompt".to_string(),
+            "Be terse.".to_string(),
+            "--verbose".to_string(),
+        ];
+        append_system_prompt(&mut args, "Answer in Polish.");
+        assert_eq!(
+            args,
+            [
+                "--append-system-prompt",
+                "Be terse.\n\nAnswer in Polish.",
+                "--verbose"
+            ]
+        );
+
+        let mut args = vec!["--append-system-prompt=Be terse.".to_string()];
+        append_system_prompt(&mut args, "Answer in Polish.");
+        assert_eq!(
+            args,
+            ["--append-system-prompt=Be terse.\n\nAnswer in Polish."]
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Structural limits on stream-json events, checked before a line is parsed.
+//!
+//! A line within the byte limit can still be pathological: thousands of nested arrays, or an
+//! object with hundreds of thousands of keys, each of which costs an allocation once parsed.
+//! The raw bytes are scanned first (linear, no allocation) and a line that goes past
+//! `parser_limits` fails the run with a `parser limit` error instead of reaching serde_json.
+
+use serde::Deserialize;
+use std::fmt;
+
+/// Nesting serde_json itself refuses to go beyond
+pub(crate) const SERDE_RECURSION_LIMIT: usize = 128;
+
+fn default_max_depth() -> usize {
+    64
+}
+
+fn default_max_keys() -> usize {
+    100_000
+}
+
+/// Limits per event line, configured via `parser_limits` in `claude-mcp.config.json`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParserLimits {
+    /// Arrays and objects nested inside each other
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+    /// Object keys across the whole event
+    #[serde(default = "default_max_keys")]
+    pub max_keys: usize,
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: default_max_depth(),
+            max_keys: default_max_keys(),
+        }
+    }
+}
+
+/// Which limit a line went past
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LimitExceeded {
+    Depth(usize),
+    Keys(usize),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Depth(limit) => write!(f, "nesting deeper than {} levels", limit),
+            LimitExceeded::Keys(limit) => write!(f, "more than {} object keys", limit),
+        }
+    }
+}
+
+impl ParserLimits {
+    /// Scan `line` for nesting and keys outside strings. Malformed JSON passes; the parser
+    /// reports it.
+    pub(crate) fn check(&self, line: &[u8]) -> Result<(), LimitExceeded> {
+        let mut depth = 0usize;
+        let mut keys = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        for &byte in line {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'{' | b'[' => {
+                    depth += 1;
+                    if depth > self.max_depth {
+                        return Err(LimitExceeded::Depth(self.max_depth));
+                    }
+                }
+                b'}' | b']' => depth = depth.saturating_sub(1),
+                // Every key is followed by a colon, and colons appear nowhere else
+                b':' => {
+                    keys += 1;
+                    if keys > self.max_keys {
+                        return Err(LimitExceeded::Keys(self.max_keys));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nesting_and_keys_outside_strings_are_counted() {
+        let limits = ParserLimits {
+            max_depth: 3,
+            max_keys: 2,
+        };
+        assert_eq!(limits.check(br#"{"a":[{"b":1}]}"#), Ok(()));
+        assert_eq!(
+            limits.check(br#"{"a":[[{"b":1}]]}"#),
+            Err(LimitExceeded::Depth(3))
+        );
+        assert_eq!(
+            limits.check(br#"{"a":1,"b":2,"c":3}"#),
+            Err(LimitExceeded::Keys(2))
+        );
+        // Brackets, colons, and escaped quotes inside strings do not count
+        assert_eq!(
+            limits.check(br#"{"text":"[[[[{\"x\":1,\"y\":2}]]]]: ok"}"#),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_default_depth_stays_below_the_serde_limit() {
+        let nested = format!("{}{}", "[".repeat(200), "]".repeat(200));
+        assert_eq!(
+            ParserLimits::default().check(nested.as_bytes()),
+            Err(LimitExceeded::Depth(64))
+        );
+        assert!(ParserLimits::default().max_depth < SERDE_RECURSION_LIMIT);
+    }
+}
@@ -0,0 +1,155 @@
+//! Platform differences in locating, addressing, and stopping the Claude CLI.
+//!
+//! On Windows the CLI is usually installed as an npm shim (`claude.cmd`). `CreateProcess` does
+//! not search PATH for `.cmd` files, `canonicalize` returns `\\?\` verbatim paths the CLI does
+//! not understand, and killing the shim's `cmd.exe` leaves the node process running. The
+//! helpers here paper over those differences and are no-ops on Unix.
+
+use crate::claude::Invocation;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Extensions tried when PATHEXT is unset
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// Resolve a bare program name to the full path of a Windows executable or script shim.
+/// Given the full path, the standard library runs `.cmd`/`.bat` files through `cmd.exe`
+/// with safe argument escaping.
+pub(crate) fn resolve_program(mut invocation: Invocation) -> Invocation {
+    if cfg!(windows) {
+        let pathext = std::env::var("PATHEXT").unwrap_or_else(|_| DEFAULT_PATHEXT.to_string());
+        if let Some(resolved) = std::env::var_os("PATH")
+            .and_then(|path| find_program(&invocation.program, &path, &pathext))
+        {
+            invocation.program = resolved.into();
+        }
+    }
+    invocation
+}
+
+/// Full path `program` would be started from, for diagnostics: explicit paths as given,
+/// bare names searched on PATH (trying PATHEXT extensions first on Windows)
+pub(crate) fn locate_program(program: &OsStr) -> Option<PathBuf> {
+    let program_path = Path::new(program);
+    if program_path.components().count() != 1 {
+        return program_path.is_file().then(|| program_path.to_path_buf());
+    }
+    let path = std::env::var_os("PATH")?;
+    if cfg!(windows) {
+        let pathext = std::env::var("PATHEXT").unwrap_or_else(|_| DEFAULT_PATHEXT.to_string());
+        if let Some(found) = find_program(program, &path, &pathext) {
+            return Some(found);
+        }
+    }
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Search the `path` list for `program`, trying each `pathext` extension in order
+fn find_program(program: &OsStr, path: &OsStr, pathext: &str) -> Option<PathBuf> {
+    let program_path = Path::new(program);
+    // Explicit paths and names that already carry an extension are left to the OS
+    if program_path.components().count() != 1 || program_path.extension().is_some() {
+        return None;
+    }
+
+    std::env::split_paths(path).find_map(|dir| {
+        pathext
+            .split(';')
+            .filter(|ext| !ext.is_empty())
+            .find_map(|ext| {
+                let mut file_name = program.to_os_string();
+                file_name.push(ext.to_ascii_lowercase());
+                let candidate = dir.join(file_name);
+                candidate.is_file().then_some(candidate)
+            })
+    })
+}
+
+/// Strip the `\\?\` prefix `canonicalize` adds to drive paths on Windows. Paths that only
+/// work in verbatim form (beyond MAX_PATH, UNC shares) are returned unchanged.
+pub(crate) fn normalize_path(path: PathBuf) -> PathBuf {
+    #[cfg(windows)]
+    {
+        use std::path::{Component, Prefix};
+
+        const MAX_PATH: usize = 260;
+        if let Some(Component::Prefix(prefix)) = path.components().next() {
+            if matches!(prefix.kind(), Prefix::VerbatimDisk(_)) {
+                let stripped = path
+                    .to_str()
+                    .and_then(|s| s.strip_prefix(r"\\?\"))
+                    .filter(|s| s.len() < MAX_PATH)
+                    .map(PathBuf::from);
+                if let Some(stripped) = stripped {
+                    return stripped;
+                }
+            }
+        }
+    }
+    path
+}
+
+/// Kills the whole process tree rooted at a child when dropped. On Windows `kill_on_drop`
+/// only reaches the shim's `cmd.exe`, so the tree is taken down with `taskkill /T`. On Unix
+/// the CLI is spawned directly and this guard does nothing.
+pub(crate) struct ProcessTreeGuard {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pid: Option<u32>,
+}
+
+impl ProcessTreeGuard {
+    pub(crate) fn new(pid: Option<u32>) -> Self {
+        Self { pid }
+    }
+
+    /// Call once the child has been reaped so a recycled PID is never targeted
+    pub(crate) fn disarm(&mut self) {
+        self.pid = None;
+    }
+
+    /// Kill the tree now. Must run before the root is killed, since `taskkill /T` finds
+    /// descendants through their living parent.
+    pub(crate) fn kill_tree(&mut self) {
+        #[cfg(windows)]
+        if let Some(pid) = self.pid.take() {
+            let _ = std::process::Command::new("taskkill")
+                .args(["/T", "/F", "/PID", &pid.to_string()])
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status();
+        }
+    }
+}
+
+impl Drop for ProcessTreeGuard {
+    fn drop(&mut self) {
+        self.kill_tree();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_program_tries_pathext_in_each_dir() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        std::fs::write(second.path().join("claude.cmd"), "@echo off\r\n").unwrap();
+
+        let path = std::env::join_paths([first.path(), second.path()]).unwrap();
+        assert_eq!(
+            find_program(OsStr::new("claude"), &path, ".EXE;.CMD"),
+            Some(second.path().join("claude.cmd"))
+        );
+        assert_eq!(find_program(OsStr::new("claude"), &path, ".EXE"), None);
+        // Explicit paths and extensions are not searched
+        assert_eq!(find_program(OsStr::new("claude.cmd"), &path, ".CMD"), None);
+        assert_eq!(
+            find_program(first.path().join("claude").as_os_str(), &path, ".CMD"),
+            None
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! The text Claude receives for a call, assembled without running it.
+//!
+//! A run's input comes from several places: the prompt (or a rendered prompt template) with
+//! the `git_context` header in front and `RESPONSE_SCHEMA` instructions appended, system prompt flags from the config and profile
+//! (`--system-prompt`, `--append-system-prompt`), and the project memory file the CLI loads
+//! from the working directory. `claude_preview_prompt` returns each piece as it would be
+//! sent, cut to [`MAX_PREVIEW_BYTES`] so a huge prompt cannot flood the client.
+
+use crate::claude::Options;
+use serde::Serialize;
+use std::path::Path;
+
+/// Bytes of each text returned; the full size is reported alongside
+pub(crate) const MAX_PREVIEW_BYTES: usize = 64 * 1024;
+
+/// One piece of the assembled input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct PreviewText {
+    pub text: String,
+    /// Size of the whole text, which may be more than `text` holds
+    pub bytes: usize,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// Everything a run adds to the conversation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct PromptPreview {
+    /// The user message
+    pub prompt: PreviewText,
+    /// Replaces the CLI's default system prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<PreviewText>,
+    /// Appended to the system prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub append_system_prompt: Option<PreviewText>,
+    /// `CLAUDE.md` in the working directory, loaded by the CLI as project memory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_file: Option<MemoryPreview>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct MemoryPreview {
+    pub path: String,
+    #[serde(flatten)]
+    pub content: PreviewText,
+}
+
+impl PreviewText {
+    fn new(text: &str) -> Self {
+        let mut end = text.len().min(MAX_PREVIEW_BYTES);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        Self {
+            text: text[..end].to_string(),
+            bytes: text.len(),
+            truncated: end < text.len(),
+        }
+    }
+}
+
+/// Assemble what a run with `opts` would send
+pub(crate) fn assemble(opts: &Options) -> PromptPreview {
+    let memory_path = opts.working_dir.join("CLAUDE.md");
+    PromptPreview {
+        prompt: PreviewText::new(&opts.prompt),
+        system_prompt: flag_value(&opts.additional_args, "--system-prompt").map(PreviewText::new),
+        append_system_prompt: flag_value(&opts.additional_args, "--append-system-prompt")
+            .map(PreviewText::new),
+        memory_file: read_memory(&memory_path),
+    }
+}
+
+/// Value of the last `flag value` or `flag=value` in `args`
+pub(crate) fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let mut value = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix(flag) {
+            Some("") => value = args.next().map(String::as_str).or(value),
+            Some(rest) if rest.starts_with('=') => value = Some(&rest[1..]),
+            _ => {}
+        }
+    }
+    value
+}
+
+fn read_memory(path: &Path) -> Option<MemoryPreview> {
+    let content = std::fs::read(path).ok()?;
+    Some(MemoryPreview {
+        path: path.display().to_string(),
+        content: PreviewText::new(&String::from_utf8_lossy(&content)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_collects_prompt_system_prompts_and_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("CLAUDE.md"), "Use tabs.\n").unwrap();
+        let opts = Options {
+            prompt: "x".repeat(MAX_PREVIEW_BYTES + 10),
+            working_dir: dir.path().to_path_buf(),
+            additional_args: [
+                "--append-system-prompt",
+                "Be brief.",
+                "--system-prompt=You review code.",
+                "--append-system-prompt",
+                "Be terse.",
+            ]
+            .map(String::from)
+            .to_vec(),
+            ..Default::default()
+        };
+        let preview = assemble(&opts);
+
+        assert_eq!(preview.prompt.text.len(), MAX_PREVIEW_BYTES);
+        assert_eq!(preview.prompt.bytes, MAX_PREVIEW_BYTES + 10);
+        assert!(preview.prompt.truncated);
+        assert_eq!(preview.system_prompt.unwrap().text, "You review code.");
+        assert_eq!(preview.append_system_prompt.unwrap().text, "Be terse.");
+        assert_eq!(preview.memory_file.unwrap().content.text, "Use tabs.\n");
+        assert_eq!(
+            flag_value(&["--system-prompt-file".to_string()], "--system-prompt"),
+            None
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! The `claude_pr` workflow: run a prompt on a new branch and open a pull request for it.
+//!
+//! The run works in a git worktree created under the repository's git directory, so the
+//! caller's checkout is left alone. After a successful run the optional `verify_command`
+//! runs in the worktree; then the changes are committed with the user's git identity, the
+//! branch is pushed, and `create_command` (by default `gh pr create`) opens the pull request.
+//! When the run, the verification, or the commit fails, the worktree is kept for inspection
+//! and its path returned; otherwise it is removed and the branch holds the work.
+
+use crate::claude::ClaudeResult;
+use crate::snapshots::{self, Verification};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Longest title derived from the prompt
+const MAX_TITLE_CHARS: usize = 72;
+
+/// Settings of `claude_pr`, configured via `pull_requests` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct PullRequestConfig {
+    /// Program and arguments run in the worktree after the run; a nonzero exit stops the
+    /// workflow before anything is committed
+    pub verify_command: Vec<String>,
+    pub verify_timeout_secs: u64,
+    /// Remote the branch is pushed to
+    pub remote: String,
+    /// Program and arguments that open the pull request and print its URL. `--head`,
+    /// `--title`, `--body`, and, when requested, `--base` and `--draft` are appended.
+    pub create_command: Vec<String>,
+}
+
+impl Default for PullRequestConfig {
+    fn default() -> Self {
+        Self {
+            verify_command: Vec::new(),
+            verify_timeout_secs: 600,
+            remote: "origin".to_string(),
+            create_command: ["gh", "pr", "create"].map(String::from).to_vec(),
+        }
+    }
+}
+
+/// What the caller asked for, besides the prompt
+pub(crate) struct Request {
+    pub title: String,
+    pub base: Option<String>,
+    pub draft: bool,
+}
+
+/// Outcome of `claude_pr`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct PullRequest {
+    pub success: bool,
+    pub branch: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pr_url: Option<String>,
+    /// The commit holding the run's changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    pub session_id: String,
+    pub agent_messages: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+    /// Worktree left in place after a failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worktree: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A worktree with its own branch, created for one `claude_pr` call or `claude_swarm` prompt
+pub(crate) struct Worktree {
+    repo: PathBuf,
+    path: PathBuf,
+    /// Where the caller's working directory sits in the repository, e.g. `crates/api/`
+    prefix: String,
+    branch: String,
+    /// Commit the branch started at
+    start: String,
+}
+
+impl Worktree {
+    /// Check out a new `branch` at `base` (default `HEAD`) of the repository containing
+    /// `working_dir`
+    pub(crate) async fn add(working_dir: &Path, branch: &str, base: Option<&str>) -> Result<Self> {
+        git(working_dir, &["check-ref-format", "--branch", branch])
+            .await
+            .with_context(|| format!("invalid branch name '{}'", branch))?;
+        let prefix = git(working_dir, &["rev-parse", "--show-prefix"]).await?;
+        let git_dir = working_dir.join(git(working_dir, &["rev-parse", "--git-common-dir"]).await?);
+        let path = git_dir
+            .join("claude-mcp")
+            .join("worktrees")
+            .join(uuid::Uuid::new_v4().to_string());
+        let path_arg = path.to_string_lossy();
+        git(
+            working_dir,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                branch,
+                &path_arg,
+                base.unwrap_or("HEAD"),
+            ],
+        )
+        .await?;
+        let start = git(&path, &["rev-parse", "HEAD"]).await?;
+        Ok(Self {
+            repo: working_dir.to_path_buf(),
+            path,
+            prefix,
+            branch: branch.to_string(),
+            start,
+        })
+    }
+
+    /// The caller's working directory inside the worktree, where the run works
+    pub(crate) fn dir(&self) -> PathBuf {
+        self.path.join(&self.prefix)
+    }
+
+    /// The root of the worktree
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn branch(&self) -> &str {
+        &self.branch
+    }
+
+    /// Delete the worktree, and the branch too unless it holds work
+    pub(crate) async fn remove(self, keep_branch: bool) {
+        let path = self.path.to_string_lossy();
+        if let Err(e) = git(&self.repo, &["worktree", "remove", "--force", &path]).await {
+            tracing::warn!(worktree = %path, "failed to remove worktree: {:#}", e);
+        }
+        if !keep_branch {
+            let _ = git(&self.repo, &["branch", "-D", &self.branch]).await;
+        }
+    }
+}
+
+/// Name for a branch the caller did not name
+pub(crate) fn default_branch() -> String {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    format!("claude/{}", &id[..8])
+}
+
+/// Title for a pull request the caller did not title: the prompt's first line, shortened
+pub(crate) fn default_title(prompt: &str) -> String {
+    let line = prompt.lines().map(str::trim).find(|l| !l.is_empty());
+    let line = line.unwrap_or("Changes by Claude");
+    match line.char_indices().nth(MAX_TITLE_CHARS) {
+        Some((end, _)) => format!("{}...", line[..end].trim_end()),
+        None => line.to_string(),
+    }
+}
+
+/// Verify, commit, push, and open the pull request for a finished run in `worktree`
+pub(crate) async fn finish(
+    worktree: Worktree,
+    result: ClaudeResult,
+    config: &PullRequestConfig,
+    request: &Request,
+) -> PullRequest {
+    let mut report = PullRequest {
+        branch: worktree.branch.clone(),
+        session_id: result.session_id,
+        agent_messages: result.agent_messages,
+        ..Default::default()
+    };
+    let keep = |mut report: PullRequest, worktree: Worktree, error: String| {
+        report.worktree = Some(worktree.path.display().to_string());
+        report.error = Some(error);
+        report
+    };
+    if !result.success {
+        let error = result.error.unwrap_or_else(|| "the run failed".to_string());
+        return keep(report, worktree, error);
+    }
+
+    if !config.verify_command.is_empty() {
+        let verification = snapshots::verify(
+            &config.verify_command,
+            config.verify_timeout_secs,
+            &worktree.dir(),
+        )
+        .await;
+        let passed = verification.passed;
+        report.verification = Some(verification);
+        if !passed {
+            let error = format!(
+                "verify_command `{}` failed after the run",
+                config.verify_command.join(" ")
+            );
+            return keep(report, worktree, error);
+        }
+    }
+
+    let commit = match commit(&worktree, &request.title).await {
+        Ok(Some(commit)) => commit,
+        Ok(None) => {
+            worktree.remove(false).await;
+            report.error = Some("the run made no changes to commit".to_string());
+            return report;
+        }
+        Err(e) => return keep(report, worktree, format!("{:#}", e)),
+    };
+    report.commit = Some(commit);
+
+    let opened = async {
+        // From the caller's checkout, where relative remote URLs resolve
+        git(
+            &worktree.repo,
+            &[
+                "push",
+                "-q",
+                "--set-upstream",
+                &config.remote,
+                &worktree.branch,
+            ],
+        )
+        .await?;
+        open(&worktree, config, request, &report.agent_messages).await
+    };
+    match opened.await {
+        Ok(url) => {
+            report.success = true;
+            report.pr_url = Some(url);
+        }
+        Err(e) => report.error = Some(format!("{:#}", e)),
+    }
+    worktree.remove(true).await;
+    report
+}
+
+/// Commit everything left uncommitted in the worktree and return the branch head; `None`
+/// when the branch has no changes at all
+pub(crate) async fn commit(worktree: &Worktree, title: &str) -> Result<Option<String>> {
+    git(&worktree.path, &["add", "--all"]).await?;
+    let dirty = !git(&worktree.path, &["status", "--porcelain"])
+        .await?
+        .is_empty();
+    if dirty {
+        git(&worktree.path, &["commit", "-q", "-m", title]).await?;
+    }
+    // The run may have committed on its own
+    let head = git(&worktree.path, &["rev-parse", "HEAD"]).await?;
+    Ok(Some(head).filter(|head| *head != worktree.start))
+}
+
+/// Run `create_command` and return the URL it prints last
+async fn open(
+    worktree: &Worktree,
+    config: &PullRequestConfig,
+    request: &Request,
+    body: &str,
+) -> Result<String> {
+    let (program, args) = config
+        .create_command
+        .split_first()
+        .context("pull_requests.create_command is empty")?;
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .args(["--head", &worktree.branch, "--title", &request.title])
+        .args([
+            "--body",
+            if body.is_empty() {
+                &request.title
+            } else {
+                body
+            },
+        ])
+        .current_dir(&worktree.path)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    if let Some(base) = &request.base {
+        command.args(["--base", base]);
+    }
+    if request.draft {
+        command.arg("--draft");
+    }
+    let output = snapshots::output_of(command, program).await?;
+    output
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+        .map(str::to_string)
+        .with_context(|| format!("{} printed no pull request URL", program))
+}
+
+/// Run git in `dir` with the user's identity and return its trimmed stdout
+async fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let mut command = Command::new("git");
+    command
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    let name = format!("git {}", args.first().copied().unwrap_or_default());
+    snapshots::output_of(command, &name).await
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(dir: &Path, script: &str) {
+        let status = std::process::Command::new("sh")
+            .args(["-c", script])
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "test")
+            .env("GIT_AUTHOR_EMAIL", "test@localhost")
+            .env("GIT_COMMITTER_NAME", "test")
+            .env("GIT_COMMITTER_EMAIL", "test@localhost")
+            .status()
+            .unwrap();
+        assert!(status.success(), "{}", script);
+    }
+
+    #[tokio::test]
+    async fn test_finish_pushes_the_branch_and_opens_the_pull_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        std::fs::create_dir(&repo).unwrap();
+        sh(dir.path(), "git init -q --bare remote.git");
+        sh(
+            &repo,
+            "git init -q && git config user.name test && git config user.email test@localhost \
+             && echo one > a.txt && git add a.txt && git commit -qm init \
+             && git remote add origin ../remote.git",
+        );
+        let args_file = dir.path().join("args");
+        let script = format!(
+            r#"echo "$@" > '{}'; echo https://example.com/pr/7"#,
+            args_file.display()
+        );
+        let config = PullRequestConfig {
+            create_command: vec!["sh".to_string(), "-c".to_string(), script, "gh".to_string()],
+            ..Default::default()
+        };
+        let request = Request {
+            title: default_title("  \nFix the greeting\nin a.txt"),
+            base: None,
+            draft: true,
+        };
+
+        let worktree = Worktree::add(&repo, "claude/test", None).await.unwrap();
+        std::fs::write(worktree.dir().join("a.txt"), "two\n").unwrap();
+        let result = ClaudeResult {
+            success: true,
+            session_id: "session".to_string(),
+            agent_messages: "Changed a.txt".to_string(),
+            ..Default::default()
+        };
+        let report = finish(worktree, result, &config, &request).await;
+
+        assert!(report.success, "{:?}", report);
+        assert_eq!(report.pr_url.as_deref(), Some("https://example.com/pr/7"));
+        assert_eq!(report.worktree, None);
+        let args = std::fs::read_to_string(&args_file).unwrap();
+        assert_eq!(
+            args.trim(),
+            "--head claude/test --title Fix the greeting --body Changed a.txt --draft"
+        );
+        sh(
+            dir.path(),
+            "git --git-dir remote.git show claude/test:a.txt | grep -qx two",
+        );
+        // The caller's checkout is untouched
+        assert_eq!(
+            std::fs::read_to_string(repo.join("a.txt")).unwrap(),
+            "one\n"
+        );
+    }
+}
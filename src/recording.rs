@@ -0,0 +1,50 @@
+//! Record and replay of raw CLI output.
+//!
+//! A run with `Options::record_to` copies the CLI's stdout byte for byte to a file: the
+//! stream-json events exactly as the parser saw them. A run with `Options::replay_from` reads
+//! such a file instead of starting the CLI, so a parser bug can be reproduced from a user's
+//! recording and the server can be demoed offline. Replays report exit code 0 and no stderr.
+
+use crate::claude::{
+    enforce_required_fields, read_stdout, ClaudeResult, EventCollector, Options, ValidationMode,
+};
+use crate::debug_capture::{self, OutputStream};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::path::Path;
+
+/// Copy `stdout` into a new file at `path`. When the file cannot be created the stream is
+/// returned as is, with a warning for the result.
+pub(crate) fn record(stdout: OutputStream, path: &Path) -> (OutputStream, Option<String>) {
+    let created = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+    .and_then(|()| File::create(path));
+    match created {
+        Ok(file) => (debug_capture::tee_into(stdout, file), None),
+        Err(e) => (
+            stdout,
+            Some(format!(
+                "Failed to start recording to {}: {}",
+                path.display(),
+                e
+            )),
+        ),
+    }
+}
+
+/// Build the result of `opts` from the events recorded at `path`
+pub(crate) async fn replay(opts: Options, path: &Path) -> Result<ClaudeResult> {
+    let started = std::time::Instant::now();
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open replay file {}", path.display()))?;
+    tracing::debug!(path = %path.display(), "replaying recorded run");
+    let mut collector = EventCollector::new(&opts, started).await;
+    // Nothing to stop: the rest of the file is read like a child's remaining output
+    read_stdout(Box::new(file), &mut collector, || {}).await;
+    let mut result = collector.finish().await;
+    result.exit_code = Some(0);
+    Ok(enforce_required_fields(result, ValidationMode::Full))
+}
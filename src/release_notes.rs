@@ -0,0 +1,164 @@
+//! Release notes for `claude_release_notes`, written by Claude from `git log`.
+//!
+//! The commits between two refs of the working directory's repository go to Claude, in
+//! plan mode, with a request for Keep a Changelog style notes. The Markdown comes back in
+//! the result and, when asked, is inserted into `CHANGELOG.md` above the newest release,
+//! with the change returned as a unified diff.
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use similar::TextDiff;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Bytes of `git log` output sent to Claude; older commits beyond it are left out
+const MAX_LOG_BYTES: usize = 100 * 1024;
+
+/// Outcome of `claude_release_notes`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct ReleaseNotes {
+    pub success: bool,
+    pub from: String,
+    pub to: String,
+    /// Commits in the range
+    pub commits: usize,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub log_truncated: bool,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub session_id: String,
+    pub notes: String,
+    /// `CHANGELOG.md` after the notes were written to it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changelog: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The commits of a range
+pub(crate) struct Log {
+    pub commits: usize,
+    pub text: String,
+    pub truncated: bool,
+}
+
+/// The most recent tag reachable from `to`, where release notes usually start
+pub(crate) async fn latest_tag(working_dir: &Path, to: &str) -> Result<String> {
+    git(working_dir, &["describe", "--tags", "--abbrev=0", to]).await
+}
+
+/// Subjects and bodies of the commits in `from..to`, newest first, without merges
+pub(crate) async fn log(working_dir: &Path, from: &str, to: &str) -> Result<Log> {
+    let range = format!("{}..{}", from, to);
+    let count = git(working_dir, &["rev-list", "--count", "--no-merges", &range]).await?;
+    let text = git(
+        working_dir,
+        &["log", "--no-merges", "--format=%h %s%n%w(0,4,4)%b", &range],
+    )
+    .await?;
+    let mut end = text.len().min(MAX_LOG_BYTES);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    Ok(Log {
+        commits: count.parse().unwrap_or_default(),
+        truncated: end < text.len(),
+        text: text[..end].to_string(),
+    })
+}
+
+/// The request for notes titled `version` from `log`
+pub(crate) fn prompt(version: &str, log: &Log) -> String {
+    format!(
+        "Write release notes for {version} from the git log below. Group user-visible changes \
+         under the Keep a Changelog headings `### Added`, `### Changed`, `### Deprecated`, \
+         `### Removed`, `### Fixed`, and `### Security`, leaving out empty ones. Write one \
+         bullet per change, merging commits that belong together, and skip changes users do \
+         not notice (CI, refactoring, tests). Reply with only the Markdown, starting with the \
+         line `## {version}`.\n\n{}",
+        log.text,
+        version = version,
+    )
+}
+
+/// The Markdown of Claude's reply, without a surrounding code fence
+pub(crate) fn notes(reply: &str) -> String {
+    let reply = reply.trim();
+    let unfenced = reply
+        .strip_prefix("```markdown")
+        .or_else(|| reply.strip_prefix("```md"))
+        .or_else(|| reply.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"));
+    unfenced.unwrap_or(reply).trim().to_string()
+}
+
+/// Insert `notes` into the changelog at `path` above the newest release (the first `## `
+/// heading), creating the file when missing. Returns the unified diff.
+pub(crate) fn write_changelog(path: &Path, notes: &str) -> Result<String> {
+    let before = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => bail!("failed to read {}: {}", path.display(), e),
+    };
+    let section = format!("{}\n\n", notes.trim_end());
+    let after = if before.is_empty() {
+        format!("# Changelog\n\n{}\n", notes.trim_end())
+    } else {
+        let at = before
+            .match_indices("## ")
+            .map(|(at, _)| at)
+            .find(|&at| at == 0 || before[..at].ends_with('\n'));
+        match at {
+            Some(at) => format!("{}{}{}", &before[..at], section, &before[at..]),
+            None => format!("{}\n\n{}", before.trim_end_matches('\n'), section),
+        }
+    };
+    std::fs::write(path, &after)
+        .map_err(|e| anyhow::anyhow!("failed to write {}: {}", path.display(), e))?;
+    Ok(TextDiff::from_lines(before.as_str(), after.as_str())
+        .unified_diff()
+        .header("a/CHANGELOG.md", "b/CHANGELOG.md")
+        .to_string())
+}
+
+async fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let mut command = Command::new("git");
+    command
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    let name = format!("git {}", args.first().copied().unwrap_or_default());
+    crate::snapshots::output_of(command, &name).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notes_go_above_the_newest_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CHANGELOG.md");
+        std::fs::write(&path, "# Changelog\n\nIntro.\n\n## 1.0.0\n\n- First\n").unwrap();
+        let notes = notes("```markdown\n## 1.1.0\n\n### Fixed\n- Crash on empty input\n```");
+        assert_eq!(notes, "## 1.1.0\n\n### Fixed\n- Crash on empty input");
+
+        let diff = write_changelog(&path, &notes).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# Changelog\n\nIntro.\n\n## 1.1.0\n\n### Fixed\n- Crash on empty input\n\n\
+             ## 1.0.0\n\n- First\n"
+        );
+        assert!(diff.contains("+- Crash on empty input"), "{}", diff);
+
+        let fresh = dir.path().join("NEW.md");
+        write_changelog(&fresh, "## 0.1.0\n\n- Initial").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&fresh).unwrap(),
+            "# Changelog\n\n## 0.1.0\n\n- Initial\n"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Remote execution of the Claude CLI over SSH.
+//!
+//! The invocation is turned into a single remote shell command and run with `ssh -T`, so the
+//! CLI's stdout/stderr stream back through the same parser as a local process. Configured via
+//! `remote` in `claude-mcp.config.json` or per profile.
+
+use crate::claude::Invocation;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// SSH host the CLI runs on
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SshRemote {
+    /// Destination passed to `ssh`, e.g. `build@ci-runner` or a `~/.ssh/config` alias
+    pub host: String,
+    /// Directory on the remote host to run in. Defaults to the local working directory path,
+    /// which suits hosts sharing the checkout over a network mount.
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+    /// Path or name of the local `ssh` binary
+    #[serde(default = "default_ssh_bin")]
+    pub ssh_bin: String,
+    /// Extra `ssh` flags placed before the host, e.g. `["-p", "2222"]`
+    #[serde(default)]
+    pub ssh_args: Vec<String>,
+    /// Name of the Claude CLI on the remote host
+    #[serde(default = "default_remote_bin")]
+    pub claude_bin: String,
+}
+
+fn default_ssh_bin() -> String {
+    "ssh".to_string()
+}
+
+fn default_remote_bin() -> String {
+    "claude".to_string()
+}
+
+impl SshRemote {
+    /// Directory the CLI runs in on the remote side
+    pub(crate) fn remote_dir<'a>(&'a self, local_dir: &'a Path) -> &'a Path {
+        self.working_dir.as_deref().unwrap_or(local_dir)
+    }
+
+    /// Turn the invocation into `ssh ... host 'cd <remote_dir> && exec <program> <args>...'`.
+    /// `invocation` may already be wrapped for a sandbox, which then runs on the remote host.
+    pub(crate) fn wrap(&self, invocation: Invocation, remote_dir: &Path) -> Invocation {
+        let mut script = format!(
+            "cd {} && exec {}",
+            shell_quote(&remote_dir.to_string_lossy()),
+            shell_quote(&invocation.program.to_string_lossy())
+        );
+        for arg in &invocation.args {
+            script.push(' ');
+            script.push_str(&shell_quote(&arg.to_string_lossy()));
+        }
+
+        let mut args: Vec<OsString> = vec!["-T".into()];
+        args.extend(self.ssh_args.iter().map(OsString::from));
+        args.push("--".into());
+        args.push(self.host.clone().into());
+        args.push(script.into());
+
+        Invocation {
+            program: self.ssh_bin.clone().into(),
+            args,
+        }
+    }
+}
+
+/// Quote a word for a POSIX shell
+fn shell_quote(word: &str) -> String {
+    let is_safe = !word.is_empty()
+        && word
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_./=:,@%+".contains(&b));
+    if is_safe {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote_escapes_metacharacters() {
+        assert_eq!(shell_quote("--print"), "--print");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(
+            shell_quote("fix the $PATH; rm -rf"),
+            "'fix the $PATH; rm -rf'"
+        );
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_ssh_remote_runs_cli_in_remote_dir() {
+        let remote: SshRemote = serde_json::from_str(
+            r#"{"host":"build@ci","working_dir":"/srv/checkout","ssh_args":["-p","2222"]}"#,
+        )
+        .unwrap();
+        let invocation = Invocation {
+            program: "claude".into(),
+            args: vec!["--print".into(), "what's new?".into()],
+        };
+
+        let local_dir = Path::new("/home/me/repo");
+        let wrapped = remote.wrap(invocation, remote.remote_dir(local_dir));
+        assert_eq!(wrapped.program, "ssh");
+        assert_eq!(
+            wrapped.args,
+            [
+                "-T",
+                "-p",
+                "2222",
+                "--",
+                "build@ci",
+                r"cd /srv/checkout && exec claude --print 'what'\''s new?'",
+            ]
+        );
+    }
+}
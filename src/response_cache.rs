@@ -0,0 +1,225 @@
+//! Opt-in cache of run results.
+//!
+//! Documentation and analysis pipelines re-ask the same questions about trees that have not
+//! changed. With `response_cache` set, a run whose prompt, options (the CLI arguments carry
+//! the model), working directory, and directory contents match an earlier successful run
+//! within the TTL returns that result instead of starting the CLI again. Runs that resume a session
+//! or execute on a remote host are never cached.
+
+use crate::claude::{ClaudeResult, Options};
+use crate::coalesce;
+use crate::context;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Trees with more files than this are not hashed, so their runs are not cached
+const MAX_TREE_FILES: usize = 20_000;
+/// Trees with more content than this are not hashed, so their runs are not cached
+const MAX_TREE_BYTES: u64 = 256 * 1024 * 1024;
+
+fn default_max_entries() -> usize {
+    256
+}
+
+/// Response cache settings, configured via `response_cache` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResponseCache {
+    /// How long a result may be reused
+    pub ttl_secs: u64,
+    /// Results kept; the oldest is evicted first
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+struct Entry {
+    key: String,
+    stored: Instant,
+    result: ClaudeResult,
+}
+
+/// Cached results of one server context, most recently stored first
+#[derive(Default)]
+pub(crate) struct CachedResults(Mutex<VecDeque<Entry>>);
+
+/// Cache key for `opts`, or `None` when the run must not be served from the cache. Hashes
+/// the working directory's contents, so it reads the whole tree.
+pub(crate) async fn key(opts: &Options) -> Option<String> {
+    // Session state and remote trees are not captured by the key
+    if opts.session_id.is_some() || opts.remote.is_some() {
+        return None;
+    }
+    let dir = opts.working_dir.clone();
+    let tree = tokio::task::spawn_blocking(move || tree_hash(&dir))
+        .await
+        .ok()??;
+    coalesce::request_key(opts, &tree)
+}
+
+/// Result stored under `key` less than `ttl_secs` ago
+pub(crate) fn get(key: &str, ttl_secs: u64) -> Option<ClaudeResult> {
+    let ttl = Duration::from_secs(ttl_secs);
+    let context = context::current();
+    let mut entries = context
+        .response_cache()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    entries.retain(|entry| entry.stored.elapsed() < ttl);
+    entries
+        .iter()
+        .find(|entry| entry.key == key)
+        .map(|entry| entry.result.clone())
+}
+
+/// Remember `result` under `key`; only successful runs are worth repeating
+pub(crate) fn put(key: String, result: &ClaudeResult, config: &ResponseCache) {
+    if !result.success || config.ttl_secs == 0 || config.max_entries == 0 {
+        return;
+    }
+    let context = context::current();
+    let mut entries = context
+        .response_cache()
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    entries.retain(|entry| entry.key != key);
+    entries.push_front(Entry {
+        key,
+        stored: Instant::now(),
+        result: result.clone(),
+    });
+    entries.truncate(config.max_entries);
+}
+
+/// SHA-256 over the relative path and content of every file below `root`, in sorted order.
+/// `.git` is skipped and symlinks are hashed by target. `None` if the tree cannot be read
+/// or exceeds the size limits.
+fn tree_hash(root: &Path) -> Option<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    let mut files = 0;
+    let mut bytes = 0;
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut children = std::fs::read_dir(&dir)
+            .ok()?
+            .collect::<std::io::Result<Vec<_>>>()
+            .ok()?;
+        children.sort_by_key(|child| child.file_name());
+        // Visit subdirectories in sorted order after this directory's files
+        let mut subdirs = Vec::new();
+        for child in children {
+            let path = child.path();
+            let file_type = child.file_type().ok()?;
+            let relative = path.strip_prefix(root).ok()?.to_string_lossy().into_owned();
+            if file_type.is_dir() {
+                if child.file_name() != ".git" {
+                    subdirs.push(path);
+                }
+                continue;
+            }
+            files += 1;
+            let content = if file_type.is_symlink() {
+                std::fs::read_link(&path)
+                    .ok()?
+                    .to_string_lossy()
+                    .into_owned()
+                    .into_bytes()
+            } else {
+                std::fs::read(&path).ok()?
+            };
+            bytes += content.len() as u64;
+            if files > MAX_TREE_FILES || bytes > MAX_TREE_BYTES {
+                return None;
+            }
+            hasher.update((relative.len() as u64).to_le_bytes());
+            hasher.update(relative.as_bytes());
+            hasher.update((content.len() as u64).to_le_bytes());
+            hasher.update(&content);
+        }
+        pending.extend(subdirs.into_iter().rev());
+    }
+    Some(hasher.finalize().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claude::ServerConfig;
+    use crate::context::ServerContext;
+
+    #[tokio::test]
+    async fn test_key_follows_directory_contents_and_skips_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "fn a() {}").unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        let opts = Options {
+            prompt: "summarize".to_string(),
+            working_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let first = key(&opts).await.unwrap();
+        std::fs::write(dir.path().join(".git").join("HEAD"), "ref").unwrap();
+        assert_eq!(key(&opts).await.unwrap(), first, ".git is ignored");
+
+        std::fs::write(dir.path().join("lib.rs"), "fn b() {}").unwrap();
+        assert_ne!(key(&opts).await.unwrap(), first);
+
+        let with_model = Options {
+            additional_args: vec!["--model".to_string(), "opus".to_string()],
+            ..opts.clone()
+        };
+        assert_ne!(key(&with_model).await, key(&opts).await);
+
+        let resumed = Options {
+            session_id: Some("11111111-1111-1111-1111-111111111111".to_string()),
+            ..opts
+        };
+        assert_eq!(key(&resumed).await, None);
+    }
+
+    #[test]
+    fn test_only_fresh_successful_results_are_served() {
+        let config = ResponseCache {
+            ttl_secs: 60,
+            max_entries: 1,
+        };
+        let ok = ClaudeResult {
+            success: true,
+            agent_messages: "cached".to_string(),
+            ..Default::default()
+        };
+        put("failed".to_string(), &ClaudeResult::default(), &config);
+        assert!(get("failed", 60).is_none());
+
+        put("first".to_string(), &ok, &config);
+        assert_eq!(get("first", 60).unwrap().agent_messages, "cached");
+        assert!(get("first", 0).is_none(), "expired entries are dropped");
+
+        put("first".to_string(), &ok, &config);
+        put("second".to_string(), &ok, &config);
+        assert!(get("first", 60).is_none(), "evicted beyond max_entries");
+        assert!(get("second", 60).is_some());
+    }
+
+    #[test]
+    fn test_contexts_do_not_share_results() {
+        let config = ResponseCache {
+            ttl_secs: 60,
+            max_entries: 8,
+        };
+        let ok = ClaudeResult {
+            success: true,
+            ..Default::default()
+        };
+        let tenant = ServerContext::new(ServerConfig::default());
+        tenant.sync_scope(|| put("tenant-tree".to_string(), &ok, &config));
+        assert!(tenant.sync_scope(|| get("tenant-tree", 60)).is_some());
+        assert!(get("tenant-tree", 60).is_none());
+    }
+}
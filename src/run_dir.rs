@@ -0,0 +1,123 @@
+//! Scratch directory of a single run, removed however the run ends.
+//!
+//! Each spawned run gets a fresh directory under the state directory (`runs/<uuid>`) that
+//! lives exactly as long as the run: it is removed when the run's future is dropped, so a
+//! success, a failure, a timeout, and a cancelled call all clean up alike. Files staged for
+//! the CLI belong here, and the CLI itself gets it as its temp directory (`TMPDIR`, or
+//! `TEMP` and `TMP` on Windows) when it runs directly on the host, so the scratch files it
+//! and its tools leave behind go too. A server that crashes cannot remove its directories;
+//! [`sweep`] removes those of servers that are gone when the next one starts, and any older
+//! than the longest possible run where a server's start time cannot be read.
+
+use crate::claude::MAX_TIMEOUT_SECS;
+use crate::orphans;
+use std::path::{Path, PathBuf};
+
+/// Names the server that created a run directory: its PID and start time
+const OWNER_FILE: &str = ".owner";
+
+/// A run's scratch directory, removed on drop
+#[derive(Debug)]
+pub(crate) struct RunDir(PathBuf);
+
+fn runs_dir() -> PathBuf {
+    std::env::temp_dir().join("claude-mcp-rs").join("runs")
+}
+
+impl RunDir {
+    pub(crate) fn create() -> std::io::Result<Self> {
+        Self::create_in(&runs_dir())
+    }
+
+    fn create_in(parent: &Path) -> std::io::Result<Self> {
+        let dir = Self(parent.join(uuid::Uuid::new_v4().to_string()));
+        std::fs::create_dir_all(&dir.0)?;
+        let server_pid = std::process::id();
+        let owner = match orphans::start_time(server_pid) {
+            Some(started) => format!("{}\n{}\n", server_pid, started),
+            None => format!("{}\n", server_pid),
+        };
+        std::fs::write(dir.0.join(OWNER_FILE), owner)?;
+        Ok(dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Environment variables that point the CLI's temp directory here
+    pub(crate) fn temp_env(&self) -> Vec<(&'static str, &Path)> {
+        let names: &[&'static str] = if cfg!(windows) {
+            &["TEMP", "TMP"]
+        } else {
+            &["TMPDIR"]
+        };
+        names.iter().map(|name| (*name, self.path())).collect()
+    }
+}
+
+impl Drop for RunDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("failed to remove run directory {}: {}", self.0.display(), e);
+            }
+        }
+    }
+}
+
+/// Remove the run directories of servers that are no longer running
+pub(crate) fn sweep() {
+    let removed = sweep_in(&runs_dir());
+    if removed > 0 {
+        tracing::info!(removed, "removed run directories left by a previous server");
+    }
+}
+
+fn sweep_in(parent: &Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for dir in entries.flatten().map(|entry| entry.path()) {
+        let owner = std::fs::read_to_string(dir.join(OWNER_FILE)).unwrap_or_default();
+        let mut lines = owner.lines();
+        let pid = lines.next().and_then(|pid| pid.parse().ok());
+        let alive = match (pid, lines.next()) {
+            (Some(pid), Some(started)) => orphans::is_running(pid, started),
+            // Without a start time, no run outlives the longest timeout
+            _ => std::fs::metadata(&dir)
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age.as_secs() < MAX_TIMEOUT_SECS),
+        };
+        if !alive && std::fs::remove_dir_all(&dir).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_dirs_are_removed_on_drop_and_swept_when_orphaned() {
+        let parent = tempfile::tempdir().unwrap();
+        let dir = RunDir::create_in(parent.path()).unwrap();
+        let path = dir.path().to_path_buf();
+        std::fs::write(path.join("prompt.txt"), "hello").unwrap();
+        drop(dir);
+        assert!(!path.exists());
+
+        let live = RunDir::create_in(parent.path()).unwrap();
+        let orphaned = parent.path().join("orphaned");
+        std::fs::create_dir(&orphaned).unwrap();
+        std::fs::write(orphaned.join(OWNER_FILE), "4000000000\n0\n").unwrap();
+        assert_eq!(sweep_in(parent.path()), 1);
+        assert!(!orphaned.exists());
+        assert!(live.path().exists());
+    }
+}
@@ -0,0 +1,180 @@
+//! Admission control for tool calls that start runs.
+//!
+//! With `run_queue` set in `claude-mcp.config.json`, at most `max_concurrent` calls run at
+//! once and up to `max_queued` more wait for a slot. A call arriving when the queue is full
+//! is rejected with a [`SERVER_BUSY`] error whose data carries the queue depth and an
+//! estimated wait, so clients can back off instead of retrying blindly.
+
+use crate::metrics;
+use rmcp::model::ErrorCode;
+use rmcp::ErrorData as McpError;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// JSON-RPC error code of a call rejected because the queue is full, in the range reserved
+/// for implementation-defined server errors
+pub const SERVER_BUSY: ErrorCode = ErrorCode(-32000);
+
+fn default_max_queued() -> usize {
+    16
+}
+
+/// Concurrency limit for runs, configured via `run_queue` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunQueueConfig {
+    /// Calls running at once
+    pub max_concurrent: usize,
+    /// Calls waiting for a slot before new ones are rejected
+    #[serde(default = "default_max_queued")]
+    pub max_queued: usize,
+}
+
+/// Why a call was turned away
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Busy {
+    pub running: usize,
+    pub queue_depth: usize,
+    pub max_queued: usize,
+    /// Seconds until a slot is likely to free up, from the mean run duration so far
+    pub estimated_wait_secs: Option<u64>,
+}
+
+impl From<Busy> for McpError {
+    fn from(busy: Busy) -> Self {
+        let wait = busy
+            .estimated_wait_secs
+            .map(|secs| format!("; estimated wait {}s", secs))
+            .unwrap_or_default();
+        McpError::new(
+            SERVER_BUSY,
+            format!(
+                "server busy: {} runs active and {} queued{}",
+                busy.running, busy.queue_depth, wait
+            ),
+            Some(json!({
+                "reason": "server_busy",
+                "running": busy.running,
+                "queue_depth": busy.queue_depth,
+                "max_queued": busy.max_queued,
+                "estimated_wait_secs": busy.estimated_wait_secs,
+            })),
+        )
+    }
+}
+
+pub(crate) struct RunQueue {
+    slots: Arc<Semaphore>,
+    max_concurrent: usize,
+    max_queued: usize,
+    waiting: AtomicUsize,
+}
+
+/// Counts a caller as queued until it gets a slot or gives up
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl RunQueue {
+    pub(crate) fn new(config: &RunQueueConfig) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(config.max_concurrent)),
+            max_concurrent: config.max_concurrent,
+            max_queued: config.max_queued,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait for a slot, or fail right away when `max_queued` callers are already waiting.
+    /// The slot is released when the permit is dropped.
+    pub(crate) async fn admit(&self) -> Result<OwnedSemaphorePermit, Busy> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        let queued = self
+            .waiting
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |waiting| {
+                (waiting < self.max_queued).then_some(waiting + 1)
+            });
+        let Ok(ahead) = queued else {
+            return Err(self.busy());
+        };
+        let _waiting = Waiting(&self.waiting);
+        tracing::debug!(ahead, "waiting for a run slot");
+        // The semaphore is never closed
+        Ok(self
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("run queue semaphore closed"))
+    }
+
+    fn busy(&self) -> Busy {
+        let queue_depth = self.waiting.load(Ordering::Relaxed);
+        let running = self.max_concurrent - self.slots.available_permits();
+        // Everyone queued ahead, plus this caller, gets a slot in rounds of max_concurrent
+        let rounds = (queue_depth + 1).div_ceil(self.max_concurrent.max(1)) as f64;
+        Busy {
+            running,
+            queue_depth,
+            max_queued: self.max_queued,
+            estimated_wait_secs: metrics::metrics()
+                .mean_run_duration()
+                .map(|mean| (mean.as_secs_f64() * rounds).ceil() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_full_queue_rejects_with_depth() {
+        let queue = RunQueue::new(&RunQueueConfig {
+            max_concurrent: 1,
+            max_queued: 1,
+        });
+        let running = queue.admit().await.unwrap();
+        let waiting = queue.admit();
+        tokio::pin!(waiting);
+        // Polled once, the second call takes the only queue place
+        let pending = tokio::time::timeout(Duration::from_millis(10), &mut waiting).await;
+        assert!(pending.is_err());
+
+        let busy = queue.admit().await.unwrap_err();
+        assert_eq!((busy.running, busy.queue_depth, busy.max_queued), (1, 1, 1));
+
+        drop(running);
+        let _permit = waiting.await.unwrap();
+        assert_eq!(queue.waiting.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_busy_error_is_machine_readable() {
+        let error = McpError::from(Busy {
+            running: 2,
+            queue_depth: 4,
+            max_queued: 4,
+            estimated_wait_secs: Some(90),
+        });
+        assert_eq!(error.code, SERVER_BUSY);
+        assert_eq!(
+            error.message,
+            "server busy: 2 runs active and 4 queued; estimated wait 90s"
+        );
+        let data = error.data.unwrap();
+        assert_eq!(data["reason"], "server_busy");
+        assert_eq!(data["queue_depth"], 4);
+        assert_eq!(data["estimated_wait_secs"], 90);
+    }
+}
@@ -0,0 +1,324 @@
+//! Reports of runs written into the project they ran in.
+//!
+//! With `run_reports` in `claude-mcp.config.json`, every run leaves a directory
+//! `<dir>/<timestamp>/` under its working directory: `report.md` holds the prompt, the
+//! outcome with cost and usage, and the final answer, and `changes.diff` the changes the run
+//! made to the tree, when there are any. The results then sit in the project itself, where
+//! they can be browsed, committed, and reviewed in a pull request. The diff comes from
+//! working-tree snapshots taken around the run, so it is only written inside a git
+//! repository. Remote runs work on another host's tree and are not reported.
+
+use crate::audit::{self, PromptRetention};
+use crate::claude::{self, ClaudeResult, Options};
+use crate::snapshots::{self, Snapshot};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Report settings, configured via `run_reports` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunReportConfig {
+    /// Directory holding the reports; a relative path is resolved against the run's working
+    /// directory
+    #[serde(default = "default_dir")]
+    pub dir: PathBuf,
+}
+
+fn default_dir() -> PathBuf {
+    PathBuf::from(".claude-runs")
+}
+
+/// A run being reported, from before it starts
+pub(crate) struct Pending {
+    dir: PathBuf,
+    working_dir: PathBuf,
+    prompt: String,
+    started: DateTime<Utc>,
+    before: Option<Snapshot>,
+}
+
+/// Start the report of the run about to start with `opts`, when `run_reports` is configured
+pub(crate) async fn before_run(opts: &Options) -> Option<Pending> {
+    let config = claude::run_reports()?;
+    if opts.remote.is_some() {
+        return None;
+    }
+    Some(Pending::start(config.dir, opts).await)
+}
+
+impl Pending {
+    async fn start(dir: PathBuf, opts: &Options) -> Self {
+        Self {
+            dir: opts.working_dir.join(dir),
+            working_dir: opts.working_dir.clone(),
+            prompt: reported_prompt(&opts.prompt, claude::prompt_retention()),
+            started: Utc::now(),
+            before: snapshots::baseline(&opts.working_dir).await,
+        }
+    }
+
+    /// Drop the report of a run that never produced a result
+    pub(crate) async fn discard(self) {
+        if let Some(before) = self.before {
+            snapshots::discard(before).await;
+        }
+    }
+
+    /// Write the report of the finished run and return its directory in `result`. A report
+    /// that cannot be written only adds a warning.
+    pub(crate) async fn finish(self, result: &mut ClaudeResult) {
+        // Reports of other runs written in the meantime are not this run's changes
+        let reports = self.before.as_ref().and_then(|before| {
+            let repo = before.repo().canonicalize().ok()?;
+            let dir = self.dir.canonicalize().unwrap_or_else(|_| self.dir.clone());
+            dir.strip_prefix(repo).ok().map(Path::to_path_buf)
+        });
+        let in_git = self.before.is_some();
+        let mut patch = snapshots::changes_since(self.before, &self.working_dir).await;
+        if let (Some(patch), Some(reports)) = (patch.as_mut(), reports) {
+            patch.diff = without(&patch.diff, &reports);
+        }
+        let diff = patch.as_ref().map(|patch| patch.diff.as_str());
+        let report = render(&self.prompt, self.started, result, in_git, patch.as_ref());
+        match write(&self.dir, self.started, &report, diff) {
+            Ok(dir) => result.report_dir = Some(dir),
+            Err(e) => {
+                result.warnings = claude::push_warning(
+                    result.warnings.take(),
+                    &format!("run report not written to {}: {}", self.dir.display(), e),
+                );
+            }
+        }
+    }
+}
+
+/// `prompt` as `retention` lets reports show it; whole when unset
+fn reported_prompt(prompt: &str, retention: Option<PromptRetention>) -> String {
+    let Some(retention) = retention else {
+        return prompt.to_string();
+    };
+    let hash = audit::prompt_sha256(prompt);
+    match retention.kept(prompt) {
+        Some(kept) if kept.len() == prompt.len() => kept,
+        Some(kept) => format!("{}\n\n(cut off; SHA-256 `{}`)", kept, hash),
+        None => format!("(not kept; SHA-256 `{}`)", hash),
+    }
+}
+
+/// Create a new directory for a report of a run started at `started` and fill it
+fn write(
+    parent: &Path,
+    started: DateTime<Utc>,
+    report: &str,
+    diff: Option<&str>,
+) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(parent)?;
+    // No colons, which Windows does not allow in file names
+    let name = started.format("%Y-%m-%dT%H-%M-%SZ").to_string();
+    let mut dir = parent.join(&name);
+    let mut attempt = 1;
+    // Runs that start in the same second each get their own directory
+    loop {
+        match std::fs::create_dir(&dir) {
+            Ok(()) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                attempt += 1;
+                dir = parent.join(format!("{}-{}", name, attempt));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    std::fs::write(dir.join("report.md"), report)?;
+    if let Some(diff) = diff.filter(|diff| !diff.is_empty()) {
+        // git's output comes trimmed, and a patch ends in a newline
+        std::fs::write(dir.join("changes.diff"), format!("{}\n", diff.trim_end()))?;
+    }
+    Ok(dir)
+}
+
+/// `diff` without the sections of files under `dir`, relative to the repository
+fn without(diff: &str, dir: &Path) -> String {
+    let mut kept = String::new();
+    let mut keep = true;
+    for line in diff.split_inclusive('\n') {
+        if let Some(paths) = line.strip_prefix("diff --git a/") {
+            let path = paths.split(" b/").next().unwrap_or_default();
+            keep = !Path::new(path).starts_with(dir);
+        }
+        if keep {
+            kept.push_str(line);
+        }
+    }
+    kept
+}
+
+/// `report.md` of a run
+fn render(
+    prompt: &str,
+    started: DateTime<Utc>,
+    result: &ClaudeResult,
+    in_git: bool,
+    patch: Option<&snapshots::Patch>,
+) -> String {
+    let mut report = format!(
+        "# Run of {}\n\n",
+        started.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    );
+    let outcome = match (&result.error, result.success) {
+        (_, true) => "succeeded".to_string(),
+        (Some(error), false) => format!("failed: {}", error),
+        (None, false) => "failed".to_string(),
+    };
+    let _ = writeln!(report, "- Outcome: {}", outcome);
+    if !result.session_id.is_empty() {
+        let _ = writeln!(report, "- Session: `{}`", result.session_id);
+    }
+    let _ = writeln!(
+        report,
+        "- Duration: {:.1} s",
+        result.timing.duration_ms as f64 / 1000.0
+    );
+    if let Some(turns) = result.num_turns {
+        let _ = writeln!(report, "- Turns: {}", turns);
+    }
+    if let Some(cost) = result.total_cost_usd {
+        let _ = writeln!(report, "- Cost: ${:.4}", cost);
+    }
+    if let Some(usage) = &result.usage {
+        let _ = writeln!(
+            report,
+            "- Tokens: {} input, {} output, {} cache read, {} cache write",
+            usage.input_tokens,
+            usage.output_tokens,
+            usage.cache_read_input_tokens,
+            usage.cache_creation_input_tokens
+        );
+    }
+    let _ = write!(report, "\n## Prompt\n\n{}\n", prompt.trim_end());
+    let _ = write!(
+        report,
+        "\n## Summary\n\n{}\n",
+        match result.agent_messages.trim_end() {
+            "" => "(no answer)",
+            answer => answer,
+        }
+    );
+    report.push_str("\n## Changes\n\n");
+    let files: Vec<&str> = patch
+        .into_iter()
+        .flat_map(|patch| patch.diff.lines())
+        .filter_map(|line| line.strip_prefix("diff --git a/"))
+        .filter_map(|paths| paths.split(" b/").next())
+        .collect();
+    match patch {
+        _ if !in_git => report.push_str("Not tracked: the working directory is not in git.\n"),
+        _ if files.is_empty() => report.push_str("No files changed.\n"),
+        Some(patch) => {
+            for file in files {
+                let _ = writeln!(report, "- `{}`", file);
+            }
+            report.push_str("\nSee `changes.diff`");
+            if patch.diff_truncated {
+                report.push_str(", cut off at its first 64 KiB");
+            }
+            report.push_str(".\n");
+        }
+        None => report.push_str("No files changed.\n"),
+    }
+    report
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::audit::PromptRecord;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    #[tokio::test]
+    async fn test_report_holds_prompt_outcome_and_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        git(repo, &["init", "-q"]);
+        git(repo, &["config", "user.name", "test"]);
+        git(repo, &["config", "user.email", "test@localhost"]);
+        std::fs::write(repo.join("a.txt"), "one\n").unwrap();
+        git(repo, &["add", "a.txt"]);
+        git(repo, &["commit", "-qm", "init"]);
+
+        let opts = Options {
+            prompt: "Bump a.txt".to_string(),
+            working_dir: repo.to_path_buf(),
+            ..Default::default()
+        };
+        let first = Pending::start(default_dir(), &opts).await;
+        let second = Pending::start(default_dir(), &opts).await;
+        // Another size, so the stat cache of the copied index cannot hide the change
+        std::fs::write(repo.join("a.txt"), "three\n").unwrap();
+        let mut result = ClaudeResult {
+            success: true,
+            session_id: "5f0c6d1e-0000-4000-8000-000000000000".to_string(),
+            agent_messages: "Bumped it".to_string(),
+            total_cost_usd: Some(0.0123),
+            ..Default::default()
+        };
+        first.finish(&mut result).await;
+
+        let report_dir = result.report_dir.clone().unwrap();
+        assert!(report_dir.starts_with(repo.join(".claude-runs")));
+        let report = std::fs::read_to_string(report_dir.join("report.md")).unwrap();
+        for expected in [
+            "- Outcome: succeeded",
+            "- Cost: $0.0123",
+            "## Prompt\n\nBump a.txt\n",
+            "## Summary\n\nBumped it\n",
+            "- `a.txt`",
+        ] {
+            assert!(report.contains(expected), "{}", report);
+        }
+        let diff = std::fs::read_to_string(report_dir.join("changes.diff")).unwrap();
+        assert!(diff.contains("-one\n+three\n"), "{}", diff);
+
+        // A run started in the same second gets a directory of its own
+        let mut failed = ClaudeResult {
+            error: Some("timed out".to_string()),
+            ..Default::default()
+        };
+        second.finish(&mut failed).await;
+        let other = failed.report_dir.unwrap();
+        assert_ne!(other, report_dir);
+        let report = std::fs::read_to_string(other.join("report.md")).unwrap();
+        assert!(
+            report.contains("- Outcome: failed: timed out"),
+            "{}",
+            report
+        );
+        // The first report is not among the changes of the second run
+        let diff = std::fs::read_to_string(other.join("changes.diff")).unwrap();
+        assert!(!diff.contains(".claude-runs"), "{}", diff);
+        assert!(diff.contains("+three\n"), "{}", diff);
+    }
+
+    #[test]
+    fn test_reported_prompt_follows_prompt_retention() {
+        let retention = |mode| PromptRetention { mode, max_chars: 4 };
+        assert_eq!(reported_prompt("Fix the build", None), "Fix the build");
+        assert_eq!(
+            reported_prompt("Fix the build", Some(retention(PromptRecord::Full))),
+            "Fix the build"
+        );
+        let cut = reported_prompt("Fix the build", Some(retention(PromptRecord::Truncated)));
+        assert!(cut.starts_with("Fix \n\n(cut off; SHA-256 `"), "{}", cut);
+        let hashed = reported_prompt("Fix the build", Some(retention(PromptRecord::Hash)));
+        assert!(!hashed.contains("Fix"), "{}", hashed);
+    }
+}
@@ -0,0 +1,305 @@
+//! Sandboxed execution of the Claude CLI.
+//!
+//! A sandbox wraps the `claude` invocation so agent-driven shell commands cannot touch the
+//! host outside the working directory. Configured via `sandbox` in `claude-mcp.config.json`.
+//! Directories passed to the CLI with `--add-dir`, such as `claude_migrate`'s `EXTRA_DIRS`,
+//! are made writable inside the sandbox as well.
+
+use crate::claude::Invocation;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Isolation applied around the CLI process
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Sandbox {
+    /// Run the CLI directly on the host
+    #[default]
+    None,
+    /// Run the CLI inside a throwaway container with only the working directory mounted
+    Docker(DockerSandbox),
+    /// Run the CLI under bubblewrap: read-only host filesystem, writable working directory,
+    /// private `/tmp`, and the CLI's own state directory
+    Bubblewrap(BubblewrapSandbox),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DockerSandbox {
+    /// Image that has the Claude CLI installed
+    pub image: String,
+    /// Container runtime binary, e.g. `docker` or `podman`
+    #[serde(default = "default_runtime")]
+    pub runtime: String,
+    /// Name of the Claude CLI inside the image
+    #[serde(default = "default_container_bin")]
+    pub claude_bin: String,
+    /// Host environment variables forwarded into the container (e.g. `ANTHROPIC_API_KEY`)
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Extra `run` flags, e.g. additional read-only mounts for CLI credentials
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BubblewrapSandbox {
+    /// Path or name of the `bwrap` binary
+    #[serde(default)]
+    pub bwrap_bin: Option<String>,
+    /// Additional host paths the CLI may write to (bound only if they exist)
+    #[serde(default)]
+    pub writable_paths: Vec<PathBuf>,
+    /// Cut the CLI off from the network (`--unshare-net`). The CLI needs API access, so only
+    /// useful with a local proxy bound into the sandbox.
+    #[serde(default)]
+    pub unshare_net: bool,
+    /// Extra bwrap flags
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+}
+
+fn default_runtime() -> String {
+    "docker".to_string()
+}
+
+fn default_container_bin() -> String {
+    "claude".to_string()
+}
+
+impl Sandbox {
+    /// Wrap the CLI invocation for this sandbox. `working_dir` is the only host path exposed.
+    pub(crate) fn wrap(&self, invocation: Invocation, working_dir: &Path) -> Invocation {
+        let added = added_dirs(&invocation.args, working_dir);
+        match self {
+            Sandbox::None => invocation,
+            Sandbox::Docker(docker) => docker.wrap(invocation, working_dir, &added),
+            Sandbox::Bubblewrap(bwrap) => bwrap.wrap(invocation, working_dir, &added),
+        }
+    }
+}
+
+/// Directories the CLI is given with `--add-dir`, relative ones resolved against
+/// `working_dir`
+fn added_dirs(args: &[OsString], working_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let dir = if arg == "--add-dir" {
+            args.next().map(PathBuf::from)
+        } else {
+            arg.to_str()
+                .and_then(|arg| arg.strip_prefix("--add-dir="))
+                .map(PathBuf::from)
+        };
+        dirs.extend(dir.map(|dir| working_dir.join(dir)));
+    }
+    dirs
+}
+
+fn bind_spec(dir: &Path) -> OsString {
+    let mut mount = OsString::from(dir);
+    mount.push(":");
+    mount.push(dir);
+    mount
+}
+
+impl DockerSandbox {
+    fn wrap(
+        &self,
+        mut invocation: Invocation,
+        working_dir: &Path,
+        added: &[PathBuf],
+    ) -> Invocation {
+        // The host binary path means nothing inside the image
+        invocation.program = self.claude_bin.clone().into();
+
+        let mut args: Vec<OsString> = vec![
+            "run".into(),
+            "--rm".into(),
+            "--interactive".into(),
+            "--init".into(),
+            "--volume".into(),
+            bind_spec(working_dir),
+            "--workdir".into(),
+            working_dir.into(),
+        ];
+        for dir in added {
+            args.extend(["--volume".into(), bind_spec(dir)]);
+        }
+        for name in &self.env {
+            args.push("--env".into());
+            args.push(name.into());
+        }
+        args.extend(self.extra_args.iter().map(OsString::from));
+        args.push(self.image.clone().into());
+
+        invocation.wrap(self.runtime.clone(), args)
+    }
+}
+
+impl BubblewrapSandbox {
+    fn wrap(&self, invocation: Invocation, working_dir: &Path, added: &[PathBuf]) -> Invocation {
+        let mut args: Vec<OsString> = [
+            "--ro-bind",
+            "/",
+            "/",
+            "--dev",
+            "/dev",
+            "--proc",
+            "/proc",
+            "--tmpfs",
+            "/tmp",
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+
+        // The working directory and the directories added to the run are the only project
+        // paths the agent may modify
+        for dir in std::iter::once(working_dir).chain(added.iter().map(PathBuf::as_path)) {
+            args.extend(["--bind".into(), dir.into(), dir.into()]);
+        }
+
+        // The CLI persists sessions and settings under the home directory
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        let cli_state = home
+            .iter()
+            .flat_map(|home| [home.join(".claude"), home.join(".claude.json")]);
+        for path in cli_state.chain(self.writable_paths.iter().cloned()) {
+            args.extend(["--bind-try".into(), path.clone().into(), path.into()]);
+        }
+
+        if self.unshare_net {
+            args.push("--unshare-net".into());
+        }
+        args.extend(
+            ["--unshare-pid", "--die-with-parent", "--chdir"]
+                .into_iter()
+                .map(OsString::from),
+        );
+        args.push(working_dir.into());
+        args.extend(self.extra_args.iter().map(OsString::from));
+        args.push("--".into());
+
+        let bwrap = self
+            .bwrap_bin
+            .clone()
+            .unwrap_or_else(|| "bwrap".to_string());
+        invocation.wrap(bwrap, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_docker_sandbox_mounts_only_working_dir() {
+        let sandbox: Sandbox = serde_json::from_str(
+            r#"{"mode":"docker","image":"ghcr.io/acme/claude:latest","env":["ANTHROPIC_API_KEY"]}"#,
+        )
+        .unwrap();
+        let invocation = Invocation {
+            program: "/usr/local/bin/claude".into(),
+            args: vec!["--print".into()],
+        };
+
+        let wrapped = sandbox.wrap(invocation, Path::new("/work/repo"));
+        assert_eq!(wrapped.program, "docker");
+        assert_eq!(
+            wrapped.args,
+            [
+                "run",
+                "--rm",
+                "--interactive",
+                "--init",
+                "--volume",
+                "/work/repo:/work/repo",
+                "--workdir",
+                "/work/repo",
+                "--env",
+                "ANTHROPIC_API_KEY",
+                "ghcr.io/acme/claude:latest",
+                "claude",
+                "--print",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bubblewrap_sandbox_binds_working_dir_writable() {
+        let sandbox: Sandbox =
+            serde_json::from_str(r#"{"mode":"bubblewrap","writable_paths":["/var/cache/build"]}"#)
+                .unwrap();
+        let invocation = Invocation {
+            program: "claude".into(),
+            args: vec!["--print".into()],
+        };
+
+        let wrapped = sandbox.wrap(invocation, Path::new("/work/repo"));
+        assert_eq!(wrapped.program, "bwrap");
+
+        let args: Vec<String> = wrapped
+            .args
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        let joined = args.join(" ");
+        assert!(joined.starts_with("--ro-bind / / --dev /dev --proc /proc --tmpfs /tmp"));
+        assert!(joined.contains("--bind /work/repo /work/repo"));
+        assert!(joined.contains("--bind-try /var/cache/build /var/cache/build"));
+        assert!(joined.contains("--chdir /work/repo"));
+        assert!(joined.ends_with("-- claude --print"));
+    }
+
+    #[test]
+    fn test_added_dirs_are_writable_in_the_sandbox() {
+        let invocation = || Invocation {
+            program: "claude".into(),
+            args: vec![
+                "--add-dir".into(),
+                "/work/shared".into(),
+                "--add-dir=../lib".into(),
+                "--print".into(),
+            ],
+        };
+
+        let docker: Sandbox =
+            serde_json::from_str(r#"{"mode":"docker","image":"claude"}"#).unwrap();
+        let wrapped = docker.wrap(invocation(), Path::new("/work/repo"));
+        let args: Vec<String> = wrapped
+            .args
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert!(args.join(" ").contains(
+            "--volume /work/shared:/work/shared --volume /work/repo/../lib:/work/repo/../lib"
+        ));
+
+        let bwrap: Sandbox = serde_json::from_str(r#"{"mode":"bubblewrap"}"#).unwrap();
+        let wrapped = bwrap.wrap(invocation(), Path::new("/work/repo"));
+        let args: Vec<String> = wrapped
+            .args
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        let joined = args.join(" ");
+        assert!(joined.contains("--bind /work/repo /work/repo --bind /work/shared /work/shared"));
+        assert!(joined.contains("--bind /work/repo/../lib /work/repo/../lib"));
+        assert!(joined.ends_with("-- claude --add-dir /work/shared --add-dir=../lib --print"));
+    }
+
+    #[test]
+    fn test_no_sandbox_leaves_invocation_untouched() {
+        let invocation = Invocation {
+            program: "claude".into(),
+            args: vec!["--print".into()],
+        };
+        let wrapped = Sandbox::None.wrap(invocation, Path::new("/tmp"));
+        assert_eq!(wrapped.program, "claude");
+        assert_eq!(wrapped.args, ["--print"]);
+    }
+}
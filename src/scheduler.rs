@@ -0,0 +1,302 @@
+//! Recurring runs of predefined prompts.
+//!
+//! `schedules` in `claude-mcp.config.json` lists prompts to run on cron expressions, such as
+//! a nightly dependency triage or a weekly TODO sweep. While the server is up, each matching
+//! minute starts the run in its working directory; the session lands in the session registry
+//! like any other run, and the tool response is POSTed to the schedule's webhook, if any.
+//! Schedules are re-read every minute, so a config reload takes effect without a restart.
+
+use crate::claude;
+use crate::server::{self, ClaudeArgs};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+/// How long the webhook request may take
+const WEBHOOK_TIMEOUT_SECS: u32 = 30;
+
+/// A prompt run on a schedule, configured in `schedules`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// Identifies the schedule in logs and webhook payloads
+    pub name: String,
+    pub cron: CronExpr,
+    pub prompt: String,
+    pub working_dir: PathBuf,
+    pub profile: Option<String>,
+    pub model: Option<String>,
+    /// URL the tool response is POSTed to when the run finishes
+    pub webhook: Option<String>,
+}
+
+/// Five-field cron expression (minute, hour, day of month, month, day of week) evaluated in
+/// UTC. Fields take `*`, numbers, ranges `a-b`, lists `a,b`, and steps `*/n` or `a-b/n`;
+/// day of week runs 0-7 with both 0 and 7 meaning Sunday. `@hourly`, `@daily`, `@weekly`,
+/// and `@monthly` are accepted as shorthands.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Day of month was `*`; otherwise either day field may match, as in classic cron
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl TryFrom<String> for CronExpr {
+    type Error = String;
+
+    fn try_from(expr: String) -> Result<Self, String> {
+        let expanded = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "cron expression '{}' must have 5 fields (minute hour day month weekday)",
+                expr
+            ));
+        };
+        let field = |value: &str, name: &str, min: u32, max: u32| {
+            parse_field(value, min, max)
+                .map_err(|e| format!("cron expression '{}': {}: {}", expr, name, e))
+        };
+        let mut weekdays = field(weekday, "day of week", 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: field(minute, "minute", 0, 59)?,
+            hours: field(hour, "hour", 0, 23)?,
+            days: field(day, "day of month", 1, 31)?,
+            months: field(month, "month", 1, 12)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+}
+
+/// Bit set of the values `field` selects within `min..=max`
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let number = |s: &str| {
+        s.parse::<u32>()
+            .map_err(|_| format!("'{}' is not a number", s))
+    };
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match number(step)? {
+                0 => return Err("step must be at least 1".to_string()),
+                step => (range, step),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // `5/15` means every 15th value from 5
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("'{}' is outside {}-{}", part, min, max));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl CronExpr {
+    /// Whether the expression fires in the minute starting at `time`
+    pub fn matches(&self, time: DateTime<Utc>) -> bool {
+        let has = |set: u64, value: u32| set & (1 << value) != 0;
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        };
+        day_matches
+            && has(self.minutes, time.minute())
+            && has(self.hours, time.hour())
+            && has(self.months, time.month())
+    }
+}
+
+/// Start due schedules at the top of every minute until the server exits. A schedule whose
+/// previous run is still going skips its turn.
+pub async fn run() {
+    let running: Arc<Mutex<HashSet<String>>> = Arc::default();
+    loop {
+        let now = Utc::now();
+        let next = DateTime::from_timestamp(now.timestamp() / 60 * 60 + 60, 0).unwrap_or(now);
+        tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+
+        for schedule in claude::schedules() {
+            if !schedule.cron.matches(next) {
+                continue;
+            }
+            let started = running
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(schedule.name.clone());
+            if !started {
+                warn!(schedule = %schedule.name, "previous run still in progress; skipping");
+                continue;
+            }
+            let running = running.clone();
+            tokio::spawn(async move {
+                run_schedule(&schedule).await;
+                running
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(&schedule.name);
+            });
+        }
+    }
+}
+
+async fn run_schedule(schedule: &Schedule) {
+    info!(schedule = %schedule.name, "starting scheduled run");
+    let args = ClaudeArgs {
+        prompt: schedule.prompt.clone(),
+        profile: schedule.profile.clone(),
+        model: schedule.model.clone(),
+        ..Default::default()
+    };
+    let (success, output) = match server::run_once(args, &schedule.working_dir).await {
+        Ok(outcome) => outcome,
+        Err(e) => (false, json!({ "success": false, "error": e.message })),
+    };
+    info!(schedule = %schedule.name, success, "scheduled run finished");
+
+    if let Some(url) = &schedule.webhook {
+        let payload = json!({ "schedule": schedule.name, "result": output });
+        if let Err(e) = post_webhook(url, &payload).await {
+            warn!(schedule = %schedule.name, "webhook failed: {:#}", e);
+        }
+    }
+}
+
+/// POST `payload` as JSON with `curl`, which brings TLS and proxy support along
+async fn post_webhook(url: &str, payload: &Value) -> Result<()> {
+    let mut child = tokio::process::Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--max-time"])
+        .arg(WEBHOOK_TIMEOUT_SECS.to_string())
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", "--url", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to start curl")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(payload.to_string().as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "curl exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cron(expr: &str) -> CronExpr {
+        CronExpr::try_from(expr.to_string()).unwrap()
+    }
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_cron_fields_match_like_classic_cron() {
+        // 2026-10-16 is a Friday
+        assert!(cron("0 3 * * *").matches(at("2026-10-16T03:00:00Z")));
+        assert!(!cron("0 3 * * *").matches(at("2026-10-16T03:01:00Z")));
+        assert!(cron("*/15 9-17 * * 1-5").matches(at("2026-10-16T09:45:00Z")));
+        assert!(!cron("*/15 9-17 * * 1-5").matches(at("2026-10-17T09:45:00Z")));
+        assert!(cron("@weekly").matches(at("2026-10-18T00:00:00Z")));
+        assert!(cron("0 0 * * 7").matches(at("2026-10-18T00:00:00Z")));
+        assert!(cron("30 6 1,15 * *").matches(at("2026-10-15T06:30:00Z")));
+        // With both day fields restricted, either one matching is enough
+        assert!(cron("0 0 1 * 5").matches(at("2026-10-16T00:00:00Z")));
+        assert!(cron("5/20 * * * *").matches(at("2026-10-16T00:45:00Z")));
+    }
+
+    #[test]
+    fn test_invalid_cron_expressions_are_rejected() {
+        for expr in [
+            "0 3 * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "a * * * *",
+        ] {
+            assert!(CronExpr::try_from(expr.to_string()).is_err(), "{}", expr);
+        }
+        let err = CronExpr::try_from("0 25 * * *".to_string()).unwrap_err();
+        assert!(err.contains("hour"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_posts_json() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).contains("nightly") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        post_webhook(&url, &json!({ "schedule": "nightly" }))
+            .await
+            .unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook"), "{}", request);
+        assert!(request.contains("application/json"), "{}", request);
+    }
+}
@@ -1,16 +1,59 @@
-use crate::claude::{self, Options};
+use crate::agents;
+use crate::ask;
+use crate::audit::{self, AuditEntry, PromptRetention};
+use crate::capabilities;
+use crate::claude::{
+    self, ContextOverflow, EventLevel, MissingSession, Options, ProcessPriority, RunEvent,
+};
+use crate::cli_features;
+use crate::compare;
+use crate::context::ServerContext;
+use crate::dry_run::{self, DryRun};
+use crate::flaky::{self, FlakyDiagnosis};
+use crate::git_context;
+use crate::idle;
+use crate::journal::{self, Journaled};
+use crate::limits::CALLER_NICE_RANGE;
+use crate::lint_fix::{self, LintFix};
+use crate::memory;
+use crate::migration::{self, Job};
+use crate::notify_throttle::{self, Throttle};
+use crate::output_filter;
+use crate::output_language;
+use crate::platform;
+use crate::prompt_preview;
+use crate::pull_request;
+use crate::release_notes::{self, ReleaseNotes};
+use crate::run_report;
+use crate::sessions;
+use crate::snapshots;
+use crate::store::{self, SessionOrder, SessionQuery};
+use crate::structured::{self, ResponseSchema};
+use crate::swarm::{self, Swarm};
+use crate::templates;
+use crate::tenants;
+use crate::test_writer;
+use crate::token_estimate;
+use crate::transcript;
+use crate::triage;
 use rmcp::{
-    handler::server::{router::tool::ToolRouter, wrapper::Parameters},
+    handler::server::{router::tool::ToolRouter, tool::ToolCallContext, wrapper::Parameters},
     model::*,
-    schemars, tool, tool_handler, tool_router, ErrorData as McpError, ServerHandler,
+    schemars,
+    service::RequestContext,
+    tool, tool_router, ErrorData as McpError, Peer, RoleServer, ServerHandler,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
 use uuid::Uuid;
 
 /// Input parameters for claude tool
-#[derive(Debug, Deserialize, schemars::JsonSchema)]
+#[derive(Debug, Default, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct ClaudeArgs {
     /// Instruction for task to send to Claude
     #[serde(rename = "PROMPT")]
@@ -19,33 +62,514 @@ pub struct ClaudeArgs {
     /// `SESSION_ID` string returned by an earlier `claude` tool call (typically
     /// a UUID). If omitted, a new session is created. Do not pass custom labels
     /// here, and never send an empty string value: when starting a new session,
-    /// omit the `SESSION_ID` field entirely instead of passing `""`.
+    /// omit the `SESSION_ID` field entirely instead of passing `""`. Recent ids are offered
+    /// through argument completion.
     #[serde(rename = "SESSION_ID", default)]
     pub session_id: Option<String>,
+    /// Nice level (0-19) for this run's Claude CLI process. Higher values yield CPU to
+    /// interactive work, which suits background batch runs. Overrides the configured default.
+    #[serde(rename = "PRIORITY", default)]
+    pub priority: Option<i32>,
+    /// Name of a profile from the server config (`profiles`) bundling extra CLI flags,
+    /// timeout, and sandbox settings for this run. Omit to use the top-level defaults.
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model for this run, as a CLI alias (`sonnet`, `opus`, `haiku`) or a full model name.
+    /// Omit to use the CLI's default or the one set in the config or profile.
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+    /// Name of a CLI binary from the server config (`binaries`), e.g. a nightly build or a
+    /// differently authenticated wrapper. Omit to use the profile's or the default binary.
+    #[serde(rename = "BINARY", default)]
+    pub binary: Option<String>,
+    /// Record the exact argv, redacted environment, and raw stdout/stderr of this run to a
+    /// directory returned as `debug_dir`, for reproducing unexpected CLI behavior.
+    #[serde(rename = "DEBUG", default)]
+    pub debug: Option<bool>,
+    /// Return the model's extended-thinking blocks in a separate `thinking` field (capped
+    /// at 1MB). Only produces output when thinking is enabled on the CLI.
+    #[serde(rename = "INCLUDE_THINKING", default)]
+    pub include_thinking: Option<bool>,
+    /// Which part of the response to keep when it exceeds the size limit: `head` (the
+    /// beginning), `tail` (the end, where the final answer usually is), or `middle` (both
+    /// ends with the middle elided). Defaults to the configured `truncation`.
+    #[serde(rename = "TRUNCATION", default)]
+    pub truncation: Option<claude::TruncationStrategy>,
+    /// Size limit in bytes for the response `message`, e.g. 16384 for clients with a small
+    /// context. `TRUNCATION` picks the part that is kept. Capped at 10MB.
+    #[serde(rename = "MAX_OUTPUT_BYTES", default)]
+    pub max_output_bytes: Option<usize>,
+    /// Return the complete raw event stream as base64-encoded gzip of JSONL in
+    /// `all_messages_gzip`, with its uncompressed size in `all_messages_bytes`. The size cap
+    /// applies to the compressed data, so far larger streams fit.
+    #[serde(rename = "ALL_MESSAGES_GZIP", default)]
+    pub all_messages_gzip: Option<bool>,
+    /// JSON Schema the answer must match. Claude is told to reply with JSON only; the reply
+    /// is validated, corrected in up to 2 follow-up turns when it does not match, and
+    /// returned parsed in `answer` (and as structured content when it is an object).
+    #[serde(rename = "RESPONSE_SCHEMA", default)]
+    pub response_schema: Option<Value>,
+    /// Resolve the run without starting it: return the exact command line, the environment
+    /// variables set for the CLI, the working directory, and the timeout that would be used.
+    /// For checking profiles and flags.
+    #[serde(rename = "DRY_RUN", default)]
+    pub dry_run: Option<bool>,
+    /// Subagents Claude can hand work to in this run, by name, e.g. `{"reviewer":
+    /// {"description": "Reviews diffs for bugs", "prompt": "You review code...", "tools":
+    /// ["Read", "Grep"]}}`. Added to the agents in the server config; same names replace them.
+    #[serde(rename = "AGENTS", default)]
+    pub agents: Option<BTreeMap<String, claude::AgentDefinition>>,
+    /// API beta features to enable for this run, e.g. `["context-1m-2025-08-07"]`, on top of
+    /// those of the config and profile. Unknown names are rejected.
+    #[serde(rename = "CLI_FEATURES", default)]
+    pub cli_features: Option<Vec<String>>,
+    /// Language Claude answers in, e.g. `German` or `pt-BR`; code and identifiers stay as they
+    /// are. Omit to use the one set in the config or profile.
+    #[serde(rename = "OUTPUT_LANGUAGE", default)]
+    pub output_language: Option<String>,
+}
+
+/// Input parameters for the claude_ask tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct AskArgs {
+    /// Question about the code, e.g. "Where are retries implemented?"
+    #[serde(rename = "QUESTION")]
+    pub question: String,
+    /// Turns Claude gets to look around before answering (default 8, at most 30)
+    #[serde(rename = "MAX_TURNS", default)]
+    pub max_turns: Option<u32>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model override, as for the `claude` tool
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+}
+
+/// Input parameters for the claude_compact tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct CompactArgs {
+    /// Session to compact: the exact `SESSION_ID` returned by an earlier `claude` call
+    #[serde(rename = "SESSION_ID")]
+    pub session_id: String,
+    /// What the summary should preserve, e.g. "the failing test names and the plan"
+    #[serde(rename = "FOCUS", default)]
+    pub focus: Option<String>,
+    /// Instead of compacting the session in place, have Claude summarize it and start a new
+    /// session seeded with the summary. The old session is left untouched.
+    #[serde(rename = "FRESH_SESSION", default)]
+    pub fresh_session: Option<bool>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+}
+
+/// Input parameters for the claude_extend_timeout tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct ExtendTimeoutArgs {
+    /// Session of the run in progress: the SESSION_ID passed to resume it, or the one
+    /// announced in its `session <id>` log message
+    #[serde(rename = "SESSION_ID")]
+    pub session_id: String,
+    /// Seconds to add to the run's deadline; a run never gets more than 3600 seconds in total
+    #[serde(rename = "SECONDS")]
+    pub seconds: u64,
+}
+
+/// Input parameters for the claude_rollback_run tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct RollbackArgs {
+    /// The `snapshot_id` returned by the failed run
+    #[serde(rename = "SNAPSHOT_ID")]
+    pub snapshot_id: String,
+}
+
+/// Input parameters for the claude_pr tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct PullRequestArgs {
+    /// Task for Claude; the changes it makes become the pull request
+    #[serde(rename = "PROMPT")]
+    pub prompt: String,
+    /// Branch to create, which must not exist yet (default `claude/<random id>`)
+    #[serde(rename = "BRANCH", default)]
+    pub branch: Option<String>,
+    /// Branch or commit to start from and open the pull request against (default: start
+    /// from `HEAD` and let `create_command` pick the base)
+    #[serde(rename = "BASE", default)]
+    pub base: Option<String>,
+    /// Pull request title and commit message (default: the prompt's first line)
+    #[serde(rename = "TITLE", default)]
+    pub title: Option<String>,
+    /// Open the pull request as a draft
+    #[serde(rename = "DRAFT", default)]
+    pub draft: Option<bool>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model override, as for the `claude` tool
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+}
+
+/// Input parameters for the claude_triage_issue tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct TriageArgs {
+    /// Issue number or URL, fetched with `gh issue view`; omit when using `ISSUE_TEXT`
+    #[serde(rename = "ISSUE", default)]
+    pub issue: Option<String>,
+    /// Issue description to triage instead of fetching `ISSUE`
+    #[serde(rename = "ISSUE_TEXT", default)]
+    pub issue_text: Option<String>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model override, as for the `claude` tool
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+}
+
+/// Input parameters for the claude_write_tests tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct WriteTestsArgs {
+    /// Files or functions to test, e.g. `src/parse.rs` or `parse::parse_header`
+    #[serde(rename = "TARGETS")]
+    pub targets: Vec<String>,
+    /// Extra guidance, e.g. which cases matter or which helpers to use
+    #[serde(rename = "INSTRUCTIONS", default)]
+    pub instructions: Option<String>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model override, as for the `claude` tool
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+}
+
+/// Input parameters for the claude_fix_lints tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct FixLintsArgs {
+    /// Rounds of fixing and re-linting before giving up (default `lint_fix.max_rounds`,
+    /// at most 10)
+    #[serde(rename = "MAX_ROUNDS", default)]
+    pub max_rounds: Option<usize>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model override, as for the `claude` tool
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+}
+
+/// Input parameters for the claude_diagnose_flaky tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct DiagnoseFlakyArgs {
+    /// Program and arguments to run, e.g. `["cargo", "test", "cache::tests"]`; not passed
+    /// through a shell
+    #[serde(rename = "COMMAND")]
+    pub command: Vec<String>,
+    /// Runs before giving up on reproducing a failure (default 20, at most 200)
+    #[serde(rename = "MAX_ATTEMPTS", default)]
+    pub max_attempts: Option<usize>,
+    /// Time each run gets before it counts as a failure (default 600)
+    #[serde(rename = "ATTEMPT_TIMEOUT_SECS", default)]
+    pub attempt_timeout_secs: Option<u64>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model override, as for the `claude` tool
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+}
+
+/// Input parameters for the claude_release_notes tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct ReleaseNotesArgs {
+    /// Ref after which the notes start (default: the latest tag reachable from `TO`)
+    #[serde(rename = "FROM", default)]
+    pub from: Option<String>,
+    /// Ref at which the notes end (default `HEAD`)
+    #[serde(rename = "TO", default)]
+    pub to: Option<String>,
+    /// Heading of the notes, e.g. `[1.4.0] - 2026-10-16` (default `[Unreleased]`)
+    #[serde(rename = "VERSION", default)]
+    pub version: Option<String>,
+    /// Insert the notes into `CHANGELOG.md` above the newest release and return the diff
+    #[serde(rename = "WRITE_CHANGELOG", default)]
+    pub write_changelog: Option<bool>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model override, as for the `claude` tool
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+}
+
+/// Input parameters for the claude_migrate tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct MigrateArgs {
+    /// The migration to apply to every file, e.g. "Replace moment with date-fns"; omit when
+    /// resuming with `JOB_ID`
+    #[serde(rename = "INSTRUCTIONS", default)]
+    pub instructions: Option<String>,
+    /// Git glob selecting the files in the working directory and each of `EXTRA_DIRS`,
+    /// e.g. `src/**/*.ts`; omit when using `FILES`
+    #[serde(rename = "PATTERN", default)]
+    pub pattern: Option<String>,
+    /// Files to migrate, relative to the working directory or absolute inside `EXTRA_DIRS`
+    #[serde(rename = "FILES", default)]
+    pub files: Option<Vec<String>>,
+    /// Directories besides the working directory that the migration covers; passed to the
+    /// CLI with `--add-dir` and writable inside a configured sandbox
+    #[serde(rename = "EXTRA_DIRS", default)]
+    pub extra_dirs: Option<Vec<String>>,
+    /// Files per run (default 10, at most 100)
+    #[serde(rename = "CHUNK_SIZE", default)]
+    pub chunk_size: Option<usize>,
+    /// Resume this job from an earlier call, running only its pending and failed files
+    #[serde(rename = "JOB_ID", default)]
+    pub job_id: Option<String>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model override, as for the `claude` tool
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+}
+
+/// Input parameters for the claude_swarm tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct SwarmArgs {
+    /// Independent prompts, each run in its own worktree on its own branch (at most 16)
+    #[serde(rename = "PROMPTS")]
+    pub prompts: Vec<String>,
+    /// Branch or commit the worktrees start from (default `HEAD`)
+    #[serde(rename = "BASE", default)]
+    pub base: Option<String>,
+    /// Branches are named `<BRANCH_PREFIX>-1`, `<BRANCH_PREFIX>-2`, and so on (default
+    /// `claude/swarm-<random>`)
+    #[serde(rename = "BRANCH_PREFIX", default)]
+    pub branch_prefix: Option<String>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Model override, as for the `claude` tool
+    #[serde(rename = "MODEL", default)]
+    pub model: Option<String>,
+}
+
+/// Input parameters for the claude_preview_prompt tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct PreviewPromptArgs {
+    /// Prompt as it would be passed to the `claude` tool; omit when using `TEMPLATE`
+    #[serde(rename = "PROMPT", default)]
+    pub prompt: Option<String>,
+    /// Name of a prompt template (as listed by `prompts/list`) to render instead of `PROMPT`
+    #[serde(rename = "TEMPLATE", default)]
+    pub template: Option<String>,
+    /// Arguments for `TEMPLATE`
+    #[serde(rename = "ARGUMENTS", default)]
+    pub arguments: Option<serde_json::Map<String, Value>>,
+    /// Profile from the server config (`profiles`), as for the `claude` tool
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// JSON Schema whose instructions are appended to the prompt, as for the `claude` tool
+    #[serde(rename = "RESPONSE_SCHEMA", default)]
+    pub response_schema: Option<Value>,
+}
+
+/// Input parameters for the claude_estimate_tokens tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct EstimateTokensArgs {
+    /// Prompt as it would be passed to the `claude` tool
+    #[serde(rename = "PROMPT")]
+    pub prompt: String,
+    /// Files the run would work on, relative to the working directory
+    #[serde(rename = "FILES", default)]
+    pub files: Option<Vec<String>>,
+    /// Profile from the server config (`profiles`), whose system prompts are counted
+    #[serde(rename = "PROFILE", default)]
+    pub profile: Option<String>,
+    /// Context window to compare against (default 200000)
+    #[serde(rename = "CONTEXT_WINDOW", default)]
+    pub context_window: Option<usize>,
+}
+
+/// Input parameters for the claude_transcript tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct TranscriptArgs {
+    /// Session whose transcript to read
+    #[serde(rename = "SESSION_ID")]
+    pub session_id: String,
+    /// Index of the first event to return; use `next_offset` from the previous page
+    #[serde(rename = "OFFSET", default)]
+    pub offset: Option<usize>,
+    /// Events per page (default 100, at most 1000). Pages also end after about 1MB.
+    #[serde(rename = "LIMIT", default)]
+    pub limit: Option<usize>,
+}
+
+/// Input parameters for the claude_compare_runs tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct CompareRunsArgs {
+    /// First session, e.g. the run with one model or prompt
+    #[serde(rename = "SESSION_A")]
+    pub session_a: String,
+    /// Second session, compared against `SESSION_A`
+    #[serde(rename = "SESSION_B")]
+    pub session_b: String,
 }
 
+/// Input parameters for the claude_sessions tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct SessionsArgs {
+    /// Only sessions with calls made in this directory
+    #[serde(rename = "WORKING_DIR", default)]
+    pub working_dir: Option<String>,
+    /// Only sessions with calls in the last this many days
+    #[serde(rename = "SINCE_DAYS", default)]
+    pub since_days: Option<u32>,
+    /// `recent` (default) or `cost`, highest first
+    #[serde(rename = "SORT_BY", default)]
+    pub sort_by: Option<SessionOrder>,
+    /// Sessions returned (default 20, at most 200)
+    #[serde(rename = "LIMIT", default)]
+    pub limit: Option<usize>,
+}
+
+/// Input parameters for the claude_memory_append tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct MemoryAppendArgs {
+    /// Markdown added at the end of the memory file, on a new line
+    #[serde(rename = "TEXT")]
+    pub text: String,
+}
+
+/// Input parameters for the claude_memory_write tool
+#[derive(Debug, Default, Deserialize, schemars::JsonSchema)]
+pub struct MemoryWriteArgs {
+    /// Complete new contents of the memory file
+    #[serde(rename = "CONTENT")]
+    pub content: String,
+}
+
+/// Server instructions sent on initialization unless `instructions` replaces them
+const DEFAULT_INSTRUCTIONS: &str = "This server provides a claude tool for AI-assisted coding \
+    tasks. Use the claude tool to execute coding tasks via the Claude CLI.";
+
+/// Asks for the summary that seeds a fresh session with `FRESH_SESSION`
+const SUMMARY_PROMPT: &str = "Summarize this session so that it can be continued in a new \
+    one: the goal, decisions made, files changed, current state, and open next steps. Reply \
+    with the summary only.";
+
+/// Model aliases accepted by the CLI's `--model`, offered when completing `MODEL`
+const MODEL_ALIASES: [&str; 3] = ["sonnet", "opus", "haiku"];
+
+/// Sessions `claude_sessions` returns when the caller does not ask for a count
+const DEFAULT_SESSIONS_LIMIT: usize = 20;
+/// Most sessions `claude_sessions` returns
+const MAX_SESSIONS_LIMIT: usize = 200;
+
 /// Output from the claude tool
 #[derive(Debug, Serialize, schemars::JsonSchema)]
 struct ClaudeOutput {
     success: bool,
     #[serde(rename = "SESSION_ID")]
     session_id: String,
+    /// On failure, whether `SESSION_ID` can be passed back to continue the session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resumable: Option<bool>,
     message: String,
+    /// Parsed answer matching `RESPONSE_SCHEMA`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answer: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     agent_messages_truncated: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    all_messages: Option<Vec<HashMap<String, Value>>>,
+    agent_messages_omitted: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    all_messages: Option<Vec<Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     all_messages_truncated: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    all_messages_gzip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    all_messages_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    all_messages_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recording_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking_truncated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     warnings: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    injection_warnings: Option<Vec<claude::InjectionWarning>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr: Option<Vec<claude::StderrLine>>,
+    duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cached: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coalesced: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spawn_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_event_ms: Option<u64>,
+    num_events: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_turns: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cli_duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_cost_usd: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<TokenUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actions: Option<claude::ActionSummary>,
+    /// Snapshot of the working tree before a failed run; pass it to `claude_rollback_run`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapshot_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verification: Option<claude::Verification>,
+}
+
+/// Token counts reported by the CLI for the run
+#[derive(Debug, Serialize, schemars::JsonSchema)]
+struct TokenUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+    cache_creation_input_tokens: u64,
+    cache_read_input_tokens: u64,
+}
+
+impl From<claude::Usage> for TokenUsage {
+    fn from(usage: claude::Usage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cache_creation_input_tokens: usage.cache_creation_input_tokens,
+            cache_read_input_tokens: usage.cache_read_input_tokens,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct ClaudeServer {
     tool_router: ToolRouter<ClaudeServer>,
+    /// Minimum level of run events forwarded as `notifications/message`, set by the client
+    log_level: Arc<Mutex<LoggingLevel>>,
+    /// Config, sessions, metrics, and run queue; every request is handled inside it
+    context: ServerContext,
 }
 
 impl Default for ClaudeServer {
@@ -55,11 +579,246 @@ impl Default for ClaudeServer {
 }
 
 impl ClaudeServer {
+    /// A server using the global context: the config from the command line,
+    /// `CLAUDE_MCP_CONFIG_PATH`, or the working directory
     pub fn new() -> Self {
+        Self::with_context(ServerContext::global().clone())
+    }
+
+    /// A server whose tools use `context`, so servers with different configs can run in one
+    /// process
+    pub fn with_context(context: ServerContext) -> Self {
         Self {
             tool_router: Self::tool_router(),
+            log_level: Arc::new(Mutex::new(LoggingLevel::Info)),
+            context,
+        }
+    }
+
+    /// Registered tools limited to `enabled_tools` when set, minus those listed in
+    /// `disabled_tools`, with `tool_descriptions` applied
+    fn enabled_tools(&self) -> Vec<Tool> {
+        self.context.sync_scope(|| {
+            let mut tools = self.tool_router.list_all();
+            if let Some(enabled) = claude::enabled_tools() {
+                tools = only_enabled(tools, &enabled);
+            }
+            let tools = without_disabled(tools, &claude::disabled_tools());
+            with_descriptions(tools, &claude::tool_descriptions())
+        })
+    }
+
+    /// Re-read the config file and send `notifications/tools/list_changed` to `peer` when the
+    /// set of enabled tools or their descriptions changed
+    pub async fn reload_config(&self, peer: &Peer<RoleServer>) {
+        let listing = |tools: Vec<Tool>| -> Vec<(String, Option<String>)> {
+            tools
+                .into_iter()
+                .map(|tool| {
+                    let description = tool.description.map(|d| d.into_owned());
+                    (tool.name.into_owned(), description)
+                })
+                .collect()
+        };
+        let before = listing(self.enabled_tools());
+        self.context.reload_config();
+        let after = listing(self.enabled_tools());
+        if before != after {
+            let tools: Vec<&str> = after.iter().map(|(name, _)| name.as_str()).collect();
+            tracing::info!(?tools, "enabled tools changed");
+            let _ = peer.notify_tool_list_changed().await;
         }
     }
+
+    /// Forward run events to the client as log notifications until the run drops its sender.
+    /// When nothing has been sent for `heartbeat_secs`, an info-level heartbeat goes out so
+    /// clients that drop silent connections keep this one through long runs. Without a
+    /// client, events only update the run's journaled `job`.
+    fn forward_run_events(
+        &self,
+        peer: Option<Peer<RoleServer>>,
+        job: Option<Arc<Journaled>>,
+    ) -> (claude::EventSender, tokio::task::JoinHandle<()>) {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<RunEvent>();
+        let log_level = Arc::clone(&self.log_level);
+        let heartbeat = peer.as_ref().and(claude::heartbeat_interval());
+        let handle = tokio::spawn(async move {
+            let started = Instant::now();
+            let mut last_sent = started;
+            let mut throttle = peer
+                .as_ref()
+                .and(claude::max_notifications_per_sec())
+                .map(|per_sec| Throttle::new(per_sec, started));
+            loop {
+                let deadline = heartbeat.map(|every| last_sent + every);
+                let next_heartbeat = async move {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let flush_at = throttle.as_ref().and_then(Throttle::flush_at);
+                let next_flush = async move {
+                    match flush_at {
+                        Some(at) => tokio::time::sleep_until(at.into()).await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let (level, message, batched) = tokio::select! {
+                    event = receiver.recv() => match event {
+                        Some(event) => {
+                            if let (Some(job), RunEvent::SessionStarted { session_id }) =
+                                (&job, &event)
+                            {
+                                job.session_started(session_id);
+                            }
+                            (logging_level(event.level()), event.message(), false)
+                        }
+                        None => break,
+                    },
+                    () = next_heartbeat => {
+                        // Counts as sent even when filtered out, so the next waits a full interval
+                        last_sent = Instant::now();
+                        (
+                            LoggingLevel::Info,
+                            format!("still running ({}s elapsed)", started.elapsed().as_secs()),
+                            false,
+                        )
+                    }
+                    () = next_flush => {
+                        let held = throttle
+                            .as_mut()
+                            .map(|throttle| throttle.flush(Instant::now()))
+                            .unwrap_or_default();
+                        let (level, message) = batch(held);
+                        (level, message, true)
+                    }
+                };
+                let threshold = *log_level.lock().unwrap_or_else(|e| e.into_inner());
+                let Some(peer) = peer
+                    .as_ref()
+                    .filter(|_| severity(level) >= severity(threshold))
+                else {
+                    continue;
+                };
+                let (level, message) = match throttle.as_mut().filter(|_| !batched) {
+                    Some(throttle) => match throttle.offer(level, message, Instant::now()) {
+                        Some(allowed) => allowed,
+                        None => continue,
+                    },
+                    None => (level, message),
+                };
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level,
+                        logger: Some("claude".to_string()),
+                        data: Value::String(message),
+                    })
+                    .await;
+                last_sent = Instant::now();
+            }
+            // Whatever the rate still held goes out when the run ends
+            let held = throttle
+                .map(|mut throttle| throttle.flush(Instant::now()))
+                .unwrap_or_default();
+            if let Some(peer) = peer.as_ref().filter(|_| !held.is_empty()) {
+                let (level, message) = batch(held);
+                let _ = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level,
+                        logger: Some("claude".to_string()),
+                        data: Value::String(message),
+                    })
+                    .await;
+            }
+        });
+        (sender, handle)
+    }
+}
+
+fn only_enabled(tools: Vec<Tool>, enabled: &[String]) -> Vec<Tool> {
+    tools
+        .into_iter()
+        .filter(|tool| enabled.iter().any(|name| *name == tool.name))
+        .collect()
+}
+
+fn without_disabled(tools: Vec<Tool>, disabled: &[String]) -> Vec<Tool> {
+    tools
+        .into_iter()
+        .filter(|tool| !disabled.iter().any(|name| *name == tool.name))
+        .collect()
+}
+
+/// Structured tool output (`structuredContent`) arrived with protocol revision 2025-06-18
+fn supports_structured_output(version: &ProtocolVersion) -> bool {
+    *version >= ProtocolVersion::V_2025_06_18
+}
+
+/// `tools` with descriptions replaced by the configured ones; unknown names are ignored
+fn with_descriptions(tools: Vec<Tool>, descriptions: &HashMap<String, String>) -> Vec<Tool> {
+    tools
+        .into_iter()
+        .map(|mut tool| {
+            if let Some(description) = descriptions.get(tool.name.as_ref()) {
+                tool.description = Some(description.clone().into());
+            }
+            tool
+        })
+        .collect()
+}
+
+/// Completion candidates for a tool or prompt argument, filtered by the typed prefix
+fn completion_values(argument: &str, prefix: &str) -> Vec<String> {
+    let candidates = match argument.to_ascii_lowercase().as_str() {
+        "session_id" => sessions::recent(),
+        "model" => MODEL_ALIASES.iter().map(|m| m.to_string()).collect(),
+        "profile" => claude::profile_names(),
+        "binary" => claude::binaries()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect(),
+        _ => Vec::new(),
+    };
+    candidates
+        .into_iter()
+        .filter(|value| value.starts_with(prefix))
+        .take(CompletionInfo::MAX_VALUES)
+        .collect()
+}
+
+fn logging_level(level: EventLevel) -> LoggingLevel {
+    match level {
+        EventLevel::Debug => LoggingLevel::Debug,
+        EventLevel::Info => LoggingLevel::Info,
+        EventLevel::Warning => LoggingLevel::Warning,
+        EventLevel::Error => LoggingLevel::Error,
+    }
+}
+
+/// One notification for messages held by the rate limit, at the level of the most severe
+fn batch(held: Vec<(LoggingLevel, String)>) -> (LoggingLevel, String) {
+    let level = held
+        .iter()
+        .map(|(level, _)| *level)
+        .max_by_key(|level| severity(*level))
+        .unwrap_or(LoggingLevel::Info);
+    let messages: Vec<String> = held.into_iter().map(|(_, message)| message).collect();
+    (level, notify_throttle::batch_message(&messages))
+}
+
+/// RFC 5424 severity order of MCP log levels, lowest first
+fn severity(level: LoggingLevel) -> u8 {
+    match level {
+        LoggingLevel::Debug => 0,
+        LoggingLevel::Info => 1,
+        LoggingLevel::Notice => 2,
+        LoggingLevel::Warning => 3,
+        LoggingLevel::Error => 4,
+        LoggingLevel::Critical => 5,
+        LoggingLevel::Alert => 6,
+        LoggingLevel::Emergency => 7,
+    }
 }
 
 #[tool_router]
@@ -74,109 +833,2266 @@ impl ClaudeServer {
     async fn claude(
         &self,
         Parameters(args): Parameters<ClaudeArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if args.dry_run.unwrap_or(false) {
+            let dry_run = dry_run(&args, "claude", &server_working_dir()?).await?;
+            return toon_response(&dry_run);
+        }
+        let structured = peer
+            .peer_info()
+            .is_none_or(|info| supports_structured_output(&info.protocol_version));
+        let (result, answer) = self.audited("claude", args, peer).await?;
+        let mut response = respond(result, answer)?;
+        if !structured {
+            response.structured_content = None;
+        }
+        Ok(response)
+    }
+
+    /// Shrinks a long-running session's context so it does not hit the limit mid-task, either
+    /// with the CLI's `/compact` or by seeding a new session with a summary.
+    #[tool(
+        name = "claude_compact",
+        description = "Compact a Claude CLI session's context and return the SESSION_ID to continue with"
+    )]
+    async fn claude_compact(
+        &self,
+        Parameters(args): Parameters<CompactArgs>,
+        peer: Peer<RoleServer>,
     ) -> Result<CallToolResult, McpError> {
-        // Validate required parameters
-        if args.prompt.is_empty() {
+        if args.session_id.is_empty() {
             return Err(McpError::invalid_params(
-                "PROMPT is required and must be a non-empty string",
+                "SESSION_ID is required and must be a non-empty string",
                 None,
             ));
         }
+        let focus = args.focus.filter(|f| !f.trim().is_empty());
+        if !args.fresh_session.unwrap_or(false) {
+            let compact = ClaudeArgs {
+                prompt: match &focus {
+                    Some(focus) => format!("/compact {}", focus),
+                    None => "/compact".to_string(),
+                },
+                session_id: Some(args.session_id),
+                profile: args.profile,
+                ..Default::default()
+            };
+            let (result, _) = self.audited("claude_compact", compact, peer).await?;
+            return respond(result, None);
+        }
+
+        let summarize = ClaudeArgs {
+            prompt: match &focus {
+                Some(focus) => format!("{} Pay particular attention to: {}", SUMMARY_PROMPT, focus),
+                None => SUMMARY_PROMPT.to_string(),
+            },
+            session_id: Some(args.session_id),
+            profile: args.profile.clone(),
+            ..Default::default()
+        };
+        let (summary, _) = self
+            .audited("claude_compact", summarize, peer.clone())
+            .await?;
+        if !summary.success {
+            return respond(summary, None);
+        }
+        let seed = ClaudeArgs {
+            prompt: format!(
+                "This session continues earlier work, summarized below. Read the summary and \
+                 reply only with \"Ready.\"\n\n{}",
+                summary.agent_messages
+            ),
+            profile: args.profile,
+            ..Default::default()
+        };
+        let (mut seeded, _) = self.audited("claude_compact", seed, peer).await?;
+        // The summary is more useful to the caller than the acknowledgement
+        seeded.agent_messages = summary.agent_messages;
+        respond(seeded, None)
+    }
+
+    #[tool(
+        name = "claude_sessions",
+        description = "List recorded sessions with call counts and cost, filtered by working directory and age"
+    )]
+    async fn claude_sessions(
+        &self,
+        Parameters(args): Parameters<SessionsArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let store = store::current().ok_or_else(|| {
+            McpError::invalid_params("state_store cannot be opened; see the server log", None)
+        })?;
+        let query = SessionQuery {
+            // Calls are recorded with the canonical working directory
+            working_dir: args.working_dir.filter(|d| !d.is_empty()).map(|dir| {
+                Path::new(&dir)
+                    .canonicalize()
+                    .map(platform::normalize_path)
+                    .unwrap_or_else(|_| dir.into())
+            }),
+            since: args
+                .since_days
+                .map(|days| chrono::Utc::now() - chrono::Duration::days(days.into())),
+            order: args.sort_by.unwrap_or_default(),
+            limit: args
+                .limit
+                .unwrap_or(DEFAULT_SESSIONS_LIMIT)
+                .min(MAX_SESSIONS_LIMIT),
+        };
+        let sessions = tokio::task::spawn_blocking(move || store.sessions(&query))
+            .await
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?
+            .map_err(|e| McpError::internal_error(format!("{:#}", e), None))?;
+        toon_response(&sessions)
+    }
 
-        // Normalize empty string session_id to None so that clients should
-        // either omit the field or provide a real session id.
-        let session_id = args.session_id.filter(|s| !s.is_empty());
+    #[tool(
+        name = "claude_transcript",
+        description = "Read a session's event transcript one page at a time"
+    )]
+    async fn claude_transcript(
+        &self,
+        Parameters(args): Parameters<TranscriptArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        if Uuid::parse_str(&args.session_id).is_err() {
+            return Err(McpError::invalid_params(
+                "SESSION_ID must be a valid UUID string",
+                None,
+            ));
+        }
+        tenants::check_session(&args.session_id)?;
+        let limit = match args.limit {
+            Some(0) => return Err(McpError::invalid_params("LIMIT must be at least 1", None)),
+            Some(limit) => limit.min(transcript::MAX_PAGE_EVENTS),
+            None => transcript::DEFAULT_PAGE_EVENTS,
+        };
+        let path = transcript::find(&args.session_id).ok_or_else(|| {
+            McpError::invalid_params(
+                format!("no transcript found for session {}", args.session_id),
+                None,
+            )
+        })?;
+        let offset = args.offset.unwrap_or(0);
+        let page = tokio::task::spawn_blocking(move || transcript::page(&path, offset, limit))
+            .await
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?
+            .map_err(|e| McpError::internal_error(e, None))?;
+        toon_response(&page)
+    }
 
-        if let Some(ref id) = session_id {
-            if Uuid::parse_str(id).is_err() {
+    #[tool(
+        name = "claude_compare_runs",
+        description = "Compare two sessions on the same task (e.g. two models or prompts): final answers, models, turns, actions, and the edits each made per file"
+    )]
+    async fn claude_compare_runs(
+        &self,
+        Parameters(args): Parameters<CompareRunsArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let mut summaries = Vec::new();
+        for (name, session_id) in [("SESSION_A", args.session_a), ("SESSION_B", args.session_b)] {
+            if Uuid::parse_str(&session_id).is_err() {
                 return Err(McpError::invalid_params(
-                    "SESSION_ID must be a valid UUID string",
+                    format!("{} must be a valid UUID string", name),
                     None,
                 ));
             }
+            tenants::check_session(&session_id)?;
+            let path = transcript::find(&session_id).ok_or_else(|| {
+                McpError::invalid_params(
+                    format!("no transcript found for session {}", session_id),
+                    None,
+                )
+            })?;
+            let summary =
+                tokio::task::spawn_blocking(move || compare::summarize(&session_id, &path))
+                    .await
+                    .map_err(|e| McpError::internal_error(e.to_string(), None))?
+                    .map_err(|e| McpError::internal_error(e, None))?;
+            summaries.push(summary);
         }
+        let b = summaries.pop().unwrap_or_default();
+        let a = summaries.pop().unwrap_or_default();
+        toon_response(&compare::compare(a, b))
+    }
 
-        // Resolve and validate working directory based on the current process directory.
-        let working_dir = std::env::current_dir().map_err(|e| {
-            McpError::invalid_params(
-                format!("failed to resolve current working directory: {}", e),
-                None,
-            )
-        })?;
-        let canonical_working_dir = working_dir.canonicalize().map_err(|e| {
-            McpError::invalid_params(
-                format!(
-                    "working directory does not exist or is not accessible: {} ({})",
-                    working_dir.display(),
-                    e
-                ),
+    #[tool(
+        name = "claude_extend_timeout",
+        description = "Give a run in progress more time before its timeout stops it"
+    )]
+    async fn claude_extend_timeout(
+        &self,
+        Parameters(args): Parameters<ExtendTimeoutArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        if Uuid::parse_str(&args.session_id).is_err() {
+            return Err(McpError::invalid_params(
+                "SESSION_ID must be a valid UUID string",
                 None,
-            )
+            ));
+        }
+        if args.seconds == 0 {
+            return Err(McpError::invalid_params("SECONDS must be at least 1", None));
+        }
+        let extended = self
+            .context
+            .deadlines()
+            .extend(&args.session_id, Duration::from_secs(args.seconds))
+            .ok_or_else(|| {
+                McpError::resource_not_found(
+                    format!("no run in progress for session {}", args.session_id),
+                    Some(serde_json::json!({
+                        "reason": "run_not_found",
+                        "SESSION_ID": args.session_id,
+                    })),
+                )
+            })?;
+        tracing::info!(
+            session_id = %args.session_id,
+            timeout_secs = extended.timeout_secs,
+            "run timeout extended"
+        );
+        toon_response(&extended)
+    }
+
+    #[tool(
+        name = "claude_status",
+        description = "List the runs in progress with their last output, current tool, and bytes read, to tell a slow run from a hung one"
+    )]
+    async fn claude_status(&self) -> Result<CallToolResult, McpError> {
+        let statuses = self.context.deadlines().statuses();
+        toon_response(&statuses)
+    }
+
+    #[tool(
+        name = "claude_jobs",
+        description = "List the calls in the job journal: queued, running, and interrupted by a server restart, with the session id to resume"
+    )]
+    async fn claude_jobs(&self) -> Result<CallToolResult, McpError> {
+        let store = store::current().ok_or_else(|| {
+            McpError::invalid_params("state_store cannot be opened; see the server log", None)
         })?;
+        let jobs = tokio::task::spawn_blocking(move || store.jobs())
+            .await
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?
+            .map_err(|e| McpError::internal_error(format!("{:#}", e), None))?;
+        let jobs: Vec<journal::ListedJob> = jobs.into_iter().map(Into::into).collect();
+        toon_response(&jobs)
+    }
+
+    #[tool(
+        name = "claude_capabilities",
+        description = "Describe this server: transport, callable tools, execution and sandbox mode, state store and job journal, limits, budget, and configured features"
+    )]
+    async fn claude_capabilities(&self) -> Result<CallToolResult, McpError> {
+        let tools = self
+            .enabled_tools()
+            .into_iter()
+            .map(|tool| tool.name.to_string())
+            .collect();
+        let described = self.context.sync_scope(|| capabilities::describe(tools));
+        toon_response(&described)
+    }
 
-        if !canonical_working_dir.is_dir() {
+    #[tool(
+        name = "claude_rollback_run",
+        description = "Restore the working tree snapshotted before a failed run"
+    )]
+    async fn claude_rollback_run(
+        &self,
+        Parameters(args): Parameters<RollbackArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        if Uuid::parse_str(&args.snapshot_id).is_err() {
             return Err(McpError::invalid_params(
-                format!(
-                    "working directory is not a directory: {}",
-                    working_dir.display()
-                ),
+                "SNAPSHOT_ID must be a valid UUID string",
                 None,
             ));
         }
+        let working_dir = acted_on_working_dir()?;
+        let rolled_back = snapshots::rollback(&working_dir, &args.snapshot_id)
+            .await
+            .map_err(|e| McpError::internal_error(format!("rollback failed: {:#}", e), None))?
+            .ok_or_else(|| {
+                McpError::resource_not_found(
+                    format!("no snapshot {} in this repository", args.snapshot_id),
+                    Some(serde_json::json!({
+                        "reason": "snapshot_not_found",
+                        "SNAPSHOT_ID": args.snapshot_id,
+                    })),
+                )
+            })?;
+        tracing::info!(
+            snapshot_id = %args.snapshot_id,
+            restored = rolled_back.restored.len(),
+            removed = rolled_back.removed.len(),
+            "working tree rolled back"
+        );
+        toon_response(&rolled_back)
+    }
 
-        // Create options for Claude CLI client
-        let opts = Options {
+    /// Runs a prompt on a new branch in a separate worktree, verifies the result, and opens a
+    /// pull request for it.
+    #[tool(
+        name = "claude_pr",
+        description = "Run a prompt on a new branch, verify the build, commit, push, and open a pull request; returns the branch and PR URL"
+    )]
+    async fn claude_pr(
+        &self,
+        Parameters(args): Parameters<PullRequestArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        check_prompt(&args.prompt)?;
+        let working_dir = acted_on_working_dir()?;
+        let branch = args
+            .branch
+            .filter(|b| !b.is_empty())
+            .unwrap_or_else(pull_request::default_branch);
+        let base = args.base.filter(|b| !b.is_empty());
+        let worktree = pull_request::Worktree::add(&working_dir, &branch, base.as_deref())
+            .await
+            .map_err(|e| {
+                McpError::invalid_params(format!("failed to create a worktree: {:#}", e), None)
+            })?;
+        let request = pull_request::Request {
+            title: args
+                .title
+                .filter(|t| !t.trim().is_empty())
+                .unwrap_or_else(|| pull_request::default_title(&args.prompt)),
+            base,
+            draft: args.draft.unwrap_or(false),
+        };
+        let run = ClaudeArgs {
             prompt: args.prompt,
-            working_dir: canonical_working_dir,
-            session_id,
-            additional_args: claude::default_additional_args(),
-            timeout_secs: None,
+            profile: args.profile,
+            model: args.model,
+            ..Default::default()
         };
+        let (result, _) = match self
+            .audited_with("claude_pr", run, &RunSetup::in_dir(worktree.dir()), peer)
+            .await
+        {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                worktree.remove(false).await;
+                return Err(e);
+            }
+        };
+        let report =
+            pull_request::finish(worktree, result, &claude::pull_requests(), &request).await;
+        tracing::info!(
+            branch = %report.branch,
+            pr_url = report.pr_url.as_deref(),
+            success = report.success,
+            "claude_pr finished"
+        );
+        toon_response(&report)
+    }
 
-        // Execute claude
-        let result = claude::run(opts).await.map_err(|e| {
-            McpError::internal_error(format!("Failed to execute claude: {}", e), None)
-        })?;
-
-        let combined_warnings = result.warnings.clone();
-
-        // Prepare the response using TOON format for token efficiency
-        let output = ClaudeOutput {
+    /// Answers a question about the code without being able to change it, citing the files
+    /// it read.
+    #[tool(
+        name = "claude_ask",
+        description = "Answer a question about the repository (e.g. where something is implemented) in read-only mode with a low turn limit; returns the answer and the files it cited"
+    )]
+    async fn claude_ask(
+        &self,
+        Parameters(args): Parameters<AskArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if args.question.trim().is_empty() {
+            return Err(McpError::invalid_params("QUESTION must not be empty", None));
+        }
+        let max_turns = args
+            .max_turns
+            .unwrap_or(ask::DEFAULT_MAX_TURNS)
+            .clamp(1, ask::MAX_TURNS);
+        let working_dir = server_working_dir()?;
+        let run = ClaudeArgs {
+            prompt: ask::prompt(&args.question),
+            profile: args.profile,
+            model: args.model,
+            ..Default::default()
+        };
+        let setup = RunSetup {
+            working_dir: working_dir.clone(),
+            additional_args: ask::read_only_args(max_turns),
+        };
+        let (result, _) = self.audited_with("claude_ask", run, &setup, peer).await?;
+        let report = ask::Answer {
             success: result.success,
+            files: ask::cited(&result.actions.files_read, &working_dir),
+            answer: result.agent_messages,
             session_id: result.session_id,
-            message: result.agent_messages,
-            agent_messages_truncated: result.agent_messages_truncated.then_some(true),
-            all_messages: None,
-            all_messages_truncated: None,
             error: result.error,
-            warnings: combined_warnings,
         };
-
-        let toon_output = toon_format::encode_default(&output).map_err(|e| {
-            McpError::internal_error(format!("Failed to serialize output: {}", e), None)
-        })?;
-
-        // Return structured content so callers can inspect success, error, and warning fields
-        Ok(CallToolResult::success(vec![Content::text(toon_output)]))
+        toon_response(&report)
     }
-}
 
-#[tool_handler]
-impl ServerHandler for ClaudeServer {
-    fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder()
-                .enable_tools()
-                .build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some("This server provides a claude tool for AI-assisted coding tasks. Use the claude tool to execute coding tasks via the Claude CLI.".to_string()),
+    /// Runs independent prompts concurrently, each in its own worktree on its own branch.
+    #[tool(
+        name = "claude_swarm",
+        description = "Run independent prompts concurrently, each in its own git worktree on a new branch; returns per-prompt results with branch names and commits"
+    )]
+    async fn claude_swarm(
+        &self,
+        Parameters(args): Parameters<SwarmArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if args.prompts.is_empty() || args.prompts.iter().any(|p| p.trim().is_empty()) {
+            return Err(McpError::invalid_params(
+                "PROMPTS must hold at least one prompt, none of them empty",
+                None,
+            ));
+        }
+        if args.prompts.len() > swarm::MAX_PROMPTS {
+            return Err(McpError::invalid_params(
+                format!("PROMPTS holds at most {} prompts", swarm::MAX_PROMPTS),
+                None,
+            ));
+        }
+        let working_dir = acted_on_working_dir()?;
+        let prefix = args
+            .branch_prefix
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(swarm::default_prefix);
+        // One at a time, since they all write to the same repository
+        let mut worktrees = Vec::new();
+        for index in 0..args.prompts.len() {
+            let branch = swarm::branch(&prefix, index);
+            match pull_request::Worktree::add(&working_dir, &branch, args.base.as_deref()).await {
+                Ok(worktree) => worktrees.push(worktree),
+                Err(e) => {
+                    for worktree in worktrees {
+                        worktree.remove(false).await;
+                    }
+                    return Err(McpError::invalid_params(
+                        format!("failed to create a worktree for {}: {:#}", branch, e),
+                        Some(serde_json::json!({ "reason": "worktree_failed" })),
+                    ));
+                }
+            }
+        }
+
+        let parallel = claude::default_run_queue()
+            .map_or(swarm::DEFAULT_PARALLEL_RUNS, |queue| queue.max_concurrent)
+            .max(1);
+        let slots = Arc::new(tokio::sync::Semaphore::new(parallel));
+        let mut runs = tokio::task::JoinSet::new();
+        for (index, (prompt, worktree)) in args.prompts.into_iter().zip(worktrees).enumerate() {
+            let (server, peer, slots) = (self.clone(), peer.clone(), Arc::clone(&slots));
+            let (profile, model) = (args.profile.clone(), args.model.clone());
+            // Spawned tasks start outside the request's context
+            let context = self.context.clone();
+            runs.spawn(async move {
+                let run = context.scope(async move {
+                    // The semaphore is never closed
+                    let _slot = slots.acquire_owned().await.expect("run slots closed");
+                    let run = ClaudeArgs {
+                        prompt: prompt.clone(),
+                        profile,
+                        model,
+                        ..Default::default()
+                    };
+                    let setup = RunSetup::in_dir(worktree.dir());
+                    match server.audited_with("claude_swarm", run, &setup, peer).await {
+                        Ok((result, _)) => swarm::finish(worktree, result, &prompt).await,
+                        Err(e) => {
+                            let branch = worktree.branch().to_string();
+                            worktree.remove(false).await;
+                            swarm::SwarmRun {
+                                branch,
+                                error: Some(e.message.to_string()),
+                                ..Default::default()
+                            }
+                        }
+                    }
+                });
+                (index, run.await)
+            });
+        }
+        let mut report = Swarm {
+            runs: vec![Default::default(); runs.len()],
+            ..Default::default()
+        };
+        while let Some(joined) = runs.join_next().await {
+            match joined {
+                Ok((index, run)) => report.runs[index] = run,
+                Err(e) => tracing::warn!("swarm run panicked: {}", e),
+            }
         }
+        report.success = report.runs.iter().all(|run| run.success);
+        tracing::info!(
+            prompts = report.runs.len(),
+            succeeded = report.runs.iter().filter(|run| run.success).count(),
+            "claude_swarm finished"
+        );
+        toon_response(&report)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    #[allow(unused_imports)]
-    use super::*;
+    /// Investigates an issue in plan mode and returns a structured root-cause hypothesis and
+    /// fix plan, without changing files.
+    #[tool(
+        name = "claude_triage_issue",
+        description = "Triage an issue (text, or a number/URL fetched with gh) in plan mode: returns a root-cause hypothesis, affected files, and a fix plan"
+    )]
+    async fn claude_triage_issue(
+        &self,
+        Parameters(args): Parameters<TriageArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let working_dir = server_working_dir()?;
+        let issue = match (
+            args.issue.filter(|i| !i.is_empty()),
+            args.issue_text.filter(|t| !t.trim().is_empty()),
+        ) {
+            (Some(reference), None) => {
+                triage::fetch(&reference, &working_dir).await.map_err(|e| {
+                    McpError::invalid_params(
+                        format!("failed to fetch issue {}: {:#}", reference, e),
+                        Some(serde_json::json!({ "reason": "issue_unavailable" })),
+                    )
+                })?
+            }
+            (None, Some(text)) => triage::Issue::from_text(&text),
+            _ => {
+                return Err(McpError::invalid_params(
+                    "exactly one of ISSUE and ISSUE_TEXT is required",
+                    None,
+                ))
+            }
+        };
+        let run = ClaudeArgs {
+            prompt: triage::prompt(&issue),
+            profile: args.profile,
+            model: args.model,
+            response_schema: Some(triage::report_schema()),
+            ..Default::default()
+        };
+        let setup = RunSetup {
+            working_dir,
+            additional_args: claude::PLAN_MODE_ARGS.map(String::from).to_vec(),
+        };
+        let (result, answer) = self
+            .audited_with("claude_triage_issue", run, &setup, peer)
+            .await?;
+        respond(result, answer)
+    }
+
+    /// Has Claude add tests for the given targets, then runs the configured test command.
+    #[tool(
+        name = "claude_write_tests",
+        description = "Add tests for the given files or functions, run the configured test command, and report the new test names and whether they pass"
+    )]
+    async fn claude_write_tests(
+        &self,
+        Parameters(args): Parameters<WriteTestsArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let config = claude::write_tests().ok_or_else(|| {
+            McpError::invalid_params(
+                "claude_write_tests needs write_tests.test_command in the server config",
+                None,
+            )
+        })?;
+        let targets: Vec<String> = args
+            .targets
+            .into_iter()
+            .filter(|t| !t.trim().is_empty())
+            .collect();
+        if targets.is_empty() {
+            return Err(McpError::invalid_params(
+                "TARGETS must name at least one file or function",
+                None,
+            ));
+        }
+        let working_dir = server_working_dir()?;
+        let run = ClaudeArgs {
+            prompt: test_writer::prompt(&targets, args.instructions.as_deref()),
+            profile: args.profile,
+            model: args.model,
+            response_schema: Some(test_writer::answer_schema()),
+            ..Default::default()
+        };
+        let setup = RunSetup {
+            working_dir: working_dir.clone(),
+            additional_args: vec![
+                "--append-system-prompt".to_string(),
+                test_writer::SYSTEM_PROMPT.to_string(),
+            ],
+        };
+        let (result, answer) = self
+            .audited_with("claude_write_tests", run, &setup, peer)
+            .await?;
+        let report = test_writer::finish(result, answer, &config, &working_dir).await;
+        toon_response(&report)
+    }
+
+    /// Runs the configured linter and has Claude fix what it reports, for a bounded number
+    /// of rounds.
+    #[tool(
+        name = "claude_fix_lints",
+        description = "Run the configured linter, have Claude fix the diagnostics, and repeat up to MAX_ROUNDS; returns the remaining diagnostics and the diff"
+    )]
+    async fn claude_fix_lints(
+        &self,
+        Parameters(args): Parameters<FixLintsArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let config = claude::lint_fix().ok_or_else(|| {
+            McpError::invalid_params(
+                "claude_fix_lints needs lint_fix.lint_command in the server config",
+                None,
+            )
+        })?;
+        let max_rounds = args
+            .max_rounds
+            .unwrap_or(config.max_rounds)
+            .min(lint_fix::MAX_ROUNDS);
+        let working_dir = acted_on_working_dir()?;
+        let before = snapshots::baseline(&working_dir).await;
+        let mut report = LintFix {
+            lint: lint_fix::lint(&config, &working_dir).await,
+            ..Default::default()
+        };
+        // A linter that did not run to completion has no diagnostics to fix
+        while !report.lint.passed && report.lint.exit_code.is_some() && report.rounds < max_rounds {
+            let run = ClaudeArgs {
+                prompt: lint_fix::prompt(&config, &report.lint),
+                session_id: Some(report.session_id.clone()).filter(|s| !s.is_empty()),
+                profile: args.profile.clone(),
+                model: args.model.clone(),
+                ..Default::default()
+            };
+            let (result, _) = match self.audited("claude_fix_lints", run, peer.clone()).await {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    snapshots::changes_since(before, &working_dir).await;
+                    return Err(e);
+                }
+            };
+            report.rounds += 1;
+            if !result.session_id.is_empty() {
+                report.session_id = result.session_id;
+            }
+            if !result.success {
+                report.error = Some(result.error.unwrap_or_else(|| "the run failed".to_string()));
+                break;
+            }
+            report.lint = lint_fix::lint(&config, &working_dir).await;
+        }
+        report.success = report.lint.passed;
+        if !report.success && report.error.is_none() {
+            report.error = Some(match report.lint.exit_code {
+                Some(_) => format!("diagnostics remain after {} rounds", report.rounds),
+                None => "lint_command did not run to completion".to_string(),
+            });
+        }
+        report.patch = snapshots::changes_since(before, &working_dir).await;
+        toon_response(&report)
+    }
+
+    /// Runs a command until it fails, then has Claude diagnose and fix the intermittent
+    /// failure.
+    #[tool(
+        name = "claude_diagnose_flaky",
+        description = "Run a command repeatedly until it fails (bounded), then have Claude diagnose and fix the flaky failure; returns the failing output, the analysis, and the patch"
+    )]
+    async fn claude_diagnose_flaky(
+        &self,
+        Parameters(args): Parameters<DiagnoseFlakyArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if args
+            .command
+            .first()
+            .is_none_or(|program| program.is_empty())
+        {
+            return Err(McpError::invalid_params(
+                "COMMAND must start with the program to run",
+                None,
+            ));
+        }
+        let attempts = args
+            .max_attempts
+            .unwrap_or(flaky::DEFAULT_ATTEMPTS)
+            .clamp(1, flaky::MAX_ATTEMPTS);
+        let timeout_secs = args
+            .attempt_timeout_secs
+            .unwrap_or(flaky::DEFAULT_ATTEMPT_TIMEOUT_SECS)
+            .max(1);
+        let working_dir = acted_on_working_dir()?;
+        let reproduction = flaky::reproduce(&args.command, timeout_secs, attempts, &working_dir)
+            .await
+            .map_err(|e| McpError::invalid_params(e, None))?;
+        let mut report = FlakyDiagnosis {
+            attempts: reproduction.attempts,
+            ..Default::default()
+        };
+        if let Some(failure) = reproduction.failure {
+            report.reproduced = true;
+            let run = ClaudeArgs {
+                prompt: flaky::prompt(&args.command, reproduction.attempts, &failure),
+                profile: args.profile,
+                model: args.model,
+                ..Default::default()
+            };
+            report.failure = Some(failure);
+            let before = snapshots::baseline(&working_dir).await;
+            let outcome = self.audited("claude_diagnose_flaky", run, peer).await;
+            report.patch = snapshots::changes_since(before, &working_dir).await;
+            let (result, _) = outcome?;
+            report.success = result.success;
+            report.session_id = result.session_id;
+            report.analysis = result.agent_messages;
+            report.error = result.error;
+        } else {
+            report.error = Some(format!("the command passed all {} attempts", attempts));
+        }
+        toon_response(&report)
+    }
+
+    /// Has Claude write categorized release notes from the commits between two refs,
+    /// optionally inserting them into `CHANGELOG.md`.
+    #[tool(
+        name = "claude_release_notes",
+        description = "Write categorized release notes (Markdown) from git log between two refs; optionally insert them into CHANGELOG.md and return the diff"
+    )]
+    async fn claude_release_notes(
+        &self,
+        Parameters(args): Parameters<ReleaseNotesArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let working_dir = acted_on_working_dir()?;
+        let to = args
+            .to
+            .filter(|t| !t.is_empty())
+            .unwrap_or_else(|| "HEAD".to_string());
+        let from = match args.from.filter(|f| !f.is_empty()) {
+            Some(from) => from,
+            None => release_notes::latest_tag(&working_dir, &to)
+                .await
+                .map_err(|e| {
+                    McpError::invalid_params(
+                        format!(
+                            "FROM is required when there is no tag to start from: {:#}",
+                            e
+                        ),
+                        None,
+                    )
+                })?,
+        };
+        let log = release_notes::log(&working_dir, &from, &to)
+            .await
+            .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+        if log.commits == 0 {
+            return Err(McpError::invalid_params(
+                format!("there are no commits in {}..{}", from, to),
+                None,
+            ));
+        }
+        let version = args
+            .version
+            .filter(|v| !v.trim().is_empty())
+            .unwrap_or_else(|| "[Unreleased]".to_string());
+        let run = ClaudeArgs {
+            prompt: release_notes::prompt(&version, &log),
+            profile: args.profile,
+            model: args.model,
+            ..Default::default()
+        };
+        let setup = RunSetup {
+            working_dir: working_dir.clone(),
+            additional_args: claude::PLAN_MODE_ARGS.map(String::from).to_vec(),
+        };
+        let (result, _) = self
+            .audited_with("claude_release_notes", run, &setup, peer)
+            .await?;
+        let mut report = ReleaseNotes {
+            success: result.success,
+            from,
+            to,
+            commits: log.commits,
+            log_truncated: log.truncated,
+            session_id: result.session_id,
+            notes: release_notes::notes(&result.agent_messages),
+            error: result.error,
+            ..Default::default()
+        };
+        if report.success && report.notes.is_empty() {
+            report.success = false;
+            report.error = Some("the run returned no release notes".to_string());
+        }
+        if report.success && args.write_changelog.unwrap_or(false) {
+            let path = working_dir.join("CHANGELOG.md");
+            match release_notes::write_changelog(&path, &report.notes) {
+                Ok(diff) => {
+                    report.changelog = Some(path.display().to_string());
+                    report.diff = Some(diff);
+                }
+                Err(e) => {
+                    report.success = false;
+                    report.error = Some(format!("{:#}", e));
+                }
+            }
+        }
+        toon_response(&report)
+    }
+
+    /// Applies a mechanical migration to many files, in chunks of files run in parallel,
+    /// and reports each file's outcome. The job can be resumed with its id.
+    #[tool(
+        name = "claude_migrate",
+        description = "Apply a mechanical migration to files matched by PATTERN or listed in FILES (working dir and EXTRA_DIRS), in parallel chunks; returns per-file status and diffs and a JOB_ID to resume pending or failed files"
+    )]
+    async fn claude_migrate(
+        &self,
+        Parameters(args): Parameters<MigrateArgs>,
+        peer: Peer<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let working_dir = canonical_path(&acted_on_working_dir()?)?;
+        let job = match args.job_id.filter(|id| !id.is_empty()) {
+            Some(id) => {
+                if args.instructions.is_some()
+                    || args.pattern.is_some()
+                    || args.files.is_some()
+                    || args.extra_dirs.is_some()
+                {
+                    return Err(McpError::invalid_params(
+                        "JOB_ID resumes a job as it was started; omit INSTRUCTIONS, PATTERN, \
+                         FILES, and EXTRA_DIRS",
+                        None,
+                    ));
+                }
+                let job = Job::load(&id).map_err(|e| McpError::invalid_params(e, None))?;
+                if job.working_dir != working_dir {
+                    return Err(McpError::invalid_params(
+                        format!("job {} belongs to {}", job.id, job.working_dir.display()),
+                        None,
+                    ));
+                }
+                for dir in &job.extra_dirs {
+                    tenants::check_dir(dir)?;
+                }
+                job
+            }
+            None => {
+                new_migration(
+                    args.instructions,
+                    args.pattern,
+                    args.files,
+                    args.extra_dirs,
+                    args.chunk_size,
+                    working_dir,
+                )
+                .await?
+            }
+        };
+        job.save().map_err(|e| McpError::internal_error(e, None))?;
+
+        // `--add-dir` takes any number of values, so a bare one would take the prompt too
+        let additional_args = job
+            .extra_dirs
+            .iter()
+            .map(|dir| format!("--add-dir={}", dir.display()))
+            .collect();
+        let setup = Arc::new(RunSetup {
+            working_dir: job.working_dir.clone(),
+            additional_args,
+        });
+        let parallel = claude::default_run_queue()
+            .map_or(migration::DEFAULT_PARALLEL_CHUNKS, |queue| {
+                queue.max_concurrent
+            })
+            .max(1);
+        let slots = Arc::new(tokio::sync::Semaphore::new(parallel));
+        let chunks = job.chunks();
+        let job = Arc::new(Mutex::new(job));
+        let mut runs = tokio::task::JoinSet::new();
+        for chunk in chunks {
+            let (server, peer, setup, slots, job) = (
+                self.clone(),
+                peer.clone(),
+                Arc::clone(&setup),
+                Arc::clone(&slots),
+                Arc::clone(&job),
+            );
+            let (profile, model) = (args.profile.clone(), args.model.clone());
+            // Spawned tasks start outside the request's context
+            let context = self.context.clone();
+            runs.spawn(async move {
+                context
+                    .scope(async move {
+                        // The semaphore is never closed
+                        let _slot = slots.acquire_owned().await.expect("chunk slots closed");
+                        let (instructions, paths, before) = {
+                            let job = job.lock().unwrap_or_else(|e| e.into_inner());
+                            (
+                                job.instructions.clone(),
+                                job.paths(&chunk),
+                                job.contents(&chunk),
+                            )
+                        };
+                        let run = ClaudeArgs {
+                            prompt: migration::prompt(&instructions, &paths),
+                            profile,
+                            model,
+                            ..Default::default()
+                        };
+                        let outcome = server
+                            .audited_with("claude_migrate", run, &setup, peer)
+                            .await;
+                        let mut job = job.lock().unwrap_or_else(|e| e.into_inner());
+                        match &outcome {
+                            Ok((result, _)) => job.settle(&chunk, &before, Ok(result)),
+                            Err(e) => job.settle(&chunk, &before, Err(e.message.to_string())),
+                        }
+                        if let Err(e) = job.save() {
+                            tracing::warn!("{}", e);
+                        }
+                    })
+                    .await
+            });
+        }
+        while let Some(joined) = runs.join_next().await {
+            if let Err(e) = joined {
+                tracing::warn!("migration chunk panicked: {}", e);
+            }
+        }
+        let report = job.lock().unwrap_or_else(|e| e.into_inner()).report();
+        tracing::info!(
+            job_id = %report.job_id,
+            changed = report.changed,
+            failed = report.failed,
+            "claude_migrate finished"
+        );
+        toon_response(&report)
+    }
+
+    #[tool(
+        name = "claude_preview_prompt",
+        description = "Show the prompt, system prompts, and project memory a claude call would send, without running it"
+    )]
+    async fn claude_preview_prompt(
+        &self,
+        Parameters(args): Parameters<PreviewPromptArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let prompt = match (args.prompt.filter(|p| !p.is_empty()), args.template) {
+            (Some(prompt), None) => prompt,
+            (None, Some(name)) => templates::all()
+                .remove(&name)
+                .ok_or_else(|| {
+                    McpError::invalid_params(format!("unknown TEMPLATE '{}'", name), None)
+                })?
+                .render(&args.arguments.unwrap_or_default())
+                .map_err(|e| McpError::invalid_params(e, None))?,
+            _ => {
+                return Err(McpError::invalid_params(
+                    "exactly one of PROMPT and TEMPLATE is required",
+                    None,
+                ))
+            }
+        };
+        let claude_args = ClaudeArgs {
+            prompt,
+            profile: args.profile,
+            response_schema: args.response_schema,
+            ..Default::default()
+        };
+        let opts = options_as_sent(&claude_args, "claude", &server_working_dir()?).await?;
+        toon_response(&prompt_preview::assemble(&opts))
+    }
+
+    #[tool(
+        name = "claude_estimate_tokens",
+        description = "Estimate the tokens a prompt plus attached files would take (local approximation, errs high) and whether they fit the context window"
+    )]
+    async fn claude_estimate_tokens(
+        &self,
+        Parameters(args): Parameters<EstimateTokensArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let claude_args = ClaudeArgs {
+            prompt: args.prompt,
+            profile: args.profile,
+            ..Default::default()
+        };
+        let opts = options_as_sent(&claude_args, "claude", &server_working_dir()?).await?;
+        let context_window = args
+            .context_window
+            .filter(|&window| window > 0)
+            .unwrap_or(token_estimate::DEFAULT_CONTEXT_WINDOW);
+        let estimate =
+            token_estimate::estimate(&opts, &args.files.unwrap_or_default(), context_window)
+                .map_err(|e| McpError::invalid_params(e, None))?;
+        toon_response(&estimate)
+    }
+
+    #[tool(
+        name = "claude_memory_read",
+        description = "Read the project memory file (CLAUDE.md) in the working directory"
+    )]
+    async fn claude_memory_read(&self) -> Result<CallToolResult, McpError> {
+        memory_response(memory::read(&memory_path()?))
+    }
+
+    #[tool(
+        name = "claude_memory_append",
+        description = "Append text to the project memory file (CLAUDE.md) and return the diff"
+    )]
+    async fn claude_memory_append(
+        &self,
+        Parameters(args): Parameters<MemoryAppendArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        if args.text.trim().is_empty() {
+            return Err(McpError::invalid_params(
+                "TEXT is required and must be a non-empty string",
+                None,
+            ));
+        }
+        memory_response(memory::append(&memory_path()?, &args.text))
+    }
+
+    #[tool(
+        name = "claude_memory_write",
+        description = "Replace the project memory file (CLAUDE.md) and return the diff"
+    )]
+    async fn claude_memory_write(
+        &self,
+        Parameters(args): Parameters<MemoryWriteArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        memory_response(memory::write(&memory_path()?, &args.content))
+    }
+}
+
+/// The directory the server runs in, where tool calls run the CLI
+fn server_working_dir() -> Result<std::path::PathBuf, McpError> {
+    std::env::current_dir().map_err(|e| {
+        McpError::invalid_params(
+            format!("failed to resolve current working directory: {}", e),
+            None,
+        )
+    })
+}
+
+/// The server's working directory, for tools that change it without a run; the tenant of
+/// the call must be allowed to use it
+fn acted_on_working_dir() -> Result<std::path::PathBuf, McpError> {
+    let working_dir = server_working_dir()?;
+    let canonical = working_dir
+        .canonicalize()
+        .map(platform::normalize_path)
+        .unwrap_or_else(|_| working_dir.clone());
+    tenants::check_dir(&canonical)?;
+    Ok(working_dir)
+}
+
+/// `path` resolved and normalized the way tenant `allowed_dirs` are
+fn canonical_path(path: &Path) -> Result<PathBuf, McpError> {
+    path.canonicalize()
+        .map(platform::normalize_path)
+        .map_err(|e| McpError::invalid_params(format!("{}: {}", path.display(), e), None))
+}
+
+/// A `claude_migrate` job over the files selected by `pattern` or listed in `files`, in
+/// `working_dir` and `extra_dirs`
+async fn new_migration(
+    instructions: Option<String>,
+    pattern: Option<String>,
+    files: Option<Vec<String>>,
+    extra_dirs: Option<Vec<String>>,
+    chunk_size: Option<usize>,
+    working_dir: PathBuf,
+) -> Result<Job, McpError> {
+    let instructions = instructions
+        .filter(|i| !i.trim().is_empty())
+        .ok_or_else(|| McpError::invalid_params("INSTRUCTIONS is required", None))?;
+    let mut dirs = Vec::new();
+    for dir in extra_dirs.unwrap_or_default() {
+        let dir = canonical_path(&working_dir.join(dir))?;
+        if !dir.is_dir() {
+            return Err(McpError::invalid_params(
+                format!("EXTRA_DIRS entry {} is not a directory", dir.display()),
+                None,
+            ));
+        }
+        tenants::check_dir(&dir)?;
+        if dir != working_dir && !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    let mut paths = Vec::new();
+    match (
+        pattern.filter(|p| !p.is_empty()),
+        files.filter(|f| !f.is_empty()),
+    ) {
+        (Some(pattern), None) => {
+            paths.extend(
+                migration::matching(&working_dir, &pattern)
+                    .await
+                    .map_err(|e| McpError::invalid_params(e, None))?,
+            );
+            for dir in &dirs {
+                let matched = migration::matching(dir, &pattern)
+                    .await
+                    .map_err(|e| McpError::invalid_params(e, None))?;
+                paths.extend(
+                    matched
+                        .iter()
+                        .map(|path| dir.join(path).display().to_string()),
+                );
+            }
+        }
+        (None, Some(files)) => {
+            for file in files {
+                let path = canonical_path(&working_dir.join(&file))?;
+                if !path.is_file() {
+                    return Err(McpError::invalid_params(
+                        format!("{} is not a file", file),
+                        None,
+                    ));
+                }
+                let path = if let Ok(relative) = path.strip_prefix(&working_dir) {
+                    relative.display().to_string()
+                } else if dirs.iter().any(|dir| path.starts_with(dir)) {
+                    path.display().to_string()
+                } else {
+                    return Err(McpError::invalid_params(
+                        format!("{} is outside the working directory and EXTRA_DIRS", file),
+                        None,
+                    ));
+                };
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+        _ => {
+            return Err(McpError::invalid_params(
+                "exactly one of PATTERN and FILES is required",
+                None,
+            ))
+        }
+    }
+    if paths.is_empty() {
+        return Err(McpError::invalid_params("no files to migrate", None));
+    }
+    if paths.len() > migration::MAX_FILES {
+        return Err(McpError::invalid_params(
+            format!(
+                "{} files match; a job takes at most {}",
+                paths.len(),
+                migration::MAX_FILES
+            ),
+            None,
+        ));
+    }
+    let chunk_size = chunk_size
+        .unwrap_or(migration::DEFAULT_CHUNK_SIZE)
+        .clamp(1, migration::MAX_CHUNK_SIZE);
+    Ok(Job::new(instructions, working_dir, dirs, chunk_size, paths))
+}
+
+/// The configured memory file in the server's working directory
+fn memory_path() -> Result<std::path::PathBuf, McpError> {
+    let working_dir = acted_on_working_dir()?;
+    memory::resolve(&working_dir, &claude::memory_file())
+        .map_err(|e| McpError::invalid_params(e, None))
+}
+
+fn memory_response(
+    outcome: Result<memory::MemoryFile, String>,
+) -> Result<CallToolResult, McpError> {
+    let file = outcome.map_err(|e| McpError::invalid_params(e, None))?;
+    toon_response(&file)
+}
+
+/// `value` encoded as TOON in a successful tool result
+fn toon_response<T: Serialize>(value: &T) -> Result<CallToolResult, McpError> {
+    let toon_output = toon_format::encode_default(value).map_err(|e| {
+        McpError::internal_error(format!("Failed to serialize output: {}", e), None)
+    })?;
+    Ok(CallToolResult::success(vec![Content::text(toon_output)]))
+}
+
+/// Where a tool runs the CLI, and the flags it adds to the caller's
+struct RunSetup {
+    working_dir: PathBuf,
+    /// Appended after the config's and profile's flags, so they take precedence
+    additional_args: Vec<String>,
+}
+
+impl RunSetup {
+    fn in_dir(working_dir: PathBuf) -> Self {
+        Self {
+            working_dir,
+            additional_args: Vec::new(),
+        }
+    }
+}
+
+impl ClaudeServer {
+    /// Run `args` for `tool` in the server's working directory, appending an audit record
+    /// when the audit log is enabled
+    async fn audited(
+        &self,
+        tool: &'static str,
+        args: ClaudeArgs,
+        peer: Peer<RoleServer>,
+    ) -> Result<(claude::ClaudeResult, Option<Value>), McpError> {
+        let setup = RunSetup::in_dir(server_working_dir()?);
+        self.audited_with(tool, args, &setup, peer).await
+    }
+
+    /// Run `args` for `tool` as `setup` says, appending an audit record when the audit log
+    /// is enabled
+    async fn audited_with(
+        &self,
+        tool: &'static str,
+        args: ClaudeArgs,
+        setup: &RunSetup,
+        peer: Peer<RoleServer>,
+    ) -> Result<(claude::ClaudeResult, Option<Value>), McpError> {
+        self.recorded(tool, args, setup, Some(peer), None).await
+    }
+
+    /// [`audited_with`](Self::audited_with) for a call with or without a client, journaled
+    /// in the state store while it waits and runs. `adopted` is a job journaled by a server
+    /// that is gone, run again under its id.
+    async fn recorded(
+        &self,
+        tool: &str,
+        args: ClaudeArgs,
+        setup: &RunSetup,
+        peer: Option<Peer<RoleServer>>,
+        adopted: Option<journal::Job>,
+    ) -> Result<(claude::ClaudeResult, Option<Value>), McpError> {
+        let audit_log = claude::default_audit_log();
+        let store = store::current();
+        let job = store.clone().map(|store| match adopted {
+            Some(job) => Journaled::adopted(store, job),
+            None => Journaled::queued(
+                store,
+                tool,
+                &args,
+                &setup.working_dir,
+                &setup.additional_args,
+            ),
+        });
+        if audit_log.is_none() && store.is_none() {
+            return self.run_claude(tool, args, setup, peer, None, job).await;
+        }
+
+        let started = Instant::now();
+        let retention = PromptRetention::for_calls(audit_log.as_ref());
+        let mut entry = AuditEntry::new(tool, &args.prompt, retention);
+        entry.client = peer
+            .as_ref()
+            .and_then(|peer| peer.peer_info())
+            .map(|info| format!("{}/{}", info.client_info.name, info.client_info.version));
+        entry.profile = args.profile.clone();
+        entry.priority = args.priority;
+        entry.resume_session_id = args.session_id.clone().filter(|s| !s.is_empty());
+
+        let outcome = self
+            .run_claude(tool, args, setup, peer, Some(&mut entry), job)
+            .await;
+        entry.duration_ms = started.elapsed().as_millis() as u64;
+        if let Err(e) = &outcome {
+            entry.success = false;
+            entry.error = Some(e.message.to_string());
+        }
+        if let Some(audit_log) = &audit_log {
+            audit::record(audit_log, &entry);
+        }
+        if let Some(store) = store {
+            if let Err(e) = store.record_call(&entry) {
+                tracing::warn!("failed to record call in state_store: {:#}", e);
+            }
+        }
+        outcome
+    }
+
+    /// Run `args` as `setup` says, forwarding run events to `peer`; `audit` collects what the
+    /// call did for the audit log, and `job` is its journal entry
+    async fn run_claude(
+        &self,
+        tool: &str,
+        args: ClaudeArgs,
+        setup: &RunSetup,
+        peer: Option<Peer<RoleServer>>,
+        mut audit: Option<&mut AuditEntry>,
+        job: Option<Arc<Journaled>>,
+    ) -> Result<(claude::ClaudeResult, Option<Value>), McpError> {
+        let mut opts = options(&args, tool, &setup.working_dir)?;
+        let mut setup_args = setup.additional_args.iter();
+        while let Some(arg) = setup_args.next() {
+            // Joined with the configured system prompt and language directive, which a second
+            // flag would replace
+            if arg == "--append-system-prompt" {
+                if let Some(text) = setup_args.next() {
+                    output_language::append_system_prompt(&mut opts.additional_args, text);
+                    continue;
+                }
+            }
+            opts.additional_args.push(arg.clone());
+        }
+        let schema = response_schema(&args)?;
+        tenants::check_budget()?;
+        if let Some(entry) = audit.as_deref_mut() {
+            entry.working_dir = Some(opts.working_dir.clone());
+        }
+
+        // Execute claude
+        let span = tracing::info_span!("tool_call", tool, profile = args.profile.as_deref());
+        // Held until the call finishes; a full queue rejects the call with a busy error
+        let _slot = match self.context.run_queue() {
+            Some(queue) => Some(queue.admit().await?),
+            None => None,
+        };
+        if let Some(job) = &job {
+            job.running();
+        }
+        let (events, forwarder) = self.forward_run_events(peer, job);
+        let opts = Options {
+            events: Some(events),
+            ..opts
+        };
+        let result = execute(opts, schema.as_ref()).instrument(span).await;
+        // The run has dropped its sender; let queued notifications go out before the response
+        let _ = forwarder.await;
+        let (result, answer) = result.map_err(|e| {
+            McpError::internal_error(format!("Failed to execute claude: {}", e), None)
+        })?;
+
+        if let Some(entry) = audit {
+            entry.session_id = Some(result.session_id.clone()).filter(|s| !s.is_empty());
+            entry.success = result.success;
+            entry.exit_code = result.exit_code;
+            entry.error = result.error.clone();
+            entry.total_cost_usd = result.total_cost_usd;
+        }
+        if !result.cached {
+            tenants::charge(result.total_cost_usd.unwrap_or_default());
+        }
+        reject_missing_session(&result, args.session_id.as_deref())?;
+        Ok((result, answer))
+    }
+}
+
+/// A `session_not_found` error when the run failed because the CLI does not know
+/// `session_id`, so callers can tell it apart from other failures and start over
+fn reject_missing_session(
+    result: &claude::ClaudeResult,
+    session_id: Option<&str>,
+) -> Result<(), McpError> {
+    match session_id.filter(|id| !id.is_empty()) {
+        Some(session_id) if claude::is_missing_session(result) => {
+            Err(McpError::resource_not_found(
+                format!(
+                    "session {} not found; omit SESSION_ID to start a new session",
+                    session_id
+                ),
+                Some(serde_json::json!({
+                    "reason": "session_not_found",
+                    "SESSION_ID": session_id,
+                })),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Tool response for a finished run
+fn respond(
+    result: claude::ClaudeResult,
+    answer: Option<Value>,
+) -> Result<CallToolResult, McpError> {
+    // Prepare the response using TOON format for token efficiency
+    let output = ClaudeOutput {
+        answer: answer.clone(),
+        ..ClaudeOutput::from(result)
+    };
+    // Return structured content so callers can inspect success, error, and warning fields
+    let mut response = toon_response(&output)?;
+    // MCP only allows an object as structured content
+    response.structured_content = answer.filter(Value::is_object);
+    Ok(response)
+}
+
+/// Run `args` in `working_dir` without an MCP client, as the `run` subcommand and schedules
+/// do. Returns whether the run succeeded and the tool response as JSON.
+pub async fn run_once(args: ClaudeArgs, working_dir: &Path) -> Result<(bool, Value), McpError> {
+    if args.dry_run.unwrap_or(false) {
+        let json =
+            serde_json::to_value(dry_run(&args, "claude", working_dir).await?).map_err(|e| {
+                McpError::internal_error(format!("Failed to serialize output: {}", e), None)
+            })?;
+        return Ok((true, json));
+    }
+    let opts = options(&args, "claude", working_dir)?;
+    let schema = response_schema(&args)?;
+    let span = tracing::info_span!("run", profile = args.profile.as_deref());
+    let (result, answer) = execute(opts, schema.as_ref())
+        .instrument(span)
+        .await
+        .map_err(|e| McpError::internal_error(format!("Failed to execute claude: {}", e), None))?;
+    reject_missing_session(&result, args.session_id.as_deref())?;
+    let success = result.success;
+    let output = ClaudeOutput {
+        answer,
+        ..ClaudeOutput::from(result)
+    };
+    let json = serde_json::to_value(output).map_err(|e| {
+        McpError::internal_error(format!("Failed to serialize output: {}", e), None)
+    })?;
+    Ok((success, json))
+}
+
+/// Run a call again that a server journaled but never started before it went away. There
+/// is no client to answer, so the outcome only lands in the session registry, state store,
+/// and audit log.
+pub(crate) async fn rerun(job: journal::Job) {
+    let (id, tool) = (job.id.clone(), job.tool.clone());
+    let args = match serde_json::from_value::<ClaudeArgs>(job.args.clone()) {
+        Ok(args) => args,
+        Err(e) => {
+            tracing::warn!(job = %id, "dropping a journaled job with invalid arguments: {}", e);
+            if let Some(store) = store::current() {
+                let _ = store.remove_job(&id);
+            }
+            return;
+        }
+    };
+    let setup = RunSetup {
+        working_dir: job.working_dir.clone(),
+        additional_args: job.additional_args.clone(),
+    };
+    let outcome = ClaudeServer::new()
+        .recorded(&tool, args, &setup, None, Some(job))
+        .await;
+    match outcome {
+        Ok((result, _)) => {
+            tracing::info!(job = %id, success = result.success, session_id = %result.session_id, "journaled job finished");
+        }
+        Err(e) => tracing::warn!(job = %id, "journaled job failed: {}", e.message),
+    }
+}
+
+/// Run `opts` through [`answer`] and apply the configured `output_filters` to the agent
+/// text. A failing filter leaves the text unfiltered and adds a warning. With `snapshots`
+/// configured, the working tree is snapshotted first and the run verified afterwards. With
+/// `git_context` configured, the prompt starts with the repository's git context. With
+/// `run_reports` configured, a report of the run is written under the working directory.
+async fn execute(
+    mut opts: Options,
+    schema: Option<&ResponseSchema>,
+) -> anyhow::Result<(claude::ClaudeResult, Option<Value>)> {
+    // Reports show the prompt as the caller wrote it
+    let report = run_report::before_run(&opts).await;
+    opts.prompt = git_context::prepend(&opts.prompt, &opts.working_dir).await;
+    let (snapshot, snapshot_warning) = snapshots::before_run(&opts).await;
+    let (mut result, answer) = match answer(opts.clone(), schema).await {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            if let Some(snapshot) = snapshot {
+                snapshots::discard(snapshot).await;
+            }
+            if let Some(report) = report {
+                report.discard().await;
+            }
+            return Err(e);
+        }
+    };
+    if let Some(warning) = snapshot_warning {
+        result.warnings = claude::push_warning(result.warnings.take(), &warning);
+    }
+    let filters = claude::output_filters();
+    if !filters.is_empty() && !result.agent_messages.is_empty() {
+        let text = result.agent_messages.clone();
+        match output_filter::apply(&filters, text, &opts).await {
+            Ok(filtered) => result.agent_messages = filtered,
+            Err(e) => {
+                result.warnings = claude::push_warning(
+                    result.warnings.take(),
+                    &format!(
+                        "output_filters failed; returning unfiltered output: {:#}",
+                        e
+                    ),
+                );
+            }
+        }
+    }
+    if let Some(snapshot) = snapshot {
+        snapshots::after_run(snapshot, &mut result, &opts).await;
+    }
+    if let Some(report) = report {
+        report.finish(&mut result).await;
+    }
+    Ok((result, answer))
+}
+
+/// Run `opts`. With a schema, ask for JSON matching it and resume the session with the
+/// validation errors until the answer matches or the corrections run out; the parsed answer
+/// is returned alongside the result, and a final mismatch fails the result.
+async fn answer(
+    mut opts: Options,
+    schema: Option<&ResponseSchema>,
+) -> anyhow::Result<(claude::ClaudeResult, Option<Value>)> {
+    let Some(schema) = schema else {
+        return Ok((run_with_fallbacks(opts).await?, None));
+    };
+    opts.prompt = schema.prompt(&opts.prompt);
+    let mut corrections = 0;
+    loop {
+        let mut result = run_with_fallbacks(opts.clone()).await?;
+        if !result.success {
+            return Ok((result, None));
+        }
+        match schema.check(&result.agent_messages) {
+            Ok(answer) => return Ok((result, Some(answer))),
+            Err(problem)
+                if corrections < structured::MAX_CORRECTIONS && !result.session_id.is_empty() =>
+            {
+                corrections += 1;
+                tracing::debug!(
+                    corrections,
+                    "answer does not match RESPONSE_SCHEMA: {}",
+                    problem
+                );
+                opts.session_id = Some(result.session_id);
+                opts.prompt = structured::correction(&problem);
+            }
+            Err(problem) => {
+                result.success = false;
+                result.error = Some(format!(
+                    "answer does not match RESPONSE_SCHEMA after {} attempts: {}",
+                    corrections + 1,
+                    problem
+                ));
+                return Ok((result, None));
+            }
+        }
+    }
+}
+
+/// [`run_recovering`], moving down `fallbacks` while the run fails with a retryable error
+/// (overloaded or unavailable model) or the CLI cannot be started. Each substitution is noted
+/// in `warnings`.
+async fn run_with_fallbacks(opts: Options) -> anyhow::Result<claude::ClaudeResult> {
+    let mut outcome = run_recovering(opts.clone()).await;
+    let mut notes = Vec::new();
+    for fallback in claude::fallbacks() {
+        let reason = match &outcome {
+            Ok(result) if claude::is_retryable_failure(result) => {
+                result.error.clone().unwrap_or_default()
+            }
+            Ok(_) => break,
+            Err(e) => format!("{:#}", e),
+        };
+        let note = format!("run failed ({}); retried with {}", reason, fallback);
+        tracing::info!("{}", note);
+        notes.push(note);
+        outcome = run_recovering(fallback.apply(opts.clone())).await;
+    }
+    let mut result = outcome?;
+    for note in notes {
+        result.warnings = claude::push_warning(result.warnings, &note);
+    }
+    Ok(result)
+}
+
+/// [`run_resuming`], retrying once after compacting or replacing the session when the run hit
+/// the context limit and `context_overflow` allows it. What happened is noted in `warnings`.
+async fn run_recovering(opts: Options) -> anyhow::Result<claude::ClaudeResult> {
+    let policy = claude::default_context_overflow();
+    let result = run_resuming(opts.clone()).await?;
+    if policy == ContextOverflow::Fail || !claude::is_context_overflow(&result) {
+        return Ok(result);
+    }
+    let session_id = Some(result.session_id.clone())
+        .filter(|id| !id.is_empty())
+        .or_else(|| opts.session_id.clone());
+
+    let (retry, note) = match (policy, session_id) {
+        (ContextOverflow::Compact, Some(session_id)) => {
+            let compacted = claude::run(Options {
+                prompt: "/compact".to_string(),
+                session_id: Some(session_id.clone()),
+                ..opts.clone()
+            })
+            .await?;
+            if !compacted.success {
+                let mut result = result;
+                result.warnings = claude::push_warning(
+                    result.warnings,
+                    &format!(
+                        "context limit reached; compacting session {} failed: {}",
+                        session_id,
+                        compacted.error.as_deref().unwrap_or("unknown error")
+                    ),
+                );
+                return Ok(result);
+            }
+            let session_id = Some(compacted.session_id)
+                .filter(|id| !id.is_empty())
+                .unwrap_or(session_id);
+            let note = format!(
+                "context limit reached; compacted session {} and retried the prompt",
+                session_id
+            );
+            (
+                Options {
+                    session_id: Some(session_id),
+                    ..opts
+                },
+                note,
+            )
+        }
+        _ => (
+            Options {
+                session_id: None,
+                ..opts
+            },
+            "context limit reached; retried the prompt in a new session without the earlier \
+             history"
+                .to_string(),
+        ),
+    };
+    tracing::info!("{}", note);
+    let mut retried = claude::run(retry).await?;
+    retried.warnings = claude::push_warning(retried.warnings, &note);
+    Ok(retried)
+}
+
+/// Run `opts`; when the session to resume is unknown and `missing_session` is
+/// `"fresh_session"`, run the prompt again in a new session and note it in `warnings`
+async fn run_resuming(opts: Options) -> anyhow::Result<claude::ClaudeResult> {
+    let result = claude::run(opts.clone()).await?;
+    let Some(session_id) = opts.session_id.clone() else {
+        return Ok(result);
+    };
+    if claude::default_missing_session() == MissingSession::Fail
+        || !claude::is_missing_session(&result)
+    {
+        return Ok(result);
+    }
+    let note = format!(
+        "session {} was not found; ran the prompt in a new session",
+        session_id
+    );
+    tracing::info!("{}", note);
+    let mut fresh = claude::run(Options {
+        session_id: None,
+        ..opts
+    })
+    .await?;
+    fresh.warnings = claude::push_warning(fresh.warnings, &note);
+    Ok(fresh)
+}
+
+/// What running `args` for `tool` in `working_dir` would start, for `DRY_RUN`
+async fn dry_run(args: &ClaudeArgs, tool: &str, working_dir: &Path) -> Result<DryRun, McpError> {
+    Ok(dry_run::describe(
+        &options_as_sent(args, tool, working_dir).await?,
+    ))
+}
+
+/// [`options`] with the prompt as the CLI receives it, the git context header and
+/// `RESPONSE_SCHEMA` instructions included
+async fn options_as_sent(
+    args: &ClaudeArgs,
+    tool: &str,
+    working_dir: &Path,
+) -> Result<Options, McpError> {
+    let mut opts = options(args, tool, working_dir)?;
+    opts.prompt = git_context::prepend(&opts.prompt, &opts.working_dir).await;
+    if let Some(schema) = response_schema(args)? {
+        opts.prompt = schema.prompt(&opts.prompt);
+    }
+    Ok(opts)
+}
+
+fn response_schema(args: &ClaudeArgs) -> Result<Option<ResponseSchema>, McpError> {
+    args.response_schema
+        .as_ref()
+        .map(|schema| ResponseSchema::new(schema).map_err(|e| McpError::invalid_params(e, None)))
+        .transpose()
+}
+
+/// Reject a `PROMPT` the CLI cannot run: empty or blank, with a NUL byte, which ends a
+/// command-line argument early, starting with `-`, which the CLI reads as an option, or over
+/// `max_prompt_bytes`. Prompts are JSON strings and so always valid UTF-8.
+fn check_prompt(prompt: &str) -> Result<(), McpError> {
+    if prompt.is_empty() {
+        return Err(McpError::invalid_params(
+            "PROMPT is required and must be a non-empty string",
+            None,
+        ));
+    }
+    if prompt.trim().is_empty() {
+        return Err(McpError::invalid_params(
+            "PROMPT must contain more than whitespace",
+            None,
+        ));
+    }
+    if let Some(at) = prompt.find('\0') {
+        return Err(McpError::invalid_params(
+            format!(
+                "PROMPT must not contain NUL characters (found at byte {}); the command line \
+                 would cut the prompt off there",
+                at
+            ),
+            None,
+        ));
+    }
+    let max_prompt_bytes = claude::max_prompt_bytes();
+    if prompt.len() > max_prompt_bytes {
+        return Err(McpError::invalid_params(
+            format!(
+                "PROMPT is {} bytes, over the limit of {} bytes (max_prompt_bytes); pass large \
+                 inputs as files in the working directory instead",
+                prompt.len(),
+                max_prompt_bytes
+            ),
+            Some(serde_json::json!({
+                "reason": "prompt_too_large",
+                "prompt_bytes": prompt.len(),
+                "max_prompt_bytes": max_prompt_bytes,
+            })),
+        ));
+    }
+    Ok(())
+}
+
+/// Validate the arguments of a call to `tool` and resolve them against the config, the
+/// tool's `tools` block, and the profile into run options
+fn options(args: &ClaudeArgs, tool: &str, working_dir: &Path) -> Result<Options, McpError> {
+    check_prompt(&args.prompt)?;
+
+    // Normalize empty string session_id to None so that clients should
+    // either omit the field or provide a real session id.
+    let session_id = args.session_id.clone().filter(|s| !s.is_empty());
+
+    if let Some(ref id) = session_id {
+        if Uuid::parse_str(id).is_err() {
+            return Err(McpError::invalid_params(
+                "SESSION_ID must be a valid UUID string",
+                None,
+            ));
+        }
+        tenants::check_session(id)?;
+    }
+
+    if let Some(nice) = args.priority {
+        if !CALLER_NICE_RANGE.contains(&nice) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "PRIORITY must be a nice level between {} and {}",
+                    CALLER_NICE_RANGE.start(),
+                    CALLER_NICE_RANGE.end()
+                ),
+                None,
+            ));
+        }
+    }
+
+    if args.max_output_bytes == Some(0) {
+        return Err(McpError::invalid_params(
+            "MAX_OUTPUT_BYTES must be a positive number of bytes",
+            None,
+        ));
+    }
+
+    let defaults = claude::tool_defaults(tool);
+    let profile = match args
+        .profile
+        .as_deref()
+        .filter(|p| !p.is_empty())
+        .or(defaults.profile.as_deref())
+    {
+        Some(name) => Some(claude::profile(name).ok_or_else(|| {
+            McpError::invalid_params(
+                format!(
+                    "unknown PROFILE '{}'; configured profiles: [{}]",
+                    name,
+                    claude::profile_names().join(", ")
+                ),
+                None,
+            )
+        })?),
+        None => None,
+    };
+
+    let binary = match args
+        .binary
+        .clone()
+        .filter(|b| !b.is_empty())
+        .or_else(|| profile.as_ref().and_then(|p| p.binary.clone()))
+    {
+        Some(name) => Some(claude::binary(&name).ok_or_else(|| {
+            let names: Vec<String> = claude::binaries().into_iter().map(|(n, _)| n).collect();
+            McpError::invalid_params(
+                format!(
+                    "unknown BINARY '{}'; configured binaries: [{}]",
+                    name,
+                    names.join(", ")
+                ),
+                None,
+            )
+        })?),
+        None => profile.as_ref().and_then(|p| p.claude_bin.clone()),
+    };
+
+    // Resolve and validate the working directory; a profile's replaces the server's
+    let working_dir = &match profile.as_ref().and_then(|p| p.working_dir.as_ref()) {
+        Some(dir) => working_dir.join(dir),
+        None => working_dir.to_path_buf(),
+    };
+    let canonical_working_dir = working_dir
+        .canonicalize()
+        .map(platform::normalize_path)
+        .map_err(|e| {
+            McpError::invalid_params(
+                format!(
+                    "working directory does not exist or is not accessible: {} ({})",
+                    working_dir.display(),
+                    e
+                ),
+                None,
+            )
+        })?;
+
+    if !canonical_working_dir.is_dir() {
+        return Err(McpError::invalid_params(
+            format!(
+                "working directory is not a directory: {}",
+                working_dir.display()
+            ),
+            None,
+        ));
+    }
+    tenants::check_dir(&canonical_working_dir)?;
+
+    let default_priority = claude::default_priority();
+    let mut additional_args = claude::default_additional_args();
+    additional_args.extend(defaults.additional_args);
+    let tool_timeout_secs = defaults
+        .timeout_secs
+        .map(|t| claude::clamp_timeout_secs(Some(t)));
+    let settings = profile
+        .as_ref()
+        .and_then(|p| p.settings.clone())
+        .or_else(claude::default_settings);
+    let output_language = args
+        .output_language
+        .clone()
+        .or_else(|| profile.as_ref().and_then(|p| p.output_language.clone()))
+        .or_else(claude::output_language)
+        .filter(|language| !language.trim().is_empty());
+    let mut features = claude::cli_features();
+    if let Some(profile) = profile.as_ref() {
+        features.extend(profile.cli_features.iter().cloned());
+    }
+    features.extend(args.cli_features.iter().flatten().cloned());
+    let (timeout_secs, sandbox, remote, env) = match profile {
+        Some(profile) => {
+            additional_args.extend(profile.additional_args);
+            (
+                profile
+                    .timeout_secs
+                    .map(|t| claude::clamp_timeout_secs(Some(t)))
+                    .or(tool_timeout_secs),
+                profile.sandbox.unwrap_or_else(claude::default_sandbox),
+                profile.remote.or_else(claude::default_remote),
+                profile.env,
+            )
+        }
+        None => (
+            tool_timeout_secs,
+            claude::default_sandbox(),
+            claude::default_remote(),
+            Default::default(),
+        ),
+    };
+    if let Some(agents) = agents::arg(claude::agents(), args.agents.as_ref())? {
+        additional_args.extend(["--agents".to_string(), agents]);
+    }
+    additional_args.extend(cli_features::args(&features)?);
+    if let Some(settings) = settings {
+        additional_args.extend(["--settings".to_string(), settings.to_arg()]);
+    }
+    if let Some(model) = args
+        .model
+        .clone()
+        .filter(|m| !m.is_empty())
+        .or(defaults.model)
+    {
+        additional_args.extend(["--model".to_string(), model]);
+    }
+
+    if let Some(language) = output_language {
+        output_language::append_system_prompt(
+            &mut additional_args,
+            &output_language::directive(&language),
+        );
+    }
+
+    Ok(Options {
+        prompt: args.prompt.clone(),
+        working_dir: canonical_working_dir,
+        session_id,
+        additional_args,
+        timeout_secs,
+        binary,
+        env,
+        spill_all_messages: claude::default_spill_all_messages(),
+        execution_mode: claude::default_execution_mode(),
+        warm_pool_size: claude::default_warm_pool_size(),
+        resource_limits: claude::default_resource_limits(),
+        priority: ProcessPriority {
+            nice: args.priority.or(default_priority.nice),
+            ..default_priority
+        },
+        sandbox,
+        remote,
+        events: None,
+        debug_capture: args.debug.unwrap_or_else(claude::default_debug_capture),
+        include_thinking: args.include_thinking.unwrap_or(false),
+        sanitize_text: claude::default_sanitize_text(),
+        screen_injections: claude::default_screen_injections(),
+        truncation: args.truncation.unwrap_or_else(claude::default_truncation),
+        max_output_bytes: args.max_output_bytes,
+        compress_all_messages: args.all_messages_gzip.unwrap_or(false),
+        response_cache: claude::default_response_cache(),
+        record_to: claude::record_dir()
+            .map(|dir| dir.join(format!("{}.jsonl", uuid::Uuid::new_v4()))),
+        replay_from: claude::replay_file(),
+        parser_limits: claude::parser_limits(),
+        max_skipped_lines: claude::max_skipped_lines(),
+    })
+}
+
+impl From<claude::ClaudeResult> for ClaudeOutput {
+    fn from(result: claude::ClaudeResult) -> Self {
+        ClaudeOutput {
+            success: result.success,
+            resumable: (!result.success).then_some(!result.session_id.is_empty()),
+            session_id: result.session_id,
+            message: result.agent_messages,
+            answer: None,
+            agent_messages_truncated: result.agent_messages_truncated.then_some(true),
+            agent_messages_omitted: (result.agent_messages_omitted > 0)
+                .then_some(result.agent_messages_omitted),
+            all_messages: None,
+            all_messages_truncated: result
+                .all_messages_gzip
+                .as_ref()
+                .and_then(|gzip| gzip.truncated.then_some(true)),
+            all_messages_bytes: result
+                .all_messages_gzip
+                .as_ref()
+                .map(|gzip| gzip.uncompressed_bytes),
+            all_messages_gzip: result.all_messages_gzip.map(|gzip| gzip.gzip_base64),
+            all_messages_file: result
+                .all_messages_file
+                .map(|path| path.to_string_lossy().into_owned()),
+            debug_dir: result
+                .debug_dir
+                .map(|path| path.to_string_lossy().into_owned()),
+            recording_file: result
+                .recording_file
+                .map(|path| path.to_string_lossy().into_owned()),
+            report_dir: result
+                .report_dir
+                .map(|path| path.to_string_lossy().into_owned()),
+            thinking: (!result.thinking.is_empty()).then_some(result.thinking),
+            thinking_truncated: result.thinking_truncated.then_some(true),
+            error: result.error,
+            warnings: result.warnings,
+            injection_warnings: (!result.injection_warnings.is_empty())
+                .then_some(result.injection_warnings),
+            stderr: (!result.stderr.is_empty()).then_some(result.stderr),
+            duration_ms: result.timing.duration_ms,
+            cached: result.cached.then_some(true),
+            coalesced: result.coalesced.then_some(true),
+            spawn_ms: result.timing.spawn_ms,
+            first_event_ms: result.timing.first_event_ms,
+            num_events: result.timing.num_events,
+            num_turns: result.num_turns,
+            cli_duration_ms: result.cli_duration_ms,
+            api_duration_ms: result.api_duration_ms,
+            total_cost_usd: result.total_cost_usd,
+            usage: result.usage.map(TokenUsage::from),
+            actions: (!result.actions.is_empty()).then_some(result.actions),
+            snapshot_id: result.snapshot_id,
+            verification: result.verification,
+        }
+    }
+}
+
+impl ServerHandler for ClaudeServer {
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            // The newest revision supported; a client asking for an older one gets its own
+            // version back during initialization
+            protocol_version: ProtocolVersion::V_2025_06_18,
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_tool_list_changed()
+                .enable_logging()
+                .enable_completions()
+                .enable_prompts()
+                .build(),
+            server_info: Implementation::from_build_env(),
+            instructions: Some(
+                self.context
+                    .sync_scope(claude::instructions)
+                    .unwrap_or_else(|| DEFAULT_INSTRUCTIONS.to_string()),
+            ),
+        }
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.context
+            .scope(async {
+                if !claude::is_tool_enabled(&request.name) {
+                    return Err(McpError::invalid_params(
+                        format!("tool '{}' is disabled", request.name),
+                        None,
+                    ));
+                }
+                let _active = idle::call_started();
+                let tcc = ToolCallContext::new(self, request, context);
+                self.tool_router.call(tcc).await
+            })
+            .await
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult::with_all_items(self.enabled_tools()))
+    }
+
+    fn get_tool(&self, name: &str) -> Option<Tool> {
+        self.enabled_tools()
+            .into_iter()
+            .find(|tool| tool.name == name)
+    }
+
+    async fn set_level(
+        &self,
+        request: SetLevelRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        *self.log_level.lock().unwrap_or_else(|e| e.into_inner()) = request.level;
+        Ok(())
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, McpError> {
+        let prompts = self
+            .context
+            .sync_scope(templates::all)
+            .into_iter()
+            .map(|(name, template)| {
+                let arguments = template
+                    .arguments
+                    .into_iter()
+                    .map(|argument| PromptArgument {
+                        name: argument.name,
+                        title: None,
+                        description: argument.description,
+                        required: Some(argument.required),
+                    })
+                    .collect();
+                Prompt::new(name, template.description, Some(arguments))
+            })
+            .collect();
+        Ok(ListPromptsResult::with_all_items(prompts))
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, McpError> {
+        let template = self
+            .context
+            .sync_scope(templates::all)
+            .remove(&request.name)
+            .ok_or_else(|| {
+                McpError::invalid_params(format!("unknown prompt '{}'", request.name), None)
+            })?;
+        let text = template
+            .render(&request.arguments.unwrap_or_default())
+            .map_err(|e| McpError::invalid_params(e, None))?;
+        Ok(GetPromptResult {
+            description: template.description,
+            messages: vec![PromptMessage::new_text(PromptMessageRole::User, text)],
+        })
+    }
+
+    async fn complete(
+        &self,
+        request: CompleteRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<CompleteResult, McpError> {
+        let values = self
+            .context
+            .sync_scope(|| completion_values(&request.argument.name, &request.argument.value));
+        let completion = CompletionInfo::with_all_values(values)
+            .map_err(|e| McpError::internal_error(e, None))?;
+        Ok(CompleteResult { completion })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_run_event_levels_map_to_increasing_severity() {
+        let levels = [
+            EventLevel::Debug,
+            EventLevel::Info,
+            EventLevel::Warning,
+            EventLevel::Error,
+        ];
+        let severities: Vec<u8> = levels
+            .into_iter()
+            .map(|level| severity(logging_level(level)))
+            .collect();
+        assert!(severities.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_disabled_tools_are_not_listed() {
+        let tools = ClaudeServer::tool_router().list_all();
+        let names = |tools: Vec<Tool>| -> Vec<String> {
+            let mut names: Vec<String> = tools.into_iter().map(|t| t.name.into_owned()).collect();
+            names.sort();
+            names
+        };
+        let all = [
+            "claude",
+            "claude_ask",
+            "claude_capabilities",
+            "claude_compact",
+            "claude_compare_runs",
+            "claude_diagnose_flaky",
+            "claude_estimate_tokens",
+            "claude_extend_timeout",
+            "claude_fix_lints",
+            "claude_jobs",
+            "claude_memory_append",
+            "claude_memory_read",
+            "claude_memory_write",
+            "claude_migrate",
+            "claude_pr",
+            "claude_preview_prompt",
+            "claude_release_notes",
+            "claude_rollback_run",
+            "claude_sessions",
+            "claude_status",
+            "claude_swarm",
+            "claude_transcript",
+            "claude_triage_issue",
+            "claude_write_tests",
+        ];
+        assert_eq!(names(without_disabled(tools.clone(), &[])), all);
+        assert_eq!(
+            names(without_disabled(tools.clone(), &["claude".to_string()])),
+            all[1..]
+        );
+        assert_eq!(
+            without_disabled(tools, &["codex".to_string()]).len(),
+            all.len(),
+            "unknown names are ignored"
+        );
+    }
+
+    #[test]
+    fn test_prompts_the_cli_cannot_run_are_rejected() {
+        let message = |prompt: &str| check_prompt(prompt).unwrap_err().message.into_owned();
+        assert_eq!(
+            message(""),
+            "PROMPT is required and must be a non-empty string"
+        );
+        assert_eq!(message(" \n\t"), "PROMPT must contain more than whitespace");
+        assert!(message("Fix\0it").contains("found at byte 3"));
+        assert!(check_prompt("- rename Foo\n- update callers").is_ok());
+        assert!(check_prompt("Fix the build -- then run the tests\n").is_ok());
+    }
+
+    #[test]
+    fn test_only_enabled_tools_are_listed_and_callable() {
+        let enabled = ["claude_ask".to_string(), "claude_status".to_string()];
+        let tools = only_enabled(ClaudeServer::tool_router().list_all(), &enabled);
+        let mut names: Vec<String> = tools.into_iter().map(|t| t.name.into_owned()).collect();
+        names.sort();
+        assert_eq!(names, enabled);
+
+        let config = claude::ServerConfig::from_json(
+            r#"{"enabled_tools": ["claude_ask", "claude_status"], "disabled_tools": ["claude_status"]}"#,
+        )
+        .unwrap();
+        ServerContext::new(config).sync_scope(|| {
+            assert!(claude::is_tool_enabled("claude_ask"));
+            assert!(!claude::is_tool_enabled("claude_status"));
+            assert!(!claude::is_tool_enabled("claude"));
+        });
+    }
+
+    #[test]
+    fn test_structured_output_only_from_2025_06_18() {
+        assert!(!supports_structured_output(&ProtocolVersion::V_2024_11_05));
+        assert!(!supports_structured_output(&ProtocolVersion::V_2025_03_26));
+        assert!(supports_structured_output(&ProtocolVersion::V_2025_06_18));
+    }
+
+    #[test]
+    fn test_configured_descriptions_replace_builtin_ones() {
+        let descriptions = HashMap::from([
+            (
+                "claude".to_string(),
+                "Use plan mode before editing more than one file".to_string(),
+            ),
+            ("codex".to_string(), "unknown names are ignored".to_string()),
+        ]);
+        let tools = with_descriptions(ClaudeServer::tool_router().list_all(), &descriptions);
+        let description = |name: &str| {
+            tools
+                .iter()
+                .find(|tool| tool.name == name)
+                .and_then(|tool| tool.description.as_deref().map(str::to_string))
+        };
+        assert_eq!(
+            description("claude").as_deref(),
+            Some("Use plan mode before editing more than one file")
+        );
+        assert_eq!(
+            description("claude_sessions").as_deref(),
+            Some("List recorded sessions with call counts and cost, filtered by working directory and age")
+        );
+    }
+
+    #[test]
+    fn test_completion_values_match_argument_and_prefix() {
+        assert_eq!(completion_values("MODEL", "s"), vec!["sonnet"]);
+        assert_eq!(completion_values("model", ""), MODEL_ALIASES.to_vec());
+        assert!(completion_values("PROMPT", "").is_empty());
+
+        let id = "0f8e2c61-9a47-4d3b-8c55-2e1d7a9b4f10";
+        sessions::record(id);
+        assert_eq!(completion_values("SESSION_ID", "0f8e2c61"), vec![id]);
+    }
 }
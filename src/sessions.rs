@@ -0,0 +1,72 @@
+//! Registry of sessions started through this server.
+//!
+//! Every run that reports a session id is remembered here, most recent first, so clients can
+//! autocomplete `SESSION_ID` instead of guessing at it. The registry lives in memory and is
+//! bounded; it does not survive a restart. For a tenant it is also the list of sessions it
+//! may read and resume.
+
+use crate::context;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Sessions kept; older ones are forgotten
+const MAX_SESSIONS: usize = 100;
+
+/// Sessions of one server context
+#[derive(Default)]
+pub(crate) struct SessionRegistry(Mutex<VecDeque<String>>);
+
+impl SessionRegistry {
+    fn record(&self, session_id: &str) {
+        if session_id.is_empty() {
+            return;
+        }
+        let mut sessions = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.retain(|id| id != session_id);
+        sessions.push_front(session_id.to_string());
+        sessions.truncate(MAX_SESSIONS);
+    }
+
+    /// Whether `session_id` is one of the remembered sessions
+    pub(crate) fn contains(&self, session_id: &str) -> bool {
+        let sessions = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.iter().any(|id| id == session_id)
+    }
+
+    fn recent(&self) -> Vec<String> {
+        let sessions = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.iter().cloned().collect()
+    }
+}
+
+/// Remember `session_id` as the most recently used session
+pub(crate) fn record(session_id: &str) {
+    context::current().sessions().record(session_id);
+}
+
+/// Known session ids, most recent first
+pub(crate) fn recent() -> Vec<String> {
+    context::current().sessions().recent()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_sessions_are_deduplicated_and_bounded() {
+        let first = "11111111-1111-1111-1111-111111111111";
+        record(first);
+        record("");
+        for n in 0..MAX_SESSIONS {
+            record(&format!("session-{}", n));
+        }
+        record(first);
+
+        let recent = recent();
+        assert_eq!(recent.len(), MAX_SESSIONS);
+        assert_eq!(recent[0], first);
+        assert_eq!(recent.iter().filter(|id| *id == first).count(), 1);
+        assert!(!recent.contains(&"session-0".to_string()));
+    }
+}
@@ -0,0 +1,539 @@
+//! Working-tree snapshots taken before runs that may edit files, so a failed run can be undone.
+//!
+//! With `snapshots` in `claude-mcp.config.json`, the git working tree of a run is committed to
+//! `refs/claude-mcp/snapshots/<id>` before the CLI starts. The commit is built through a
+//! temporary index, so the real index, the stash, and `HEAD` are left alone; untracked files
+//! are included and ignored ones are not. After a successful run the optional
+//! `verify_command` (e.g. `cargo test`) runs in the working directory. When the run or the
+//! verification fails, the snapshot id is returned and `claude_rollback_run` restores the
+//! tree; otherwise the snapshot is deleted.
+//!
+//! Runs in plan mode (`--permission-mode plan`) cannot edit files, and remote runs edit a
+//! tree on another host, so neither is snapshotted.
+
+use crate::claude::{self, ClaudeResult, Options};
+use anyhow::{bail, Context, Result};
+use rmcp::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Namespace of the snapshot refs
+const REF_PREFIX: &str = "refs/claude-mcp/snapshots/";
+/// Identity recorded on snapshot commits, so they work without a configured `user.name`
+const COMMITTER: (&str, &str) = ("claude-mcp", "claude-mcp@localhost");
+/// End of the verification output kept in the result
+const MAX_VERIFY_OUTPUT: usize = 4096;
+/// Start of a [`Patch`] kept in the result
+const MAX_PATCH_BYTES: usize = 64 * 1024;
+
+fn default_verify_timeout_secs() -> u64 {
+    600
+}
+
+/// Snapshot settings, configured via `snapshots` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// Program and arguments run in the working directory after a successful run; a nonzero
+    /// exit fails the run
+    #[serde(default)]
+    pub verify_command: Vec<String>,
+    #[serde(default = "default_verify_timeout_secs")]
+    pub verify_timeout_secs: u64,
+}
+
+/// Outcome of `verify_command`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Verification {
+    pub passed: bool,
+    /// `None` when the command could not be started, timed out, or was killed by a signal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// The last 4KB of its stdout and stderr
+    pub output: String,
+}
+
+/// What a rollback changed, relative to the repository root
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct RolledBack {
+    pub snapshot_id: String,
+    /// Files changed or deleted since the snapshot, now restored
+    pub restored: Vec<String>,
+    /// Files created since the snapshot, now deleted
+    pub removed: Vec<String>,
+}
+
+/// Changes a tool made, as a unified diff
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct Patch {
+    pub diff: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub diff_truncated: bool,
+}
+
+/// A snapshot taken before a run
+pub(crate) struct Snapshot {
+    id: String,
+    repo: PathBuf,
+}
+
+impl Snapshot {
+    /// Top level of the snapshotted repository
+    pub(crate) fn repo(&self) -> &Path {
+        &self.repo
+    }
+
+    /// Changes from this snapshot to `later` as a unified diff
+    pub(crate) async fn diff(&self, later: &Snapshot) -> Result<String> {
+        let from = format!("{}{}", REF_PREFIX, self.id);
+        let to = format!("{}{}", REF_PREFIX, later.id);
+        git(
+            &self.repo,
+            None,
+            &["diff", "--no-color", "--no-ext-diff", &from, &to],
+        )
+        .await
+    }
+}
+
+/// Snapshot the tree of the run about to start with `opts`, when snapshots are configured
+/// and the run may edit files. A tree that cannot be snapshotted, e.g. one outside git, is
+/// reported as a warning and the run goes ahead.
+pub(crate) async fn before_run(opts: &Options) -> (Option<Snapshot>, Option<String>) {
+    if claude::snapshots().is_none() || opts.remote.is_some() || is_plan_mode(opts) {
+        return (None, None);
+    }
+    match take(&opts.working_dir).await {
+        Ok(snapshot) => {
+            tracing::debug!(snapshot_id = %snapshot.id, "working tree snapshotted");
+            (Some(snapshot), None)
+        }
+        Err(e) => (None, Some(format!("working tree not snapshotted: {:#}", e))),
+    }
+}
+
+/// Verify a successful run with `verify_command`, then keep the snapshot for
+/// `claude_rollback_run` if the run failed, or delete it
+pub(crate) async fn after_run(snapshot: Snapshot, result: &mut ClaudeResult, opts: &Options) {
+    let config = claude::snapshots();
+    if let Some(config) = config.filter(|c| result.success && !c.verify_command.is_empty()) {
+        let verification = verify(
+            &config.verify_command,
+            config.verify_timeout_secs,
+            &opts.working_dir,
+        )
+        .await;
+        if !verification.passed {
+            result.success = false;
+            result.error = Some(format!(
+                "verify_command `{}` failed after the run",
+                config.verify_command.join(" ")
+            ));
+        }
+        result.verification = Some(verification);
+    }
+    if result.success {
+        discard(snapshot).await;
+    } else {
+        result.snapshot_id = Some(snapshot.id);
+    }
+}
+
+fn is_plan_mode(opts: &Options) -> bool {
+    let mut args = opts.additional_args.iter();
+    while let Some(arg) = args.next() {
+        let mode = match arg.strip_prefix("--permission-mode") {
+            Some("") => args.next().map(String::as_str),
+            Some(value) => value.strip_prefix('='),
+            None => None,
+        };
+        if mode == Some("plan") {
+            return true;
+        }
+    }
+    false
+}
+
+/// Commit the whole working tree of the repository containing `working_dir`
+pub(crate) async fn take(working_dir: &Path) -> Result<Snapshot> {
+    let repo = PathBuf::from(git(working_dir, None, &["rev-parse", "--show-toplevel"]).await?);
+    let id = uuid::Uuid::new_v4().to_string();
+    let index = TempIndex::new(&repo, &id).await?;
+    git(&repo, Some(&index.0), &["add", "--all"]).await?;
+    let tree = git(&repo, Some(&index.0), &["write-tree"]).await?;
+    let message = format!("claude-mcp snapshot {}", id);
+    let mut commit_tree = vec!["commit-tree", tree.as_str(), "-m", message.as_str()];
+    let head = git(&repo, None, &["rev-parse", "--verify", "--quiet", "HEAD"])
+        .await
+        .ok();
+    if let Some(head) = &head {
+        commit_tree.extend(["-p", head.as_str()]);
+    }
+    let commit = git(&repo, None, &commit_tree).await?;
+    let reference = format!("{}{}", REF_PREFIX, id);
+    git(
+        &repo,
+        None,
+        &["update-ref", reference.as_str(), commit.as_str()],
+    )
+    .await?;
+    Ok(Snapshot { id, repo })
+}
+
+/// Delete a snapshot that is not needed, e.g. because the CLI never started
+pub(crate) async fn discard(snapshot: Snapshot) {
+    let reference = format!("{}{}", REF_PREFIX, snapshot.id);
+    if let Err(e) = git(
+        &snapshot.repo,
+        None,
+        &["update-ref", "-d", reference.as_str()],
+    )
+    .await
+    {
+        tracing::warn!(snapshot_id = %snapshot.id, "failed to delete snapshot: {:#}", e);
+    }
+}
+
+/// Snapshot of the tree before a tool makes changes it reports with [`changes_since`];
+/// `None` outside git
+pub(crate) async fn baseline(working_dir: &Path) -> Option<Snapshot> {
+    match take(working_dir).await {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            tracing::debug!("no baseline snapshot: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Changes in the working tree since `before`, up to [`MAX_PATCH_BYTES`]. Deletes the
+/// snapshot.
+pub(crate) async fn changes_since(before: Option<Snapshot>, working_dir: &Path) -> Option<Patch> {
+    let before = before?;
+    let mut patch = None;
+    if let Ok(after) = take(working_dir).await {
+        match before.diff(&after).await {
+            Ok(diff) => {
+                let mut end = diff.len().min(MAX_PATCH_BYTES);
+                while !diff.is_char_boundary(end) {
+                    end -= 1;
+                }
+                patch = Some(Patch {
+                    diff: diff[..end].to_string(),
+                    diff_truncated: end < diff.len(),
+                });
+            }
+            Err(e) => tracing::warn!("failed to diff the working tree: {:#}", e),
+        }
+        discard(after).await;
+    }
+    discard(before).await;
+    patch
+}
+
+/// Restore the tree of snapshot `id` in the repository containing `working_dir` and delete
+/// the snapshot. `None` when there is no such snapshot. Files ignored by git, the index, and
+/// commits made since are left alone.
+pub(crate) async fn rollback(working_dir: &Path, id: &str) -> Result<Option<RolledBack>> {
+    let repo = PathBuf::from(git(working_dir, None, &["rev-parse", "--show-toplevel"]).await?);
+    let reference = format!("{}{}", REF_PREFIX, id);
+    let tree = format!("{}^{{tree}}", reference);
+    if git(
+        &repo,
+        None,
+        &["rev-parse", "--verify", "--quiet", tree.as_str()],
+    )
+    .await
+    .is_err()
+    {
+        return Ok(None);
+    }
+    let index = TempIndex::new(&repo, id).await?;
+    git(&repo, Some(&index.0), &["read-tree", tree.as_str()]).await?;
+    // Entries read from a tree have no file stats; without a refresh every file looks changed
+    let _ = git(&repo, Some(&index.0), &["update-index", "-q", "--refresh"]).await;
+    let restored = paths(git(&repo, Some(&index.0), &["diff", "--name-only", "-z"]).await?);
+    git(
+        &repo,
+        Some(&index.0),
+        &["checkout-index", "--all", "--force"],
+    )
+    .await?;
+    let removed = paths(
+        git(
+            &repo,
+            Some(&index.0),
+            &["ls-files", "--others", "--exclude-standard", "-z"],
+        )
+        .await?,
+    );
+    for path in &removed {
+        tokio::fs::remove_file(repo.join(path))
+            .await
+            .with_context(|| format!("failed to remove {}", path))?;
+    }
+    git(&repo, None, &["update-ref", "-d", reference.as_str()]).await?;
+    Ok(Some(RolledBack {
+        snapshot_id: id.to_string(),
+        restored,
+        removed,
+    }))
+}
+
+fn paths(listing: String) -> Vec<String> {
+    listing
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Index file used instead of the repository's, removed on drop
+struct TempIndex(PathBuf);
+
+impl TempIndex {
+    /// A copy of the repository's index, so `git add` can reuse its cached file stats
+    async fn new(repo: &Path, id: &str) -> Result<Self> {
+        let real = git(
+            repo,
+            None,
+            &["rev-parse", "--path-format=absolute", "--git-path", "index"],
+        )
+        .await?;
+        let path = std::env::temp_dir().join(format!("claude-mcp-index-{}", id));
+        let index = Self(path);
+        if Path::new(&real).exists() {
+            tokio::fs::copy(&real, &index.0)
+                .await
+                .context("failed to copy the git index")?;
+        }
+        Ok(index)
+    }
+}
+
+impl Drop for TempIndex {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Run git in `dir`, optionally with another index file, and return its trimmed stdout
+async fn git(dir: &Path, index: Option<&Path>, args: &[&str]) -> Result<String> {
+    let mut command = Command::new("git");
+    command
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", COMMITTER.0)
+        .env("GIT_AUTHOR_EMAIL", COMMITTER.1)
+        .env("GIT_COMMITTER_NAME", COMMITTER.0)
+        .env("GIT_COMMITTER_EMAIL", COMMITTER.1)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    if let Some(index) = index {
+        command.env("GIT_INDEX_FILE", index);
+    }
+    let name = format!("git {}", args.first().copied().unwrap_or_default());
+    output_of(command, &name).await
+}
+
+/// Trimmed stdout of `command`, which must exit successfully; `name` labels errors
+pub(crate) async fn output_of(mut command: Command, name: &str) -> Result<String> {
+    let output = command
+        .output()
+        .await
+        .with_context(|| format!("failed to run {}", name))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .with_context(|| format!("{} printed invalid UTF-8", name))?;
+    Ok(stdout.trim_end_matches('\n').to_string())
+}
+
+/// Run `command` (program and arguments) in `working_dir` for at most `timeout_secs`
+pub(crate) async fn verify(
+    command: &[String],
+    timeout_secs: u64,
+    working_dir: &Path,
+) -> Verification {
+    run_command(command, timeout_secs, working_dir)
+        .await
+        .unwrap_or_else(failed)
+}
+
+fn failed(output: String) -> Verification {
+    Verification {
+        passed: false,
+        exit_code: None,
+        output,
+    }
+}
+
+/// Like [`verify`], but a command that cannot be started is an error instead of a failure
+pub(crate) async fn run_command(
+    command: &[String],
+    timeout_secs: u64,
+    working_dir: &Path,
+) -> Result<Verification, String> {
+    let Some((program, args)) = command.split_first() else {
+        return Err("the command is empty".to_string());
+    };
+    let child = Command::new(program)
+        .args(args)
+        .current_dir(working_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => return Err(format!("failed to start '{}': {}", program, e)),
+    };
+    let timeout = Duration::from_secs(timeout_secs);
+    let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return Ok(failed(format!("failed to wait for '{}': {}", program, e))),
+        Err(_) => {
+            return Ok(failed(format!(
+                "'{}' timed out after {} seconds",
+                program, timeout_secs
+            )))
+        }
+    };
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(Verification {
+        passed: output.status.success(),
+        exit_code: output.status.code(),
+        output: tail(text.trim_end(), MAX_VERIFY_OUTPUT).to_string(),
+    })
+}
+
+/// The last `max` bytes of `text`, starting at a character boundary
+fn tail(text: &str, max: usize) -> &str {
+    let mut start = text.len().saturating_sub(max);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn sh(dir: &Path, script: &str) {
+        let status = std::process::Command::new("sh")
+            .args(["-c", script])
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "test")
+            .env("GIT_AUTHOR_EMAIL", "test@localhost")
+            .env("GIT_COMMITTER_NAME", "test")
+            .env("GIT_COMMITTER_EMAIL", "test@localhost")
+            .status()
+            .unwrap();
+        assert!(status.success(), "{}", script);
+    }
+
+    #[tokio::test]
+    async fn test_rollback_restores_the_snapshotted_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        sh(
+            repo,
+            "git init -q && echo same > same.txt && echo one > kept.txt && echo two > gone.txt && echo target > \
+             .gitignore && git add -A && git commit -qm init && echo draft > untracked.txt",
+        );
+
+        let snapshot = take(repo).await.unwrap();
+        let id = snapshot.id.clone();
+        fs::write(repo.join("kept.txt"), "edited\n").unwrap();
+        fs::remove_file(repo.join("gone.txt")).unwrap();
+        fs::remove_file(repo.join("untracked.txt")).unwrap();
+        fs::write(repo.join("new.txt"), "created\n").unwrap();
+        fs::create_dir(repo.join("target")).unwrap();
+        fs::write(repo.join("target/build.log"), "ignored\n").unwrap();
+
+        let mut rolled_back = rollback(repo, &id).await.unwrap().unwrap();
+        rolled_back.restored.sort();
+        assert_eq!(
+            rolled_back.restored,
+            ["gone.txt", "kept.txt", "untracked.txt"]
+        );
+        assert_eq!(rolled_back.removed, ["new.txt"]);
+        assert_eq!(fs::read_to_string(repo.join("kept.txt")).unwrap(), "one\n");
+        assert_eq!(fs::read_to_string(repo.join("gone.txt")).unwrap(), "two\n");
+        assert!(repo.join("untracked.txt").exists());
+        assert!(!repo.join("new.txt").exists());
+        assert!(repo.join("target/build.log").exists());
+        // The real index still has the untracked file untracked
+        sh(repo, "test -z \"$(git diff --cached --name-only)\"");
+
+        assert_eq!(rollback(repo, &id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_patch_covers_the_changes_since_the_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        sh(
+            dir.path(),
+            "git init -q && echo one > a.txt && echo draft > notes.txt \
+             && git add a.txt && git -c user.name=t -c user.email=t@localhost commit -qm init",
+        );
+
+        let before = baseline(dir.path()).await;
+        fs::write(dir.path().join("a.txt"), "two\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "new\n").unwrap();
+        let diff = changes_since(before, dir.path()).await.unwrap().diff;
+
+        assert!(diff.contains("-one\n+two"), "{}", diff);
+        assert!(diff.contains("+++ b/b.txt"), "{}", diff);
+        // Uncommitted before the baseline, so not part of the changes
+        assert!(!diff.contains("notes.txt"), "{}", diff);
+        let refs = std::process::Command::new("git")
+            .args(["for-each-ref", REF_PREFIX])
+            .current_dir(dir.path())
+            .output()
+            .unwrap();
+        assert!(refs.stdout.is_empty(), "snapshots are deleted");
+    }
+
+    #[tokio::test]
+    async fn test_verification_reports_exit_code_and_output_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = [
+            "sh".to_string(),
+            "-c".to_string(),
+            "echo compiling; echo 'test failed' >&2; exit 3".to_string(),
+        ];
+        let verification = verify(&command, 10, dir.path()).await;
+        assert!(!verification.passed);
+        assert_eq!(verification.exit_code, Some(3));
+        assert_eq!(verification.output, "compiling\ntest failed");
+        assert_eq!(tail("añb", 2), "b");
+    }
+
+    #[test]
+    fn test_plan_mode_runs_are_not_snapshotted() {
+        let with_args = |args: &[&str]| Options {
+            additional_args: args.iter().map(|a| a.to_string()).collect(),
+            ..Default::default()
+        };
+        assert!(is_plan_mode(&with_args(&["--permission-mode", "plan"])));
+        assert!(is_plan_mode(&with_args(&["--permission-mode=plan"])));
+        assert!(!is_plan_mode(&with_args(&[
+            "--permission-mode",
+            "acceptEdits"
+        ])));
+        assert!(!is_plan_mode(&with_args(&["--model", "plan"])));
+    }
+}
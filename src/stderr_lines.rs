@@ -0,0 +1,133 @@
+//! Classification of CLI stderr output into structured lines.
+//!
+//! The CLI and the Node runtime underneath it write a mix of npm notices, runtime warnings,
+//! deprecations, and genuine errors to stderr. Each line is tagged with a level where the
+//! format is recognizable so callers can filter noise from failures.
+
+use rmcp::schemars::{self, JsonSchema};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StderrLevel {
+    Error,
+    Warning,
+    Deprecation,
+    /// Anything without a recognizable severity marker
+    Info,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct StderrLine {
+    pub level: StderrLevel,
+    /// The line with its severity prefix removed
+    pub message: String,
+    /// Tool that produced the line, e.g. `npm` or `node`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// Split captured stderr into classified, non-empty lines
+pub(crate) fn parse(output: &str) -> Vec<StderrLine> {
+    output
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &str) -> StderrLine {
+    let make = |level, message: &str, source: Option<&str>| StderrLine {
+        level,
+        message: message.trim().to_string(),
+        source: source.map(str::to_string),
+    };
+
+    // npm: `npm WARN deprecated ...`, `npm ERR! code E404`, and the lowercase forms of npm 9+
+    if let Some(rest) = line.strip_prefix("npm ") {
+        for (prefix, level) in [
+            ("WARN ", StderrLevel::Warning),
+            ("warn ", StderrLevel::Warning),
+            ("ERR! ", StderrLevel::Error),
+            ("error ", StderrLevel::Error),
+        ] {
+            if let Some(message) = rest.strip_prefix(prefix) {
+                let level = if message.starts_with("deprecated ") {
+                    StderrLevel::Deprecation
+                } else {
+                    level
+                };
+                return make(level, message, Some("npm"));
+            }
+        }
+    }
+
+    // Node process warnings: `(node:1234) [DEP0040] DeprecationWarning: ...`
+    if let Some(rest) = line
+        .strip_prefix("(node:")
+        .and_then(|rest| rest.split_once(") "))
+        .map(|(_, rest)| rest)
+    {
+        let level = if rest.contains("DeprecationWarning") {
+            StderrLevel::Deprecation
+        } else if rest.contains("Warning") {
+            StderrLevel::Warning
+        } else {
+            StderrLevel::Info
+        };
+        return make(level, rest, Some("node"));
+    }
+
+    let lower = line.trim_start().to_ascii_lowercase();
+    for (prefix, level) in [
+        ("error:", StderrLevel::Error),
+        ("fatal:", StderrLevel::Error),
+        ("warning:", StderrLevel::Warning),
+        ("warn:", StderrLevel::Warning),
+        ("deprecated:", StderrLevel::Deprecation),
+        ("deprecation warning:", StderrLevel::Deprecation),
+    ] {
+        if lower.starts_with(prefix) {
+            return make(level, &line.trim_start()[prefix.len()..], None);
+        }
+    }
+
+    make(StderrLevel::Info, line, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recognizable_lines_get_levels() {
+        let lines = parse(
+            "npm WARN deprecated inflight@1.0.6: leaks memory\n\
+             npm ERR! code E404\n\
+             (node:4242) [DEP0040] DeprecationWarning: The `punycode` module is deprecated.\n\
+             (node:4242) ExperimentalWarning: Fetch is experimental\n\
+             \n\
+             Error: API key missing\n\
+             Warning: rate limited\n\
+             Loaded 3 MCP servers",
+        );
+        let levels: Vec<StderrLevel> = lines.iter().map(|l| l.level).collect();
+        assert_eq!(
+            levels,
+            vec![
+                StderrLevel::Deprecation,
+                StderrLevel::Error,
+                StderrLevel::Deprecation,
+                StderrLevel::Warning,
+                StderrLevel::Error,
+                StderrLevel::Warning,
+                StderrLevel::Info,
+            ]
+        );
+        assert_eq!(lines[1].message, "code E404");
+        assert_eq!(lines[1].source.as_deref(), Some("npm"));
+        assert_eq!(lines[4].message, "API key missing");
+        assert_eq!(lines[6].source, None);
+    }
+}
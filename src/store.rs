@@ -0,0 +1,647 @@
+//! Record of tool calls behind `state_store`.
+//!
+//! The audit log is an append-only file meant for shipping elsewhere; answering questions
+//! such as "sessions for this repo in the last week, most expensive first" from it means
+//! rereading everything. Every tool call is also written to a [`StateStore`], which the
+//! `claude_sessions` tool queries. The backend trades durability for setup:
+//!
+//! - `memory` (the default) needs nothing and forgets everything on restart
+//! - `file` appends JSON lines to a file and scans it for queries
+//! - `sqlite` keeps an indexed SQLite database (WAL journal, so a crash loses at most the
+//!   call in flight)
+//!
+//! The store also holds the [job journal](crate::journal) of calls in the queue or running.
+
+use crate::audit::AuditEntry;
+use crate::claude;
+use crate::context;
+use crate::journal::Job;
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rmcp::schemars::{self, JsonSchema};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+/// How long a write waits for another process holding the database lock
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Calls the memory backend keeps; older ones are forgotten
+const MAX_MEMORY_CALLS: usize = 10_000;
+
+/// State store settings, configured via `state_store` in `claude-mcp.config.json`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreConfig {
+    #[serde(default)]
+    pub backend: StoreBackend,
+    /// Database or JSONL file, created on first use; required by `sqlite` and `file`
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreBackend {
+    #[default]
+    Memory,
+    File,
+    Sqlite,
+}
+
+impl StoreConfig {
+    /// Open the configured backend
+    pub(crate) fn open(&self) -> Result<Arc<dyn StateStore>> {
+        let path = || {
+            self.path
+                .as_deref()
+                .context("state_store.path is required by this backend")
+        };
+        Ok(match self.backend {
+            StoreBackend::Memory => Arc::new(MemoryStore::default()),
+            StoreBackend::File => Arc::new(FileStore::open(path()?)?),
+            StoreBackend::Sqlite => Arc::new(SqliteStore::open(path()?)?),
+        })
+    }
+}
+
+/// Order of `claude_sessions` results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionOrder {
+    /// Most recently used first
+    #[default]
+    Recent,
+    /// Highest total cost first
+    Cost,
+}
+
+/// Filter and order for [`StateStore::sessions`]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SessionQuery {
+    /// Only calls made in this directory
+    pub working_dir: Option<PathBuf>,
+    /// Only calls made at or after this time
+    pub since: Option<DateTime<Utc>>,
+    pub order: SessionOrder,
+    pub limit: usize,
+}
+
+/// Calls of one session, aggregated
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SessionSummary {
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    pub first_call: String,
+    pub last_call: String,
+    pub calls: u64,
+    pub failed_calls: u64,
+    pub total_cost_usd: f64,
+}
+
+/// Where tool calls are kept and queried
+pub(crate) trait StateStore: Send + Sync {
+    /// Persist one finished tool call
+    fn record_call(&self, entry: &AuditEntry) -> Result<()>;
+    /// Sessions with calls matching `query`
+    fn sessions(&self, query: &SessionQuery) -> Result<Vec<SessionSummary>>;
+    /// Add `job` to the journal, replacing the job with its id
+    fn put_job(&self, job: &Job) -> Result<()>;
+    /// Drop job `id` from the journal
+    fn remove_job(&self, id: &str) -> Result<()>;
+    /// The journaled jobs, oldest first
+    fn jobs(&self) -> Result<Vec<Job>>;
+}
+
+/// The fields of a call that queries need. File records are full audit entries; the other
+/// fields are ignored when reading them back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CallRecord {
+    timestamp: String,
+    #[serde(default)]
+    working_dir: Option<String>,
+    #[serde(default)]
+    session_id: Option<String>,
+    success: bool,
+    #[serde(default)]
+    total_cost_usd: Option<f64>,
+}
+
+impl From<&AuditEntry> for CallRecord {
+    fn from(entry: &AuditEntry) -> Self {
+        Self {
+            timestamp: entry.timestamp.clone(),
+            working_dir: entry
+                .working_dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().into_owned()),
+            session_id: entry.session_id.clone(),
+            success: entry.success,
+            total_cost_usd: entry.total_cost_usd,
+        }
+    }
+}
+
+/// Answer `query` by aggregating `calls` in memory, as the SQLite backend does in SQL
+fn summarize<'a>(
+    calls: impl Iterator<Item = &'a CallRecord>,
+    query: &SessionQuery,
+) -> Vec<SessionSummary> {
+    let working_dir = query
+        .working_dir
+        .as_ref()
+        .map(|dir| dir.to_string_lossy().into_owned());
+    let since = query.since.map(timestamp);
+    let mut sessions: BTreeMap<&str, SessionSummary> = BTreeMap::new();
+    for call in calls {
+        let Some(session_id) = call.session_id.as_deref() else {
+            continue;
+        };
+        if (working_dir.is_some() && call.working_dir != working_dir)
+            || since.as_ref().is_some_and(|since| call.timestamp < *since)
+        {
+            continue;
+        }
+        let summary = sessions
+            .entry(session_id)
+            .or_insert_with(|| SessionSummary {
+                session_id: session_id.to_string(),
+                working_dir: None,
+                first_call: call.timestamp.clone(),
+                last_call: call.timestamp.clone(),
+                calls: 0,
+                failed_calls: 0,
+                total_cost_usd: 0.0,
+            });
+        summary.working_dir = summary.working_dir.clone().max(call.working_dir.clone());
+        summary.first_call = summary.first_call.clone().min(call.timestamp.clone());
+        summary.last_call = summary.last_call.clone().max(call.timestamp.clone());
+        summary.calls += 1;
+        summary.failed_calls += u64::from(!call.success);
+        summary.total_cost_usd += call.total_cost_usd.unwrap_or(0.0);
+    }
+    let mut sessions: Vec<SessionSummary> = sessions.into_values().collect();
+    sessions.sort_by(|a, b| match query.order {
+        SessionOrder::Recent => b.last_call.cmp(&a.last_call),
+        SessionOrder::Cost => b
+            .total_cost_usd
+            .total_cmp(&a.total_cost_usd)
+            .then_with(|| b.last_call.cmp(&a.last_call)),
+    });
+    sessions.truncate(query.limit);
+    sessions
+}
+
+/// [`StateStore`] in process memory, bounded to the most recent calls
+#[derive(Default)]
+pub(crate) struct MemoryStore {
+    calls: Mutex<VecDeque<CallRecord>>,
+    jobs: Mutex<BTreeMap<String, Job>>,
+}
+
+impl StateStore for MemoryStore {
+    fn record_call(&self, entry: &AuditEntry) -> Result<()> {
+        let mut calls = self.calls.lock().unwrap_or_else(|e| e.into_inner());
+        if calls.len() >= MAX_MEMORY_CALLS {
+            calls.pop_front();
+        }
+        calls.push_back(entry.into());
+        Ok(())
+    }
+
+    fn sessions(&self, query: &SessionQuery) -> Result<Vec<SessionSummary>> {
+        let calls = self.calls.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(summarize(calls.iter(), query))
+    }
+
+    fn put_job(&self, job: &Job) -> Result<()> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.insert(job.id.clone(), job.clone());
+        Ok(())
+    }
+
+    fn remove_job(&self, id: &str) -> Result<()> {
+        self.jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(id);
+        Ok(())
+    }
+
+    fn jobs(&self) -> Result<Vec<Job>> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(oldest_first(jobs.values().cloned().collect()))
+    }
+}
+
+/// [`StateStore`] appending one JSON line per call to a file; queries scan the whole file.
+/// The job journal is a JSON file next to it (`<path>.jobs.json`), replaced as a whole so a
+/// crash leaves either the old or the new version.
+pub(crate) struct FileStore {
+    path: PathBuf,
+    /// Serializes appends from concurrent calls
+    lock: Mutex<()>,
+    /// Serializes journal updates
+    jobs_lock: Mutex<()>,
+}
+
+impl FileStore {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            lock: Mutex::new(()),
+            jobs_lock: Mutex::new(()),
+        })
+    }
+
+    fn jobs_path(&self) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(".jobs.json");
+        PathBuf::from(name)
+    }
+
+    fn read_jobs(&self) -> Result<BTreeMap<String, Job>> {
+        let path = self.jobs_path();
+        match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .with_context(|| format!("failed to parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e).context(format!("failed to read {}", path.display())),
+        }
+    }
+
+    fn update_jobs(&self, update: impl FnOnce(&mut BTreeMap<String, Job>)) -> Result<()> {
+        let _guard = self.jobs_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut jobs = self.read_jobs()?;
+        update(&mut jobs);
+        let path = self.jobs_path();
+        let mut staging = path.clone().into_os_string();
+        staging.push(".tmp");
+        std::fs::write(&staging, serde_json::to_vec(&jobs)?)
+            .and_then(|()| std::fs::rename(&staging, &path))
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+impl StateStore for FileStore {
+    fn record_call(&self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&line))
+            .with_context(|| format!("failed to append to {}", self.path.display()))
+    }
+
+    fn sessions(&self, query: &SessionQuery) -> Result<Vec<SessionSummary>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context(format!("failed to open {}", self.path.display())),
+        };
+        // A line torn by a crash mid-append is skipped
+        let calls: Vec<CallRecord> = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect();
+        Ok(summarize(calls.iter(), query))
+    }
+
+    fn put_job(&self, job: &Job) -> Result<()> {
+        self.update_jobs(|jobs| {
+            jobs.insert(job.id.clone(), job.clone());
+        })
+    }
+
+    fn remove_job(&self, id: &str) -> Result<()> {
+        self.update_jobs(|jobs| {
+            jobs.remove(id);
+        })
+    }
+
+    fn jobs(&self) -> Result<Vec<Job>> {
+        let _guard = self.jobs_lock.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(oldest_first(self.read_jobs()?.into_values().collect()))
+    }
+}
+
+/// [`StateStore`] in a SQLite database file
+pub(crate) struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let conn =
+            Connection::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // WAL keeps committed calls intact across crashes without blocking readers
+        conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS calls (
+                 id INTEGER PRIMARY KEY,
+                 timestamp TEXT NOT NULL,
+                 client TEXT,
+                 tool TEXT NOT NULL,
+                 prompt_sha256 TEXT NOT NULL,
+                 prompt TEXT,
+                 profile TEXT,
+                 priority INTEGER,
+                 working_dir TEXT,
+                 resume_session_id TEXT,
+                 session_id TEXT,
+                 duration_ms INTEGER NOT NULL,
+                 success INTEGER NOT NULL,
+                 exit_code INTEGER,
+                 error TEXT,
+                 total_cost_usd REAL
+             );
+             CREATE INDEX IF NOT EXISTS calls_session ON calls (session_id);
+             CREATE INDEX IF NOT EXISTS calls_dir_time ON calls (working_dir, timestamp);
+             CREATE TABLE IF NOT EXISTS jobs (
+                 id TEXT PRIMARY KEY,
+                 queued_at TEXT NOT NULL,
+                 job TEXT NOT NULL
+             );",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn record_call(&self, entry: &AuditEntry) -> Result<()> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        conn.execute(
+            "INSERT INTO calls (timestamp, client, tool, prompt_sha256, prompt, profile,
+                 priority, working_dir, resume_session_id, session_id, duration_ms, success,
+                 exit_code, error, total_cost_usd)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            params![
+                entry.timestamp,
+                entry.client,
+                entry.tool,
+                entry.prompt_sha256,
+                entry.prompt,
+                entry.profile,
+                entry.priority,
+                entry
+                    .working_dir
+                    .as_ref()
+                    .map(|dir| dir.to_string_lossy().into_owned()),
+                entry.resume_session_id,
+                entry.session_id,
+                entry.duration_ms as i64,
+                entry.success,
+                entry.exit_code,
+                entry.error,
+                entry.total_cost_usd,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn sessions(&self, query: &SessionQuery) -> Result<Vec<SessionSummary>> {
+        let order = match query.order {
+            SessionOrder::Recent => "last_call DESC",
+            SessionOrder::Cost => "total_cost_usd DESC, last_call DESC",
+        };
+        let sql = format!(
+            "SELECT session_id, MAX(working_dir), MIN(timestamp), MAX(timestamp) AS last_call,
+                 COUNT(*), SUM(NOT success), COALESCE(SUM(total_cost_usd), 0) AS total_cost_usd
+             FROM calls
+             WHERE session_id IS NOT NULL
+                 AND (?1 IS NULL OR working_dir = ?1)
+                 AND (?2 IS NULL OR timestamp >= ?2)
+             GROUP BY session_id
+             ORDER BY {}
+             LIMIT ?3",
+            order
+        );
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statement = conn.prepare(&sql)?;
+        let rows = statement.query_map(
+            params![
+                query
+                    .working_dir
+                    .as_ref()
+                    .map(|dir| dir.to_string_lossy().into_owned()),
+                query.since.map(timestamp),
+                query.limit as i64,
+            ],
+            |row| {
+                Ok(SessionSummary {
+                    session_id: row.get(0)?,
+                    working_dir: row.get(1)?,
+                    first_call: row.get(2)?,
+                    last_call: row.get(3)?,
+                    calls: row.get(4)?,
+                    failed_calls: row.get(5)?,
+                    total_cost_usd: row.get(6)?,
+                })
+            },
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn put_job(&self, job: &Job) -> Result<()> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        conn.execute(
+            "INSERT OR REPLACE INTO jobs (id, queued_at, job) VALUES (?1, ?2, ?3)",
+            params![job.id, job.queued_at, serde_json::to_string(job)?],
+        )?;
+        Ok(())
+    }
+
+    fn remove_job(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        conn.execute("DELETE FROM jobs WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    fn jobs(&self) -> Result<Vec<Job>> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statement = conn.prepare("SELECT job FROM jobs ORDER BY queued_at, id")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut jobs = Vec::new();
+        for job in rows {
+            jobs.push(serde_json::from_str(&job?)?);
+        }
+        Ok(jobs)
+    }
+}
+
+/// `jobs` in the order they were queued
+fn oldest_first(mut jobs: Vec<Job>) -> Vec<Job> {
+    jobs.sort_by(|a, b| a.queued_at.cmp(&b.queued_at).then_with(|| a.id.cmp(&b.id)));
+    jobs
+}
+
+/// Timestamps are stored as RFC 3339 in UTC with milliseconds, which sort as text
+pub(crate) fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Store for the configured `state_store` of the current context, opened on first use and
+/// reopened when a config reload changes it. `None` when the backend cannot be opened.
+pub(crate) fn current() -> Option<Arc<dyn StateStore>> {
+    let config = claude::default_state_store();
+    let context = context::current();
+    let mut open = context.store().lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((open_config, store)) = open.as_ref() {
+        if *open_config == config {
+            return Some(store.clone());
+        }
+    }
+    match config.open() {
+        Ok(store) => {
+            *open = Some((config, store.clone()));
+            Some(store)
+        }
+        Err(e) => {
+            warn!("state_store unavailable: {:#}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::JobState;
+
+    fn call(session_id: &str, dir: &str, timestamp: &str, cost: f64, success: bool) -> AuditEntry {
+        AuditEntry {
+            timestamp: timestamp.to_string(),
+            tool: "claude".to_string(),
+            prompt_sha256: "0".repeat(64),
+            working_dir: Some(PathBuf::from(dir)),
+            session_id: Some(session_id.to_string()),
+            success,
+            total_cost_usd: Some(cost),
+            ..Default::default()
+        }
+    }
+
+    fn job(id: &str, queued_at: &str) -> Job {
+        Job {
+            id: id.to_string(),
+            tool: "claude".to_string(),
+            state: JobState::Queued,
+            working_dir: PathBuf::from("/repo"),
+            args: serde_json::json!({"PROMPT": "Fix it"}),
+            additional_args: Vec::new(),
+            session_id: None,
+            server_pid: 1,
+            server_started: None,
+            queued_at: queued_at.to_string(),
+            updated_at: queued_at.to_string(),
+        }
+    }
+
+    /// Record the same calls and jobs in `store` (through `reopen`, to check they persist)
+    /// and check the answers every backend must agree on
+    fn check_backend(store: Arc<dyn StateStore>, reopen: impl Fn() -> Arc<dyn StateStore>) {
+        for (session_id, dir, timestamp, cost, success) in [
+            ("a", "/repo", "2026-10-01T10:00:00.000Z", 0.5, true),
+            ("b", "/repo", "2026-10-14T10:00:00.000Z", 0.1, true),
+            ("b", "/repo", "2026-10-15T10:00:00.000Z", 0.3, false),
+            ("c", "/other", "2026-10-15T11:00:00.000Z", 9.0, true),
+        ] {
+            store
+                .record_call(&call(session_id, dir, timestamp, cost, success))
+                .unwrap();
+        }
+        drop(store);
+
+        let store = reopen();
+        let query = SessionQuery {
+            working_dir: Some(PathBuf::from("/repo")),
+            limit: 10,
+            ..Default::default()
+        };
+        let recent = store.sessions(&query).unwrap();
+        let ids: Vec<&str> = recent.iter().map(|s| s.session_id.as_str()).collect();
+        assert_eq!(ids, ["b", "a"]);
+        assert_eq!(recent[0].calls, 2);
+        assert_eq!(recent[0].failed_calls, 1);
+        assert!((recent[0].total_cost_usd - 0.4).abs() < 1e-9);
+        assert_eq!(recent[0].first_call, "2026-10-14T10:00:00.000Z");
+
+        let by_cost = store
+            .sessions(&SessionQuery {
+                order: SessionOrder::Cost,
+                ..query.clone()
+            })
+            .unwrap();
+        assert_eq!(by_cost[0].session_id, "a");
+
+        let last_week = store
+            .sessions(&SessionQuery {
+                since: DateTime::parse_from_rfc3339("2026-10-09T00:00:00Z")
+                    .ok()
+                    .map(|t| t.with_timezone(&Utc)),
+                working_dir: None,
+                limit: 1,
+                ..query
+            })
+            .unwrap();
+        let ids: Vec<&str> = last_week.iter().map(|s| s.session_id.as_str()).collect();
+        assert_eq!(ids, ["c"]);
+
+        for (id, queued_at) in [
+            ("y", "2026-10-15T10:00:01.000Z"),
+            ("x", "2026-10-15T10:00:00.000Z"),
+        ] {
+            store.put_job(&job(id, queued_at)).unwrap();
+        }
+        let mut running = job("y", "2026-10-15T10:00:01.000Z");
+        running.state = JobState::Running;
+        store.put_job(&running).unwrap();
+        let store = reopen();
+        let jobs = store.jobs().unwrap();
+        let ids: Vec<(&str, JobState)> = jobs.iter().map(|j| (j.id.as_str(), j.state)).collect();
+        assert_eq!(ids, [("x", JobState::Queued), ("y", JobState::Running)]);
+        store.remove_job("x").unwrap();
+        assert_eq!(reopen().jobs().unwrap(), [running]);
+    }
+
+    #[test]
+    fn test_backends_filter_and_sort_sessions_alike() {
+        let memory: Arc<dyn StateStore> = Arc::new(MemoryStore::default());
+        check_backend(memory.clone(), || memory.clone());
+
+        let dir = tempfile::tempdir().unwrap();
+        for backend in [StoreBackend::File, StoreBackend::Sqlite] {
+            let config = StoreConfig {
+                backend,
+                path: Some(dir.path().join("state").join(format!("{:?}", backend))),
+            };
+            check_backend(config.open().unwrap(), || config.open().unwrap());
+        }
+        assert!(StoreConfig {
+            backend: StoreBackend::Sqlite,
+            path: None
+        }
+        .open()
+        .is_err());
+    }
+}
@@ -0,0 +1,407 @@
+//! Incremental scanner for stream-json lines that are too large to buffer.
+//!
+//! When a single event exceeds the per-line byte limit, the runner feeds the rest of the
+//! line through [`EventScanner`] chunk by chunk instead of failing the whole run. The
+//! scanner tokenizes the JSON without building a tree and only keeps the fields the runner
+//! cares about: top-level `type`, `session_id`, `result`, `is_error`, and the text blocks
+//! under `message.content`.
+
+/// Cap for captured scalar strings other than assistant text
+const MAX_SCALAR_LEN: usize = 64 * 1024;
+/// Keys we match on are short; anything longer is irrelevant
+const MAX_KEY_LEN: usize = 64;
+/// Bare literals we inspect (`true`, `false`, `null`, numbers) are short
+const MAX_LITERAL_LEN: usize = 16;
+
+/// Fields extracted from an oversized event
+#[derive(Debug, Default)]
+pub(crate) struct ScannedEvent {
+    pub event_type: Option<String>,
+    pub session_id: Option<String>,
+    pub result: Option<String>,
+    pub is_error: bool,
+    pub texts: Vec<String>,
+    /// Set when text blocks exceeded the scanner's text budget and were cut
+    pub text_truncated: bool,
+    /// Total number of bytes fed to the scanner
+    pub bytes: usize,
+}
+
+/// Where in the event a container sits, relative to the fields we extract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Loc {
+    Root,
+    Message,
+    Content,
+    Block,
+    Other,
+}
+
+#[derive(Debug)]
+struct Frame {
+    loc: Loc,
+    is_object: bool,
+    expect_key: bool,
+    key: Vec<u8>,
+}
+
+/// What the string currently being read will be stored as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Discard,
+    Key,
+    EventType,
+    SessionId,
+    Result,
+    BlockType,
+    BlockText,
+}
+
+#[derive(Debug)]
+pub(crate) struct EventScanner {
+    stack: Vec<Frame>,
+    in_string: bool,
+    escape: bool,
+    /// Accumulated value and digit count of a `\uXXXX` escape in progress
+    unicode: Option<(u32, u8)>,
+    high_surrogate: Option<u32>,
+    target: Target,
+    capture: Vec<u8>,
+    literal: Vec<u8>,
+    root_seen: bool,
+    max_text_len: usize,
+    text_len: usize,
+    block_type: Option<String>,
+    block_text: Option<String>,
+    event: ScannedEvent,
+}
+
+impl EventScanner {
+    /// Create a scanner that keeps at most `max_text_len` bytes of assistant text
+    pub(crate) fn new(max_text_len: usize) -> Self {
+        Self {
+            stack: Vec::new(),
+            in_string: false,
+            escape: false,
+            unicode: None,
+            high_surrogate: None,
+            target: Target::Discard,
+            capture: Vec::new(),
+            literal: Vec::new(),
+            root_seen: false,
+            max_text_len,
+            text_len: 0,
+            block_type: None,
+            block_text: None,
+            event: ScannedEvent::default(),
+        }
+    }
+
+    /// Forget the previous line so the scanner can take the next one, keeping its buffers
+    pub(crate) fn reset(&mut self) {
+        self.stack.clear();
+        self.in_string = false;
+        self.escape = false;
+        self.unicode = None;
+        self.high_surrogate = None;
+        self.target = Target::Discard;
+        self.capture.clear();
+        self.literal.clear();
+        self.root_seen = false;
+        self.text_len = 0;
+        self.block_type = None;
+        self.block_text = None;
+        self.event = ScannedEvent::default();
+    }
+
+    /// Feed the next chunk of the line
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        self.event.bytes += chunk.len();
+        for &byte in chunk {
+            if self.in_string {
+                self.string_byte(byte);
+            } else {
+                self.structural_byte(byte);
+            }
+        }
+    }
+
+    /// Finish the line, returning the extracted fields if it held one complete JSON object
+    pub(crate) fn finish(&mut self) -> Result<ScannedEvent, &'static str> {
+        self.end_literal();
+        if !self.root_seen || !self.stack.is_empty() || self.in_string {
+            return Err("oversized line is not a complete JSON object");
+        }
+        Ok(std::mem::take(&mut self.event))
+    }
+
+    fn structural_byte(&mut self, byte: u8) {
+        match byte {
+            b'{' | b'[' => {
+                self.end_literal();
+                self.begin_container(byte == b'{');
+            }
+            b'}' | b']' => {
+                self.end_literal();
+                self.end_container();
+            }
+            b':' => {
+                self.end_literal();
+                if let Some(frame) = self.stack.last_mut() {
+                    frame.expect_key = false;
+                }
+            }
+            b',' => {
+                self.end_literal();
+                if let Some(frame) = self.stack.last_mut() {
+                    if frame.is_object {
+                        frame.expect_key = true;
+                    }
+                }
+            }
+            b'"' => {
+                self.end_literal();
+                self.target = match self.stack.last() {
+                    Some(frame) if frame.is_object && frame.expect_key => Target::Key,
+                    _ => self.value_target(),
+                };
+                self.capture.clear();
+                self.in_string = true;
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => self.end_literal(),
+            _ => {
+                if self.literal.len() < MAX_LITERAL_LEN {
+                    self.literal.push(byte);
+                }
+            }
+        }
+    }
+
+    fn string_byte(&mut self, byte: u8) {
+        if let Some((value, digits)) = self.unicode {
+            let digit = (byte as char).to_digit(16).unwrap_or(0);
+            let value = (value << 4) | digit;
+            if digits + 1 == 4 {
+                self.unicode = None;
+                self.push_code_unit(value);
+            } else {
+                self.unicode = Some((value, digits + 1));
+            }
+            return;
+        }
+
+        if self.escape {
+            self.escape = false;
+            let decoded = match byte {
+                b'n' => b'\n',
+                b't' => b'\t',
+                b'r' => b'\r',
+                b'b' => 0x08,
+                b'f' => 0x0c,
+                b'u' => {
+                    self.unicode = Some((0, 0));
+                    return;
+                }
+                other => other,
+            };
+            self.push_captured(&[decoded]);
+            return;
+        }
+
+        match byte {
+            b'\\' => self.escape = true,
+            b'"' => {
+                self.in_string = false;
+                self.finish_string();
+            }
+            _ => self.push_captured(&[byte]),
+        }
+    }
+
+    fn push_code_unit(&mut self, unit: u32) {
+        let ch = match (self.high_surrogate.take(), unit) {
+            (None, 0xD800..=0xDBFF) => {
+                self.high_surrogate = Some(unit);
+                return;
+            }
+            (Some(high), 0xDC00..=0xDFFF) => {
+                char::from_u32(0x10000 + ((high - 0xD800) << 10) + (unit - 0xDC00))
+            }
+            (_, unit) => char::from_u32(unit),
+        };
+        let mut encoded = [0u8; 4];
+        let ch = ch.unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.push_captured(ch.encode_utf8(&mut encoded).as_bytes());
+    }
+
+    fn push_captured(&mut self, bytes: &[u8]) {
+        let limit = match self.target {
+            Target::Discard => return,
+            Target::Key => MAX_KEY_LEN,
+            Target::BlockText => {
+                let room = self.max_text_len.saturating_sub(self.text_len);
+                if bytes.len() > room {
+                    self.event.text_truncated = true;
+                    return;
+                }
+                self.text_len += bytes.len();
+                usize::MAX
+            }
+            _ => MAX_SCALAR_LEN,
+        };
+        if self.capture.len() + bytes.len() <= limit {
+            self.capture.extend_from_slice(bytes);
+        }
+    }
+
+    fn finish_string(&mut self) {
+        let captured = std::mem::take(&mut self.capture);
+        let text = || String::from_utf8_lossy(&captured).into_owned();
+        match self.target {
+            Target::Discard => {}
+            Target::Key => {
+                if let Some(frame) = self.stack.last_mut() {
+                    frame.key = captured;
+                }
+            }
+            Target::EventType => self.event.event_type = Some(text()),
+            Target::SessionId => self.event.session_id = Some(text()),
+            Target::Result => self.event.result = Some(text()),
+            Target::BlockType => self.block_type = Some(text()),
+            Target::BlockText => self.block_text = Some(text()),
+        }
+        self.target = Target::Discard;
+    }
+
+    fn value_target(&self) -> Target {
+        let Some(frame) = self.stack.last() else {
+            return Target::Discard;
+        };
+        if !frame.is_object {
+            return Target::Discard;
+        }
+        match (frame.loc, frame.key.as_slice()) {
+            (Loc::Root, b"type") => Target::EventType,
+            (Loc::Root, b"session_id") => Target::SessionId,
+            (Loc::Root, b"result") => Target::Result,
+            (Loc::Block, b"type") => Target::BlockType,
+            (Loc::Block, b"text") => Target::BlockText,
+            _ => Target::Discard,
+        }
+    }
+
+    fn end_literal(&mut self) {
+        if self.literal.is_empty() {
+            return;
+        }
+        if let Some(frame) = self.stack.last() {
+            if frame.is_object
+                && frame.loc == Loc::Root
+                && frame.key == b"is_error"
+                && self.literal == b"true"
+            {
+                self.event.is_error = true;
+            }
+        }
+        self.literal.clear();
+    }
+
+    fn begin_container(&mut self, is_object: bool) {
+        let loc = match self.stack.last() {
+            None => {
+                self.root_seen = true;
+                if is_object {
+                    Loc::Root
+                } else {
+                    Loc::Other
+                }
+            }
+            Some(parent) => match (parent.loc, parent.is_object, parent.key.as_slice()) {
+                (Loc::Root, true, b"message") if is_object => Loc::Message,
+                (Loc::Message, true, b"content") if !is_object => Loc::Content,
+                (Loc::Content, false, _) if is_object => Loc::Block,
+                _ => Loc::Other,
+            },
+        };
+        if loc == Loc::Block {
+            self.block_type = None;
+            self.block_text = None;
+        }
+        self.stack.push(Frame {
+            loc,
+            is_object,
+            expect_key: is_object,
+            key: Vec::new(),
+        });
+    }
+
+    fn end_container(&mut self) {
+        let Some(frame) = self.stack.pop() else {
+            return;
+        };
+        if frame.loc == Loc::Block && self.block_type.as_deref() == Some("text") {
+            if let Some(text) = self.block_text.take() {
+                self.event.texts.push(text);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan_in_chunks(input: &str, chunk_size: usize) -> ScannedEvent {
+        let mut scanner = EventScanner::new(1024);
+        for chunk in input.as_bytes().chunks(chunk_size) {
+            scanner.feed(chunk);
+        }
+        scanner.finish().unwrap()
+    }
+
+    #[test]
+    fn test_extracts_fields_across_chunk_boundaries() {
+        let input = r#"{"type":"assistant","message":{"content":[{"text":"a\"bé😀","type":"text"},{"type":"tool_use","input":{"text":"ignored"}}]},"session_id":"s-1"}"#;
+
+        for chunk_size in [1, 3, 7, input.len()] {
+            let event = scan_in_chunks(input, chunk_size);
+            assert_eq!(event.event_type.as_deref(), Some("assistant"));
+            assert_eq!(event.session_id.as_deref(), Some("s-1"));
+            assert_eq!(event.texts, vec!["a\"bé😀".to_string()]);
+            assert!(!event.is_error);
+        }
+    }
+
+    #[test]
+    fn test_detects_error_results() {
+        let event = scan_in_chunks(
+            r#"{"type":"result","is_error": true ,"result":"boom","nested":{"is_error":false}}"#,
+            5,
+        );
+        assert_eq!(event.event_type.as_deref(), Some("result"));
+        assert_eq!(event.result.as_deref(), Some("boom"));
+        assert!(event.is_error);
+    }
+
+    #[test]
+    fn test_caps_text_and_rejects_incomplete_objects() {
+        let mut scanner = EventScanner::new(4);
+        scanner.feed(
+            br#"{"type":"assistant","message":{"content":[{"type":"text","text":"abcdefgh"}]}}"#,
+        );
+        let event = scanner.finish().unwrap();
+        assert!(event.text_truncated);
+        assert_eq!(event.texts, vec!["abcd".to_string()]);
+
+        scanner.reset();
+        scanner.feed(br#"{"type":"assistant","message":{"#);
+        assert!(scanner.finish().is_err());
+
+        // A reset scanner takes a new line as if it were fresh
+        scanner.reset();
+        scanner.feed(br#"{"type":"result","result":"ok"}"#);
+        let event = scanner.finish().unwrap();
+        assert_eq!(event.result.as_deref(), Some("ok"));
+        assert!(!event.text_truncated);
+    }
+}
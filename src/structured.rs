@@ -0,0 +1,134 @@
+//! Answers in JSON matching a caller-supplied schema (`RESPONSE_SCHEMA`).
+//!
+//! The prompt is extended with the schema and an instruction to answer with JSON only. The
+//! final agent text is then parsed, tolerating a code fence or a sentence around the JSON,
+//! and validated. When it does not match, the session is resumed with the validation errors
+//! so the model can correct itself, a bounded number of times.
+
+use jsonschema::Validator;
+use serde_json::Value;
+
+/// Corrective turns after the first answer before giving up
+pub(crate) const MAX_CORRECTIONS: usize = 2;
+
+/// Validation errors listed in a correction prompt or error message
+const MAX_REPORTED_ERRORS: usize = 5;
+
+pub(crate) struct ResponseSchema {
+    schema: Value,
+    validator: Validator,
+}
+
+impl ResponseSchema {
+    pub(crate) fn new(schema: &Value) -> Result<Self, String> {
+        let validator = jsonschema::validator_for(schema)
+            .map_err(|e| format!("RESPONSE_SCHEMA is not a valid JSON Schema: {}", e))?;
+        Ok(Self {
+            schema: schema.clone(),
+            validator,
+        })
+    }
+
+    /// `prompt` with the instructions appended, as sent to the CLI
+    pub(crate) fn prompt(&self, prompt: &str) -> String {
+        format!("{}\n\n{}", prompt, self.instructions())
+    }
+
+    /// Appended to the caller's prompt
+    pub(crate) fn instructions(&self) -> String {
+        format!(
+            "Respond with a single JSON value that validates against the JSON Schema below. \
+             Output only the JSON, without a code fence or any other text.\n\n{}",
+            serde_json::to_string_pretty(&self.schema).unwrap_or_else(|_| self.schema.to_string())
+        )
+    }
+
+    /// The JSON answer in `text`, or a description of why it does not match
+    pub(crate) fn check(&self, text: &str) -> Result<Value, String> {
+        let answer = extract_json(text).ok_or("the answer is not valid JSON")?;
+        let errors: Vec<String> = self
+            .validator
+            .iter_errors(&answer)
+            .take(MAX_REPORTED_ERRORS)
+            .map(|error| match error.instance_path().as_str() {
+                "" => error.to_string(),
+                path => format!("{}: {}", path, error),
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(answer)
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+}
+
+/// Prompt for the turn after an answer that failed `check`
+pub(crate) fn correction(problem: &str) -> String {
+    format!(
+        "Your answer does not match the required JSON Schema: {}. Reply again with only the \
+         corrected JSON.",
+        problem
+    )
+}
+
+/// Parse `text` as JSON, falling back to the contents of a code fence and then to the span
+/// from the first `{` or `[` to the last `}` or `]`
+fn extract_json(text: &str) -> Option<Value> {
+    let text = text.trim();
+    if let Ok(value) = serde_json::from_str(text) {
+        return Some(value);
+    }
+    if let Some((_, fenced)) = text.split_once("```") {
+        // Skip the info string (`json`) on the opening fence line
+        let body = fenced.split_once('\n').map_or(fenced, |(_, body)| body);
+        if let Some((inner, _)) = body.split_once("```") {
+            if let Ok(value) = serde_json::from_str(inner.trim()) {
+                return Some(value);
+            }
+        }
+    }
+    let start = text.find(['{', '['])?;
+    let end = text.rfind(['}', ']'])?;
+    serde_json::from_str(text.get(start..=end)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_is_found_in_fences_and_prose() {
+        assert_eq!(extract_json(" {\"a\": 1} "), Some(json!({"a": 1})));
+        assert_eq!(
+            extract_json("Here you go:\n```json\n[1, 2]\n```\nDone."),
+            Some(json!([1, 2]))
+        );
+        assert_eq!(
+            extract_json("The result is {\"ok\": true}."),
+            Some(json!({"ok": true}))
+        );
+        assert_eq!(extract_json("no json here"), None);
+    }
+
+    #[test]
+    fn test_answers_are_validated_against_the_schema() {
+        let schema = ResponseSchema::new(&json!({
+            "type": "object",
+            "properties": {"files": {"type": "array", "items": {"type": "string"}}},
+            "required": ["files"]
+        }))
+        .unwrap();
+        assert_eq!(
+            schema.check("{\"files\": [\"a.rs\"]}"),
+            Ok(json!({"files": ["a.rs"]}))
+        );
+        let problem = schema.check("{\"files\": [1]}").unwrap_err();
+        assert!(problem.starts_with("/files/0: "), "{}", problem);
+        assert!(schema.check("{}").unwrap_err().contains("files"));
+        assert!(schema.check("sorry").is_err());
+
+        assert!(ResponseSchema::new(&json!({"type": "nonsense"})).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! Independent prompts run side by side for `claude_swarm`, each in its own git worktree.
+//!
+//! Every prompt gets a worktree on a new branch `<prefix>-<n>`, created the way `claude_pr`
+//! creates its one, so the runs cannot see or overwrite each other's changes and the
+//! caller's checkout is left alone. The runs go in parallel up to the run queue's
+//! `max_concurrent`. After a successful run its changes are committed on the branch and
+//! the worktree is removed; a failed run keeps its worktree for inspection, and a run that
+//! changed nothing loses its branch too.
+
+use crate::claude::ClaudeResult;
+use crate::pull_request::{self, Worktree};
+use serde::Serialize;
+
+/// Most prompts in one call
+pub(crate) const MAX_PROMPTS: usize = 16;
+/// Runs at once without a `run_queue`
+pub(crate) const DEFAULT_PARALLEL_RUNS: usize = 4;
+
+/// Outcome of one prompt
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct SwarmRun {
+    pub success: bool,
+    pub branch: String,
+    /// The commit holding the run's changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub session_id: String,
+    pub agent_messages: String,
+    /// Worktree left in place after a failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worktree: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of `claude_swarm`, with the runs in the order of the prompts
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct Swarm {
+    /// Whether every run committed its changes
+    pub success: bool,
+    pub runs: Vec<SwarmRun>,
+}
+
+/// Branch prefix for a swarm the caller did not name
+pub(crate) fn default_prefix() -> String {
+    pull_request::default_branch().replacen("claude/", "claude/swarm-", 1)
+}
+
+/// The branch of prompt `index` (counted from zero)
+pub(crate) fn branch(prefix: &str, index: usize) -> String {
+    format!("{}-{}", prefix, index + 1)
+}
+
+/// Commit a finished run's changes on its branch and clean up its worktree
+pub(crate) async fn finish(worktree: Worktree, result: ClaudeResult, prompt: &str) -> SwarmRun {
+    let mut run = SwarmRun {
+        branch: worktree.branch().to_string(),
+        session_id: result.session_id,
+        agent_messages: result.agent_messages,
+        ..Default::default()
+    };
+    if !result.success {
+        run.worktree = Some(worktree.path().display().to_string());
+        run.error = Some(result.error.unwrap_or_else(|| "the run failed".to_string()));
+        return run;
+    }
+    match pull_request::commit(&worktree, &pull_request::default_title(prompt)).await {
+        Ok(Some(commit)) => {
+            run.success = true;
+            run.commit = Some(commit);
+            worktree.remove(true).await;
+        }
+        Ok(None) => {
+            run.error = Some("the run made no changes to commit".to_string());
+            worktree.remove(false).await;
+        }
+        Err(e) => {
+            run.worktree = Some(worktree.path().display().to_string());
+            run.error = Some(format!("{:#}", e));
+        }
+    }
+    run
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?}", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_runs_commit_on_their_own_branches() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        git(repo, &["init", "-q"]);
+        git(repo, &["config", "user.name", "test"]);
+        git(repo, &["config", "user.email", "test@localhost"]);
+        std::fs::write(repo.join("a.txt"), "one\n").unwrap();
+        git(repo, &["add", "a.txt"]);
+        git(repo, &["commit", "-qm", "init"]);
+
+        let prefix = default_prefix();
+        assert!(prefix.starts_with("claude/swarm-"), "{}", prefix);
+        let changed = Worktree::add(repo, &branch(&prefix, 0), None)
+            .await
+            .unwrap();
+        let idle = Worktree::add(repo, &branch(&prefix, 1), None)
+            .await
+            .unwrap();
+        std::fs::write(changed.dir().join("a.txt"), "two\n").unwrap();
+        let result = ClaudeResult {
+            success: true,
+            ..Default::default()
+        };
+
+        let run = finish(changed, result.clone(), "Bump a.txt\nto two").await;
+        assert!(run.success, "{:?}", run);
+        assert_eq!(run.branch, format!("{}-1", prefix));
+        let show = format!("{}:a.txt", run.branch);
+        assert_eq!(git(repo, &["show", &show]), "two");
+        assert_eq!(
+            git(repo, &["log", "-1", "--format=%s", &run.branch]),
+            "Bump a.txt"
+        );
+
+        let run = finish(idle, result, "Nothing").await;
+        assert_eq!(
+            run.error.as_deref(),
+            Some("the run made no changes to commit")
+        );
+        let branches = git(repo, &["branch", "--list", &run.branch]);
+        assert!(branches.is_empty(), "{}", branches);
+        // The caller's checkout is untouched
+        assert_eq!(
+            std::fs::read_to_string(repo.join("a.txt")).unwrap(),
+            "one\n"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Prompt templates offered to clients through the MCP `prompts` capability.
+//!
+//! A few curated templates ship with the server; `prompt_templates` in
+//! `claude-mcp.config.json` adds more or replaces a built-in of the same name. Templates are
+//! plain text with `{{argument}}` placeholders.
+
+use crate::claude;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PromptTemplate {
+    pub description: Option<String>,
+    /// Prompt text with `{{argument}}` placeholders
+    pub template: String,
+    #[serde(default)]
+    pub arguments: Vec<TemplateArgument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateArgument {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    /// Value used when an optional argument is not given
+    pub default: Option<String>,
+}
+
+impl PromptTemplate {
+    /// Fill in the placeholders from `args`. Missing required arguments are an error;
+    /// missing optional ones take their default or become empty.
+    pub fn render(&self, args: &Map<String, Value>) -> Result<String, String> {
+        let mut text = self.template.clone();
+        for argument in &self.arguments {
+            let value = match args.get(&argument.name) {
+                Some(Value::String(value)) => value.clone(),
+                Some(Value::Null) | None => {
+                    if argument.required {
+                        return Err(format!("missing required argument '{}'", argument.name));
+                    }
+                    argument.default.clone().unwrap_or_default()
+                }
+                Some(other) => other.to_string(),
+            };
+            text = text.replace(&format!("{{{{{}}}}}", argument.name), &value);
+        }
+        Ok(text)
+    }
+}
+
+fn argument(
+    name: &str,
+    description: &str,
+    required: bool,
+    default: Option<&str>,
+) -> TemplateArgument {
+    TemplateArgument {
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        required,
+        default: default.map(str::to_string),
+    }
+}
+
+fn builtin() -> BTreeMap<String, PromptTemplate> {
+    let mut templates = BTreeMap::new();
+    templates.insert(
+        "review_changes".to_string(),
+        PromptTemplate {
+            description: Some(
+                "Review the uncommitted changes in the working directory".to_string(),
+            ),
+            template: "Review the uncommitted changes in this repository (`git diff HEAD`). \
+                       Focus on {{focus}}. Report concrete problems with file and line, most \
+                       important first, and do not modify any files."
+                .to_string(),
+            arguments: vec![argument(
+                "focus",
+                "What to look for, e.g. correctness, security, or performance",
+                false,
+                Some("correctness and missing tests"),
+            )],
+        },
+    );
+    templates.insert(
+        "fix_failing_tests".to_string(),
+        PromptTemplate {
+            description: Some("Run a test command and fix the failures".to_string()),
+            template: "Run `{{command}}`. For each failure, find the root cause and fix the \
+                       code rather than weakening the test. Rerun until the command passes, \
+                       then summarize what you changed."
+                .to_string(),
+            arguments: vec![argument(
+                "command",
+                "Test command to run, e.g. `cargo test`",
+                true,
+                None,
+            )],
+        },
+    );
+    templates.insert(
+        "explain_code".to_string(),
+        PromptTemplate {
+            description: Some("Explain a file, module, or feature without changing it".to_string()),
+            template: "Explain how {{target}} works: its purpose, the main data flow, and any \
+                       non-obvious details. Do not modify any files."
+                .to_string(),
+            arguments: vec![argument(
+                "target",
+                "File, module, or feature to explain",
+                true,
+                None,
+            )],
+        },
+    );
+    templates
+}
+
+/// Built-in templates merged with the configured ones, sorted by name
+pub fn all() -> BTreeMap<String, PromptTemplate> {
+    let mut templates = builtin();
+    templates.extend(claude::prompt_templates());
+    templates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_fills_placeholders_and_defaults() {
+        let template = &builtin()["review_changes"];
+        let text = template.render(&Map::new()).unwrap();
+        assert!(text.contains("Focus on correctness and missing tests."));
+
+        let args = json!({"focus": "security"});
+        let text = template.render(args.as_object().unwrap()).unwrap();
+        assert!(text.contains("Focus on security."));
+    }
+
+    #[test]
+    fn test_render_rejects_missing_required_argument() {
+        let template = &builtin()["fix_failing_tests"];
+        assert_eq!(
+            template.render(&Map::new()),
+            Err("missing required argument 'command'".to_string())
+        );
+        let args = json!({"command": "cargo test"});
+        assert!(template
+            .render(args.as_object().unwrap())
+            .unwrap()
+            .starts_with("Run `cargo test`."));
+    }
+}
@@ -0,0 +1,209 @@
+//! Tenants of the HTTP transport: bearer tokens mapped to isolated server contexts.
+//!
+//! With `tenants` configured, every request to `/mcp` must carry `Authorization: Bearer
+//! <token>` with one of their tokens. Each tenant gets its own [`ServerContext`], so sessions,
+//! live interactive processes, cached and in-flight runs, run deadlines, metrics, the run
+//! queue, and the state store are not shared, and its own config file when `config` is set
+//! (profiles included). Its runs are held to `allowed_dirs` and stop being accepted once
+//! `budget_usd` has been spent, and it can only read and resume sessions its own runs
+//! reported.
+
+use crate::context::{self, ServerContext};
+use crate::platform;
+use rmcp::ErrorData as McpError;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// One entry of `tenants` in `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    /// Bearer token that identifies the tenant's requests
+    pub token: String,
+    /// Config file of the tenant; the server's own config when unset
+    pub config: Option<PathBuf>,
+    /// Directories runs may use, subdirectories included; any when empty
+    #[serde(default)]
+    pub allowed_dirs: Vec<PathBuf>,
+    /// Cost of runs, since the server started, after which calls are refused
+    pub budget_usd: Option<f64>,
+}
+
+/// What a tenant's context enforces
+pub(crate) struct TenantPolicy {
+    name: String,
+    /// Canonical, so runs cannot leave them through `..` or symlinks
+    allowed_dirs: Vec<PathBuf>,
+    budget_usd: Option<f64>,
+    spent_usd: Mutex<f64>,
+}
+
+impl TenantPolicy {
+    pub(crate) fn new(name: &str, tenant: &Tenant) -> Self {
+        Self {
+            name: name.to_string(),
+            allowed_dirs: tenant
+                .allowed_dirs
+                .iter()
+                .map(|dir| {
+                    dir.canonicalize()
+                        .map(platform::normalize_path)
+                        .unwrap_or_else(|_| dir.clone())
+                })
+                .collect(),
+            budget_usd: tenant.budget_usd,
+            spent_usd: Mutex::new(0.0),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn budget_usd(&self) -> Option<f64> {
+        self.budget_usd
+    }
+
+    pub(crate) fn spent_usd(&self) -> f64 {
+        *self.spent_usd.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A context for tenant `name`. `previous` is the context of its entry before a reload,
+/// whose spending carries over so changing an entry does not reset its budget.
+pub(crate) fn context(
+    name: &str,
+    tenant: &Tenant,
+    previous: Option<&ServerContext>,
+) -> ServerContext {
+    let policy = TenantPolicy::new(name, tenant);
+    if let Some(previous) = previous.and_then(ServerContext::tenant) {
+        *policy.spent_usd.lock().unwrap_or_else(|e| e.into_inner()) = previous.spent_usd();
+    }
+    ServerContext::for_tenant(policy, tenant)
+}
+
+/// Tokens are looked up by digest, so lookups do not compare the secret itself
+pub(crate) fn token_key(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Reject `dir` when the current tenant may not run there. `dir` must be canonical.
+pub(crate) fn check_dir(dir: &Path) -> Result<(), McpError> {
+    let context = context::current();
+    let Some(tenant) = context.tenant() else {
+        return Ok(());
+    };
+    if tenant.allowed_dirs.is_empty() || tenant.allowed_dirs.iter().any(|d| dir.starts_with(d)) {
+        return Ok(());
+    }
+    Err(McpError::invalid_params(
+        format!(
+            "working directory {} is outside the directories allowed for tenant '{}'",
+            dir.display(),
+            tenant.name
+        ),
+        Some(json!({ "reason": "working_dir_not_allowed" })),
+    ))
+}
+
+/// Reject `session_id` when the current tenant's runs did not report it, so one tenant
+/// cannot read or resume another's sessions
+pub(crate) fn check_session(session_id: &str) -> Result<(), McpError> {
+    let context = context::current();
+    let Some(tenant) = context.tenant() else {
+        return Ok(());
+    };
+    if context.sessions().contains(session_id) {
+        return Ok(());
+    }
+    Err(McpError::invalid_params(
+        format!(
+            "session {} is not a session of tenant '{}'",
+            session_id, tenant.name
+        ),
+        Some(json!({ "reason": "unknown_session" })),
+    ))
+}
+
+/// Reject a run when the current tenant has spent its budget
+pub(crate) fn check_budget() -> Result<(), McpError> {
+    let context = context::current();
+    let Some(tenant) = context.tenant() else {
+        return Ok(());
+    };
+    let spent = tenant.spent_usd();
+    match tenant.budget_usd {
+        Some(budget) if spent >= budget => Err(McpError::invalid_request(
+            format!(
+                "tenant '{}' has spent ${:.2} of its ${:.2} budget",
+                tenant.name, spent, budget
+            ),
+            Some(json!({
+                "reason": "budget_exhausted",
+                "spent_usd": spent,
+                "budget_usd": budget,
+            })),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Count the cost of a finished run against the current tenant's budget
+pub(crate) fn charge(cost_usd: f64) {
+    if let Some(tenant) = context::current().tenant() {
+        *tenant.spent_usd.lock().unwrap_or_else(|e| e.into_inner()) += cost_usd;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenants_are_held_to_their_dirs_and_budget() {
+        let allowed = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let tenant = Tenant {
+            token: "secret".to_string(),
+            config: None,
+            allowed_dirs: vec![allowed.path().to_path_buf()],
+            budget_usd: Some(1.0),
+        };
+        let context = context("alice", &tenant, None);
+        let canonical = |dir: &Path| dir.canonicalize().unwrap();
+
+        context.sync_scope(|| {
+            assert!(check_dir(&canonical(allowed.path())).is_ok());
+            let rejected = check_dir(&canonical(other.path())).unwrap_err();
+            assert_eq!(rejected.data.unwrap()["reason"], "working_dir_not_allowed");
+
+            charge(0.6);
+            assert!(check_budget().is_ok());
+            charge(0.6);
+            let exhausted = check_budget().unwrap_err();
+            assert_eq!(exhausted.data.unwrap()["reason"], "budget_exhausted");
+
+            let own = "0b6f3c2e-8d41-4a57-b9e0-5c7d2a1f9e38";
+            let foreign = "7e2a9d14-3c6b-4f80-a1d5-9b8e0c4f6a27";
+            crate::sessions::record(own);
+            assert!(check_session(own).is_ok());
+            let unknown = check_session(foreign).unwrap_err();
+            assert_eq!(unknown.data.unwrap()["reason"], "unknown_session");
+        });
+        // A changed entry keeps what the tenant has spent
+        let renewed = Tenant {
+            budget_usd: Some(2.0),
+            ..tenant.clone()
+        };
+        let renewed = super::context("alice", &renewed, Some(&context));
+        assert_eq!(renewed.tenant().unwrap().spent_usd(), 1.2);
+        // Outside the tenant nothing is enforced
+        assert!(check_dir(&canonical(other.path())).is_ok());
+        assert!(check_budget().is_ok());
+        assert!(check_session("7e2a9d14-3c6b-4f80-a1d5-9b8e0c4f6a27").is_ok());
+    }
+}
@@ -0,0 +1,174 @@
+//! Test generation for `claude_write_tests`.
+//!
+//! Claude is given the target files or functions and a system prompt dedicated to writing
+//! tests, and answers with the tests it added as JSON matching [`answer_schema`]. The
+//! configured `write_tests.test_command` then runs in the working directory, and its outcome
+//! is reported next to the new test names.
+
+use crate::claude::ClaudeResult;
+use crate::snapshots::{self, Verification};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// Appended to the system prompt of the run
+pub(crate) const SYSTEM_PROMPT: &str = "You write automated tests for existing code. Follow \
+the project's test framework, file layout, naming, and helpers; read neighbouring tests \
+before adding any. Cover the behavior of the targets, including edge cases and error paths, \
+with focused, deterministic tests. Change production code only if a test cannot be written \
+otherwise, and say so. Run the new tests if you can and fix the ones that fail.";
+
+/// Settings of `claude_write_tests`, configured via `write_tests` in
+/// `claude-mcp.config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WriteTestsConfig {
+    /// Program and arguments that run the tests, e.g. `["cargo", "test"]`
+    pub test_command: Vec<String>,
+    #[serde(default = "default_test_timeout_secs")]
+    pub test_timeout_secs: u64,
+}
+
+fn default_test_timeout_secs() -> u64 {
+    600
+}
+
+/// A test Claude reports having added
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct NewTest {
+    pub name: String,
+    pub file: String,
+}
+
+/// What Claude answers with
+#[derive(Debug, Clone, Default, Deserialize)]
+struct Answer {
+    tests: Vec<NewTest>,
+    summary: String,
+}
+
+/// Outcome of `claude_write_tests`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct TestsWritten {
+    /// Whether tests were added and the test command passed
+    pub success: bool,
+    pub session_id: String,
+    pub tests: Vec<NewTest>,
+    pub summary: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_run: Option<Verification>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What Claude's answer must contain
+pub(crate) fn answer_schema() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["tests", "summary"],
+        "properties": {
+            "tests": {
+                "type": "array",
+                "description": "Every test function added",
+                "items": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "required": ["name", "file"],
+                    "properties": {
+                        "name": {"type": "string"},
+                        "file": {
+                            "type": "string",
+                            "description": "Path relative to the working directory"
+                        }
+                    }
+                }
+            },
+            "summary": {"type": "string", "description": "What the tests cover"}
+        }
+    })
+}
+
+/// The request for tests of `targets`, with the caller's extra `instructions`
+pub(crate) fn prompt(targets: &[String], instructions: Option<&str>) -> String {
+    let mut prompt = String::from("Add tests for the following targets:\n");
+    for target in targets {
+        prompt.push_str(&format!("- {}\n", target));
+    }
+    if let Some(instructions) = instructions {
+        prompt.push_str(&format!("\n{}\n", instructions.trim()));
+    }
+    prompt
+}
+
+/// Run the test command after a run that wrote tests and report both
+pub(crate) async fn finish(
+    result: ClaudeResult,
+    answer: Option<Value>,
+    config: &WriteTestsConfig,
+    working_dir: &Path,
+) -> TestsWritten {
+    let mut report = TestsWritten {
+        session_id: result.session_id,
+        ..Default::default()
+    };
+    if !result.success {
+        report.error = Some(result.error.unwrap_or_else(|| "the run failed".to_string()));
+        return report;
+    }
+    // The answer was validated against `answer_schema`
+    let answer: Answer = answer
+        .and_then(|answer| serde_json::from_value(answer).ok())
+        .unwrap_or_default();
+    report.tests = answer.tests;
+    report.summary = answer.summary;
+
+    let test_run =
+        snapshots::verify(&config.test_command, config.test_timeout_secs, working_dir).await;
+    report.success = test_run.passed && !report.tests.is_empty();
+    if !test_run.passed {
+        report.error = Some(format!(
+            "test_command `{}` failed",
+            config.test_command.join(" ")
+        ));
+    } else if report.tests.is_empty() {
+        report.error = Some("no tests were added".to_string());
+    }
+    report.test_run = Some(test_run);
+    report
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::structured::ResponseSchema;
+
+    #[tokio::test]
+    async fn test_report_lists_new_tests_and_the_test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = WriteTestsConfig {
+            test_command: ["sh", "-c", "echo '2 passed'"].map(String::from).to_vec(),
+            test_timeout_secs: 10,
+        };
+        let result = ClaudeResult {
+            success: true,
+            session_id: "session".to_string(),
+            ..Default::default()
+        };
+        let answer = json!({
+            "tests": [{"name": "test_parse_empty", "file": "src/parse.rs"}],
+            "summary": "Covers empty input",
+        });
+        let schema = ResponseSchema::new(&answer_schema()).unwrap();
+        assert!(schema.check(&answer.to_string()).is_ok());
+
+        let report = finish(result, Some(answer), &config, dir.path()).await;
+        assert!(report.success, "{:?}", report);
+        assert_eq!(report.tests[0].name, "test_parse_empty");
+        assert_eq!(report.test_run.unwrap().output, "2 passed");
+        assert_eq!(
+            prompt(&["src/parse.rs".to_string()], Some(" Use proptest. ")),
+            "Add tests for the following targets:\n- src/parse.rs\n\nUse proptest.\n"
+        );
+    }
+}
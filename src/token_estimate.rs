@@ -0,0 +1,170 @@
+//! Token estimates for `claude_estimate_tokens`, without calling the API.
+//!
+//! The count comes from a local approximation of the tokenizer rather than the tokenizer
+//! itself: words count one token per five letters, numbers one per three digits, and every
+//! punctuation mark and non-ASCII character one token of its own. It tends to err high for
+//! English prose and code, which is the safe side for deciding whether work has to be
+//! split before it is submitted.
+
+use crate::claude::Options;
+use crate::prompt_preview;
+use serde::Serialize;
+use std::path::Path;
+
+/// Context window assumed when the caller does not say
+pub(crate) const DEFAULT_CONTEXT_WINDOW: usize = 200_000;
+/// Files larger than this are estimated from their size instead of read
+const MAX_READ_BYTES: u64 = 8 * 1024 * 1024;
+
+/// A file attached to the estimate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct FileEstimate {
+    pub path: String,
+    pub bytes: u64,
+    pub tokens: usize,
+}
+
+/// Outcome of `claude_estimate_tokens`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct TokenEstimate {
+    /// The prompt as sent, with the `git_context` header
+    pub prompt_tokens: usize,
+    /// `--system-prompt` and `--append-system-prompt` from the config and profile
+    pub system_prompt_tokens: usize,
+    /// `CLAUDE.md` in the working directory, which the CLI loads as project memory
+    pub memory_file_tokens: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileEstimate>,
+    pub total_tokens: usize,
+    pub context_window: usize,
+    /// Whether the total stays within the context window
+    pub fits: bool,
+}
+
+/// Estimate what a run with `opts` sends, plus `files` (relative to the working directory)
+pub(crate) fn estimate(
+    opts: &Options,
+    files: &[String],
+    context_window: usize,
+) -> Result<TokenEstimate, String> {
+    let mut report = TokenEstimate {
+        prompt_tokens: count(&opts.prompt),
+        system_prompt_tokens: ["--system-prompt", "--append-system-prompt"]
+            .iter()
+            .filter_map(|flag| prompt_preview::flag_value(&opts.additional_args, flag))
+            .map(count)
+            .sum(),
+        memory_file_tokens: std::fs::read(opts.working_dir.join("CLAUDE.md"))
+            .map(|content| count(&String::from_utf8_lossy(&content)))
+            .unwrap_or_default(),
+        context_window,
+        ..Default::default()
+    };
+    for file in files {
+        report.files.push(estimate_file(&opts.working_dir, file)?);
+    }
+    report.total_tokens = report.prompt_tokens
+        + report.system_prompt_tokens
+        + report.memory_file_tokens
+        + report.files.iter().map(|f| f.tokens).sum::<usize>();
+    report.fits = report.total_tokens <= context_window;
+    Ok(report)
+}
+
+/// Estimate `file`, which must be inside `working_dir`
+fn estimate_file(working_dir: &Path, file: &str) -> Result<FileEstimate, String> {
+    let path = working_dir
+        .join(file)
+        .canonicalize()
+        .map_err(|e| format!("{}: {}", file, e))?;
+    if !path.starts_with(working_dir) {
+        return Err(format!("{} is outside the working directory", file));
+    }
+    let bytes = std::fs::metadata(&path)
+        .map_err(|e| format!("{}: {}", file, e))?
+        .len();
+    let tokens = if bytes > MAX_READ_BYTES {
+        bytes.div_ceil(3) as usize
+    } else {
+        let content = std::fs::read(&path).map_err(|e| format!("{}: {}", file, e))?;
+        count(&String::from_utf8_lossy(&content))
+    };
+    Ok(FileEstimate {
+        path: file.to_string(),
+        bytes,
+        tokens,
+    })
+}
+
+/// Approximate token count of `text`
+pub(crate) fn count(text: &str) -> usize {
+    let mut tokens = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphabetic() || c.is_ascii_digit() {
+            let digits = c.is_ascii_digit();
+            let mut len: usize = 1;
+            while let Some(&next) = chars.peek() {
+                let same = if digits {
+                    next.is_ascii_digit()
+                } else {
+                    next.is_ascii_alphabetic()
+                };
+                if !same {
+                    break;
+                }
+                len += 1;
+                chars.next();
+            }
+            tokens += len.div_ceil(if digits { 3 } else { 5 });
+        } else if c == ' ' {
+            // A single space is part of the following word; indentation is a token
+            if chars.peek() == Some(&' ') {
+                tokens += 1;
+                while chars.peek() == Some(&' ') {
+                    chars.next();
+                }
+            }
+        } else {
+            tokens += 1;
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_counts_prompt_system_prompt_memory_and_files() {
+        assert_eq!(count(""), 0);
+        assert_eq!(count("Hello, world!"), 4);
+        assert_eq!(count("fn main() {\n    x = 12345;\n}"), 14);
+
+        let dir = tempfile::tempdir().unwrap();
+        let working_dir = dir.path().canonicalize().unwrap();
+        std::fs::write(working_dir.join("CLAUDE.md"), "Use tabs.").unwrap();
+        std::fs::write(working_dir.join("a.rs"), "let x = 1;").unwrap();
+        let opts = Options {
+            prompt: "Refactor a.rs".to_string(),
+            working_dir: working_dir.clone(),
+            additional_args: ["--append-system-prompt", "Be brief."]
+                .map(String::from)
+                .to_vec(),
+            ..Default::default()
+        };
+        let report = estimate(&opts, &["a.rs".to_string()], 10).unwrap();
+        assert_eq!(
+            (
+                report.prompt_tokens,
+                report.system_prompt_tokens,
+                report.memory_file_tokens
+            ),
+            (5, 3, 3)
+        );
+        assert_eq!(report.files[0].tokens, 5);
+        assert_eq!((report.total_tokens, report.fits), (16, false));
+        assert!(estimate(&opts, &["../outside".to_string()], 10).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Paged access to session transcripts behind the `claude_transcript` tool.
+//!
+//! The CLI keeps every session as a JSONL file of events under
+//! `~/.claude/projects/<project>/<session id>.jsonl` (`CLAUDE_CONFIG_DIR` replaces
+//! `~/.claude`). Long sessions run to many megabytes, so the tool returns a range of events
+//! with the offset of the next page instead of the whole file.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Events returned when the caller does not ask for a page size
+pub(crate) const DEFAULT_PAGE_EVENTS: usize = 100;
+/// Largest page size a caller may ask for
+pub(crate) const MAX_PAGE_EVENTS: usize = 1000;
+/// A page ends early once its events add up to this many bytes, keeping at least one event
+const MAX_PAGE_BYTES: usize = 1024 * 1024;
+
+/// One page of a transcript
+#[derive(Debug, Serialize)]
+pub(crate) struct TranscriptPage {
+    /// Events in the whole transcript
+    pub total_events: usize,
+    /// Index of the first event in `events`
+    pub offset: usize,
+    pub events: Vec<Value>,
+    /// Offset of the following page; absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<usize>,
+}
+
+/// Directory the CLI keeps its state in
+fn cli_state_dir() -> Option<PathBuf> {
+    std::env::var_os("CLAUDE_CONFIG_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".claude")))
+}
+
+/// Transcript file of `session_id`, searched across all projects
+pub(crate) fn find(session_id: &str) -> Option<PathBuf> {
+    let projects = cli_state_dir()?.join("projects");
+    let file_name = format!("{}.jsonl", session_id);
+    std::fs::read_dir(projects)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|project| project.path().join(&file_name))
+        .find(|path| path.is_file())
+}
+
+/// Events `offset..offset + limit` of the transcript at `path`. Lines that are not JSON are
+/// returned as strings so event indexes stay stable.
+pub(crate) fn page(path: &Path, offset: usize, limit: usize) -> Result<TranscriptPage, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    let mut events = Vec::new();
+    let mut bytes = 0;
+    let mut total_events = 0;
+    let mut next_offset = None;
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let index = total_events;
+        total_events += 1;
+        if index < offset || next_offset.is_some() {
+            continue;
+        }
+        let full =
+            events.len() >= limit || (!events.is_empty() && bytes + line.len() > MAX_PAGE_BYTES);
+        if full {
+            next_offset = Some(index);
+            continue;
+        }
+        bytes += line.len();
+        events.push(serde_json::from_str(&line).unwrap_or(Value::String(line)));
+    }
+    Ok(TranscriptPage {
+        total_events,
+        offset,
+        events,
+        next_offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_pages_cover_the_transcript_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let lines: Vec<String> = (0..5)
+            .map(|i| json!({"type": "assistant", "index": i}).to_string())
+            .collect();
+        std::fs::write(&path, lines.join("\n") + "\n\nnot json\n").unwrap();
+
+        let first = page(&path, 0, 2).unwrap();
+        assert_eq!(first.total_events, 6);
+        assert_eq!(
+            first.events,
+            [
+                json!({"type": "assistant", "index": 0}),
+                json!({"type": "assistant", "index": 1})
+            ]
+        );
+        assert_eq!(first.next_offset, Some(2));
+
+        let last = page(&path, 4, 10).unwrap();
+        assert_eq!(last.events[1], json!("not json"));
+        assert_eq!(last.next_offset, None);
+
+        assert!(page(&path, 10, 10).unwrap().events.is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+//! Issue triage for `claude_triage_issue`: a root-cause hypothesis and fix plan, without
+//! changing anything.
+//!
+//! The issue is given as text or fetched with `gh issue view`. Claude investigates it in
+//! plan mode (`--permission-mode plan`), so it can read the code but not edit it, and
+//! answers with JSON matching [`report_schema`], validated like a `RESPONSE_SCHEMA` answer.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// An issue to triage
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub(crate) struct Issue {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+impl Issue {
+    /// An issue given as text by the caller
+    pub(crate) fn from_text(text: &str) -> Self {
+        Self {
+            body: text.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Fetch `reference` (a number or URL) with `gh issue view`, run in `working_dir` so a
+/// number refers to that repository's issues
+pub(crate) async fn fetch(reference: &str, working_dir: &Path) -> Result<Issue> {
+    let mut command = Command::new("gh");
+    command
+        .args(["issue", "view", reference, "--json", "title,body,url"])
+        .current_dir(working_dir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    let output = crate::snapshots::output_of(command, "gh issue view").await?;
+    serde_json::from_str(&output).context("gh issue view printed unexpected JSON")
+}
+
+/// What the report must contain
+pub(crate) fn report_schema() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": [
+            "summary", "category", "root_cause", "confidence",
+            "affected_files", "fix_plan", "risks", "open_questions"
+        ],
+        "properties": {
+            "summary": {"type": "string", "description": "The problem in one or two sentences"},
+            "category": {"enum": ["bug", "feature", "question", "docs", "other"]},
+            "root_cause": {
+                "type": "string",
+                "description": "Most likely cause, pointing at the code involved"
+            },
+            "confidence": {"enum": ["low", "medium", "high"]},
+            "affected_files": {
+                "type": "array",
+                "items": {"type": "string"},
+                "description": "Paths relative to the working directory"
+            },
+            "fix_plan": {
+                "type": "array",
+                "items": {"type": "string"},
+                "description": "Ordered steps of the fix, tests included"
+            },
+            "risks": {"type": "array", "items": {"type": "string"}},
+            "open_questions": {
+                "type": "array",
+                "items": {"type": "string"},
+                "description": "What to ask the reporter before fixing"
+            }
+        }
+    })
+}
+
+/// The triage instructions followed by `issue`
+pub(crate) fn prompt(issue: &Issue) -> String {
+    let mut prompt = String::from(
+        "Triage the issue below against the code in the current working directory. Read the \
+         relevant code to find the most likely root cause and plan a fix scoped to this \
+         directory. Do not change any files.\n",
+    );
+    if !issue.title.is_empty() {
+        prompt.push_str(&format!("\nTitle: {}", issue.title));
+    }
+    if !issue.url.is_empty() {
+        prompt.push_str(&format!("\nURL: {}", issue.url));
+    }
+    prompt.push_str(&format!("\n\n{}", issue.body.trim()));
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structured::ResponseSchema;
+
+    #[test]
+    fn test_prompt_carries_the_fetched_issue_and_the_schema_is_valid() {
+        let issue: Issue = serde_json::from_str(
+            r#"{"title": "Crash on empty input", "body": "Steps: run with ''\n", "url": "https://example.com/issues/3"}"#,
+        )
+        .unwrap();
+        let prompt = prompt(&issue);
+        assert!(prompt.starts_with("Triage the issue below"));
+        assert!(prompt.ends_with(
+            "\nTitle: Crash on empty input\nURL: https://example.com/issues/3\n\nSteps: run with ''"
+        ));
+        assert!(!super::prompt(&Issue::from_text("It hangs")).contains("Title:"));
+        assert!(ResponseSchema::new(&report_schema()).is_ok());
+    }
+}
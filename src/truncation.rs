@@ -0,0 +1,275 @@
+//! Size-limited accumulation of agent text.
+//!
+//! `agent_messages` is capped at MAX_AGENT_MESSAGES_SIZE. The truncation strategy picks which
+//! part survives: the beginning, the end (where the final answer usually is), or both ends
+//! with the middle elided. Whole messages are dropped first; a message that has to be cut is
+//! cut at a paragraph, line, or sentence break, and the marker says how much was left out.
+
+use rmcp::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Which part of oversized agent text is kept, configured via `truncation` in
+/// `claude-mcp.config.json` or per call with `TRUNCATION`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    /// Keep the beginning and drop everything after the limit
+    #[default]
+    Head,
+    /// Keep the most recent text
+    Tail,
+    /// Keep the first and last halves with an elision marker between them
+    Middle,
+}
+
+/// Agent text pieces collected under a byte limit. Pieces are joined with newlines.
+#[derive(Debug)]
+pub(crate) struct AgentText {
+    strategy: TruncationStrategy,
+    limit: usize,
+    head: String,
+    /// Set once a piece did not fit in the head; later pieces go to the tail (or are dropped)
+    head_full: bool,
+    /// Kept pieces, flagged when already cut so they are counted as omitted only once
+    tail: VecDeque<(String, bool)>,
+    /// Bytes in `tail` including the separators between pieces
+    tail_len: usize,
+    /// Messages dropped entirely or in part
+    omitted_messages: usize,
+    omitted_bytes: usize,
+}
+
+impl AgentText {
+    pub(crate) fn new(strategy: TruncationStrategy, limit: usize) -> Self {
+        Self {
+            strategy,
+            limit,
+            head: String::new(),
+            head_full: false,
+            tail: VecDeque::new(),
+            tail_len: 0,
+            omitted_messages: 0,
+            omitted_bytes: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.strategy {
+            TruncationStrategy::Head => {
+                if !self.push_head(text, self.limit, true) {
+                    self.omitted_messages += 1;
+                    self.omitted_bytes += text.len();
+                }
+            }
+            TruncationStrategy::Tail => self.push_tail(text, self.limit),
+            TruncationStrategy::Middle => {
+                if !self.push_head(text, self.limit / 2, false) {
+                    let budget = self.limit.saturating_sub(self.head.len());
+                    self.push_tail(text, budget);
+                }
+            }
+        }
+    }
+
+    /// Append to the head if it stays within `budget`, or with `partial` the part of the
+    /// first overflowing piece that ends at a break. Returns whether the text was taken.
+    fn push_head(&mut self, text: &str, budget: usize, partial: bool) -> bool {
+        if self.head_full {
+            return false;
+        }
+        let separator = usize::from(!self.head.is_empty());
+        let room = budget.saturating_sub(self.head.len() + separator);
+        let kept = if text.len() <= room {
+            text
+        } else {
+            self.head_full = true;
+            match prefix_at_break(text, room).filter(|_| partial) {
+                Some(prefix) => {
+                    self.omitted_messages += 1;
+                    self.omitted_bytes += text.len() - prefix.len();
+                    prefix
+                }
+                None => return false,
+            }
+        };
+        if separator == 1 {
+            self.head.push('\n');
+        }
+        self.head.push_str(kept);
+        true
+    }
+
+    /// Append to the tail, dropping the oldest messages beyond `budget`
+    fn push_tail(&mut self, text: &str, budget: usize) {
+        if !self.tail.is_empty() {
+            self.tail_len += 1;
+        }
+        self.tail_len += text.len();
+        self.tail.push_back((text.to_string(), false));
+
+        while self.tail_len > budget && self.tail.len() > 1 {
+            if let Some((front, cut)) = self.tail.pop_front() {
+                if !cut {
+                    self.omitted_messages += 1;
+                }
+                self.omitted_bytes += front.len();
+                self.tail_len -= front.len() + 1;
+            }
+        }
+        if self.tail_len > budget {
+            if let Some((front, cut)) = self.tail.front_mut() {
+                let kept = suffix_at_break(front, budget).len();
+                let dropped = front.len() - kept;
+                front.drain(..dropped);
+                if !*cut {
+                    self.omitted_messages += 1;
+                    *cut = true;
+                }
+                self.omitted_bytes += dropped;
+                self.tail_len -= dropped;
+            }
+        }
+    }
+
+    /// The kept text with a marker where text was dropped, and the number of messages
+    /// dropped entirely or in part
+    pub(crate) fn finish(self) -> (String, usize) {
+        let marker = format!(
+            "[... {} agent message(s), {} bytes, omitted due to size limit ...]",
+            self.omitted_messages, self.omitted_bytes
+        );
+        let tail = self
+            .tail
+            .into_iter()
+            .map(|(piece, _)| piece)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let parts: Vec<&str> = if self.omitted_messages == 0 {
+            vec![&self.head, &tail]
+        } else {
+            match self.strategy {
+                TruncationStrategy::Head => vec![&self.head, &marker],
+                TruncationStrategy::Tail => vec![&marker, &tail],
+                TruncationStrategy::Middle => vec![&self.head, &marker, &tail],
+            }
+        };
+        let text = parts
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        (text, self.omitted_messages)
+    }
+}
+
+/// Separators tried in order when a message has to be cut, from paragraph to sentence
+const BREAKS: [&str; 3] = ["\n\n", "\n", ". "];
+
+/// Longest non-empty prefix of `text` within `max` bytes that ends at a break
+fn prefix_at_break(text: &str, max: usize) -> Option<&str> {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let window = &text[..end];
+    BREAKS.iter().find_map(|sep| {
+        window
+            .rfind(sep)
+            .map(|i| window[..i + sep.len()].trim_end())
+            .filter(|prefix| !prefix.is_empty())
+    })
+}
+
+/// Longest suffix of `text` within `max` bytes that starts after a break, falling back to
+/// the nearest character boundary
+fn suffix_at_break(text: &str, max: usize) -> &str {
+    let mut start = text.len().saturating_sub(max);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    let window = &text[start..];
+    BREAKS
+        .iter()
+        .find_map(|sep| {
+            window
+                .find(sep)
+                .map(|i| &window[i + sep.len()..])
+                .filter(|suffix| !suffix.is_empty())
+        })
+        .unwrap_or(window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(strategy: TruncationStrategy, limit: usize, pieces: &[&str]) -> (String, usize) {
+        let mut text = AgentText::new(strategy, limit);
+        for piece in pieces {
+            text.push(piece);
+        }
+        text.finish()
+    }
+
+    #[test]
+    fn test_text_within_limit_is_joined_unchanged() {
+        for strategy in [
+            TruncationStrategy::Head,
+            TruncationStrategy::Tail,
+            TruncationStrategy::Middle,
+        ] {
+            assert_eq!(
+                collect(strategy, 100, &["one", "", "two"]),
+                ("one\ntwo".to_string(), 0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_each_strategy_keeps_whole_messages_from_its_end() {
+        let pieces = ["first", "second", "third", "answer"];
+
+        let (head, omitted) = collect(TruncationStrategy::Head, 14, &pieces);
+        assert_eq!(omitted, 2);
+        assert_eq!(
+            head,
+            "first\nsecond\n[... 2 agent message(s), 11 bytes, omitted due to size limit ...]"
+        );
+
+        let (tail, _) = collect(TruncationStrategy::Tail, 14, &pieces);
+        assert_eq!(
+            tail,
+            "[... 2 agent message(s), 11 bytes, omitted due to size limit ...]\nthird\nanswer"
+        );
+
+        let (middle, omitted) = collect(TruncationStrategy::Middle, 14, &pieces);
+        assert_eq!(omitted, 2);
+        assert_eq!(
+            middle,
+            "first\n[... 2 agent message(s), 11 bytes, omitted due to size limit ...]\nanswer"
+        );
+    }
+
+    #[test]
+    fn test_oversized_message_is_cut_at_paragraph_break() {
+        let message = "Plan.\n\nStep one done. Step two done.\n\nFinal answer: 42";
+
+        let (head, omitted) = collect(TruncationStrategy::Head, 30, &[message]);
+        assert_eq!(omitted, 1);
+        assert!(head.starts_with("Plan.\n[... 1 agent message(s)"));
+
+        let (tail, _) = collect(TruncationStrategy::Tail, 30, &[message]);
+        assert!(tail.ends_with("...]\nFinal answer: 42"));
+    }
+
+    #[test]
+    fn test_tail_without_breaks_cuts_on_char_boundary() {
+        let (tail, omitted) = collect(TruncationStrategy::Tail, 5, &["ééé"]);
+        assert_eq!(omitted, 1);
+        assert!(tail.ends_with("...]\néé"));
+    }
+}
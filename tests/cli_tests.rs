@@ -0,0 +1,723 @@
+use claude_mcp_rs::fake_cli::FakeCli;
+use std::process::Command;
+use std::time::Duration;
+use tempfile::tempdir;
+
+#[allow(dead_code)]
+mod common;
+
+#[test]
+fn test_run_subcommand_prints_result_as_json() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let script_path = common::write_mock_cli(
+        temp_dir.path(),
+        "run_subcommand",
+        &[
+            r#"{"type":"assistant","message":{"content":[{"type":"text","text":"Done."}]},"session_id":"cli-session"}"#,
+            r#"{"type":"result","result":"Done.","is_error":false,"session_id":"cli-session"}"#,
+        ],
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .args(["run", "--dir"])
+        .arg(temp_dir.path())
+        .arg("say done")
+        .current_dir(temp_dir.path())
+        .env("CLAUDE_BIN", &script_path)
+        .env_remove("CLAUDE_MCP_CONFIG_PATH")
+        .output()
+        .expect("Failed to run binary");
+
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout should be JSON");
+    assert_eq!(json["success"], true);
+    assert_eq!(json["SESSION_ID"], "cli-session");
+    assert_eq!(json["message"], "Done.");
+}
+
+#[test]
+fn test_run_subcommand_rejects_invalid_arguments() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let output = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .args(["run", "--session-id", "not-a-uuid", "hello"])
+        .current_dir(temp_dir.path())
+        .env_remove("CLAUDE_MCP_CONFIG_PATH")
+        .output()
+        .expect("Failed to run binary");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("SESSION_ID must be a valid UUID"));
+}
+
+#[test]
+fn test_doctor_reports_checks_and_fails_without_binary() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let script_path = common::write_mock_cli(temp_dir.path(), "doctor", &["2.0.14 (Claude Code)"]);
+    let doctor = |claude_bin: &std::path::Path| {
+        Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+            .arg("doctor")
+            .current_dir(temp_dir.path())
+            .env("CLAUDE_BIN", claude_bin)
+            .env_remove("CLAUDE_MCP_CONFIG_PATH")
+            .output()
+            .expect("Failed to run binary")
+    };
+
+    let output = doctor(&script_path);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("claude version: 2.0.14 (Claude Code)"));
+    assert!(stdout.contains("using defaults"));
+
+    let output = doctor(&temp_dir.path().join("missing-claude"));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("[FAIL] claude binary"));
+}
+
+#[test]
+fn test_config_init_writes_a_config_that_validates() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let config = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+            .arg("config")
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env_remove("CLAUDE_MCP_CONFIG_PATH")
+            .output()
+            .expect("Failed to run binary")
+    };
+
+    assert!(config(&["init"]).status.success());
+    assert!(temp_dir.path().join("claude-mcp.config.json").is_file());
+    assert!(
+        !config(&["init"]).status.success(),
+        "init must not overwrite"
+    );
+    assert!(config(&["validate"]).status.success());
+
+    let bad = temp_dir.path().join("bad.json");
+    std::fs::write(
+        &bad,
+        r#"{"timeout_sec": 60, "additional_args": ["--output-format", "json"],
+            "schedules": [{"name": "nightly", "cron": "0 3 * * *", "prompt": "triage",
+                           "working_dir": ".", "webhook": "ftp://example.com"}]}"#,
+    )
+    .unwrap();
+    let output = config(&["validate", "--path", bad.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains("unknown option 'timeout_sec'"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("'--output-format' is set by the server"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("schedules.nightly.webhook"), "{}", stdout);
+
+    std::fs::write(
+        &bad,
+        r#"{"schedules": [{"name": "n", "cron": "0 3 * *", "prompt": "p", "working_dir": "."}]}"#,
+    )
+    .unwrap();
+    let output = config(&["validate", "--path", bad.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(stdout.contains("must have 5 fields"), "{}", stdout);
+}
+
+#[test]
+fn test_http_transport_answers_initialize() {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to pick a port")
+        .port();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .args(["--transport", "http", "--bind"])
+        .arg(format!("127.0.0.1:{}", port))
+        .current_dir(temp_dir.path())
+        .env_remove("CLAUDE_MCP_CONFIG_PATH")
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => break stream,
+            Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => panic!("server did not start listening: {}", e),
+        }
+    };
+    let body = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2024-11-05","capabilities":{},"clientInfo":{"name":"test","version":"1"}}}"#;
+    write!(
+        stream,
+        "POST /mcp HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Type: application/json\r\n\
+         Accept: application/json, text/event-stream\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !String::from_utf8_lossy(&response).contains("serverInfo") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+
+    let response = String::from_utf8_lossy(&response);
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("serverInfo"), "{}", response);
+    // An older client is answered with the version it asked for
+    assert!(
+        response.contains(r#""protocolVersion":"2024-11-05""#),
+        "{}",
+        response
+    );
+}
+
+#[test]
+fn test_http_tenants_need_their_bearer_token() {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    std::fs::write(
+        temp_dir.path().join("claude-mcp.config.json"),
+        r#"{"tenants": {"alice": {"token": "alice-token", "budget_usd": 5}}}"#,
+    )
+    .unwrap();
+    let port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to pick a port")
+        .port();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .args(["--transport", "http", "--bind"])
+        .arg(format!("127.0.0.1:{}", port))
+        .current_dir(temp_dir.path())
+        .env_remove("CLAUDE_MCP_CONFIG_PATH")
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+
+    let initialize = |authorization: &str| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => break stream,
+                Err(_) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(50))
+                }
+                Err(e) => panic!("server did not start listening: {}", e),
+            }
+        };
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2024-11-05","capabilities":{},"clientInfo":{"name":"test","version":"1"}}}"#;
+        write!(
+            stream,
+            "POST /mcp HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Type: application/json\r\n\
+             Accept: application/json, text/event-stream\r\nConnection: close\r\n{}\
+             Content-Length: {}\r\n\r\n{}",
+            authorization,
+            body.len(),
+            body
+        )
+        .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !String::from_utf8_lossy(&response).contains("serverInfo") {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => response.extend_from_slice(&buf[..n]),
+            }
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    };
+    let anonymous = initialize("");
+    let wrong_token = initialize("Authorization: Bearer bob-token\r\n");
+    let tenant = initialize("Authorization: Bearer alice-token\r\n");
+
+    // A reload replaces the token without a restart
+    #[cfg(unix)]
+    let (rotated, revoked) = {
+        std::fs::write(
+            temp_dir.path().join("claude-mcp.config.json"),
+            r#"{"tenants": {"alice": {"token": "alice-rotated-token", "budget_usd": 5}}}"#,
+        )
+        .unwrap();
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGHUP) };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let rotated = loop {
+            let response = initialize("Authorization: Bearer alice-rotated-token\r\n");
+            if response.starts_with("HTTP/1.1 200") || Instant::now() > deadline {
+                break response;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        (rotated, initialize("Authorization: Bearer alice-token\r\n"))
+    };
+    let _ = child.kill();
+    let _ = child.wait();
+
+    assert!(anonymous.starts_with("HTTP/1.1 401"), "{}", anonymous);
+    assert!(
+        anonymous.contains("www-authenticate: Bearer"),
+        "{}",
+        anonymous
+    );
+    assert!(wrong_token.starts_with("HTTP/1.1 401"), "{}", wrong_token);
+    assert!(tenant.starts_with("HTTP/1.1 200"), "{}", tenant);
+    assert!(tenant.contains("serverInfo"), "{}", tenant);
+    #[cfg(unix)]
+    {
+        assert!(rotated.starts_with("HTTP/1.1 200"), "{}", rotated);
+        assert!(revoked.starts_with("HTTP/1.1 401"), "{}", revoked);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_daemon_reports_status_and_stops() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let pid_file = temp_dir.path().join("server.pid");
+    let cli = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+            .args(args)
+            .arg("--pid-file")
+            .arg(&pid_file)
+            .current_dir(temp_dir.path())
+            .env_remove("CLAUDE_MCP_CONFIG_PATH")
+            .output()
+            .expect("Failed to run binary")
+    };
+
+    let output = cli(&["--transport", "http", "--bind", "127.0.0.1:0", "--daemon"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("started (pid "));
+
+    let output = cli(&["status"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("at http://127.0.0.1:"), "{}", stdout);
+
+    assert!(cli(&["stop"]).status.success());
+    assert!(!pid_file.exists());
+    let output = cli(&["status"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "not running"
+    );
+}
+
+#[test]
+fn test_response_schema_answer_is_corrected_and_parsed() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let session = "11111111-1111-1111-1111-111111111111";
+    // Answers with prose first, and with the JSON once the session is resumed
+    let script_path = FakeCli::new()
+        .when_args(
+            "--resume",
+            FakeCli::new().assistant_text(session, "```json\n{\"files\": [\"src/lib.rs\"]}\n```"),
+        )
+        .assistant_text(session, "The file is src/lib.rs.")
+        .write(temp_dir.path(), "schema_claude")
+        .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .args(["run", "--response-schema"])
+        .arg(r#"{"type":"object","required":["files"],"properties":{"files":{"type":"array","items":{"type":"string"}}}}"#)
+        .arg("which files?")
+        .current_dir(temp_dir.path())
+        .env("CLAUDE_BIN", &script_path)
+        .env_remove("CLAUDE_MCP_CONFIG_PATH")
+        .output()
+        .expect("Failed to run binary");
+
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout should be JSON");
+    assert_eq!(json["answer"], serde_json::json!({"files": ["src/lib.rs"]}));
+}
+
+#[test]
+fn test_context_overflow_is_compacted_and_retried() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let session = "22222222-2222-2222-2222-222222222222";
+    // Fails with a full context unless the session is compacted or resumed afterwards
+    let script_path = FakeCli::new()
+        .when_args("/compact", FakeCli::new().result(session, "Compacted."))
+        .when_args("--resume", FakeCli::new().assistant_text(session, "Done."))
+        .event(serde_json::json!({
+            "type": "result",
+            "result": "Prompt is too long",
+            "is_error": true,
+            "session_id": session,
+        }))
+        .write(temp_dir.path(), "overflow_claude")
+        .unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    std::fs::write(&config_path, r#"{"context_overflow": "compact"}"#).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .arg("--config")
+        .arg(&config_path)
+        .args(["run", "continue the refactor"])
+        .current_dir(temp_dir.path())
+        .env("CLAUDE_BIN", &script_path)
+        .output()
+        .expect("Failed to run binary");
+
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout should be JSON");
+    assert_eq!(json["message"], "Done.");
+    assert!(
+        json["warnings"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("compacted session 22222222-2222-2222-2222-222222222222"),
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn test_profile_selects_a_named_binary() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let mock = |name: &str| {
+        let text = format!(
+            r#"{{"type":"assistant","message":{{"content":[{{"type":"text","text":"{}"}}]}},"session_id":"bin-session"}}"#,
+            name
+        );
+        common::write_mock_cli(temp_dir.path(), name, &[text.as_str()])
+    };
+    let stable = mock("stable");
+    let nightly = mock("nightly");
+    let config_path = temp_dir.path().join("config.json");
+    let config = serde_json::json!({
+        "binaries": {"nightly": nightly},
+        "profiles": {"canary": {"binary": "nightly"}}
+    });
+    std::fs::write(&config_path, config.to_string()).unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+            .arg("--config")
+            .arg(&config_path)
+            .arg("run")
+            .args(args)
+            .arg("which build?")
+            .current_dir(temp_dir.path())
+            .env("CLAUDE_BIN", &stable)
+            .output()
+            .expect("Failed to run binary");
+        assert!(output.status.success(), "{:?}", output);
+        let json: serde_json::Value =
+            serde_json::from_slice(&output.stdout).expect("stdout should be JSON");
+        json["message"].as_str().unwrap_or_default().to_string()
+    };
+    assert_eq!(run(&[]), "stable");
+    assert_eq!(run(&["--profile", "canary"]), "nightly");
+}
+
+#[test]
+fn test_overloaded_model_falls_back() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let session = "33333333-3333-3333-3333-333333333333";
+    // Only the fallback model answers
+    let script_path = FakeCli::new()
+        .when_args(
+            "--model haiku",
+            FakeCli::new().assistant_text(session, "Done."),
+        )
+        .event(serde_json::json!({
+            "type": "result",
+            "result": "API Error: 529 Overloaded",
+            "is_error": true,
+            "session_id": session,
+        }))
+        .write(temp_dir.path(), "overloaded_claude")
+        .unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    std::fs::write(&config_path, r#"{"fallbacks": [{"model": "haiku"}]}"#).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .arg("--config")
+        .arg(&config_path)
+        .args(["run", "--model", "opus", "summarize the changelog"])
+        .current_dir(temp_dir.path())
+        .env("CLAUDE_BIN", &script_path)
+        .output()
+        .expect("Failed to run binary");
+
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout should be JSON");
+    assert_eq!(json["message"], "Done.");
+    let warnings = json["warnings"].as_str().unwrap_or_default();
+    assert!(warnings.contains("retried with model haiku"), "{}", json);
+}
+
+#[test]
+fn test_profile_sets_binary_env_and_working_dir() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let proxy_log = temp_dir.path().join("proxy.log");
+    let wrapper = FakeCli::new()
+        .log_env("HTTPS_PROXY", &proxy_log)
+        .write_file("ran-here", "")
+        .assistant_text("work-session", "Done.")
+        .write(temp_dir.path(), "proxied-claude")
+        .unwrap();
+    std::fs::create_dir(temp_dir.path().join("work-repo")).unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    let config = serde_json::json!({
+        "profiles": {"work": {
+            "claude_bin": wrapper,
+            "env": {"HTTPS_PROXY": "http://proxy.corp:3128"},
+            "working_dir": "work-repo"
+        }}
+    });
+    std::fs::write(&config_path, config.to_string()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .arg("--config")
+        .arg(&config_path)
+        .args(["run", "--profile", "work", "status?"])
+        .current_dir(temp_dir.path())
+        .env_remove("HTTPS_PROXY")
+        .output()
+        .expect("Failed to run binary");
+
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout should be JSON");
+    assert_eq!(json["message"], "Done.");
+    assert_eq!(
+        std::fs::read_to_string(&proxy_log).unwrap().trim(),
+        "http://proxy.corp:3128"
+    );
+    assert!(temp_dir.path().join("work-repo/ran-here").exists());
+}
+
+#[test]
+fn test_dry_run_reports_the_invocation_without_running_it() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let marker = temp_dir.path().join("ran");
+    let config_path = temp_dir.path().join("config.json");
+    let config = serde_json::json!({
+        "claude_bin": marker,
+        "additional_args": ["--verbose"],
+        "profiles": {"review": {"additional_args": ["--model", "opus"], "timeout_secs": 90}}
+    });
+    std::fs::write(&config_path, config.to_string()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .arg("--config")
+        .arg(&config_path)
+        .args(["run", "--dry-run", "--profile", "review", "review the diff"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to run binary");
+
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout should be JSON");
+    let argv: Vec<&str> = json["argv"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|arg| arg.as_str())
+        .collect();
+    assert_eq!(argv[0], marker.to_str().unwrap());
+    assert_eq!(
+        argv[1..],
+        [
+            "--print",
+            "--output-format",
+            "stream-json",
+            "--verbose",
+            "--model",
+            "opus",
+            "--",
+            "review the diff"
+        ]
+    );
+    assert_eq!(json["timeout_secs"], 90);
+    assert!(!marker.exists());
+}
+
+#[test]
+fn test_timed_out_run_keeps_its_session_for_resuming() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let script_path = FakeCli::new()
+        .event(serde_json::json!({
+            "type": "system",
+            "subtype": "init",
+            "session_id": "44444444-4444-4444-4444-444444444444",
+        }))
+        .sleep(Duration::from_secs(30))
+        .write(temp_dir.path(), "slow_claude")
+        .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .args(["--timeout-secs", "1", "run", "refactor the parser"])
+        .current_dir(temp_dir.path())
+        .env("CLAUDE_BIN", &script_path)
+        .env_remove("CLAUDE_MCP_CONFIG_PATH")
+        .output()
+        .expect("Failed to run binary");
+
+    assert!(!output.status.success(), "{:?}", output);
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout should be JSON");
+    assert_eq!(json["success"], false);
+    assert_eq!(json["SESSION_ID"], "44444444-4444-4444-4444-444444444444");
+    assert_eq!(json["resumable"], true);
+    let error = json["error"].as_str().unwrap_or_default();
+    assert!(error.contains("timed out"), "{}", json);
+}
+
+#[test]
+fn test_unknown_session_fails_or_starts_fresh() {
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let script_path = FakeCli::new()
+        .when_args(
+            "--resume",
+            FakeCli::new()
+                .stderr(
+                    "No conversation found with session ID: 66666666-6666-6666-6666-666666666666",
+                )
+                .exit_code(1),
+        )
+        .assistant_text("55555555-5555-5555-5555-555555555555", "Done.")
+        .write(temp_dir.path(), "forgetful_claude")
+        .unwrap();
+    let run = |config: &str| {
+        let config_path = temp_dir.path().join("config.json");
+        std::fs::write(&config_path, config).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+            .arg("--config")
+            .arg(&config_path)
+            .args([
+                "run",
+                "--session-id",
+                "66666666-6666-6666-6666-666666666666",
+                "continue",
+            ])
+            .current_dir(temp_dir.path())
+            .env("CLAUDE_BIN", &script_path)
+            .output()
+            .expect("Failed to run binary")
+    };
+
+    let failed = run("{}");
+    assert!(!failed.status.success(), "{:?}", failed);
+    let stderr = String::from_utf8_lossy(&failed.stderr);
+    assert!(
+        stderr.contains("session 66666666-6666-6666-6666-666666666666 not found"),
+        "{}",
+        stderr
+    );
+
+    let fresh = run(r#"{"missing_session": "fresh_session"}"#);
+    assert!(fresh.status.success(), "{:?}", fresh);
+    let json: serde_json::Value =
+        serde_json::from_slice(&fresh.stdout).expect("stdout should be JSON");
+    assert_eq!(json["SESSION_ID"], "55555555-5555-5555-5555-555555555555");
+    let warnings = json["warnings"].as_str().unwrap_or_default();
+    assert!(
+        warnings.contains("was not found; ran the prompt in a new session"),
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn test_migration_passes_extra_dirs_without_taking_the_prompt() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::Stdio;
+
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let work_dir = temp_dir.path().join("app");
+    std::fs::create_dir_all(temp_dir.path().join("shared")).unwrap();
+    std::fs::create_dir(&work_dir).unwrap();
+    std::fs::write(work_dir.join("main.js"), "moment()\n").unwrap();
+    let args_log = temp_dir.path().join("args.log");
+    let script_path = FakeCli::new()
+        .log_args(&args_log)
+        .result("migrate-session", "Migrated.")
+        .write(temp_dir.path(), "migrate_claude")
+        .unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_claude-mcp-rs"))
+        .current_dir(&work_dir)
+        .env("CLAUDE_BIN", &script_path)
+        .env_remove("CLAUDE_MCP_CONFIG_PATH")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    let mut stdin = child.stdin.take().unwrap();
+    let call = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "tools/call",
+        "params": {"name": "claude_migrate", "arguments": {
+            "INSTRUCTIONS": "Replace moment with date-fns",
+            "FILES": ["main.js"],
+            "EXTRA_DIRS": ["../shared"],
+        }},
+    });
+    writeln!(
+        stdin,
+        r#"{{"jsonrpc":"2.0","id":1,"method":"initialize","params":{{"protocolVersion":"2024-11-05","capabilities":{{}},"clientInfo":{{"name":"test","version":"1"}}}}}}"#
+    )
+    .unwrap();
+    writeln!(
+        stdin,
+        r#"{{"jsonrpc":"2.0","method":"notifications/initialized"}}"#
+    )
+    .unwrap();
+    writeln!(stdin, "{}", call).unwrap();
+
+    let response = BufReader::new(child.stdout.take().unwrap())
+        .lines()
+        .map_while(Result::ok)
+        .find(|line| line.contains(r#""id":2"#))
+        .expect("server should answer the tool call");
+    drop(stdin);
+    child.wait().unwrap();
+
+    assert!(!response.contains(r#""isError":true"#), "{}", response);
+    let args = std::fs::read_to_string(&args_log).expect("the CLI should have run");
+    let args: Vec<&str> = args.split_whitespace().collect();
+    assert!(!args.contains(&"--add-dir"), "{:?}", args);
+    let add_dir = args
+        .iter()
+        .find(|arg| arg.starts_with("--add-dir="))
+        .expect("EXTRA_DIRS should be passed with --add-dir=");
+    assert!(add_dir.ends_with("shared"), "{}", add_dir);
+}
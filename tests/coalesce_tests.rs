@@ -0,0 +1,55 @@
+// Sets CLAUDE_BIN, so it lives in its own test binary
+
+use claude_mcp_rs::claude::{self, Options};
+use claude_mcp_rs::fake_cli::FakeCli;
+use std::time::Duration;
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn test_identical_concurrent_runs_share_one_cli_process() {
+    let work_dir = tempdir().expect("Failed to create temp dir");
+    let tools_dir = tempdir().expect("Failed to create temp dir");
+    let counter = tools_dir.path().join("runs.log");
+    let script_path = FakeCli::new()
+        .log_args(&counter)
+        .sleep(Duration::from_secs(1))
+        .assistant_text("shared-session", "Shared.")
+        .result("shared-session", "Shared.")
+        .write(tools_dir.path(), "slow_claude")
+        .unwrap();
+    std::env::set_var("CLAUDE_BIN", &script_path);
+
+    let options = |prompt: &str| Options {
+        prompt: prompt.to_string(),
+        working_dir: work_dir.path().to_path_buf(),
+        timeout_secs: Some(10),
+        ..Default::default()
+    };
+    let (first, second) = tokio::join!(
+        claude::run(options("explain")),
+        claude::run(options("explain"))
+    );
+    let (first, second) = (first.unwrap(), second.unwrap());
+    assert_eq!(first.agent_messages, "Shared.");
+    assert_eq!(second.agent_messages, "Shared.");
+    assert_eq!(second.session_id, "shared-session");
+    assert!(
+        first.coalesced != second.coalesced,
+        "exactly one run is shared"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&counter).unwrap().lines().count(),
+        1
+    );
+
+    // Different prompts are never merged
+    let (first, second) = tokio::join!(
+        claude::run(options("explain")),
+        claude::run(options("summarize"))
+    );
+    assert!(!first.unwrap().coalesced && !second.unwrap().coalesced);
+    assert_eq!(
+        std::fs::read_to_string(&counter).unwrap().lines().count(),
+        3
+    );
+}
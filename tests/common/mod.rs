@@ -1,6 +1,7 @@
 // Common test utilities and helpers
 
-use std::path::PathBuf;
+use claude_mcp_rs::fake_cli::FakeCli;
+use std::path::{Path, PathBuf};
 
 /// Get a temporary directory for testing
 pub fn get_temp_dir() -> PathBuf {
@@ -15,16 +16,27 @@ pub fn create_test_options(prompt: &str, working_dir: &str) -> claude_mcp_rs::cl
         session_id: None,
         additional_args: Vec::new(),
         timeout_secs: None,
+        ..Default::default()
     }
 }
 
+/// Write a mock Claude CLI that prints `lines` to stdout. Returns the path to point
+/// `CLAUDE_BIN` at; see [`FakeCli`] for stderr, pauses, and exit codes.
+pub fn write_mock_cli(dir: &Path, name: &str, lines: &[&str]) -> PathBuf {
+    lines
+        .iter()
+        .fold(FakeCli::new(), |cli, line| cli.line(*line))
+        .write(dir, name)
+        .expect("Failed to write mock CLI")
+}
+
 /// Mock session ID generator
 pub fn generate_mock_session_id() -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_secs();
+        .as_nanos();
     format!("test-session-{}", timestamp)
 }
 
@@ -46,6 +58,16 @@ mod tests {
         assert_eq!(opts.working_dir, PathBuf::from("/tmp"));
     }
 
+    #[test]
+    fn test_write_mock_cli_prints_lines_verbatim() {
+        let dir = tempfile::tempdir().unwrap();
+        let line = r#"{"type":"result","result":"it's <ok> & 100%"}"#;
+        let path = write_mock_cli(dir.path(), "mock", &[line]);
+
+        let output = std::process::Command::new(&path).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), line);
+    }
+
     #[test]
     fn test_generate_mock_session_id() {
         let id1 = generate_mock_session_id();
@@ -1,8 +1,10 @@
 use claude_mcp_rs::claude::{ClaudeResult, Options};
-use serde_json::Value;
-use std::collections::HashMap;
+use claude_mcp_rs::fake_cli::FakeCli;
 use std::path::PathBuf;
 
+#[allow(dead_code)]
+mod common;
+
 #[test]
 fn test_agent_messages_size_limit() {
     // Create a mock result that would exceed the agent messages limit
@@ -16,6 +18,7 @@ fn test_agent_messages_size_limit() {
         all_messages_truncated: false,
         error: None,
         warnings: None,
+        ..Default::default()
     };
 
     // The agent_messages should be truncatable in practice
@@ -34,6 +37,7 @@ fn test_agent_messages_truncation_flag() {
         all_messages_truncated: false,
         error: None,
         warnings: None,
+        ..Default::default()
     };
 
     assert!(result.agent_messages_truncated);
@@ -52,15 +56,15 @@ fn test_all_messages_limit() {
         all_messages_truncated: false,
         error: None,
         warnings: None,
+        ..Default::default()
     };
 
     // Simulate adding messages up to limit
     for i in 0..50001 {
         if result.all_messages.len() < 50000 {
-            result.all_messages.push(HashMap::from([
-                ("id".to_string(), Value::String(format!("msg_{}", i))),
-                ("type".to_string(), Value::String("test".to_string())),
-            ]));
+            result
+                .all_messages
+                .push(serde_json::json!({"id": format!("msg_{}", i), "type": "test"}));
         } else {
             result.all_messages_truncated = true;
             break;
@@ -82,6 +86,7 @@ fn test_error_and_warning_handling() {
         all_messages_truncated: false,
         error: Some("Test error message".to_string()),
         warnings: Some("Test warning message".to_string()),
+        ..Default::default()
     };
 
     assert!(!result.success);
@@ -101,6 +106,7 @@ fn test_path_handling_with_non_utf8() {
         session_id: None,
         additional_args: Vec::new(),
         timeout_secs: None,
+        ..Default::default()
     };
 
     // Should be able to create options without panicking
@@ -140,33 +146,15 @@ async fn test_additional_args_are_passed_to_claude_cli() {
     // Path where the helper script will log its argv
     let log_path = temp_path.join("claude_args.log");
 
-    // Create a helper script that logs argv and emits a minimal JSON event
-    use std::fs;
-    use std::os::unix::fs::PermissionsExt;
-
-    let script_path = temp_path.join("echo_args.sh");
-    let script_contents = r#"#!/bin/sh
-LOG_FILE="${CLAUDE_ARGS_LOG}"
-: > "$LOG_FILE"
-printf "%s" "$0" > "$LOG_FILE"
-for arg in "$@"; do
-  printf " %s" "$arg" >> "$LOG_FILE"
-done
-echo '{"type":"assistant","message":{"content":[{"type":"text","text":"ok"}]},"session_id":"test-session"}'
-"#;
-
-    fs::write(&script_path, script_contents).expect("Failed to write script");
-    let mut perms = fs::metadata(&script_path)
-        .expect("Failed to get metadata")
-        .permissions();
-    perms.set_mode(0o755);
-    fs::set_permissions(&script_path, perms).expect("Failed to set permissions");
+    // A helper CLI that logs argv and emits a minimal JSON event
+    let script_path = FakeCli::new()
+        .log_args(&log_path)
+        .assistant_text("test-session", "ok")
+        .write(&temp_path, "echo_args")
+        .expect("Failed to write script");
 
     env::set_var("CLAUDE_BIN", script_path.to_str().unwrap());
 
-    // Make log path available to the helper script
-    env::set_var("CLAUDE_ARGS_LOG", log_path.to_str().unwrap());
-
     let additional = vec![
         "--dangerously-bypass-approvals-and-sandbox".to_string(),
         "--profile".to_string(),
@@ -179,6 +167,7 @@ echo '{"type":"assistant","message":{"content":[{"type":"text","text":"ok"}]},"s
         session_id: None,
         additional_args: additional.clone(),
         timeout_secs: Some(10),
+        ..Default::default()
     };
 
     let result = claude::run(opts).await.expect("run should return Ok");
@@ -216,7 +205,6 @@ echo '{"type":"assistant","message":{"content":[{"type":"text","text":"ok"}]},"s
 
     // Clean up env vars
     env::remove_var("CLAUDE_BIN");
-    env::remove_var("CLAUDE_ARGS_LOG");
 }
 
 #[tokio::test]
@@ -230,22 +218,15 @@ async fn test_no_duplicate_messages_from_assistant_and_result_events() {
     let temp_dir = tempdir().expect("Failed to create temp dir");
     let temp_path = temp_dir.path().to_path_buf();
 
-    use std::fs;
-    use std::os::unix::fs::PermissionsExt;
-
-    let script_path = temp_path.join("duplicate_test.sh");
     // Emit both "assistant" and "result" events with the same text
-    let script_contents = r#"#!/bin/sh
-echo '{"type":"assistant","message":{"content":[{"type":"text","text":"Hello from Claude!"}]},"session_id":"dup-test-session"}'
-echo '{"type":"result","result":"Hello from Claude!","is_error":false,"session_id":"dup-test-session"}'
-"#;
-
-    fs::write(&script_path, script_contents).expect("Failed to write script");
-    let mut perms = fs::metadata(&script_path)
-        .expect("Failed to get metadata")
-        .permissions();
-    perms.set_mode(0o755);
-    fs::set_permissions(&script_path, perms).expect("Failed to set permissions");
+    let script_path = common::write_mock_cli(
+        &temp_path,
+        "duplicate_test",
+        &[
+            r#"{"type":"assistant","message":{"content":[{"type":"text","text":"Hello from Claude!"}]},"session_id":"dup-test-session"}"#,
+            r#"{"type":"result","result":"Hello from Claude!","is_error":false,"session_id":"dup-test-session"}"#,
+        ],
+    );
 
     env::set_var("CLAUDE_BIN", script_path.to_str().unwrap());
 
@@ -255,6 +236,7 @@ echo '{"type":"result","result":"Hello from Claude!","is_error":false,"session_i
         session_id: None,
         additional_args: Vec::new(),
         timeout_secs: Some(10),
+        ..Default::default()
     };
 
     let result = claude::run(opts).await.expect("run should return Ok");
@@ -278,6 +260,11 @@ echo '{"type":"result","result":"Hello from Claude!","is_error":false,"session_i
         count
     );
 
+    // Timing metadata covers the spawn and both events
+    assert!(result.timing.spawn_ms.is_some());
+    assert!(result.timing.first_event_ms.is_some());
+    assert_eq!(result.timing.num_events, 2);
+
     env::remove_var("CLAUDE_BIN");
 }
 
@@ -292,21 +279,14 @@ async fn test_result_event_error_handling_without_assistant_event() {
     let temp_dir = tempdir().expect("Failed to create temp dir");
     let temp_path = temp_dir.path().to_path_buf();
 
-    use std::fs;
-    use std::os::unix::fs::PermissionsExt;
-
-    let script_path = temp_path.join("error_result_test.sh");
     // Emit only a "result" event with is_error:true (no assistant event)
-    let script_contents = r#"#!/bin/sh
-echo '{"type":"result","result":"Something went wrong","is_error":true,"session_id":"error-test-session"}'
-"#;
-
-    fs::write(&script_path, script_contents).expect("Failed to write script");
-    let mut perms = fs::metadata(&script_path)
-        .expect("Failed to get metadata")
-        .permissions();
-    perms.set_mode(0o755);
-    fs::set_permissions(&script_path, perms).expect("Failed to set permissions");
+    let script_path = common::write_mock_cli(
+        &temp_path,
+        "error_result_test",
+        &[
+            r#"{"type":"result","result":"Something went wrong","is_error":true,"session_id":"error-test-session"}"#,
+        ],
+    );
 
     env::set_var("CLAUDE_BIN", script_path.to_str().unwrap());
 
@@ -316,6 +296,7 @@ echo '{"type":"result","result":"Something went wrong","is_error":true,"session_
         session_id: None,
         additional_args: Vec::new(),
         timeout_secs: Some(10),
+        ..Default::default()
     };
 
     let result = claude::run(opts).await.expect("run should return Ok");
@@ -349,3 +330,193 @@ echo '{"type":"result","result":"Something went wrong","is_error":true,"session_
 
     env::remove_var("CLAUDE_BIN");
 }
+
+#[tokio::test]
+async fn test_config_supplies_binary_args_and_timeout_without_env() {
+    use claude_mcp_rs::claude::{self, ServerConfig};
+    use claude_mcp_rs::context::ServerContext;
+    use tempfile::tempdir;
+
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    // Logs its argv to a file of its own, so the test does not depend on CLAUDE_BIN
+    let args_log = temp_dir.path().join("args.log");
+    let script_path = FakeCli::new()
+        .log_args(&args_log)
+        .assistant_text("config-session", "ok")
+        .write(temp_dir.path(), "config_bin")
+        .expect("Failed to write script");
+
+    let config = ServerConfig::from_json(
+        &serde_json::json!({
+            "claude_bin": script_path,
+            "additional_args": ["--model", "haiku"],
+            "timeout_secs": 42,
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let context = ServerContext::new(config);
+
+    let opts = context
+        .scope(async { Options::from_config("hello", temp_dir.path()) })
+        .await;
+    assert_eq!(opts.binary.as_deref(), Some(script_path.as_path()));
+    assert_eq!(opts.additional_args, ["--model", "haiku"]);
+    assert_eq!(opts.timeout_secs, Some(42));
+
+    let result = context
+        .scope(claude::run(opts))
+        .await
+        .expect("run should return Ok");
+    assert!(
+        result.success,
+        "configured binary should run: {:?}",
+        result.error
+    );
+    let args = std::fs::read_to_string(&args_log).expect("failed to read args log");
+    assert!(args.contains("--model haiku"), "{}", args);
+}
+
+#[tokio::test]
+async fn test_recorded_run_replays_without_the_cli() {
+    use claude_mcp_rs::claude;
+    use tempfile::tempdir;
+
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let script_path = common::write_mock_cli(
+        temp_dir.path(),
+        "recorded",
+        &[
+            r#"{"type":"assistant","message":{"content":[{"type":"text","text":"Recorded answer."}]},"session_id":"recorded-session"}"#,
+            r#"{"type":"result","result":"Recorded answer.","is_error":false,"session_id":"recorded-session","total_cost_usd":0.25}"#,
+        ],
+    );
+    let recording = temp_dir.path().join("recordings").join("run.jsonl");
+
+    let recorded = claude::run(Options {
+        prompt: "answer".to_string(),
+        working_dir: temp_dir.path().to_path_buf(),
+        timeout_secs: Some(10),
+        binary: Some(script_path),
+        record_to: Some(recording.clone()),
+        ..Default::default()
+    })
+    .await
+    .expect("run should return Ok");
+    assert!(recorded.success, "{:?}", recorded.error);
+    assert_eq!(recorded.recording_file.as_ref(), Some(&recording));
+
+    let replayed = claude::run(Options {
+        prompt: "answer".to_string(),
+        working_dir: temp_dir.path().to_path_buf(),
+        timeout_secs: Some(10),
+        binary: Some(temp_dir.path().join("missing-claude")),
+        replay_from: Some(recording),
+        ..Default::default()
+    })
+    .await
+    .expect("replay should return Ok");
+    assert!(replayed.success, "{:?}", replayed.error);
+    assert_eq!(replayed.session_id, "recorded-session");
+    assert_eq!(replayed.agent_messages, recorded.agent_messages);
+    assert_eq!(replayed.total_cost_usd, Some(0.25));
+    assert_eq!(replayed.exit_code, Some(0));
+}
+
+#[tokio::test]
+async fn test_deeply_nested_event_fails_with_parser_limit_error() {
+    use claude_mcp_rs::claude::{self, ParserLimits};
+    use tempfile::tempdir;
+
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    // Within serde_json's own recursion limit, past the configured one
+    let nested = format!(
+        r#"{{"type":"assistant","session_id":"nested-session","payload":{}1{}}}"#,
+        "[".repeat(20),
+        "]".repeat(20)
+    );
+    let script_path = common::write_mock_cli(
+        temp_dir.path(),
+        "nested",
+        &[
+            r#"{"type":"system","subtype":"init","session_id":"nested-session"}"#,
+            nested.as_str(),
+        ],
+    );
+
+    let result = claude::run(Options {
+        prompt: "nest".to_string(),
+        working_dir: temp_dir.path().to_path_buf(),
+        timeout_secs: Some(10),
+        binary: Some(script_path),
+        parser_limits: ParserLimits {
+            max_depth: 8,
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .await
+    .expect("run should return Ok");
+
+    assert!(!result.success);
+    let error = result.error.unwrap();
+    assert!(
+        error.contains("exceeds parser limit (nesting deeper than 8 levels)"),
+        "unexpected error: {}",
+        error
+    );
+    assert_eq!(result.session_id, "nested-session");
+}
+
+#[tokio::test]
+async fn test_malformed_lines_are_skipped_up_to_the_limit() {
+    use claude_mcp_rs::claude;
+    use tempfile::tempdir;
+
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let run = |name: &str, lines: &[&str], max_skipped_lines: usize| {
+        let script_path = common::write_mock_cli(temp_dir.path(), name, lines);
+        claude::run(Options {
+            prompt: "tolerate".to_string(),
+            working_dir: temp_dir.path().to_path_buf(),
+            timeout_secs: Some(10),
+            binary: Some(script_path),
+            max_skipped_lines,
+            ..Default::default()
+        })
+    };
+    let assistant = r#"{"type":"assistant","message":{"content":[{"type":"text","text":"Still here."}]},"session_id":"tolerant-session"}"#;
+    let result_event = r#"{"type":"result","result":"Still here.","is_error":false,"session_id":"tolerant-session"}"#;
+
+    let tolerated = run(
+        "tolerated",
+        &[assistant, "Debugger attached.", result_event],
+        1,
+    )
+    .await
+    .unwrap();
+    assert!(tolerated.success, "{:?}", tolerated.error);
+    assert_eq!(tolerated.agent_messages, "Still here.");
+    assert!(tolerated
+        .warnings
+        .unwrap()
+        .contains("Skipped malformed output line"));
+
+    let strict = run(
+        "strict",
+        &[assistant, "Debugger attached.", result_event],
+        0,
+    )
+    .await
+    .unwrap();
+    assert!(!strict.success);
+
+    let unfinished = run("unfinished", &[assistant, "Debugger attached."], 1)
+        .await
+        .unwrap();
+    assert!(!unfinished.success);
+    assert!(unfinished
+        .error
+        .unwrap()
+        .contains("no result event after skipping 1 malformed output line"));
+}
@@ -10,6 +10,7 @@ fn test_options_validation() {
         session_id: None,
         additional_args: Vec::new(),
         timeout_secs: None,
+        ..Default::default()
     };
 
     assert!(!opts.prompt.is_empty());
@@ -31,6 +32,7 @@ fn test_session_id_format() {
         session_id: Some(session_id.to_string()),
         additional_args: Vec::new(),
         timeout_secs: None,
+        ..Default::default()
     };
 
     assert!(opts.session_id.is_some());
@@ -55,6 +57,7 @@ fn test_working_directory_paths() {
             session_id: None,
             additional_args: Vec::new(),
             timeout_secs: None,
+            ..Default::default()
         };
 
         assert_eq!(opts.working_dir, PathBuf::from(path));
@@ -0,0 +1,168 @@
+use claude_mcp_rs::claude::{self, ExecutionMode, Options};
+use claude_mcp_rs::fake_cli::FakeCli;
+use std::env;
+use std::fs;
+use tempfile::tempdir;
+
+// Tests in this file point CLAUDE_BIN at different scripts, so they must not overlap
+static ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[tokio::test]
+async fn test_interactive_mode_reuses_process_across_turns() {
+    let _guard = ENV_LOCK.lock().await;
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let temp_path = temp_dir.path().to_path_buf();
+    let spawn_log = temp_path.join("spawns.log");
+
+    // Answers every stream-json user message on stdin with one assistant + result pair
+    let script_path = FakeCli::new()
+        .log_args(&spawn_log)
+        .reply_per_line(
+            FakeCli::new()
+                .assistant_text("live-session", "answered")
+                .result("live-session", "answered"),
+        )
+        .write(&temp_path, "interactive")
+        .expect("Failed to write script");
+
+    env::set_var("CLAUDE_BIN", script_path.to_str().unwrap());
+
+    let mut session_id = None;
+    for _ in 0..2 {
+        let opts = Options {
+            prompt: "hello".to_string(),
+            working_dir: temp_path.clone(),
+            session_id: session_id.clone(),
+            timeout_secs: Some(10),
+            execution_mode: ExecutionMode::Interactive,
+            ..Default::default()
+        };
+
+        let result = claude::run(opts).await.expect("run should return Ok");
+        assert!(result.success, "turn should succeed: {:?}", result.error);
+        assert_eq!(result.session_id, "live-session");
+        assert_eq!(result.agent_messages, "answered");
+        session_id = Some(result.session_id);
+    }
+
+    let spawns = fs::read_to_string(&spawn_log).expect("failed to read spawn log");
+    assert_eq!(
+        spawns.lines().count(),
+        1,
+        "second turn should reuse the live process"
+    );
+
+    env::remove_var("CLAUDE_BIN");
+}
+
+#[tokio::test]
+async fn test_interactive_turn_with_other_args_resumes_in_new_process() {
+    let _guard = ENV_LOCK.lock().await;
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let temp_path = temp_dir.path().to_path_buf();
+    let spawn_log = temp_path.join("spawns.log");
+
+    // Logs the arguments of every process it is started as
+    let script_path = FakeCli::new()
+        .log_args(&spawn_log)
+        .reply_per_line(FakeCli::new().result("args-session", "ok"))
+        .write(&temp_path, "args")
+        .expect("Failed to write script");
+
+    env::set_var("CLAUDE_BIN", script_path.to_str().unwrap());
+
+    let mut session_id = None;
+    for model in ["sonnet", "sonnet", "opus"] {
+        let opts = Options {
+            prompt: "hello".to_string(),
+            working_dir: temp_path.clone(),
+            session_id: session_id.clone(),
+            additional_args: vec!["--model".to_string(), model.to_string()],
+            timeout_secs: Some(10),
+            execution_mode: ExecutionMode::Interactive,
+            ..Default::default()
+        };
+
+        let result = claude::run(opts).await.expect("run should return Ok");
+        assert!(result.success, "turn should succeed: {:?}", result.error);
+        session_id = Some(result.session_id);
+    }
+
+    let spawns = fs::read_to_string(&spawn_log).expect("failed to read spawn log");
+    let spawns: Vec<&str> = spawns.lines().collect();
+    assert_eq!(spawns.len(), 2, "only the model change needs a new process");
+    assert!(!spawns[0].contains("--resume"));
+    assert!(spawns[1].contains("--model opus --resume args-session"));
+
+    env::remove_var("CLAUDE_BIN");
+}
+
+#[tokio::test]
+async fn test_warm_pool_hands_prespawned_process_to_new_session() {
+    let _guard = ENV_LOCK.lock().await;
+    let temp_dir = tempdir().expect("Failed to create temp dir");
+    let temp_path = temp_dir.path().to_path_buf();
+    let spawn_log = temp_path.join("spawns.log");
+
+    // Each process logs its start, so the pre-spawned one shows up before any turn uses it
+    let script_path = FakeCli::new()
+        .log_args(&spawn_log)
+        .reply_per_line(
+            FakeCli::new()
+                .assistant_text("warm-session", "warm")
+                .result("warm-session", "warm"),
+        )
+        .write(&temp_path, "warm")
+        .expect("Failed to write script");
+
+    let opts = Options {
+        prompt: "hello".to_string(),
+        working_dir: temp_path.clone(),
+        timeout_secs: Some(10),
+        execution_mode: ExecutionMode::Interactive,
+        warm_pool_size: 1,
+        // Distinct args keep this pool separate from other tests
+        additional_args: vec!["--warm-pool-test".to_string()],
+        ..Default::default()
+    };
+
+    env::set_var("CLAUDE_BIN", script_path.to_str().unwrap());
+
+    let first = claude::run(opts.clone())
+        .await
+        .expect("run should return Ok");
+    assert!(
+        first.success,
+        "first turn should succeed: {:?}",
+        first.error
+    );
+    assert!(first.timing.spawn_ms.is_some());
+
+    // Give the background replenish task a moment to pre-spawn the idle process
+    let spawns = || {
+        fs::read_to_string(&spawn_log)
+            .unwrap_or_default()
+            .lines()
+            .count()
+    };
+    for _ in 0..50 {
+        if spawns() == 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(spawns(), 2, "warm pool should pre-spawn a process");
+
+    let second = claude::run(opts).await.expect("run should return Ok");
+    assert!(
+        second.success,
+        "second turn should succeed: {:?}",
+        second.error
+    );
+    assert!(
+        second.timing.spawn_ms.is_none(),
+        "second turn should use the pre-spawned process"
+    );
+
+    env::remove_var("CLAUDE_BIN");
+}
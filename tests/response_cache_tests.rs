@@ -0,0 +1,52 @@
+use claude_mcp_rs::claude::{self, Options, ResponseCache};
+use tempfile::tempdir;
+
+#[allow(dead_code)]
+mod common;
+
+// Sets CLAUDE_BIN, so it lives in its own test binary
+#[tokio::test]
+async fn test_repeated_run_over_unchanged_tree_is_served_from_cache() {
+    let work_dir = tempdir().expect("Failed to create temp dir");
+    let tools_dir = tempdir().expect("Failed to create temp dir");
+    std::fs::write(work_dir.path().join("README.md"), "# demo").unwrap();
+    let script_path = common::write_mock_cli(
+        tools_dir.path(),
+        "response_cache",
+        &[r#"{"type":"result","result":"Fresh.","is_error":false,"session_id":"cache-session"}"#],
+    );
+    let opts = Options {
+        prompt: "summarize".to_string(),
+        working_dir: work_dir.path().to_path_buf(),
+        timeout_secs: Some(10),
+        response_cache: Some(ResponseCache {
+            ttl_secs: 60,
+            max_entries: 8,
+        }),
+        ..Default::default()
+    };
+
+    std::env::set_var("CLAUDE_BIN", &script_path);
+    let first = claude::run(opts.clone()).await.unwrap();
+    assert!(first.success, "{:?}", first.error);
+    assert!(!first.cached);
+
+    // Without a CLI to run, only the cache can answer
+    std::env::set_var("CLAUDE_BIN", tools_dir.path().join("missing-claude"));
+    let second = claude::run(opts.clone()).await.unwrap();
+    assert!(second.cached);
+    assert_eq!(second.agent_messages, first.agent_messages);
+    assert_eq!(second.session_id, "cache-session");
+
+    // The same call asking for thinking blocks needs a result that collected them
+    let thinking = claude::run(Options {
+        include_thinking: true,
+        ..opts.clone()
+    })
+    .await;
+    assert!(thinking.map_or(true, |result| !result.success && !result.cached));
+
+    std::fs::write(work_dir.path().join("README.md"), "# changed").unwrap();
+    let third = claude::run(opts).await;
+    assert!(third.map_or(true, |result| !result.success && !result.cached));
+}
@@ -1,3 +1,5 @@
+use claude_mcp_rs::claude::ServerConfig;
+use claude_mcp_rs::context::ServerContext;
 use claude_mcp_rs::server::ClaudeServer;
 use rmcp::{model::*, ServerHandler};
 
@@ -15,10 +17,19 @@ fn test_server_info() {
     let info = server.get_info();
 
     // Check protocol version
-    assert_eq!(info.protocol_version, ProtocolVersion::V_2024_11_05);
+    assert_eq!(info.protocol_version, ProtocolVersion::V_2025_06_18);
 
     // Check capabilities
-    assert!(info.capabilities.tools.is_some());
+    assert_eq!(
+        info.capabilities
+            .tools
+            .as_ref()
+            .and_then(|tools| tools.list_changed),
+        Some(true)
+    );
+    assert!(info.capabilities.logging.is_some());
+    assert!(info.capabilities.prompts.is_some());
+    assert!(info.capabilities.completions.is_some());
 
     // Check server info - name and version come from Implementation::from_build_env()
     assert!(!info.server_info.name.is_empty());
@@ -64,3 +75,254 @@ mod validation_tests {
         assert!(parts.len() >= 2, "Version should have at least major.minor");
     }
 }
+
+#[test]
+fn test_server_uses_the_config_it_was_given() {
+    let config = ServerConfig::from_json(
+        r#"{
+            // Comments are allowed, as in the config file
+            "instructions": "Only review code; never edit files"
+        }"#,
+    )
+    .unwrap();
+    let server = ClaudeServer::with_context(ServerContext::new(config));
+
+    assert_eq!(
+        server.get_info().instructions.as_deref(),
+        Some("Only review code; never edit files")
+    );
+    assert!(ClaudeServer::new()
+        .get_info()
+        .instructions
+        .unwrap()
+        .contains("claude tool"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_failed_verification_keeps_a_snapshot_of_the_tree() {
+    use claude_mcp_rs::fake_cli::FakeCli;
+    use claude_mcp_rs::server::{run_once, ClaudeArgs};
+    use std::fs;
+    use std::process::Command;
+
+    let repo = tempfile::tempdir().unwrap();
+    fs::write(repo.path().join("app.txt"), "original\n").unwrap();
+    assert!(Command::new("git")
+        .args(["init", "-q"])
+        .current_dir(repo.path())
+        .status()
+        .unwrap()
+        .success());
+
+    let bin_dir = tempfile::tempdir().unwrap();
+    let script = FakeCli::new()
+        .write_file("app.txt", "broken\n")
+        .result("edit-session", "Edited.")
+        .write(bin_dir.path(), "editing-claude")
+        .unwrap();
+
+    let config = ServerConfig::from_json(
+        &serde_json::json!({
+            "claude_bin": script,
+            "snapshots": {"verify_command": ["grep", "-q", "original", "app.txt"]},
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let args = ClaudeArgs {
+        prompt: "edit app.txt".to_string(),
+        ..Default::default()
+    };
+    let (success, output) = ServerContext::new(config)
+        .scope(run_once(args, repo.path()))
+        .await
+        .unwrap();
+
+    assert!(!success);
+    assert_eq!(output["verification"]["passed"], false);
+    assert_eq!(output["verification"]["exit_code"], 1);
+    let snapshot_id = output["snapshot_id"].as_str().unwrap();
+    let snapshotted = Command::new("git")
+        .args([
+            "show",
+            &format!("refs/claude-mcp/snapshots/{}:app.txt", snapshot_id),
+        ])
+        .current_dir(repo.path())
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&snapshotted.stdout), "original\n");
+}
+
+#[tokio::test]
+async fn test_oversized_prompt_is_rejected_with_its_size_and_the_limit() {
+    use claude_mcp_rs::server::{run_once, ClaudeArgs};
+
+    let config = ServerConfig::from_json(r#"{"max_prompt_bytes": 16}"#).unwrap();
+    let args = ClaudeArgs {
+        prompt: "x".repeat(17),
+        ..Default::default()
+    };
+    let error = ServerContext::new(config)
+        .scope(run_once(args, std::path::Path::new(".")))
+        .await
+        .unwrap_err();
+
+    assert_eq!(error.code, ErrorCode::INVALID_PARAMS);
+    assert!(
+        error
+            .message
+            .contains("PROMPT is 17 bytes, over the limit of 16 bytes"),
+        "{}",
+        error.message
+    );
+    let data = error.data.unwrap();
+    assert_eq!(data["prompt_bytes"], 17);
+    assert_eq!(data["max_prompt_bytes"], 16);
+}
+
+#[tokio::test]
+async fn test_tool_defaults_apply_between_global_settings_and_profiles() {
+    use claude_mcp_rs::server::{run_once, ClaudeArgs};
+
+    let config = ServerConfig::from_json(
+        &serde_json::json!({
+            "claude_bin": "claude",
+            "additional_args": ["--global"],
+            "tools": {
+                "claude": {
+                    "additional_args": ["--from-tool"],
+                    "timeout_secs": 90,
+                    "model": "haiku",
+                },
+            },
+            "profiles": {"slow": {"additional_args": ["--from-profile"], "timeout_secs": 900}},
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let context = ServerContext::new(config);
+    let dry_run = |profile: Option<&str>, model: Option<&str>| {
+        let args = ClaudeArgs {
+            prompt: "Fix the build".to_string(),
+            profile: profile.map(str::to_string),
+            model: model.map(str::to_string),
+            dry_run: Some(true),
+            ..Default::default()
+        };
+        context.scope(run_once(args, std::path::Path::new(".")))
+    };
+
+    let (_, output) = dry_run(None, None).await.unwrap();
+    assert_eq!(output["timeout_secs"], 90);
+    let argv: Vec<&str> = output["argv"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|arg| arg.as_str())
+        .collect();
+    let position = |arg: &str| argv.iter().position(|a| *a == arg).unwrap();
+    assert!(position("--global") < position("--from-tool"));
+    assert_eq!(argv[position("--model") + 1], "haiku");
+
+    // A profile and the call's own arguments take precedence
+    let (_, output) = dry_run(Some("slow"), Some("opus")).await.unwrap();
+    assert_eq!(output["timeout_secs"], 900);
+    let argv = output["argv"].to_string();
+    assert!(
+        argv.find("--from-tool") < argv.find("--from-profile"),
+        "{}",
+        argv
+    );
+    assert!(argv.contains(r#""--model","opus""#), "{}", argv);
+    assert!(!argv.contains("haiku"), "{}", argv);
+}
+
+#[tokio::test]
+async fn test_settings_are_passed_with_profiles_replacing_the_global_ones() {
+    use claude_mcp_rs::server::{run_once, ClaudeArgs};
+
+    let config = ServerConfig::from_json(
+        &serde_json::json!({
+            "claude_bin": "claude",
+            "settings": {"permissions": {"deny": ["Bash(rm:*)"]}},
+            "profiles": {"hooks": {"settings": "ci/claude-settings.json"}},
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let context = ServerContext::new(config);
+    let settings_arg = |profile: Option<&str>| {
+        let args = ClaudeArgs {
+            prompt: "Fix the build".to_string(),
+            profile: profile.map(str::to_string),
+            dry_run: Some(true),
+            ..Default::default()
+        };
+        let context = context.clone();
+        async move {
+            let (_, output) = context
+                .scope(run_once(args, std::path::Path::new(".")))
+                .await
+                .unwrap();
+            let argv = output["argv"].as_array().unwrap().clone();
+            let at = argv.iter().position(|arg| arg == "--settings").unwrap();
+            assert_eq!(argv.iter().filter(|arg| *arg == "--settings").count(), 1);
+            argv[at + 1].as_str().unwrap().to_string()
+        }
+    };
+
+    assert_eq!(
+        settings_arg(None).await,
+        r#"{"permissions":{"deny":["Bash(rm:*)"]}}"#
+    );
+    assert_eq!(settings_arg(Some("hooks")).await, "ci/claude-settings.json");
+}
+
+#[tokio::test]
+async fn test_output_language_is_appended_to_the_system_prompt() {
+    use claude_mcp_rs::server::{run_once, ClaudeArgs};
+
+    let config = ServerConfig::from_json(
+        &serde_json::json!({
+            "claude_bin": "claude",
+            "additional_args": ["--append-system-prompt", "Be terse."],
+            "output_language": "German",
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let context = ServerContext::new(config);
+    let system_prompts = |language: Option<&str>| {
+        let args = ClaudeArgs {
+            prompt: "Explain the build".to_string(),
+            output_language: language.map(str::to_string),
+            dry_run: Some(true),
+            ..Default::default()
+        };
+        let context = context.clone();
+        async move {
+            let (_, output) = context
+                .scope(run_once(args, std::path::Path::new(".")))
+                .await
+                .unwrap();
+            let argv = output["argv"].as_array().unwrap().clone();
+            argv.iter()
+                .enumerate()
+                .filter(|(_, arg)| *arg == "--append-system-prompt")
+                .map(|(at, _)| argv[at + 1].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let prompts = system_prompts(None).await;
+    assert_eq!(prompts.len(), 1);
+    assert!(
+        prompts[0]
+            .starts_with("Be terse.\n\nWrite all answers, summaries, and explanations in German."),
+        "{:?}",
+        prompts
+    );
+    let prompts = system_prompts(Some("Polish")).await;
+    assert!(prompts[0].contains("in Polish."), "{:?}", prompts);
+}